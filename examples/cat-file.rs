@@ -6,16 +6,25 @@ use structopt::StructOpt;
 /// Print an object
 #[derive(StructOpt)]
 struct Args {
-    /// The id of the object to print
+    /// The id of the object to print, which may be abbreviated
     #[structopt(parse(try_from_str))]
-    id: object::Id,
+    id: object::ShortId,
+    /// Print the type of the object instead of its contents
+    #[structopt(short = "t")]
+    kind: bool,
 }
 
 pub fn main() -> Result<()> {
     let args = Args::from_args();
     let repo = Repository::open(".")?;
 
-    let object = repo.object_database().parse_object(args.id)?;
+    let id = repo.object_database().resolve(&args.id)?;
+    if args.kind {
+        println!("{}", repo.object_database().read_header(id)?.kind);
+        return Ok(());
+    }
+
+    let object = repo.object_database().parse_object(id)?;
 
     println!("{:#?}", object);
     Ok(())
@@ -0,0 +1,628 @@
+//! Reading git config files.
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bstr::{BStr, BString, ByteSlice};
+use thiserror::Error;
+
+// The same limit as git, to catch include cycles.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// The variables set in a config file, in the order they appear.
+///
+/// Variables are looked up by keys of the form `section.key` or
+/// `section.subsection.key`. Section and key names are case-insensitive, but
+/// subsection names are case-sensitive.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Error)]
+#[error("{message} on line {line}")]
+pub struct ParseConfigError {
+    line: usize,
+    message: &'static str,
+}
+
+#[derive(Debug, Error)]
+pub enum ReadConfigError {
+    #[error("the config file `{path}` is invalid")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: ParseConfigError,
+    },
+    #[error("config file `{0}` exceeds the maximum include depth")]
+    IncludeDepth(PathBuf),
+    #[error("io error reading the config file")]
+    Io(
+        #[source]
+        #[from]
+        io::Error,
+    ),
+}
+
+/// A config variable whose value could not be converted to the requested type.
+#[derive(Debug, Error)]
+#[error("invalid {kind} value `{value}` for config key `{key}`")]
+pub struct ConfigValueError {
+    key: String,
+    value: BString,
+    kind: &'static str,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    // Section and key names are stored in lowercase.
+    section: String,
+    subsection: Option<BString>,
+    key: String,
+    // `None` for a key with no `=`, which is a shorthand for `true`.
+    value: Option<BString>,
+}
+
+struct ConfigParser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Config {
+    /// Parse the contents of a config file. Include directives are not
+    /// followed, since relative paths cannot be resolved.
+    pub fn parse(data: &[u8]) -> Result<Self, ParseConfigError> {
+        Ok(Config {
+            entries: ConfigParser::new(data).parse()?,
+        })
+    }
+
+    /// Read a config file. If the file does not exist, the config is empty.
+    ///
+    /// Files named by `include.path` are read in place of the directive.
+    /// Relative paths are resolved against the directory of the including
+    /// file, and paths starting with `~/` against the home directory.
+    /// Conditional `includeIf` sections are ignored.
+    pub fn read(path: &Path) -> Result<Self, ReadConfigError> {
+        let mut entries = Vec::new();
+        read_entries(path, 0, &mut entries)?;
+        Ok(Config { entries })
+    }
+
+    /// The last value of `key`, which is the one git uses if the key is set
+    /// multiple times. Keys with no value, such as `[core] bare`, have an
+    /// empty value.
+    pub fn get_str(&self, key: &str) -> Option<&BStr> {
+        self.values(key).last().map(value_or_empty)
+    }
+
+    /// The last value of `key`, interpreted as a boolean.
+    ///
+    /// Like git, `true`, `yes`, `on` and keys with no value are true, and
+    /// `false`, `no`, `off` and the empty string are false. Integers are
+    /// true if they are non-zero.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, ConfigValueError> {
+        match self.values(key).last() {
+            Some(value) => match parse_bool(value) {
+                Some(value) => Ok(Some(value)),
+                None => Err(ConfigValueError::new(key, value, "boolean")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The last value of `key`, interpreted as an integer. The value may have
+    /// a `k`, `m` or `g` suffix to scale it by 1024, 1024² or 1024³.
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>, ConfigValueError> {
+        match self.values(key).last() {
+            Some(value) => match parse_int(value_or_empty(value)) {
+                Some(value) => Ok(Some(value)),
+                None => Err(ConfigValueError::new(key, value, "integer")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Every value of `key`, in the order they were set.
+    pub fn get_all<'a>(&'a self, key: &str) -> impl Iterator<Item = &'a BStr> + 'a {
+        self.values(key).map(value_or_empty)
+    }
+
+    fn values<'a>(&'a self, key: &str) -> impl DoubleEndedIterator<Item = Option<&'a BStr>> + 'a {
+        // The key is copied so that the iterator does not borrow it.
+        let key = split_key(key).map(|(section, subsection, name)| {
+            (
+                section.to_owned(),
+                subsection.map(str::to_owned),
+                name.to_owned(),
+            )
+        });
+        self.entries
+            .iter()
+            .filter(move |entry| match &key {
+                Some((section, subsection, name)) => {
+                    entry.section.eq_ignore_ascii_case(section)
+                        && entry.subsection.as_deref().map(|sub| sub.as_bytes())
+                            == subsection.as_deref().map(str::as_bytes)
+                        && entry.key.eq_ignore_ascii_case(name)
+                }
+                None => false,
+            })
+            .map(|entry| entry.value.as_ref().map(|value| value.as_bstr()))
+    }
+}
+
+impl ConfigValueError {
+    pub(crate) fn new(key: &str, value: Option<&BStr>, kind: &'static str) -> Self {
+        ConfigValueError {
+            key: key.to_owned(),
+            value: value_or_empty(value).to_owned(),
+            kind,
+        }
+    }
+
+    /// The key whose value was invalid.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl ParseConfigError {
+    /// The line number where the error occurred, starting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl<'a> ConfigParser<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ConfigParser {
+            data,
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn parse(mut self) -> Result<Vec<Entry>, ParseConfigError> {
+        let mut entries = Vec::new();
+        let mut section = None;
+
+        loop {
+            match self.peek() {
+                None => return Ok(entries),
+                Some(b) if b.is_ascii_whitespace() => {
+                    self.next();
+                }
+                Some(b'#') | Some(b';') => self.skip_line(),
+                Some(b'[') => {
+                    self.next();
+                    section = Some(self.parse_section()?);
+                }
+                Some(b) if b.is_ascii_alphabetic() => {
+                    let (section, subsection) = match &section {
+                        Some(section) => section,
+                        None => return Err(self.error("variable is not in a section")),
+                    };
+                    let key = self.parse_name(|b| b.is_ascii_alphanumeric() || b == b'-');
+                    let value = self.parse_value()?;
+                    entries.push(Entry {
+                        section: section.clone(),
+                        subsection: subsection.clone(),
+                        key,
+                        value,
+                    });
+                }
+                Some(_) => return Err(self.error("invalid character")),
+            }
+        }
+    }
+
+    // Parse a section header, after the opening `[`.
+    fn parse_section(&mut self) -> Result<(String, Option<BString>), ParseConfigError> {
+        let name = self.parse_name(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.');
+        if name.is_empty() {
+            return Err(self.error("invalid section name"));
+        }
+
+        match self.next() {
+            Some(b']') => {
+                // The deprecated `[section.subsection]` syntax, where the
+                // subsection is case-insensitive.
+                let mut parts = name.splitn(2, '.');
+                let section = parts.next().unwrap_or_default().to_owned();
+                let subsection = parts.next().map(BString::from);
+                Ok((section, subsection))
+            }
+            Some(b' ') | Some(b'\t') if !name.contains('.') => {
+                while let Some(b' ') | Some(b'\t') = self.peek() {
+                    self.next();
+                }
+                if self.next() != Some(b'"') {
+                    return Err(self.error("invalid section header"));
+                }
+
+                let mut subsection = Vec::new();
+                loop {
+                    let b = match self.next_in_line() {
+                        Some(b'"') => break,
+                        Some(b'\\') => self.next_in_line(),
+                        b => b,
+                    };
+                    match b {
+                        Some(b) => subsection.push(b),
+                        None => return Err(self.error("unterminated section header")),
+                    }
+                }
+
+                if self.next() != Some(b']') {
+                    return Err(self.error("invalid section header"));
+                }
+                Ok((name, Some(BString::from(subsection))))
+            }
+            _ => Err(self.error("invalid section header")),
+        }
+    }
+
+    // Parse a section or key name, converting it to lowercase.
+    fn parse_name(&mut self, valid: impl Fn(u8) -> bool) -> String {
+        let mut name = String::new();
+        while let Some(b) = self.peek() {
+            if !valid(b) {
+                break;
+            }
+            name.push(b.to_ascii_lowercase() as char);
+            self.next();
+        }
+        name
+    }
+
+    // Parse the rest of a variable line, after the key name.
+    fn parse_value(&mut self) -> Result<Option<BString>, ParseConfigError> {
+        while let Some(b' ') | Some(b'\t') | Some(b'\r') = self.peek() {
+            self.next();
+        }
+        match self.peek() {
+            Some(b'=') => {
+                self.next();
+            }
+            Some(b'#') | Some(b';') => {
+                self.skip_line();
+                return Ok(None);
+            }
+            Some(b'\n') | None => return Ok(None),
+            Some(_) => return Err(self.error("invalid variable name")),
+        }
+
+        // Like git, whitespace is trimmed from the ends of the value, and each
+        // whitespace character within it becomes a space.
+        let mut value = Vec::new();
+        let mut quoted = false;
+        let mut spaces = 0;
+        loop {
+            let b = match self.next_in_line() {
+                Some(b) => b,
+                None if quoted => return Err(self.error("unterminated quote")),
+                None => {
+                    self.next();
+                    break;
+                }
+            };
+
+            if !quoted {
+                if b == b' ' || b == b'\t' || b == b'\r' {
+                    if !value.is_empty() {
+                        spaces += 1;
+                    }
+                    continue;
+                }
+                if b == b'#' || b == b';' {
+                    self.skip_line();
+                    break;
+                }
+            }
+            value.resize(value.len() + spaces, b' ');
+            spaces = 0;
+
+            match b {
+                b'"' => quoted = !quoted,
+                b'\\' => match self.next() {
+                    // A line continuation.
+                    Some(b'\n') => (),
+                    Some(b'n') => value.push(b'\n'),
+                    Some(b't') => value.push(b'\t'),
+                    Some(b'b') => value.push(b'\x08'),
+                    Some(b'\\') => value.push(b'\\'),
+                    Some(b'"') => value.push(b'"'),
+                    _ => return Err(self.error("invalid escape sequence")),
+                },
+                b => value.push(b),
+            }
+        }
+
+        Ok(Some(BString::from(value)))
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(b) = self.next() {
+            if b == b'\n' {
+                break;
+            }
+        }
+    }
+
+    // Like `next`, but returns `None` without consuming the newline at the end
+    // of a line.
+    fn next_in_line(&mut self) -> Option<u8> {
+        match self.peek()? {
+            b'\n' => None,
+            _ => self.next(),
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        if b == b'\n' {
+            self.line += 1;
+        }
+        Some(b)
+    }
+
+    fn error(&self, message: &'static str) -> ParseConfigError {
+        ParseConfigError {
+            line: self.line,
+            message,
+        }
+    }
+}
+
+fn read_entries(
+    path: &Path,
+    depth: usize,
+    entries: &mut Vec<Entry>,
+) -> Result<(), ReadConfigError> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(ReadConfigError::IncludeDepth(path.to_owned()));
+    }
+
+    let data = match fs_err::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let file_entries =
+        ConfigParser::new(&data)
+            .parse()
+            .map_err(|source| ReadConfigError::Parse {
+                path: path.to_owned(),
+                source,
+            })?;
+
+    for entry in file_entries {
+        let include = match &entry.value {
+            Some(value)
+                if entry.section == "include"
+                    && entry.subsection.is_none()
+                    && entry.key == "path" =>
+            {
+                resolve_include_path(path, value.as_bstr())
+            }
+            _ => None,
+        };
+
+        entries.push(entry);
+        if let Some(include) = include {
+            read_entries(&include, depth + 1, entries)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn resolve_include_path(path: &Path, include: &BStr) -> Option<PathBuf> {
+    let include = include.to_path().ok()?;
+    if let Ok(rest) = include.strip_prefix("~") {
+        return Some(PathBuf::from(env::var_os("HOME")?).join(rest));
+    }
+    Some(path.parent()?.join(include))
+}
+
+// Split a key into its section, subsection and name.
+fn split_key(key: &str) -> Option<(&str, Option<&str>, &str)> {
+    let first = key.find('.')?;
+    let last = key.rfind('.')?;
+    let subsection = if first == last {
+        None
+    } else {
+        Some(&key[(first + 1)..last])
+    };
+    Some((&key[..first], subsection, &key[(last + 1)..]))
+}
+
+fn value_or_empty(value: Option<&BStr>) -> &BStr {
+    value.unwrap_or_else(|| b"".as_bstr())
+}
+
+fn parse_bool(value: Option<&BStr>) -> Option<bool> {
+    let value = match value {
+        Some(value) => value,
+        None => return Some(true),
+    };
+
+    if value.is_empty() {
+        return Some(false);
+    }
+    for &name in &["true", "yes", "on"] {
+        if value.eq_ignore_ascii_case(name.as_bytes()) {
+            return Some(true);
+        }
+    }
+    for &name in &["false", "no", "off"] {
+        if value.eq_ignore_ascii_case(name.as_bytes()) {
+            return Some(false);
+        }
+    }
+    parse_int(value).map(|value| value != 0)
+}
+
+fn parse_int(value: &BStr) -> Option<i64> {
+    let value = value.as_bytes();
+    let (digits, scale) = match value.last() {
+        Some(b'k') | Some(b'K') => (&value[..value.len() - 1], 1 << 10),
+        Some(b'm') | Some(b'M') => (&value[..value.len() - 1], 1 << 20),
+        Some(b'g') | Some(b'G') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    digits
+        .to_str()
+        .ok()?
+        .parse::<i64>()
+        .ok()?
+        .checked_mul(scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &[u8] = b"\
+# A comment
+[core]
+\trepositoryformatversion = 0
+\tbare = false
+\tfilemode
+[remote \"origin\"]
+\turl = https://example.com/repo.git ; a comment
+\tfetch = +refs/heads/*:refs/remotes/origin/*
+\tfetch = +refs/tags/*:refs/tags/*
+[Remote \"Origin\"]
+\turl = other
+[branch.Main]
+\tremote = origin
+[pack]
+\twindowMemory = 10m
+\tthreads = \"  two\"
+\tmessage = \"a \\\"quoted\\\" \\\\ \\t value\"   # trailing
+\tcontinued = one \\
+two
+";
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(CONFIG).unwrap();
+
+        assert_eq!(
+            config.get_int("core.repositoryformatversion").unwrap(),
+            Some(0)
+        );
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(false));
+        assert_eq!(config.get_bool("CORE.FileMode").unwrap(), Some(true));
+        assert_eq!(config.get_str("core.filemode").unwrap(), "");
+        assert_eq!(config.get_str("core.missing"), None);
+        assert_eq!(config.get_bool("core.missing").unwrap(), None);
+
+        assert_eq!(
+            config.get_str("remote.origin.url").unwrap(),
+            "https://example.com/repo.git"
+        );
+        assert_eq!(config.get_str("remote.Origin.url").unwrap(), "other");
+        assert_eq!(
+            config.get_str("remote.origin.fetch").unwrap(),
+            "+refs/tags/*:refs/tags/*"
+        );
+        assert_eq!(
+            config.get_all("remote.origin.fetch").collect::<Vec<_>>(),
+            vec![
+                "+refs/heads/*:refs/remotes/origin/*",
+                "+refs/tags/*:refs/tags/*"
+            ]
+        );
+        assert_eq!(config.get_str("branch.main.remote").unwrap(), "origin");
+
+        assert_eq!(
+            config.get_int("pack.windowmemory").unwrap(),
+            Some(10 * 1024 * 1024)
+        );
+        assert_eq!(config.get_str("pack.threads").unwrap(), "  two");
+        let err = config.get_int("pack.threads").unwrap_err();
+        assert_eq!(err.key(), "pack.threads");
+        assert_eq!(
+            err.to_string(),
+            "invalid integer value `  two` for config key `pack.threads`"
+        );
+        assert_eq!(
+            config.get_str("pack.message").unwrap(),
+            "a \"quoted\" \\ \t value"
+        );
+        assert_eq!(config.get_str("pack.continued").unwrap(), "one two");
+
+        assert_eq!(config.get_str("invalid"), None);
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        let config = Config::parse(
+            b"[a]\nt1 = yes\nt2 = On\nt3 = 2\nf1 = no\nf2 = OFF\nf3 = 0\nf4 =\ninvalid = maybe\n",
+        )
+        .unwrap();
+
+        for key in &["a.t1", "a.t2", "a.t3"] {
+            assert_eq!(config.get_bool(key).unwrap(), Some(true));
+        }
+        for key in &["a.f1", "a.f2", "a.f3", "a.f4"] {
+            assert_eq!(config.get_bool(key).unwrap(), Some(false));
+        }
+        assert!(config.get_bool("a.invalid").is_err());
+    }
+
+    #[test]
+    fn test_parse_config_errors() {
+        let line = |data: &[u8]| Config::parse(data).unwrap_err().line();
+
+        assert_eq!(line(b"key = value\n"), 1);
+        assert_eq!(line(b"[core]\n[invalid"), 2);
+        assert_eq!(line(b"[core \"sub]\n"), 1);
+        assert_eq!(line(b"[core]\nkey = \"unterminated\n"), 2);
+        assert_eq!(line(b"[core]\nkey = \\x\n"), 2);
+        assert_eq!(line(b"[core]\nkey! = value\n"), 2);
+    }
+
+    #[test]
+    fn test_read_config_includes() {
+        let dir = tempdir::TempDir::new("rusty_git_config").unwrap();
+        let path = dir.path().join("config");
+        fs_err::write(
+            &path,
+            b"[core]\nbare = true\n[include]\npath = included\n[core]\nabbrev = 12\n",
+        )
+        .unwrap();
+        fs_err::write(
+            dir.path().join("included"),
+            b"[core]\nbare = false\nabbrev = 8\n[include]\npath = missing\n",
+        )
+        .unwrap();
+
+        let config = Config::read(&path).unwrap();
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(false));
+        assert_eq!(config.get_int("core.abbrev").unwrap(), Some(12));
+        assert_eq!(
+            config.get_all("core.abbrev").collect::<Vec<_>>(),
+            vec!["8", "12"]
+        );
+
+        fs_err::write(&path, b"[include]\npath = config\n").unwrap();
+        assert!(matches!(
+            Config::read(&path),
+            Err(ReadConfigError::IncludeDepth(_))
+        ));
+
+        let config = Config::read(&dir.path().join("missing")).unwrap();
+        assert_eq!(config.get_str("core.bare"), None);
+    }
+}
@@ -0,0 +1,428 @@
+//! Reading the index file, also known as the staging area.
+
+use std::fmt;
+use std::io;
+use std::mem::size_of;
+use std::ops::Range;
+use std::path::Path;
+
+use bstr::{BStr, BString, ByteSlice};
+use byteorder::NetworkEndian;
+use bytes::Bytes;
+use filetime::FileTime;
+use thiserror::Error;
+use zerocopy::byteorder::{U16, U32};
+use zerocopy::FromBytes;
+
+use crate::object::{FileMode, HashAlgorithm, Id};
+use crate::parse::Parser;
+
+const SIGNATURE: &[u8] = b"DIRC";
+
+/// The entries of the index file, sorted by path.
+#[derive(Clone)]
+pub struct Index {
+    // The paths of all entries. Paths are copied out of the file, since they
+    // are prefix compressed in version 4.
+    paths: BString,
+    entries: Vec<EntryRaw>,
+}
+
+/// An entry in the index.
+#[derive(Clone, Copy)]
+pub struct IndexEntry<'a> {
+    index: &'a Index,
+    raw: &'a EntryRaw,
+}
+
+#[derive(Debug, Error)]
+pub enum ParseIndexError {
+    #[error("cannot parse an index file with version `{0}`")]
+    UnknownVersion(u32),
+    #[error("the index checksum does not match its contents")]
+    ChecksumMismatch,
+    #[error("{0}")]
+    Other(&'static str),
+}
+
+#[derive(Debug, Error)]
+pub enum ReadIndexError {
+    #[error("the index file is invalid")]
+    Parse(
+        #[source]
+        #[from]
+        ParseIndexError,
+    ),
+    #[error("io error reading the index file")]
+    Io(
+        #[source]
+        #[from]
+        io::Error,
+    ),
+}
+
+#[derive(Clone)]
+struct EntryRaw {
+    ctime: FileTime,
+    mtime: FileTime,
+    id: Id,
+    mode: u32,
+    stage: u8,
+    path: Range<usize>,
+}
+
+#[repr(C)]
+#[derive(FromBytes)]
+struct EntryHeader {
+    ctime: [U32<NetworkEndian>; 2],
+    mtime: [U32<NetworkEndian>; 2],
+    dev: U32<NetworkEndian>,
+    ino: U32<NetworkEndian>,
+    mode: U32<NetworkEndian>,
+    uid: U32<NetworkEndian>,
+    gid: U32<NetworkEndian>,
+    size: U32<NetworkEndian>,
+}
+
+impl Index {
+    const EXTENDED_FLAG: u16 = 0x4000;
+    const STAGE_MASK: u16 = 0x3000;
+    const STAGE_SHIFT: u16 = 12;
+
+    /// Parse the contents of an index file.
+    ///
+    /// Versions 2, 3 and 4 of the format are supported. Any extensions
+    /// following the entries are ignored. Object ids are assumed to be SHA-1.
+    ///
+    /// The checksum at the end of the file is verified, unless it is all zeros,
+    /// which git writes when `index.skipHash` is set.
+    pub fn parse(data: impl Into<Bytes>) -> Result<Self, ParseIndexError> {
+        Index::parse_with_hash_algorithm(data, HashAlgorithm::Sha1)
+    }
+
+    /// Parse the contents of an index file from a repository using the given
+    /// hash algorithm.
+    pub fn parse_with_hash_algorithm(
+        data: impl Into<Bytes>,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self, ParseIndexError> {
+        let data = data.into();
+        let checksum_start = data
+            .len()
+            .checked_sub(hash_algorithm.digest_len())
+            .ok_or(ParseIndexError::Other("file is too short"))?;
+        let checksum = &data[checksum_start..];
+        if checksum.iter().any(|&b| b != 0)
+            && Id::from_bytes(checksum) != hash_algorithm.hash(&data[..checksum_start])
+        {
+            return Err(ParseIndexError::ChecksumMismatch);
+        }
+
+        let mut parser =
+            Parser::new(data.slice(..checksum_start)).with_hash_algorithm(hash_algorithm);
+
+        if !parser.consume_bytes(SIGNATURE) {
+            return Err(ParseIndexError::Other("invalid signature"));
+        }
+        let version = parser
+            .parse_u32()
+            .map_err(|_| ParseIndexError::Other("file is too short"))?;
+        if !(2..=4).contains(&version) {
+            return Err(ParseIndexError::UnknownVersion(version));
+        }
+        let count = parser
+            .parse_u32()
+            .map_err(|_| ParseIndexError::Other("file is too short"))?;
+
+        let mut paths = Vec::new();
+        let mut entries = Vec::new();
+        let mut prev_path = 0..0;
+        for _ in 0..count {
+            let start = parser.pos();
+            let header = parser
+                .parse_struct::<EntryHeader>()
+                .map_err(|_| ParseIndexError::Other("entry is too short"))?;
+            let mode = header.mode.get();
+            let ctime = file_time(&header.ctime);
+            let mtime = file_time(&header.mtime);
+            let id = parser
+                .parse_id()
+                .map_err(|_| ParseIndexError::Other("entry is too short"))?;
+            let flags = parser
+                .parse_struct::<U16<NetworkEndian>>()
+                .map_err(|_| ParseIndexError::Other("entry is too short"))?
+                .get();
+
+            if flags & Index::EXTENDED_FLAG != 0 {
+                if version < 3 {
+                    return Err(ParseIndexError::Other(
+                        "extended flags are not supported in version 2",
+                    ));
+                }
+                if !parser.advance(size_of::<u16>()) {
+                    return Err(ParseIndexError::Other("entry is too short"));
+                }
+            }
+
+            let path_start = paths.len();
+            if version == 4 {
+                // The path is stored as the number of bytes to remove from the
+                // end of the previous path, followed by the bytes to append.
+                let strip = parse_offset(&mut parser)
+                    .ok_or(ParseIndexError::Other("invalid entry path prefix"))?;
+                let prefix_len = prev_path
+                    .len()
+                    .checked_sub(strip)
+                    .ok_or(ParseIndexError::Other("invalid entry path prefix"))?;
+                paths.extend_from_within(prev_path.start..(prev_path.start + prefix_len));
+            }
+
+            let suffix = parser
+                .consume_until(b'\0')
+                .ok_or(ParseIndexError::Other("unterminated entry path"))?;
+            paths.extend_from_slice(&parser[suffix.clone()]);
+
+            // Before version 4, entries are padded with 1-8 nul bytes to a
+            // multiple of 8 bytes.
+            if version < 4 {
+                let len = suffix.end - start;
+                let padding = (len + 8) / 8 * 8 - (len + 1);
+                if !parser.advance(padding) {
+                    return Err(ParseIndexError::Other("entry is too short"));
+                }
+            }
+
+            let path = path_start..paths.len();
+            prev_path = path.clone();
+            entries.push(EntryRaw {
+                ctime,
+                mtime,
+                id,
+                mode,
+                stage: ((flags & Index::STAGE_MASK) >> Index::STAGE_SHIFT) as u8,
+                path,
+            });
+        }
+
+        Ok(Index {
+            paths: BString::from(paths),
+            entries,
+        })
+    }
+
+    /// Read an index file. If the file does not exist, the index is empty.
+    pub(crate) fn read(path: &Path, hash_algorithm: HashAlgorithm) -> Result<Self, ReadIndexError> {
+        match fs_err::read(path) {
+            Ok(data) => Ok(Index::parse_with_hash_algorithm(data, hash_algorithm)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Index {
+                paths: BString::from(""),
+                entries: Vec::new(),
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn entries<'a>(&'a self) -> impl ExactSizeIterator<Item = IndexEntry<'a>> + 'a {
+        self.entries
+            .iter()
+            .map(move |raw| IndexEntry { index: self, raw })
+    }
+}
+
+impl<'a> IndexEntry<'a> {
+    pub fn path(&self) -> &'a BStr {
+        self.index.paths[self.raw.path.clone()].as_bstr()
+    }
+
+    pub fn id(&self) -> Id {
+        self.raw.id
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.raw.mode
+    }
+
+    /// The kind of file, as determined by its mode.
+    pub fn file_mode(&self) -> FileMode {
+        FileMode::from_raw(self.raw.mode as u16)
+    }
+
+    /// The time the file's metadata last changed, when it was added to the index.
+    pub fn ctime(&self) -> FileTime {
+        self.raw.ctime
+    }
+
+    /// The time the file's contents last changed, when it was added to the index.
+    pub fn mtime(&self) -> FileTime {
+        self.raw.mtime
+    }
+
+    /// The merge stage of the entry. This is zero unless the path has conflicts.
+    pub fn stage(&self) -> u8 {
+        self.raw.stage
+    }
+}
+
+fn file_time(time: &[U32<NetworkEndian>; 2]) -> FileTime {
+    FileTime::from_unix_time(time[0].get().into(), time[1].get())
+}
+
+// Parse a variable-length offset, using the same encoding as offset deltas in
+// packs.
+fn parse_offset(parser: &mut Parser<Bytes>) -> Option<usize> {
+    let mut byte = parser.parse_byte().ok()?;
+    let mut value = usize::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        byte = parser.parse_byte().ok()?;
+        value = value
+            .checked_add(1)?
+            .checked_mul(1 << 7)?
+            .checked_add(usize::from(byte & 0x7f))?;
+    }
+    Some(value)
+}
+
+impl fmt::Debug for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.entries()).finish()
+    }
+}
+
+impl<'a> fmt::Debug for IndexEntry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IndexEntry")
+            .field("path", &self.path())
+            .field("id", &self.id())
+            .field("mode", &format_args!("{:o}", self.mode()))
+            .field("stage", &self.stage())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::align_of;
+
+    use super::*;
+
+    #[test]
+    fn test_entry_header_layout() {
+        assert_eq!(size_of::<EntryHeader>(), 40);
+        assert_eq!(align_of::<EntryHeader>(), 1);
+    }
+
+    fn entry(bytes: &mut Vec<u8>, path: &str, id: Id, flags: u16) {
+        let start = bytes.len();
+        entry_header(bytes, id, flags | path.len() as u16);
+        bytes.extend_from_slice(path.as_bytes());
+        let len = bytes.len() - start;
+        bytes.resize(start + (len + 8) / 8 * 8, 0);
+    }
+
+    fn entry_header(bytes: &mut Vec<u8>, id: Id, flags: u16) {
+        bytes.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4]);
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&0o100755u32.to_be_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(&hex::decode(id.to_hex()).unwrap());
+        bytes.extend_from_slice(&flags.to_be_bytes());
+    }
+
+    fn checksum(bytes: &mut Vec<u8>) {
+        let checksum = HashAlgorithm::Sha1.hash(bytes);
+        bytes.extend_from_slice(&hex::decode(checksum.to_hex()).unwrap());
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let mut bytes = b"DIRC\x00\x00\x00\x02\x00\x00\x00\x03".to_vec();
+        entry(&mut bytes, "a", Id::from_hash(b"a"), 0);
+        entry(&mut bytes, "dir/file.txt", Id::from_hash(b"b"), 0);
+        entry(&mut bytes, "conflict", Id::from_hash(b"c"), 0x2000);
+        bytes.extend_from_slice(&[0; 20]);
+
+        let index = Index::parse(bytes).unwrap();
+        let entries: Vec<_> = index.entries().collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].path(), "a");
+        assert_eq!(entries[0].id(), Id::from_hash(b"a"));
+        assert_eq!(entries[0].mode(), 0o100755);
+        assert_eq!(entries[0].file_mode(), FileMode::ExecutableBlob);
+        assert_eq!(entries[0].ctime(), FileTime::from_unix_time(1, 2));
+        assert_eq!(entries[0].mtime(), FileTime::from_unix_time(3, 4));
+        assert_eq!(entries[0].stage(), 0);
+        assert_eq!(entries[1].path(), "dir/file.txt");
+        assert_eq!(entries[1].id(), Id::from_hash(b"b"));
+        assert_eq!(entries[2].path(), "conflict");
+        assert_eq!(entries[2].stage(), 2);
+    }
+
+    #[test]
+    fn test_parse_index_v4() {
+        let mut bytes = b"DIRC\x00\x00\x00\x04\x00\x00\x00\x03".to_vec();
+        entry_header(&mut bytes, Id::from_hash(b"a"), 0);
+        bytes.extend_from_slice(b"\x00dir/a.txt\0");
+        // Remove "a.txt" and append "b.txt".
+        entry_header(&mut bytes, Id::from_hash(b"b"), 0);
+        bytes.extend_from_slice(b"\x05b.txt\0");
+        // Remove "dir/b.txt" and append "file".
+        entry_header(&mut bytes, Id::from_hash(b"c"), 0);
+        bytes.extend_from_slice(b"\x09file\0");
+        checksum(&mut bytes);
+
+        let index = Index::parse(bytes).unwrap();
+        let paths: Vec<_> = index.entries().map(|entry| entry.path()).collect();
+        assert_eq!(paths, vec!["dir/a.txt", "dir/b.txt", "file"]);
+        assert_eq!(index.entries().nth(2).unwrap().id(), Id::from_hash(b"c"));
+
+        let mut bytes = b"DIRC\x00\x00\x00\x04\x00\x00\x00\x01".to_vec();
+        entry_header(&mut bytes, Id::from_hash(b"a"), 0);
+        bytes.extend_from_slice(b"\x01file\0");
+        checksum(&mut bytes);
+        assert!(matches!(
+            Index::parse(bytes),
+            Err(ParseIndexError::Other("invalid entry path prefix"))
+        ));
+    }
+
+    #[test]
+    fn test_parse_index_checksum() {
+        let mut bytes = b"DIRC\x00\x00\x00\x02\x00\x00\x00\x01".to_vec();
+        entry(&mut bytes, "a", Id::from_hash(b"a"), 0);
+        checksum(&mut bytes);
+        assert_eq!(Index::parse(bytes.clone()).unwrap().entries().len(), 1);
+
+        bytes[12] ^= 1;
+        assert!(matches!(
+            Index::parse(bytes),
+            Err(ParseIndexError::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_parse_index_errors() {
+        // Append an all-zero checksum, which is not verified.
+        let parse = |header: &[u8]| {
+            let mut bytes = header.to_vec();
+            bytes.extend_from_slice(&[0; 20]);
+            Index::parse(bytes)
+        };
+
+        assert!(matches!(
+            parse(b"DIRX\x00\x00\x00\x02\x00\x00\x00\x00"),
+            Err(ParseIndexError::Other(_))
+        ));
+        assert!(matches!(
+            parse(b"DIRC\x00\x00\x00\x05\x00\x00\x00\x00"),
+            Err(ParseIndexError::UnknownVersion(5))
+        ));
+        assert!(matches!(
+            parse(b"DIRC\x00\x00\x00\x02\x00\x00\x00\x01"),
+            Err(ParseIndexError::Other(_))
+        ));
+        assert!(matches!(
+            Index::parse(Bytes::from_static(b"DIRC")),
+            Err(ParseIndexError::Other(_))
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod config;
+pub mod index;
 pub mod object;
 pub mod reference;
 pub mod repository;
@@ -2,52 +2,87 @@ mod blob;
 mod commit;
 mod database;
 mod parse;
+#[cfg(feature = "serde")]
+mod serialize;
 mod signature;
 mod tag;
 mod tree;
 
 pub use self::blob::Blob;
-pub use self::commit::Commit;
-pub use self::database::ObjectDatabase;
-pub use self::signature::Signature;
-pub use self::tag::Tag;
-pub use self::tree::{Tree, TreeEntry};
+pub use self::commit::{Commit, CommitData};
+pub use self::database::{
+    Bundle, InfoPacks, ObjectDatabase, PackInfo, ParseInfoPacksError, ReadBundleError,
+    ReadInfoPacksError, ReadObjectIdsError, ReadPackError, ReadPacksError, RefreshError,
+    WriteError,
+};
+pub use self::parse::{ParseObjectDataError, ParseObjectKindError};
+pub use self::signature::{ParseSignatureError, Signature, SignatureOwned, Time};
+pub use self::tag::{ParseTagError, Tag, TagData};
+pub use self::tree::{
+    FileMode, InsertTreeEntryError, Tree, TreeBuilder, TreeEntry, TreeEntryOwned,
+};
 
 use std::cmp::Ordering;
-use std::convert::TryInto;
 use std::fmt;
 use std::io;
 use std::str::FromStr;
 
-use hex::FromHex;
 use sha1::digest::Digest;
 use sha1::Sha1;
+use sha2::Sha256;
 use thiserror::Error;
-use zerocopy::FromBytes;
 
 use self::blob::ParseBlobError;
 use self::commit::ParseCommitError;
-use self::parse::ParseObjectError;
-use self::tag::ParseTagError;
+use self::parse::{ParseHeaderError, ParseObjectError};
 use self::tree::ParseTreeError;
+use crate::config::{Config, ConfigValueError};
 use crate::parse::Parser;
+use crate::repository::Repository;
 
+/// The length of a SHA-1 object id.
 pub const ID_LEN: usize = 20;
 pub const ID_HEX_LEN: usize = ID_LEN * 2;
+/// The length of the longest object id, using SHA-256.
+pub const MAX_ID_LEN: usize = 32;
+pub const MAX_ID_HEX_LEN: usize = MAX_ID_LEN * 2;
+
+// The maximum number of tags followed by `Object::peel_to`, to avoid looping forever.
+const MAX_PEEL_DEPTH: usize = 64;
 
 pub const SHORT_ID_MIN_LEN: usize = 2;
 pub const SHORT_ID_MIN_HEX_LEN: usize = SHORT_ID_MIN_LEN * 2;
 
-#[repr(transparent)]
-#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, FromBytes)]
-pub struct Id([u8; ID_LEN]);
+/// The hash function used to compute object ids, as set by the
+/// `extensions.objectFormat` config option.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+/// An object id. Ids may be SHA-1 or SHA-256 hashes, depending on the
+/// repository they are from.
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Id {
+    // Bytes past the digest length of the algorithm are always zero.
+    bytes: [u8; MAX_ID_LEN],
+    algorithm: HashAlgorithm,
+}
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ShortId {
-    id: [u8; ID_LEN],
+    id: [u8; MAX_ID_LEN],
     len: u32,
 }
 
+// Computes an id incrementally.
+pub(crate) enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
 #[derive(Debug, Clone)]
 pub enum ObjectData {
     Commit(Commit),
@@ -72,10 +107,11 @@ pub struct Object {
     data: ObjectData,
 }
 
+/// The kind and length of an object, as stored at the start of its data.
 #[derive(Debug, Clone, Copy, PartialEq)]
-struct ObjectHeader {
-    kind: ObjectKind,
-    len: usize,
+pub struct ObjectHeader {
+    pub kind: ObjectKind,
+    pub len: usize,
 }
 
 /// An error when reading an object from the database.
@@ -90,13 +126,25 @@ enum ReadObjectErrorKind {
     Database(database::ReadError),
     Parse(ParseObjectError),
     Io(io::Error),
+    WrongKind {
+        expected: ObjectKind,
+        actual: ObjectKind,
+    },
+    WrongHashAlgorithm {
+        expected: HashAlgorithm,
+    },
+    Corrupt {
+        expected: Id,
+        actual: Id,
+    },
+    PeelDepthExceeded,
 }
 
 #[derive(Debug, Error)]
 pub enum ParseIdError {
     #[error("ids must be at least {} characters long", SHORT_ID_MIN_HEX_LEN)]
     TooShort,
-    #[error("ids can be at most {} characters long", ID_HEX_LEN)]
+    #[error("ids can be at most {} characters long", MAX_ID_HEX_LEN)]
     TooLong,
     #[error(transparent)]
     Hex(#[from] hex::FromHexError),
@@ -110,31 +158,269 @@ impl Object {
     pub fn data(&self) -> &ObjectData {
         &self.data
     }
+
+    pub fn into_data(self) -> ObjectData {
+        self.data
+    }
+
+    pub fn into_parts(self) -> (Id, ObjectData) {
+        (self.id, self.data)
+    }
+
+    /// The kind of the object, as reported by `git cat-file -t`.
+    pub fn kind(&self) -> ObjectKind {
+        self.data.kind()
+    }
+
+    /// The length of the object's body in bytes, as declared in its header and
+    /// reported by `git cat-file -s`.
+    pub fn size(&self) -> usize {
+        match &self.data {
+            ObjectData::Commit(commit) => commit.body_len(),
+            ObjectData::Tree(tree) => tree.body_len(),
+            ObjectData::Blob(blob) => blob.data().len(),
+            ObjectData::Tag(tag) => tag.body_len(),
+        }
+    }
+
+    /// Follow tags until an object of the given kind is reached.
+    ///
+    /// Returns an error if the chain of tags ends at an object of a different
+    /// kind, or if too many tags are followed, which may indicate a loop.
+    pub fn peel_to(&self, repo: &Repository, kind: ObjectKind) -> Result<Object, ReadObjectError> {
+        let mut object = self.clone();
+        for _ in 0..MAX_PEEL_DEPTH {
+            if object.data.kind() == kind {
+                return Ok(object);
+            }
+
+            object = match &object.data {
+                ObjectData::Tag(tag) => repo.object_database().parse_object(tag.object())?,
+                data => return Err(ReadObjectError::wrong_kind(object.id, kind, data)),
+            };
+        }
+
+        Err(ReadObjectError::new(
+            self.id,
+            ReadObjectErrorKind::PeelDepthExceeded,
+        ))
+    }
+
+    pub(crate) fn into_commit(self) -> Result<Commit, ReadObjectError> {
+        match self.data {
+            ObjectData::Commit(commit) => Ok(commit),
+            data => Err(ReadObjectError::wrong_kind(
+                self.id,
+                ObjectKind::Commit,
+                &data,
+            )),
+        }
+    }
+
+    pub(crate) fn into_tree(self) -> Result<Tree, ReadObjectError> {
+        match self.data {
+            ObjectData::Tree(tree) => Ok(tree),
+            data => Err(ReadObjectError::wrong_kind(
+                self.id,
+                ObjectKind::Tree,
+                &data,
+            )),
+        }
+    }
+
+    pub(crate) fn into_blob(self) -> Result<Blob, ReadObjectError> {
+        match self.data {
+            ObjectData::Blob(blob) => Ok(blob),
+            data => Err(ReadObjectError::wrong_kind(
+                self.id,
+                ObjectKind::Blob,
+                &data,
+            )),
+        }
+    }
+
+    pub(crate) fn into_tag(self) -> Result<Tag, ReadObjectError> {
+        match self.data {
+            ObjectData::Tag(tag) => Ok(tag),
+            data => Err(ReadObjectError::wrong_kind(self.id, ObjectKind::Tag, &data)),
+        }
+    }
+}
+
+impl ObjectData {
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            ObjectData::Commit(_) => ObjectKind::Commit,
+            ObjectData::Tree(_) => ObjectKind::Tree,
+            ObjectData::Blob(_) => ObjectKind::Blob,
+            ObjectData::Tag(_) => ObjectKind::Tag,
+        }
+    }
+}
+
+impl HashAlgorithm {
+    /// The length of an id in bytes.
+    pub fn digest_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => ID_LEN,
+            HashAlgorithm::Sha256 => MAX_ID_LEN,
+        }
+    }
+
+    /// The length of an id in hex digits.
+    pub fn hex_len(self) -> usize {
+        self.digest_len() * 2
+    }
+
+    /// The name of the algorithm, as used by `extensions.objectFormat`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// The algorithm set by `extensions.objectFormat` in a repository config,
+    /// defaulting to SHA-1 if it is not set.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigValueError> {
+        const KEY: &str = "extensions.objectFormat";
+        match config.get_str(KEY) {
+            None => Ok(HashAlgorithm::Sha1),
+            Some(value) => [HashAlgorithm::Sha1, HashAlgorithm::Sha256]
+                .iter()
+                .copied()
+                .find(|algorithm| value.eq_ignore_ascii_case(algorithm.as_str().as_bytes()))
+                .ok_or_else(|| ConfigValueError::new(KEY, Some(value), "object format")),
+        }
+    }
+
+    /// The all-zero id for this algorithm, which git uses to mean "no object".
+    pub fn zero_id(self) -> Id {
+        Id {
+            bytes: [0; MAX_ID_LEN],
+            algorithm: self,
+        }
+    }
+
+    /// Compute the id of some bytes, such as an object including its header.
+    pub fn hash(self, bytes: &[u8]) -> Id {
+        self.hasher().chain(bytes).finish()
+    }
+
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    fn from_digest_len(len: usize) -> Option<Self> {
+        match len {
+            ID_LEN => Some(HashAlgorithm::Sha1),
+            MAX_ID_LEN => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+}
+
+impl Hasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(bytes),
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    pub fn chain(mut self, bytes: impl AsRef<[u8]>) -> Self {
+        self.update(bytes.as_ref());
+        self
+    }
+
+    pub fn finish(self) -> Id {
+        match self {
+            Hasher::Sha1(hasher) => Id::from_bytes(&hasher.finalize()),
+            Hasher::Sha256(hasher) => Id::from_bytes(&hasher.finalize()),
+        }
+    }
 }
 
 impl Id {
+    /// Create an id from a 20-byte SHA-1 or 32-byte SHA-256 digest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` has any other length.
     pub fn from_bytes(bytes: &[u8]) -> Self {
-        Id(bytes.try_into().expect("invalid length for id"))
+        let algorithm = HashAlgorithm::from_digest_len(bytes.len()).expect("invalid length for id");
+        let mut id = Id {
+            bytes: [0; MAX_ID_LEN],
+            algorithm,
+        };
+        id.bytes[..bytes.len()].copy_from_slice(bytes);
+        id
+    }
+
+    /// The all-zero SHA-1 id, which git uses to mean "no object", for example
+    /// as the old value of a newly created reference. This is the same as
+    /// `Id::default()`. Use `HashAlgorithm::zero_id` for repositories using
+    /// SHA-256.
+    pub fn zero() -> Self {
+        HashAlgorithm::Sha1.zero_id()
     }
 
+    /// Whether this is the all-zero id of its hash algorithm.
+    pub fn is_zero(&self) -> bool {
+        self.as_bytes().iter().all(|&byte| byte == 0)
+    }
+
+    /// Compute the SHA-1 hash of some bytes. Use `HashAlgorithm::hash` for
+    /// repositories using SHA-256.
+    ///
+    /// The bytes are hashed as they are, so this is not the id of an object
+    /// with `bytes` as its body. Use `Id::hash_object` for that.
     pub fn from_hash(bytes: &[u8]) -> Self {
-        Id(Sha1::new().chain(bytes).finalize().into())
+        HashAlgorithm::Sha1.hash(bytes)
     }
 
+    /// Compute the SHA-1 id of an object with the given kind and body, as
+    /// `git hash-object` does, without writing it to the database.
+    ///
+    /// The body is hashed after a header in git's `<kind> <len>\0` format.
+    pub fn hash_object(kind: ObjectKind, body: &[u8]) -> Self {
+        ObjectHeader {
+            kind,
+            len: body.len(),
+        }
+        .hash(HashAlgorithm::Sha1, body)
+    }
+
+    /// Parse a full id from hex. Both uppercase and lowercase digits are
+    /// accepted, but ids are always formatted in lowercase.
+    ///
+    /// The hash algorithm is determined by the length of the id.
     pub fn from_hex(hex: &[u8]) -> Result<Self, ParseIdError> {
-        Ok(Id(FromHex::from_hex(hex)?))
+        let algorithm = HashAlgorithm::from_digest_len(hex.len() / 2)
+            .filter(|algorithm| algorithm.hex_len() == hex.len())
+            .ok_or(hex::FromHexError::InvalidStringLength)?;
+        let mut id = Id {
+            bytes: [0; MAX_ID_LEN],
+            algorithm,
+        };
+        hex::decode_to_slice(hex, &mut id.bytes[..algorithm.digest_len()])?;
+        Ok(id)
     }
 
     pub fn to_hex(&self) -> String {
-        hex::encode(&self.0)
+        hex::encode(self.as_bytes())
     }
 
-    fn as_bytes(&self) -> &[u8] {
-        &self.0
+    /// The hash algorithm used to compute this id.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.algorithm
     }
 
-    fn starts_with(&self, short_id: &ShortId) -> bool {
-        self.0.starts_with(short_id.as_bytes())
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.algorithm.digest_len()]
     }
 
     pub fn cmp_short(&self, short_id: &ShortId) -> Ordering {
@@ -156,15 +442,17 @@ impl ShortId {
         self.as_bytes().cmp(id.as_bytes())
     }
 
+    /// Parse an abbreviated id from hex. As with `Id::from_hex`, both
+    /// uppercase and lowercase digits are accepted.
     pub fn from_hex(hex: &[u8]) -> Result<Self, ParseIdError> {
         if hex.len() < SHORT_ID_MIN_HEX_LEN {
             return Err(ParseIdError::TooShort);
         }
-        if hex.len() > ID_HEX_LEN {
+        if hex.len() > MAX_ID_HEX_LEN {
             return Err(ParseIdError::TooLong);
         }
 
-        let mut id = [0; ID_LEN];
+        let mut id = [0; MAX_ID_LEN];
         let len = hex.len() / 2;
         hex::decode_to_slice(hex, &mut id[..len])?;
         Ok(ShortId {
@@ -178,6 +466,23 @@ impl ShortId {
     }
 }
 
+impl fmt::Display for ObjectKind {
+    /// Formats the kind as in git, e.g. `commit`, `tree`, `blob` or `tag`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ObjectKind {
+    type Err = ParseObjectKindError;
+
+    /// Parses one of `commit`, `tree`, `blob` or `tag`. The delta kinds only
+    /// occur in packs, so they are not accepted.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        ObjectKind::from_bytes(input.as_bytes())
+    }
+}
+
 impl fmt::Display for Id {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         self.to_hex().fmt(f)
@@ -221,8 +526,8 @@ impl FromStr for ShortId {
 impl From<Id> for ShortId {
     fn from(id: Id) -> Self {
         ShortId {
-            id: id.0,
-            len: ID_LEN as u32,
+            id: id.bytes,
+            len: id.algorithm.digest_len() as u32,
         }
     }
 }
@@ -234,6 +539,16 @@ impl ReadObjectError {
             kind: kind.into(),
         }
     }
+
+    fn wrong_kind(id: Id, expected: ObjectKind, data: &ObjectData) -> Self {
+        ReadObjectError::new(
+            id,
+            ReadObjectErrorKind::WrongKind {
+                expected,
+                actual: data.kind(),
+            },
+        )
+    }
 }
 
 impl fmt::Display for ReadObjectError {
@@ -250,6 +565,29 @@ impl fmt::Display for ReadObjectError {
             }
             ReadObjectErrorKind::Parse(_) => write!(f, "object `{}` is invalid", self.id),
             ReadObjectErrorKind::Io(_) => write!(f, "io error reading object `{}`", self.id),
+            ReadObjectErrorKind::WrongKind { expected, actual } => write!(
+                f,
+                "object `{}` is a {}, expected a {}",
+                self.id,
+                actual.as_str(),
+                expected.as_str()
+            ),
+            ReadObjectErrorKind::WrongHashAlgorithm { expected } => write!(
+                f,
+                "object id `{}` is not a valid {} id",
+                self.id,
+                expected.as_str()
+            ),
+            ReadObjectErrorKind::Corrupt { expected, actual } => write!(
+                f,
+                "object `{}` is corrupt, its contents hash to `{}`",
+                expected, actual
+            ),
+            ReadObjectErrorKind::PeelDepthExceeded => write!(
+                f,
+                "object `{}` could not be peeled after following {} tags",
+                self.id, MAX_PEEL_DEPTH
+            ),
         }
     }
 }
@@ -262,6 +600,10 @@ impl std::error::Error for ReadObjectError {
             ReadObjectErrorKind::Database(ref err) => Some(err),
             ReadObjectErrorKind::Parse(ref err) => Some(err),
             ReadObjectErrorKind::Io(ref err) => Some(err),
+            ReadObjectErrorKind::WrongKind { .. } => None,
+            ReadObjectErrorKind::WrongHashAlgorithm { .. } => None,
+            ReadObjectErrorKind::Corrupt { .. } => None,
+            ReadObjectErrorKind::PeelDepthExceeded => None,
         }
     }
 }
@@ -299,6 +641,27 @@ impl ReadObjectError {
             _ => false,
         }
     }
+
+    pub fn is_wrong_kind(&self) -> bool {
+        matches!(self.kind, ReadObjectErrorKind::WrongKind { .. })
+    }
+
+    /// Returns whether the id does not match the hash algorithm of the repository.
+    pub fn is_wrong_hash_algorithm(&self) -> bool {
+        matches!(self.kind, ReadObjectErrorKind::WrongHashAlgorithm { .. })
+    }
+
+    /// Returns whether the object's contents do not match its id, or are not
+    /// the length given in its header.
+    pub fn is_corrupt(&self) -> bool {
+        matches!(
+            self.kind,
+            ReadObjectErrorKind::Corrupt { .. }
+                | ReadObjectErrorKind::Parse(ParseObjectError::InvalidHeader(
+                    ParseHeaderError::LengthMismatch
+                ))
+        )
+    }
 }
 
 #[cfg(test)]
@@ -328,4 +691,90 @@ mod tests {
 
         assert_eq!(ids.binary_search_by(|id| id.cmp_short(&short)), Err(3));
     }
+
+    #[test]
+    fn test_hash_object() {
+        // The ids given by `git hash-object`.
+        assert_eq!(
+            Id::hash_object(ObjectKind::Blob, b"hello"),
+            Id::from_str("b6fc4c620b67d95f953a5c1c1230aaab5db5a1b0").unwrap()
+        );
+        assert_eq!(
+            Id::hash_object(ObjectKind::Tree, b""),
+            Id::from_str("4b825dc642cb6eb9a060e54bf8d69288fbee4904").unwrap()
+        );
+        assert_ne!(
+            Id::hash_object(ObjectKind::Blob, b"hello"),
+            Id::from_hash(b"hello")
+        );
+    }
+
+    #[test]
+    fn test_object_kind_round_trip() {
+        for &kind in &[
+            ObjectKind::Commit,
+            ObjectKind::Tree,
+            ObjectKind::Blob,
+            ObjectKind::Tag,
+        ] {
+            assert_eq!(ObjectKind::from_str(&kind.to_string()).unwrap(), kind);
+        }
+
+        assert_eq!(ObjectKind::OfsDelta.to_string(), "ofs-delta");
+        assert_eq!(ObjectKind::RefDelta.to_string(), "ref-delta");
+        assert!("ofs-delta".parse::<ObjectKind>().is_err());
+        assert!("ref-delta".parse::<ObjectKind>().is_err());
+        assert!(ObjectKind::from_str("Commit").is_err());
+        // Delta kinds never appear in object headers.
+        assert!(ObjectKind::from_bytes(b"ofs-delta").is_err());
+    }
+
+    #[test]
+    fn test_zero_id() {
+        assert_eq!(Id::zero(), Id::default());
+        assert!(Id::zero().is_zero());
+        assert_eq!(Id::zero().to_hex(), "0".repeat(ID_HEX_LEN));
+
+        let sha256 = HashAlgorithm::Sha256.zero_id();
+        assert!(sha256.is_zero());
+        assert_ne!(sha256, Id::zero());
+        assert_eq!(sha256.to_hex(), "0".repeat(MAX_ID_HEX_LEN));
+
+        assert!(!Id::from_hash(b"blob 0\0").is_zero());
+    }
+
+    #[test]
+    fn test_parse_uppercase_id() {
+        let id = Id::from_str("DBAAC6CA0B9EC8FF358224E7808CD5A21395B88C").unwrap();
+        assert_eq!(
+            id,
+            Id::from_str("dbaac6ca0b9ec8ff358224e7808cd5a21395b88c").unwrap()
+        );
+        assert_eq!(id.to_hex(), "dbaac6ca0b9ec8ff358224e7808cd5a21395b88c");
+
+        let short = ShortId::from_str("DBAAC6CA").unwrap();
+        assert_eq!(short, ShortId::from_str("dbaac6ca").unwrap());
+        assert_eq!(short.to_hex(), "dbaac6ca");
+        assert_eq!(short.cmp_id(&id), Ordering::Less);
+    }
+
+    #[test]
+    fn test_sha256_id() {
+        let hex = "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813";
+        let id = Id::from_str(hex).unwrap();
+        assert_eq!(id.hash_algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(id.to_hex(), hex);
+        assert_eq!(id, HashAlgorithm::Sha256.hash(b"blob 0\0"));
+        assert_ne!(id, Id::from_hash(b"blob 0\0"));
+
+        let short = ShortId::from(id);
+        assert_eq!(short.to_hex(), hex);
+        assert_eq!(short.cmp_id(&id), Ordering::Equal);
+        assert_eq!(
+            ShortId::from_str(&hex[..8]).unwrap().cmp_id(&id),
+            Ordering::Less
+        );
+
+        assert!(Id::from_str(&hex[..50]).is_err());
+    }
 }
@@ -2,6 +2,7 @@ use std::fmt;
 
 use bstr::{BStr, ByteSlice};
 use bytes::Bytes;
+use memchr::memmem;
 use thiserror::Error;
 
 use crate::parse::Parser;
@@ -26,6 +27,33 @@ impl Blob {
     pub fn data(&self) -> &BStr {
         self.data[self.pos..].as_bstr()
     }
+
+    /// Iterate over the lines of the blob, without their `\n` terminators.
+    ///
+    /// A final line without a trailing newline is still yielded. Lines are
+    /// only split on `\n`, so any `\r` before it is kept.
+    pub fn lines(&self) -> impl Iterator<Item = &BStr> {
+        self.data()
+            .lines_with_terminator()
+            .map(|line| line.strip_suffix(b"\n").unwrap_or(line).as_bstr())
+    }
+
+    /// Returns whether the blob contains `needle` anywhere in its data,
+    /// including across line boundaries.
+    pub fn contains(&self, needle: &[u8]) -> bool {
+        memmem::find(self.data(), needle).is_some()
+    }
+
+    /// Returns whether two blobs have the same contents.
+    ///
+    /// When the ids of both blobs are known, such as from tree entries or
+    /// `Object::id`, comparing them is much faster and does not require the
+    /// blobs to be read at all. This is the fallback for when only the contents
+    /// are available, and compares the lengths before any bytes.
+    pub fn content_eq(&self, other: &Blob) -> bool {
+        let (data, other_data) = (self.data(), other.data());
+        data.len() == other_data.len() && data == other_data
+    }
 }
 
 impl fmt::Debug for Blob {
@@ -33,3 +61,48 @@ impl fmt::Debug for Blob {
         f.debug_struct("Blob").field("data", &self.data()).finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::object::{Blob, Parser};
+
+    #[test]
+    fn test_lines() {
+        let blob =
+            |data: &'static [u8]| Blob::parse(Parser::new(Bytes::from_static(data))).unwrap();
+
+        assert_eq!(
+            blob(b"a\n\nb\r\nc").lines().collect::<Vec<_>>(),
+            vec!["a", "", "b\r", "c"]
+        );
+        assert_eq!(blob(b"a\n").lines().collect::<Vec<_>>(), vec!["a"]);
+        assert_eq!(blob(b"").lines().count(), 0);
+        assert_eq!(
+            blob(b"\0\xff\n\0").lines().collect::<Vec<_>>(),
+            vec![&b"\0\xff"[..], b"\0"]
+        );
+    }
+
+    #[test]
+    fn test_contains() {
+        let blob = Blob::parse(Parser::new(Bytes::from_static(b"hello\0\nworld"))).unwrap();
+
+        assert!(blob.contains(b"hello"));
+        assert!(blob.contains(b"\0\nwor"));
+        assert!(blob.contains(b""));
+        assert!(!blob.contains(b"world!"));
+    }
+
+    #[test]
+    fn test_content_eq() {
+        let blob =
+            |data: &'static [u8]| Blob::parse(Parser::new(Bytes::from_static(data))).unwrap();
+
+        assert!(blob(b"hello").content_eq(&blob(b"hello")));
+        assert!(blob(b"").content_eq(&blob(b"")));
+        assert!(!blob(b"hello").content_eq(&blob(b"hellp")));
+        assert!(!blob(b"hello").content_eq(&blob(b"hello world")));
+    }
+}
@@ -1,26 +1,50 @@
+use std::borrow::Cow;
 use std::fmt;
 use std::ops::Range;
 
-use bstr::{BStr, ByteSlice};
+use bstr::{BStr, BString, ByteSlice};
 use bytes::Bytes;
+use memchr::memchr;
 use smallvec::SmallVec;
 
 use crate::object::signature::{ParseSignatureError, Signature, SignatureRaw};
-use crate::object::{Id, ID_HEX_LEN};
+use crate::object::{
+    HashAlgorithm, Id, ObjectHeader, ObjectKind, ParseTagError, SignatureOwned, Tag,
+};
 use crate::parse::Parser;
 use thiserror::Error;
 
+const GPG_SIGNATURE_PREFIX: &[u8] = b"gpgsig ";
+const MERGE_TAG_HEADER: &[u8] = b"mergetag";
+
 #[derive(Clone)]
 pub struct Commit {
     data: Bytes,
+    // The position of the start of the body in `data`.
+    start: usize,
+    hash_algorithm: HashAlgorithm,
     tree: usize,
     parents: SmallVec<[usize; 1]>,
     author: SignatureRaw,
     committer: SignatureRaw,
     encoding: Option<Range<usize>>,
+    gpg_signature: Option<Range<usize>>,
+    // The name and value of each header not parsed into one of the fields above.
+    extra_headers: Vec<(Range<usize>, Range<usize>)>,
     message: usize,
 }
 
+/// The fields of a commit to be written to the object database.
+#[derive(Debug, Clone)]
+pub struct CommitData {
+    tree: Id,
+    parents: Vec<Id>,
+    author: SignatureOwned,
+    committer: SignatureOwned,
+    encoding: Option<BString>,
+    message: BString,
+}
+
 #[derive(Debug, Error)]
 pub(in crate::object) enum ParseCommitError {
     #[error(transparent)]
@@ -31,6 +55,7 @@ pub(in crate::object) enum ParseCommitError {
 
 impl Commit {
     pub(in crate::object) fn parse(mut parser: Parser<Bytes>) -> Result<Self, ParseCommitError> {
+        let start = parser.pos();
         let tree = parser
             .parse_hex_id_line(b"tree ")
             .map_err(|_| ParseCommitError::Other("invalid tree object id"))?
@@ -57,6 +82,8 @@ impl Commit {
             .ok_or(ParseCommitError::Other("missing committer"))?;
 
         let mut encoding = None;
+        let mut gpg_signature = None;
+        let mut extra_headers = Vec::new();
         // Consume additional commit headers
         while !parser.consume_bytes(b"\n") {
             if let Some(range) = parser
@@ -64,32 +91,75 @@ impl Commit {
                 .map_err(|_| ParseCommitError::Other("invalid encoding"))?
             {
                 encoding = Some(range);
-            } else if parser.consume_until(b'\n').is_none() {
-                return Err(ParseCommitError::Other("missing message"));
+            } else {
+                let is_gpg_signature = parser.remaining_buffer().starts_with(GPG_SIGNATURE_PREFIX);
+                let invalid = || {
+                    if is_gpg_signature {
+                        ParseCommitError::Other("invalid gpg signature")
+                    } else {
+                        ParseCommitError::Other("missing message")
+                    }
+                };
+
+                let first_line = parser.consume_until(b'\n').ok_or_else(invalid)?;
+                let continuation_start = parser.pos();
+                // Continuation lines are indented by a single space
+                while parser.remaining_buffer().starts_with(b" ") {
+                    parser.consume_until(b'\n').ok_or_else(invalid)?;
+                }
+
+                let (name, value_start) = match memchr(b' ', &parser[first_line.clone()]) {
+                    Some(pos) => (
+                        first_line.start..(first_line.start + pos),
+                        first_line.start + pos + 1,
+                    ),
+                    // A header with no value on its first line has a value made
+                    // up of just its continuation lines, if it has any.
+                    None if parser.pos() > continuation_start => {
+                        (first_line.clone(), continuation_start)
+                    }
+                    None => (first_line.clone(), first_line.end),
+                };
+                if is_gpg_signature {
+                    gpg_signature = Some(value_start..parser.pos());
+                }
+                extra_headers.push((name, value_start..(parser.pos() - 1)));
             }
         }
 
         let message = parser.pos();
 
         Ok(Commit {
+            hash_algorithm: parser.hash_algorithm(),
             data: parser.into_inner(),
+            start,
             tree,
             parents,
             author,
             committer,
             encoding,
+            gpg_signature,
+            extra_headers,
             message,
         })
     }
 
+    /// The length of the object's body, as declared in its header.
+    pub(in crate::object) fn body_len(&self) -> usize {
+        self.data.len() - self.start
+    }
+
     pub fn tree(&self) -> Id {
-        Id::from_hex(&self.data[self.tree..][..ID_HEX_LEN]).expect("id already validated")
+        self.id_at(self.tree)
     }
 
     pub fn parents<'a>(&'a self) -> impl ExactSizeIterator<Item = Id> + 'a {
-        self.parents.iter().map(move |&parent| {
-            Id::from_hex(&self.data[parent..][..ID_HEX_LEN]).expect("id already validated")
-        })
+        self.parents.iter().map(move |&parent| self.id_at(parent))
+    }
+
+    fn id_at(&self, pos: usize) -> Id {
+        Id::from_hex(&self.data[pos..][..self.hash_algorithm.hex_len()])
+            .expect("id already validated")
     }
 
     pub fn author<'a>(&'a self) -> Signature<'a> {
@@ -106,9 +176,166 @@ impl Commit {
             .map(|encoding| self.data[encoding].as_bstr())
     }
 
+    /// The signature in the `gpgsig` header, with the indentation of its
+    /// continuation lines removed.
+    pub fn gpg_signature(&self) -> Option<BString> {
+        self.gpg_signature
+            .clone()
+            .map(|range| self.data[range].replace(b"\n ", b"\n").into())
+    }
+
+    /// The name and value of each header other than `tree`, `parent`, `author`,
+    /// `committer` and `encoding`, in the order they appear. This includes the
+    /// `gpgsig` and `mergetag` headers.
+    ///
+    /// A value may span multiple lines, in which case each continuation line
+    /// keeps the single space it is indented by. A header with nothing after
+    /// its name on the first line has a value starting with its first
+    /// continuation line.
+    pub fn extra_headers(&self) -> impl Iterator<Item = (&BStr, &BStr)> {
+        self.extra_headers.iter().map(move |(name, value)| {
+            (
+                self.data[name.clone()].as_bstr(),
+                self.data[value.clone()].as_bstr(),
+            )
+        })
+    }
+
+    /// The tags embedded in the `mergetag` headers of the commit, which are
+    /// recorded when merging annotated tags. Each header is parsed as a tag
+    /// object, so a malformed header does not prevent reading the others.
+    pub fn mergetags(&self) -> impl Iterator<Item = Result<Tag, ParseTagError>> + '_ {
+        self.extra_headers()
+            .filter(|(name, _)| *name == MERGE_TAG_HEADER)
+            .map(move |(_, value)| {
+                // The tag is stored with each line after the first indented by
+                // a single space, and without its final newline.
+                let mut data = value.replace(b"\n ", b"\n");
+                data.push(b'\n');
+                Tag::parse(Parser::new(data.into()).with_hash_algorithm(self.hash_algorithm))
+            })
+    }
+
+    /// The data covered by the commit's signature, which is the commit with
+    /// the `gpgsig` header removed.
+    pub fn signed_data(&self) -> BString {
+        match &self.gpg_signature {
+            Some(range) => {
+                let header_start = range.start - GPG_SIGNATURE_PREFIX.len();
+                let mut data = self.data[self.start..header_start].to_vec();
+                data.extend_from_slice(&self.data[range.end..]);
+                data.into()
+            }
+            None => self.data[self.start..].to_vec().into(),
+        }
+    }
+
     pub fn message(&self) -> &BStr {
         self.data[self.message..].as_bstr()
     }
+
+    /// The message decoded as UTF-8.
+    ///
+    /// With the `encoding_rs` feature enabled, a message with a declared
+    /// encoding other than UTF-8 is transcoded from that encoding. Otherwise,
+    /// or if the encoding is not recognized, invalid UTF-8 is replaced with
+    /// the replacement character.
+    pub fn message_utf8(&self) -> Cow<'_, str> {
+        #[cfg(feature = "encoding_rs")]
+        {
+            if let Some(encoding) = self
+                .encoding()
+                .and_then(|label| encoding_rs::Encoding::for_label(label))
+            {
+                if encoding != encoding_rs::UTF_8 {
+                    return encoding.decode_without_bom_handling(self.message()).0;
+                }
+            }
+        }
+
+        self.message().to_str_lossy()
+    }
+
+    /// The first paragraph of the message, with trailing whitespace removed.
+    pub fn subject(&self) -> &BStr {
+        let message = self.message();
+        match message.find(b"\n\n") {
+            Some(pos) => message[..pos].trim_end().as_bstr(),
+            None => message.trim_end().as_bstr(),
+        }
+    }
+
+    /// The rest of the message after the subject and the blank line following
+    /// it, or `None` if there is nothing after the subject.
+    pub fn body(&self) -> Option<&BStr> {
+        let message = self.message();
+        let pos = message.find(b"\n\n")?;
+        let body = &message[(pos + 2)..];
+        if body.is_empty() {
+            None
+        } else {
+            Some(body.as_bstr())
+        }
+    }
+}
+
+impl CommitData {
+    pub fn new(
+        tree: Id,
+        author: SignatureOwned,
+        committer: SignatureOwned,
+        message: impl Into<BString>,
+    ) -> Self {
+        CommitData {
+            tree,
+            parents: Vec::new(),
+            author,
+            committer,
+            encoding: None,
+            message: message.into(),
+        }
+    }
+
+    /// Add a parent to the commit. The first parent added is the first parent of the commit.
+    pub fn with_parent(mut self, parent: Id) -> Self {
+        self.parents.push(parent);
+        self
+    }
+
+    /// Set the encoding of the commit message, if it is not UTF-8.
+    pub fn with_encoding(mut self, encoding: impl Into<BString>) -> Self {
+        self.encoding = Some(encoding.into());
+        self
+    }
+
+    /// Serialize the commit in git's canonical format, including its header.
+    pub(in crate::object) fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(format!("tree {}\n", self.tree).as_bytes());
+        for parent in &self.parents {
+            body.extend_from_slice(format!("parent {}\n", parent).as_bytes());
+        }
+        push_line(&mut body, b"author ", self.author.signature().as_bytes());
+        push_line(
+            &mut body,
+            b"committer ",
+            self.committer.signature().as_bytes(),
+        );
+        if let Some(encoding) = &self.encoding {
+            push_line(&mut body, b"encoding ", encoding);
+        }
+        body.push(b'\n');
+        body.extend_from_slice(&self.message);
+
+        ObjectHeader::frame(ObjectKind::Commit, &body)
+    }
+}
+
+// Append a header line of the form `<prefix><value>\n`.
+fn push_line(body: &mut Vec<u8>, prefix: &[u8], value: &[u8]) {
+    body.extend_from_slice(prefix);
+    body.extend_from_slice(value);
+    body.push(b'\n');
 }
 
 impl fmt::Debug for Commit {
@@ -119,6 +346,8 @@ impl fmt::Debug for Commit {
             .field("author", &self.author())
             .field("committer", &self.committer())
             .field("encoding", &self.encoding())
+            .field("gpg_signature", &self.gpg_signature())
+            .field("extra_headers", &self.extra_headers().collect::<Vec<_>>())
             .field("message", &self.message())
             .finish()
     }
@@ -176,6 +405,264 @@ message"
         assert_eq!(commit.committer().timestamp(), None);
         assert_eq!(commit.committer().timezone(), None);
         assert_eq!(commit.encoding(), Some(b"UTF-8".as_bstr()));
+        assert_eq!(
+            commit.extra_headers().collect::<Vec<_>>(),
+            vec![(b"header".as_bstr(), b"value".as_bstr())]
+        );
+        assert_eq!(commit.gpg_signature(), None);
+        assert_eq!(commit.signed_data(), commit.data.as_ref());
+        assert_eq!(commit.message(), "message");
+        assert_eq!(commit.subject(), "message");
+        assert_eq!(commit.body(), None);
+    }
+
+    #[test]
+    fn test_parse_commit_uppercase_ids() {
+        let parser = Parser::new(
+            b"\
+tree A552334B3BA0630D8F82AC9F27AB55625085D9BD
+parent BEFC2587746BB7AEB8588788CAEAEADD3EB06E4B
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+
+message"
+                .to_vec()
+                .into(),
+        );
+
+        let commit = Commit::parse(parser).unwrap();
+        assert_eq!(
+            commit.tree(),
+            Id::from_str("a552334b3ba0630d8f82ac9f27ab55625085d9bd").unwrap()
+        );
+        assert_eq!(
+            commit.parents().collect::<Vec<_>>(),
+            &[Id::from_str("befc2587746bb7aeb8588788caeaeadd3eb06e4b").unwrap()]
+        );
+    }
+
+    fn commit_with_message(message: &str) -> Commit {
+        let data = format!(
+            "tree a552334b3ba0630d8f82ac9f27ab55625085d9bd\n\
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100\n\
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100\n\
+\n\
+{}",
+            message
+        );
+        Commit::parse(Parser::new(data.into_bytes().into())).unwrap()
+    }
+
+    fn commit_with_encoding(encoding: &str, message: &[u8]) -> Commit {
+        let mut data = format!(
+            "tree a552334b3ba0630d8f82ac9f27ab55625085d9bd\n\
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100\n\
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100\n\
+encoding {}\n\
+\n",
+            encoding
+        )
+        .into_bytes();
+        data.extend_from_slice(message);
+        Commit::parse(Parser::new(data.into())).unwrap()
+    }
+
+    #[test]
+    fn test_commit_message_utf8() {
+        let commit = commit_with_message("caf\u{e9}\n");
+        assert_eq!(commit.message_utf8(), "caf\u{e9}\n");
+
+        let commit = commit_with_encoding("UTF-8", b"caf\xc3\xa9\n");
+        assert_eq!(commit.message_utf8(), "caf\u{e9}\n");
+
+        let commit = commit_with_encoding("unknown-encoding", b"caf\xe9\n");
+        assert_eq!(commit.message_utf8(), "caf\u{fffd}\n");
+    }
+
+    #[cfg(feature = "encoding_rs")]
+    #[test]
+    fn test_commit_message_utf8_transcoded() {
+        let commit = commit_with_encoding("ISO-8859-1", b"caf\xe9\n");
+        assert_eq!(commit.message_utf8(), "caf\u{e9}\n");
+
+        let commit = commit_with_encoding("GBK", b"\xc4\xe3\xba\xc3\n");
+        assert_eq!(commit.message_utf8(), "\u{4f60}\u{597d}\n");
+    }
+
+    #[test]
+    fn test_commit_subject_and_body() {
+        let commit = commit_with_message("subject\n\nbody line 1\nbody line 2\n");
+        assert_eq!(commit.subject(), "subject");
+        assert_eq!(commit.body(), Some(b"body line 1\nbody line 2\n".as_bstr()));
+
+        let commit = commit_with_message("subject  \n");
+        assert_eq!(commit.subject(), "subject");
+        assert_eq!(commit.body(), None);
+
+        let commit = commit_with_message("subject\n\n");
+        assert_eq!(commit.subject(), "subject");
+        assert_eq!(commit.body(), None);
+
+        let commit = commit_with_message("multi-line\nsubject\n\nbody");
+        assert_eq!(commit.subject(), "multi-line\nsubject");
+        assert_eq!(commit.body(), Some(b"body".as_bstr()));
+
+        let commit = commit_with_message("");
+        assert_eq!(commit.subject(), "");
+        assert_eq!(commit.body(), None);
+    }
+
+    #[test]
+    fn test_parse_commit_gpg_signature() {
+        let parser = Parser::new(
+            b"\
+tree a552334b3ba0630d8f82ac9f27ab55625085d9bd
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+gpgsig -----BEGIN PGP SIGNATURE-----
+\x20
+ iQEzBAABCAAdFiEE
+ =abcd
+ -----END PGP SIGNATURE-----
+encoding UTF-8
+
+message"
+                .to_vec()
+                .into(),
+        );
+
+        let commit = Commit::parse(parser).unwrap();
+        assert_eq!(
+            commit.gpg_signature().unwrap(),
+            "-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n=abcd\n-----END PGP SIGNATURE-----\n"
+        );
+        assert_eq!(
+            commit.signed_data(),
+            "\
+tree a552334b3ba0630d8f82ac9f27ab55625085d9bd
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+encoding UTF-8
+
+message"
+        );
+        assert_eq!(commit.encoding(), Some(b"UTF-8".as_bstr()));
         assert_eq!(commit.message(), "message");
+        assert_eq!(
+            commit.extra_headers().collect::<Vec<_>>(),
+            vec![(
+                b"gpgsig".as_bstr(),
+                b"-----BEGIN PGP SIGNATURE-----\n \n iQEzBAABCAAdFiEE\n =abcd\n -----END PGP SIGNATURE-----"
+                    .as_bstr()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_commit_extra_headers() {
+        let parser = Parser::new(
+            b"\
+tree a552334b3ba0630d8f82ac9f27ab55625085d9bd
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+mergetag object befc2587746bb7aeb8588788caeaeadd3eb06e4b
+ type commit
+ tag v1.0
+encoding ISO-8859-1
+x-custom-header
+ continued
+empty
+
+message"
+                .to_vec()
+                .into(),
+        );
+
+        let commit = Commit::parse(parser).unwrap();
+        assert_eq!(
+            commit.extra_headers().collect::<Vec<_>>(),
+            vec![
+                (
+                    b"mergetag".as_bstr(),
+                    b"object befc2587746bb7aeb8588788caeaeadd3eb06e4b\n type commit\n tag v1.0"
+                        .as_bstr()
+                ),
+                (b"x-custom-header".as_bstr(), b" continued".as_bstr()),
+                (b"empty".as_bstr(), b"".as_bstr()),
+            ]
+        );
+        assert_eq!(commit.encoding(), Some(b"ISO-8859-1".as_bstr()));
+        assert_eq!(commit.gpg_signature(), None);
+        assert_eq!(commit.message(), "message");
+    }
+
+    #[test]
+    fn test_parse_commit_mergetags() {
+        let parser = Parser::new(
+            b"\
+tree a552334b3ba0630d8f82ac9f27ab55625085d9bd
+parent befc2587746bb7aeb8588788caeaeadd3eb06e4b
+parent da1a5d18c0ab0c03b20fdd91581bc90acd10d512
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+mergetag object da1a5d18c0ab0c03b20fdd91581bc90acd10d512
+ type commit
+ tag v1.0
+ tagger Andrew Hickman <me@andrewhickman.dev> 1596907099 +0100
+ 
+ Release 1.0
+ -----BEGIN PGP SIGNATURE-----
+ 
+ iQEzBAABCAAdFiEE
+ -----END PGP SIGNATURE-----
+mergetag not a tag
+
+Merge tag 'v1.0'
+"
+            .to_vec()
+            .into(),
+        );
+
+        let commit = Commit::parse(parser).unwrap();
+        let mergetags: Vec<_> = commit.mergetags().collect();
+        assert_eq!(mergetags.len(), 2);
+
+        let tag = mergetags[0].as_ref().unwrap();
+        assert_eq!(tag.tag(), "v1.0");
+        assert_eq!(
+            tag.object(),
+            Id::from_hex(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512").unwrap()
+        );
+        assert_eq!(tag.kind(), ObjectKind::Commit);
+        assert_eq!(
+            tag.tagger().unwrap().timestamp(),
+            Some(b"1596907099".as_bstr())
+        );
+        assert_eq!(
+            tag.message(),
+            Some(
+                b"Release 1.0\n-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n-----END PGP SIGNATURE-----\n"
+                    .as_bstr()
+            )
+        );
+
+        assert!(mergetags[1].is_err());
+        assert_eq!(commit.message(), "Merge tag 'v1.0'\n");
+    }
+
+    #[test]
+    fn test_parse_commit_truncated_gpg_signature() {
+        let parser = Parser::new(
+            b"\
+tree a552334b3ba0630d8f82ac9f27ab55625085d9bd
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+gpgsig -----BEGIN PGP SIGNATURE-----
+ iQEzBAABCAAdFiEE"
+                .to_vec()
+                .into(),
+        );
+
+        assert!(Commit::parse(parser).is_err());
     }
 }
@@ -1,21 +1,54 @@
+mod alternates;
+mod ancestry;
+mod info_packs;
 mod loose;
 mod packed;
+#[cfg(feature = "async")]
+mod read_async;
 mod reader;
 
+pub use self::info_packs::{InfoPacks, ParseInfoPacksError, ReadInfoPacksError};
+pub use self::packed::{Bundle, PackInfo, ReadBundleError};
 pub use self::reader::ObjectReader;
 
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
 
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+
+use bytes::Bytes;
 use thiserror::Error;
 
+use self::alternates::read_alternates;
+use self::ancestry::Ancestry;
 use self::loose::{LooseObjectDatabase, ReadLooseError, WriteLooseError};
-use self::packed::{PackedObjectDatabase, ReadPackedError};
-use crate::object::{Id, Object, ReadObjectError, ShortId};
+use self::packed::{no_external_base, FindBase, PackedObjectDatabase, ReadPackedError};
+use crate::object::{
+    Commit, CommitData, FileMode, HashAlgorithm, Id, Object, ObjectHeader, ObjectKind,
+    ReadObjectError, ReadObjectErrorKind, ShortId, TagData, TreeBuilder, TreeEntryOwned,
+};
+
+const OBJECTS_FOLDER: &str = "objects";
+const INFO_PACKS_FILE: &str = "objects/info/packs";
 
 #[derive(Debug)]
 pub struct ObjectDatabase {
     loose: LooseObjectDatabase,
     packed: PackedObjectDatabase,
+    // The object directories listed in `objects/info/alternates`, which are
+    // searched after this one.
+    alternates: Vec<Alternate>,
+    info_packs_path: PathBuf,
+    hash_algorithm: HashAlgorithm,
+}
+
+#[derive(Debug)]
+struct Alternate {
+    loose: LooseObjectDatabase,
+    packed: PackedObjectDatabase,
 }
 
 #[derive(Debug, Error)]
@@ -36,6 +69,44 @@ pub struct WriteError {
     kind: WriteErrorKind,
 }
 
+#[derive(Debug, Error)]
+#[error("failed to refresh the packed object database")]
+pub struct RefreshError {
+    #[source]
+    kind: packed::ReadPackedError,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to read the packs in the object database")]
+pub struct ReadPacksError {
+    #[source]
+    kind: packed::ReadPackedError,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to list the objects in `{}`", path.display())]
+pub struct ReadObjectIdsError {
+    path: PathBuf,
+    #[source]
+    kind: ReadError,
+}
+
+#[derive(Debug, Error)]
+#[error("failed to read the pack `{id}`")]
+pub struct ReadPackError {
+    id: Id,
+    #[source]
+    kind: ReadPackErrorKind,
+}
+
+#[derive(Debug, Error)]
+enum ReadPackErrorKind {
+    #[error("the pack was not found")]
+    NotFound,
+    #[error(transparent)]
+    Packed(packed::ReadPackedError),
+}
+
 #[derive(Debug, Error)]
 pub(in crate::object) enum WriteErrorKind {
     #[error(transparent)]
@@ -43,42 +114,651 @@ pub(in crate::object) enum WriteErrorKind {
 }
 
 impl ObjectDatabase {
-    pub fn open(dotgit: &Path) -> Self {
+    /// Open the object database in `dotgit`, whose objects are hashed with
+    /// `hash_algorithm`. This is usually read from the repository config with
+    /// `HashAlgorithm::from_config`.
+    ///
+    /// Objects which are not found in the repository are looked for in the
+    /// object directories listed in `objects/info/alternates`, as set up by
+    /// `git clone --shared`.
+    pub fn open(dotgit: &Path, hash_algorithm: HashAlgorithm) -> Self {
+        let packed = PackedObjectDatabase::open(dotgit, hash_algorithm);
+        let alternates = read_alternates(&dotgit.join(OBJECTS_FOLDER))
+            .into_iter()
+            .map(|path| Alternate {
+                loose: LooseObjectDatabase::open_objects_dir(&path),
+                packed: PackedObjectDatabase::open_objects_dir(&path, hash_algorithm)
+                    .share_cache(&packed),
+            })
+            .collect();
+
         ObjectDatabase {
             loose: LooseObjectDatabase::open(dotgit),
-            packed: PackedObjectDatabase::open(dotgit),
+            packed,
+            alternates,
+            info_packs_path: dotgit.join(INFO_PACKS_FILE),
+            hash_algorithm,
+        }
+    }
+
+    /// The hash algorithm used for object ids in this database.
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    /// Set the maximum total size of the objects cached for all packs, in bytes.
+    ///
+    /// Reconstructed pack objects are cached so that objects sharing a delta
+    /// base do not need to reconstruct it again. A single cache is shared by
+    /// every pack, including those of alternates, so the limit bounds the
+    /// memory used however many packs there are. When the cache grows past
+    /// this limit, the least recently used objects are evicted. The default is
+    /// 96MiB, the same as git's `core.deltaBaseCacheLimit`.
+    pub fn set_pack_cache_limit(&self, limit: usize) {
+        self.packed.set_cache_limit(limit);
+    }
+
+    /// Set the maximum number of deltas which may be applied to reconstruct a
+    /// packed object. Reading an object with a longer delta chain fails rather
+    /// than spending unbounded time on a malformed pack. The default is 4095,
+    /// the largest depth `git pack-objects` will write.
+    ///
+    /// This only applies to packs opened after it is called. Packs are opened
+    /// lazily, so it applies to every pack if called before any objects are read.
+    pub fn set_max_delta_depth(&self, max_delta_depth: usize) {
+        for (packed, _) in self.stores() {
+            packed.set_max_delta_depth(max_delta_depth);
+        }
+    }
+
+    /// Set whether pack files are memory mapped, rather than read through a
+    /// file handle shared between threads. This allows packed objects to be
+    /// read concurrently, at the cost of address space for each open pack.
+    /// Packs are read through a file handle by default.
+    ///
+    /// Like `set_max_delta_depth`, this only applies to packs opened after it
+    /// is called.
+    pub fn set_mmap_packs(&self, mmap: bool) {
+        for (packed, _) in self.stores() {
+            packed.set_mmap(mmap);
+        }
+    }
+
+    /// Set whether the CRC32 of each packed object is checked against the pack
+    /// index when it is read, to detect corruption of the pack. This requires
+    /// reading each object twice, so it is disabled by default.
+    ///
+    /// Like `set_max_delta_depth`, this only applies to packs opened after it
+    /// is called.
+    pub fn set_verify_pack_crc(&self, verify_crc: bool) {
+        for (packed, _) in self.stores() {
+            packed.set_verify_crc(verify_crc);
+        }
+    }
+
+    /// The total size of the objects currently cached for all packs, in bytes.
+    pub fn pack_cache_size(&self) -> usize {
+        self.packed.cache_size()
+    }
+
+    /// Read the list of packs in `objects/info/packs`, which is used by the
+    /// dumb HTTP protocol. Local packs are found by scanning the pack directory
+    /// instead, so this list may be missing or out of date.
+    pub fn read_info_packs(&self) -> Result<InfoPacks, ReadInfoPacksError> {
+        InfoPacks::read(&self.info_packs_path)
+    }
+
+    /// Scan for packs added or removed since the database was last read.
+    ///
+    /// New packs are normally only picked up when an object cannot be found,
+    /// and at most once every couple of seconds. Loose objects are always read
+    /// directly from disk, so they never need refreshing.
+    pub fn refresh_packs(&self) -> Result<(), RefreshError> {
+        for (packed, _) in self.stores() {
+            packed
+                .force_refresh()
+                .map_err(|kind| RefreshError { kind })?;
         }
+        Ok(())
+    }
+
+    /// Summarize the packs in this repository's pack directory, sorted by name.
+    ///
+    /// Packs in alternate object directories are not included. The pack
+    /// directory is always scanned first, so the list is up to date.
+    pub fn packs(&self) -> Result<Vec<PackInfo>, ReadPacksError> {
+        self.packed.packs().map_err(|kind| ReadPacksError { kind })
+    }
+
+    /// Iterate over the commits reachable from `start`, including `start`
+    /// itself, in reverse chronological order of commit time.
+    ///
+    /// Each commit is returned once, even if it is reachable through several
+    /// merge parents. Commits are read lazily, so stopping the iteration early
+    /// avoids reading the rest of the history.
+    pub fn walk_ancestry(
+        &self,
+        start: Id,
+    ) -> impl Iterator<Item = Result<(Id, Commit), ReadObjectError>> + '_ {
+        Ancestry::new(self, start)
     }
 
     pub fn parse_object(&self, id: Id) -> Result<Object, ReadObjectError> {
-        match self.read_object(id)?.parse() {
+        match self.read_object(id)?.parse(id.hash_algorithm()) {
             Ok(data) => Ok(Object { id, data }),
             Err(err) => Err(ReadObjectError::new(id, err)),
         }
     }
 
+    /// Like `parse_object`, but also check that the object's contents hash to
+    /// `id`, returning an error if the object is corrupt.
+    pub fn read_object_verified(&self, id: Id) -> Result<Object, ReadObjectError> {
+        let data = self.read_object(id)?.parse_verified(id)?;
+        Ok(Object { id, data })
+    }
+
     pub fn read_object(&self, id: Id) -> Result<ObjectReader, ReadObjectError> {
-        match self.packed.read_object(&ShortId::from(id)) {
-            Ok(reader) => return Ok(reader),
+        self.read_object_with(id, PackedObjectDatabase::read_object, Ok)
+    }
+
+    /// Find the full id of the object with the given abbreviated id.
+    ///
+    /// Returns an error if no object matches, or if more than one object
+    /// matches in either the loose or packed database.
+    pub fn resolve(&self, short_id: &ShortId) -> Result<Id, ReadObjectError> {
+        if short_id.len as usize > self.hash_algorithm.digest_len() {
+            return Err(self.wrong_hash_algorithm(*short_id));
+        }
+
+        let packed = match self.packed.resolve(short_id) {
+            Ok(id) => Some(id),
+            Err(ReadPackedError::NotFound) => None,
+            Err(err) => return Err(ReadObjectError::new(*short_id, ReadError::from(err))),
+        };
+
+        let loose = match self.loose.resolve(short_id) {
+            Ok(id) => Some(id),
+            Err(ReadLooseError::NotFound) => None,
+            Err(err) => return Err(ReadObjectError::new(*short_id, ReadError::from(err))),
+        };
+
+        let mut found = match (packed, loose) {
+            (Some(packed), Some(loose)) if packed != loose => {
+                return Err(ReadObjectError::new(*short_id, ReadError::Ambiguous))
+            }
+            (Some(id), _) | (None, Some(id)) => Some(id),
+            // object may have just been packed, try again
+            (None, None) => match self.packed.resolve(short_id) {
+                Ok(id) => Some(id),
+                Err(ReadPackedError::NotFound) => None,
+                Err(err) => return Err(ReadObjectError::new(*short_id, ReadError::from(err))),
+            },
+        };
+
+        for alternate in &self.alternates {
+            for id in alternate.resolve(short_id)? {
+                match found {
+                    Some(found) if found != id => {
+                        return Err(ReadObjectError::new(*short_id, ReadError::Ambiguous))
+                    }
+                    _ => found = Some(id),
+                }
+            }
+        }
+
+        found.ok_or_else(|| ReadObjectError::new(*short_id, ReadError::NotFound))
+    }
+
+    /// Returns whether the database contains an object, without reading it.
+    pub fn contains(&self, id: Id) -> Result<bool, ReadObjectError> {
+        self.check_hash_algorithm(id)?;
+
+        let short_id = ShortId::from(id);
+        match self.packed.contains(&short_id) {
+            Ok(true) => return Ok(true),
+            Ok(false) => (),
+            Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
+        }
+
+        match self.loose.contains(&id) {
+            Ok(true) => return Ok(true),
+            Ok(false) => (),
+            Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
+        }
+
+        // object may have just been packed, try again
+        match self.packed.contains(&short_id) {
+            Ok(true) => return Ok(true),
+            Ok(false) => (),
+            Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
+        }
+
+        for alternate in &self.alternates {
+            if alternate
+                .contains(id)
+                .map_err(|err| ReadObjectError::new(id, err))?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Open a bundle file, as in `Bundle::open`, reading the bases of any
+    /// deltas which are not in the bundle from this database.
+    ///
+    /// Bundles with prerequisites usually contain such deltas, since git
+    /// writes them as thin packs against the prerequisites.
+    pub fn open_bundle(&self, path: impl AsRef<Path>) -> Result<Bundle, ReadBundleError> {
+        Bundle::open_with(path.as_ref(), &|base| self.find_delta_base(base))
+    }
+
+    /// Iterate over the ids of every object in the database, in sorted order.
+    ///
+    /// Objects which are stored both loose and packed, or in multiple packs,
+    /// are only returned once. Any errors reading the database are returned
+    /// after all the ids which could be found, along with the path of the
+    /// object directory or pack which could not be read.
+    pub fn object_ids(&self) -> impl Iterator<Item = Result<Id, ReadObjectIdsError>> {
+        let mut ids = BTreeSet::new();
+        let mut errors = Vec::new();
+
+        for (packed, loose) in self.stores() {
+            // Read loose objects first, so that any objects packed and pruned in
+            // the meantime will be found in the pack directory.
+            match loose.object_ids() {
+                Ok(loose_ids) => ids.extend(loose_ids),
+                Err(err) => errors.push(ReadObjectIdsError {
+                    path: loose.path().to_owned(),
+                    kind: ReadError::from(err),
+                }),
+            }
+
+            let packed_errors = match packed.object_ids() {
+                Ok((packed_ids, packed_errors)) => {
+                    ids.extend(packed_ids);
+                    packed_errors
+                }
+                Err(err) => vec![err],
+            };
+            errors.extend(packed_errors.into_iter().map(|err| ReadObjectIdsError {
+                path: packed.error_path(&err),
+                kind: ReadError::from(err),
+            }));
+        }
+
+        ids.into_iter().map(Ok).chain(errors.into_iter().map(Err))
+    }
+
+    /// Iterate over every object in the pack with id `pack_id`, along with its
+    /// header and offset in the pack, similar to `git verify-pack -v`.
+    ///
+    /// The header of a deltified object has the kind and length of the object
+    /// it reconstructs. Each header is read as the iterator advances.
+    pub fn pack_objects(
+        &self,
+        pack_id: Id,
+    ) -> Result<
+        impl Iterator<Item = Result<(Id, ObjectHeader, u64), ReadPackError>> + '_,
+        ReadPackError,
+    > {
+        for (packed, _) in self.stores() {
+            match packed.pack_objects(pack_id, move |base| self.find_delta_base(base)) {
+                Ok(objects) => {
+                    return Ok(objects
+                        .map(move |object| object.map_err(|err| ReadPackError::new(pack_id, err))))
+                }
+                Err(ReadPackedError::NotFound) => continue,
+                Err(err) => return Err(ReadPackError::new(pack_id, err)),
+            }
+        }
+        Err(ReadPackError::not_found(pack_id))
+    }
+
+    /// The id of the object at `offset` in the pack with id `pack_id`, or `None`
+    /// if no object starts at that offset.
+    ///
+    /// This can be used to name the base of an offset delta, whose header only
+    /// records the offset of the base.
+    pub fn pack_object_id_at_offset(
+        &self,
+        pack_id: Id,
+        offset: u64,
+    ) -> Result<Option<Id>, ReadPackError> {
+        for (packed, _) in self.stores() {
+            match packed.object_id_at_offset(pack_id, offset) {
+                Err(ReadPackedError::NotFound) => continue,
+                result => return result.map_err(|err| ReadPackError::new(pack_id, err)),
+            }
+        }
+        Err(ReadPackError::not_found(pack_id))
+    }
+
+    /// Find the entry at `path` relative to the tree `root`, reading each
+    /// intermediate tree as it is reached.
+    ///
+    /// Returns `None` if the path does not exist. If the path descends into a
+    /// submodule, the gitlink entry for the submodule is returned, since its
+    /// contents are not stored in this repository.
+    pub fn tree_entry_at_path(
+        &self,
+        root: Id,
+        path: &Path,
+    ) -> Result<Option<TreeEntryOwned>, ReadObjectError> {
+        let mut components = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => match os_str_bytes(name) {
+                    Some(name) => components.push(name),
+                    None => return Ok(None),
+                },
+                Component::CurDir => (),
+                // Trees cannot contain the root or parent directories.
+                _ => return Ok(None),
+            }
+        }
+
+        let (last, parents) = match components.split_last() {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        let mut tree = self.parse_object(root)?.into_tree()?;
+        for name in parents {
+            let entry = match tree.entry_owned(name) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+
+            let (mode, id) = (entry.entry().file_mode(), entry.entry().id());
+            match mode {
+                FileMode::Tree => tree = self.parse_object(id)?.into_tree()?,
+                FileMode::Gitlink => return Ok(Some(entry)),
+                _ => {
+                    return Err(ReadObjectError::new(
+                        id,
+                        ReadObjectErrorKind::WrongKind {
+                            expected: ObjectKind::Tree,
+                            actual: mode.object_kind(),
+                        },
+                    ))
+                }
+            }
+        }
+
+        Ok(tree.entry_owned(last))
+    }
+
+    /// Read the kind and length of an object without reading its body.
+    ///
+    /// For deltified packed objects, this is the kind and length of the
+    /// object after all deltas have been applied.
+    pub fn read_header(&self, id: Id) -> Result<ObjectHeader, ReadObjectError> {
+        self.read_object_with(id, PackedObjectDatabase::read_header, |reader| {
+            reader
+                .read_header()
+                .map_err(|err| ReadObjectError::new(id, err))
+        })
+    }
+
+    /// Write the body of an object to `writer`, without its `<kind> <len>\0` header.
+    ///
+    /// Loose objects and undeltified packed objects are decompressed
+    /// incrementally as they are written, so they are never fully held in
+    /// memory. Deltified packed objects must be reconstructed in memory before
+    /// they can be written.
+    pub fn stream_object<W>(&self, id: Id, writer: &mut W) -> Result<(), ReadObjectError>
+    where
+        W: Write,
+    {
+        let reader = self.read_object_with(id, PackedObjectDatabase::open_object, Ok)?;
+        reader
+            .copy_body(writer)
+            .map_err(|err| ReadObjectError::new(id, err))
+    }
+
+    /// Open a reader over the contents of a blob, without its header.
+    ///
+    /// As with `stream_object`, loose blobs and undeltified packed blobs are
+    /// decompressed as they are read, so large blobs can be copied elsewhere
+    /// without being held in memory. Deltified packed blobs must be
+    /// reconstructed in memory first.
+    ///
+    /// If the blob's data ends before the length given in its header, reading
+    /// fails with an `UnexpectedEof` error rather than returning a short blob.
+    pub fn open_blob_reader(&self, id: Id) -> Result<impl Read, ReadObjectError> {
+        let reader = self.read_object_with(id, PackedObjectDatabase::open_object, Ok)?;
+        let (header, body) = reader
+            .into_body_reader()
+            .map_err(|err| ReadObjectError::new(id, err))?;
+
+        if header.kind != ObjectKind::Blob {
+            return Err(ReadObjectError::new(
+                id,
+                ReadObjectErrorKind::WrongKind {
+                    expected: ObjectKind::Blob,
+                    actual: header.kind,
+                },
+            ));
+        }
+        Ok(body)
+    }
+
+    fn read_object_with<T, P, L>(
+        &self,
+        id: Id,
+        read_packed: P,
+        read_loose: L,
+    ) -> Result<T, ReadObjectError>
+    where
+        P: Fn(&PackedObjectDatabase, &ShortId, &FindBase<'_>) -> Result<T, ReadPackedError>,
+        L: FnOnce(ObjectReader) -> Result<T, ReadObjectError>,
+    {
+        self.check_hash_algorithm(id)?;
+
+        let find_base = |base| self.find_delta_base(base);
+        match read_packed(&self.packed, &ShortId::from(id), &find_base) {
+            Ok(result) => return Ok(result),
             Err(ReadPackedError::NotFound) => (),
             Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
         };
 
         match self.loose.read_object(&id) {
-            Ok(reader) => return Ok(reader),
+            Ok(reader) => return read_loose(reader),
             Err(ReadLooseError::NotFound) => (),
             Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
         }
 
         // object may have just been packed, try again
-        self.packed
-            .read_object(&ShortId::from(id))
-            .map_err(|err| ReadObjectError::new(id, ReadError::from(err)))
+        match read_packed(&self.packed, &ShortId::from(id), &find_base) {
+            Err(ReadPackedError::NotFound) => (),
+            result => return result.map_err(|err| ReadObjectError::new(id, ReadError::from(err))),
+        }
+
+        for alternate in &self.alternates {
+            match read_packed(&alternate.packed, &ShortId::from(id), &find_base) {
+                Ok(result) => return Ok(result),
+                Err(ReadPackedError::NotFound) => (),
+                Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
+            };
+
+            match alternate.loose.read_object(&id) {
+                Ok(reader) => return read_loose(reader),
+                Err(ReadLooseError::NotFound) => (),
+                Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
+            }
+        }
+
+        Err(ReadObjectError::new(id, ReadError::NotFound))
+    }
+
+    // Find the base of a packed delta which is not in the same pack as the delta,
+    // as in a thin pack. If the base is itself packed, it must be in the same pack
+    // as any bases it has, so that deltas referring to each other across packs
+    // cannot recurse forever.
+    fn find_delta_base(&self, id: Id) -> Result<Option<(ObjectHeader, Bytes)>, ReadObjectError> {
+        for (packed, loose) in self.stores() {
+            let reader = match packed.read_object(&ShortId::from(id), &no_external_base) {
+                Ok(reader) => reader,
+                Err(ReadPackedError::NotFound) => match loose.read_object(&id) {
+                    Ok(reader) => reader,
+                    Err(ReadLooseError::NotFound) => continue,
+                    Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
+                },
+                Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
+            };
+
+            return match reader.into_bytes() {
+                Ok(base) => Ok(Some(base)),
+                Err(err) => Err(ReadObjectError::new(id, err)),
+            };
+        }
+        Ok(None)
+    }
+
+    // The packed and loose objects of this database, followed by those of each
+    // alternate.
+    fn stores(&self) -> impl Iterator<Item = (&PackedObjectDatabase, &LooseObjectDatabase)> {
+        std::iter::once((&self.packed, &self.loose)).chain(
+            self.alternates
+                .iter()
+                .map(|alternate| (&alternate.packed, &alternate.loose)),
+        )
     }
 
+    // An id from a different hash algorithm can never be found, and a SHA-1 id
+    // may even match the prefix of a SHA-256 one, so reject it up front.
+    fn check_hash_algorithm(&self, id: Id) -> Result<(), ReadObjectError> {
+        if id.hash_algorithm() == self.hash_algorithm {
+            Ok(())
+        } else {
+            Err(self.wrong_hash_algorithm(id))
+        }
+    }
+
+    fn wrong_hash_algorithm(&self, id: impl Into<ShortId>) -> ReadObjectError {
+        ReadObjectError::new(
+            id,
+            ReadObjectErrorKind::WrongHashAlgorithm {
+                expected: self.hash_algorithm,
+            },
+        )
+    }
+
+    /// Write an object to the loose database, returning its id.
+    ///
+    /// If the object already exists in a pack or an alternate, it is not
+    /// written again.
     pub fn write_object(&self, bytes: &[u8]) -> Result<Id, WriteError> {
-        Ok(self.loose.write_object(bytes)?)
+        let id = self.hash_algorithm.hash(bytes);
+        // If the packed database cannot be read, fall back to writing a loose copy.
+        if let Ok(true) = self.packed.contains(&ShortId::from(id)) {
+            return Ok(id);
+        }
+        if self
+            .alternates
+            .iter()
+            .any(|alternate| matches!(alternate.contains(id), Ok(true)))
+        {
+            return Ok(id);
+        }
+
+        Ok(self.loose.write_object(id, bytes)?)
+    }
+
+    /// Write a version 2 `.idx` file for a pack, given the id, offset and
+    /// CRC32 of each object in the pack and the id of the pack itself.
+    ///
+    /// The entries do not need to be sorted, but their ids must be unique.
+    /// Offsets which do not fit in 31 bits are written to the large offset
+    /// table, as git does.
+    pub fn write_pack_index(
+        writer: impl Write,
+        entries: &[(Id, u64, u32)],
+        pack_id: Id,
+    ) -> io::Result<()> {
+        PackedObjectDatabase::write_index(writer, entries, pack_id)
+    }
+
+    /// Serialize a commit and write it to the database, returning its id.
+    pub fn write_commit(&self, commit: &CommitData) -> Result<Id, WriteError> {
+        self.write_object(&commit.encode())
+    }
+
+    /// Serialize a tree and write it to the database, returning its id.
+    pub fn write_tree(&self, tree: &TreeBuilder) -> Result<Id, WriteError> {
+        self.write_object(&tree.encode())
+    }
+
+    /// Serialize an annotated tag and write it to the database, returning its id.
+    pub fn write_tag(&self, tag: &TagData) -> Result<Id, WriteError> {
+        self.write_object(&tag.encode())
+    }
+}
+
+impl ReadObjectIdsError {
+    /// The object directory or pack file which could not be read.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl ReadPackError {
+    fn new(id: Id, err: ReadPackedError) -> Self {
+        ReadPackError {
+            id,
+            kind: ReadPackErrorKind::Packed(err),
+        }
     }
+
+    fn not_found(id: Id) -> Self {
+        ReadPackError {
+            id,
+            kind: ReadPackErrorKind::NotFound,
+        }
+    }
+
+    /// The id of the pack which could not be read.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Whether the error was caused by there being no pack with this id.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self.kind, ReadPackErrorKind::NotFound)
+    }
+}
+
+impl Alternate {
+    fn contains(&self, id: Id) -> Result<bool, ReadError> {
+        Ok(self.packed.contains(&ShortId::from(id))? || self.loose.contains(&id)?)
+    }
+
+    // Find the ids of the packed and loose objects matching `short_id`.
+    fn resolve(&self, short_id: &ShortId) -> Result<Vec<Id>, ReadObjectError> {
+        let mut ids = Vec::new();
+        match self.packed.resolve(short_id) {
+            Ok(id) => ids.push(id),
+            Err(ReadPackedError::NotFound) => (),
+            Err(err) => return Err(ReadObjectError::new(*short_id, ReadError::from(err))),
+        }
+        match self.loose.resolve(short_id) {
+            Ok(id) => ids.push(id),
+            Err(ReadLooseError::NotFound) => (),
+            Err(err) => return Err(ReadObjectError::new(*short_id, ReadError::from(err))),
+        }
+        Ok(ids)
+    }
+}
+
+#[cfg(unix)]
+fn os_str_bytes(s: &OsStr) -> Option<&[u8]> {
+    Some(s.as_bytes())
+}
+
+#[cfg(windows)]
+fn os_str_bytes(s: &OsStr) -> Option<&[u8]> {
+    s.to_str().map(str::as_bytes)
 }
 
 impl From<ReadLooseError> for ReadError {
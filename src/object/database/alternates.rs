@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bstr::ByteSlice;
+
+const ALTERNATES_FILE: &str = "info/alternates";
+// The same limit on nested alternates as git.
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// Find the object directories listed in `info/alternates` in `objects_dir`,
+/// along with any alternates they list in turn, in the order they should be
+/// searched.
+///
+/// Like git, alternates which cannot be read are ignored. Each directory is
+/// only returned once, and never if it is `objects_dir` itself, so cyclic
+/// alternates do not recurse forever.
+pub(in crate::object::database) fn read_alternates(objects_dir: &Path) -> Vec<PathBuf> {
+    let mut visited = HashSet::new();
+    if let Ok(path) = fs::canonicalize(objects_dir) {
+        visited.insert(path);
+    }
+
+    let mut alternates = Vec::new();
+    collect_alternates(objects_dir, 0, &mut visited, &mut alternates);
+    alternates
+}
+
+fn collect_alternates(
+    objects_dir: &Path,
+    depth: usize,
+    visited: &mut HashSet<PathBuf>,
+    alternates: &mut Vec<PathBuf>,
+) {
+    if depth == MAX_ALTERNATE_DEPTH {
+        return;
+    }
+
+    let data = match fs_err::read(objects_dir.join(ALTERNATES_FILE)) {
+        Ok(data) => data,
+        Err(_) => return,
+    };
+
+    for path in parse_alternates(objects_dir, &data) {
+        let path = match fs::canonicalize(path) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        if visited.insert(path.clone()) {
+            alternates.push(path.clone());
+            collect_alternates(&path, depth + 1, visited, alternates);
+        }
+    }
+}
+
+// Each line of the file is a path to an object directory, which is relative to
+// `objects_dir` unless it is absolute. Empty lines and comments are skipped.
+fn parse_alternates(objects_dir: &Path, data: &[u8]) -> Vec<PathBuf> {
+    data.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+        .filter_map(|line| line.to_path().ok())
+        .map(|path| objects_dir.join(path))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let objects_dir = Path::new("/repo/.git/objects");
+        assert_eq!(
+            parse_alternates(
+                objects_dir,
+                b"# comment\n/other/.git/objects\n\n../../shared/objects\n"
+            ),
+            vec![
+                PathBuf::from("/other/.git/objects"),
+                objects_dir.join("../../shared/objects"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_and_cyclic_alternates() {
+        let tempdir = TempDir::new("rusty_git_alternates_tests").unwrap();
+        let root = fs::canonicalize(tempdir.path()).unwrap();
+        for name in &["a", "b", "c"] {
+            create_dir_all(root.join(name).join("info")).unwrap();
+        }
+
+        // `a` refers to `b` and a missing directory, `b` refers to `c` and back
+        // to `a`, and `c` refers to itself.
+        fs::write(
+            root.join("a").join(ALTERNATES_FILE),
+            format!("../b\n{}\n", root.join("missing").display()),
+        )
+        .unwrap();
+        fs::write(root.join("b").join(ALTERNATES_FILE), "../c\n../a\n").unwrap();
+        fs::write(root.join("c").join(ALTERNATES_FILE), "../c\n").unwrap();
+
+        assert_eq!(
+            read_alternates(&root.join("a")),
+            vec![root.join("b"), root.join("c")]
+        );
+        assert_eq!(read_alternates(&root.join("c")), Vec::<PathBuf>::new());
+        assert_eq!(
+            read_alternates(&root.join("missing")),
+            Vec::<PathBuf>::new()
+        );
+    }
+}
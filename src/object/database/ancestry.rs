@@ -0,0 +1,75 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use crate::object::{Commit, Id, ObjectDatabase, ReadObjectError};
+
+/// An iterator over the commits reachable from a starting commit, most recent
+/// first. See `ObjectDatabase::walk_ancestry`.
+pub(in crate::object) struct Ancestry<'a> {
+    db: &'a ObjectDatabase,
+    seen: HashSet<Id>,
+    // Commits which have been read but not yet returned, ordered by commit
+    // time. Ties are broken by the order the commits were queued, like git.
+    queue: BinaryHeap<(i64, Reverse<u64>, Id)>,
+    commits: HashMap<Id, Commit>,
+    // Ids which have been seen but not yet read. Parents are read on the
+    // following call to `next`, so that an error reading them does not
+    // prevent their child from being returned.
+    pending: VecDeque<Id>,
+    count: u64,
+}
+
+impl<'a> Ancestry<'a> {
+    pub(in crate::object) fn new(db: &'a ObjectDatabase, start: Id) -> Self {
+        let mut seen = HashSet::new();
+        seen.insert(start);
+        Ancestry {
+            db,
+            seen,
+            queue: BinaryHeap::new(),
+            commits: HashMap::new(),
+            pending: VecDeque::from(vec![start]),
+            count: 0,
+        }
+    }
+
+    fn enqueue(&mut self, id: Id) -> Result<(), ReadObjectError> {
+        let commit = self.db.parse_object(id)?.into_commit()?;
+        // Like git, commits with an invalid time are treated as the oldest.
+        let time = commit
+            .committer()
+            .time()
+            .map(|time| time.seconds())
+            .unwrap_or(0);
+
+        self.count += 1;
+        self.queue.push((time, Reverse(self.count), id));
+        self.commits.insert(id, commit);
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Ancestry<'a> {
+    type Item = Result<(Id, Commit), ReadObjectError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(id) = self.pending.pop_front() {
+            if let Err(err) = self.enqueue(id) {
+                return Some(Err(err));
+            }
+        }
+
+        let (_, _, id) = self.queue.pop()?;
+        let commit = self
+            .commits
+            .remove(&id)
+            .expect("queued commit was not read");
+        for parent in commit.parents() {
+            if self.seen.insert(parent) {
+                self.pending.push_back(parent);
+            }
+        }
+
+        Some(Ok((id, commit)))
+    }
+}
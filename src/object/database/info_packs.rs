@@ -0,0 +1,161 @@
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use bstr::{BStr, ByteSlice};
+use bytes::Bytes;
+use thiserror::Error;
+
+use crate::parse::{self, Parser};
+
+/// The list of pack files in `objects/info/packs`, as written by
+/// `git update-server-info` for use by the dumb HTTP protocol.
+#[derive(Clone)]
+pub struct InfoPacks {
+    data: Bytes,
+    packs: Vec<Range<usize>>,
+}
+
+#[derive(Debug, Error)]
+pub enum ParseInfoPacksError {
+    #[error("invalid pack name `{0}`")]
+    InvalidPackName(String),
+}
+
+#[derive(Debug, Error)]
+pub enum ReadInfoPacksError {
+    #[error("the packs file is invalid")]
+    Parse(
+        #[source]
+        #[from]
+        ParseInfoPacksError,
+    ),
+    #[error("io error reading the packs file")]
+    Io(
+        #[source]
+        #[from]
+        io::Error,
+    ),
+}
+
+impl InfoPacks {
+    /// Parse the contents of an `objects/info/packs` file.
+    ///
+    /// Blank lines and lines other than `P <pack-name>` entries are ignored.
+    pub fn parse(data: impl Into<Bytes>) -> Result<Self, ParseInfoPacksError> {
+        let mut parser = Parser::new(data.into());
+        let mut packs = Vec::new();
+
+        while !parser.finished() {
+            let line = match parser.consume_until(b'\n') {
+                Some(line) => line,
+                // git always writes a trailing newline, but it is not required.
+                None => {
+                    let start = parser.pos();
+                    parser.advance(parser.remaining());
+                    start..parser.pos()
+                }
+            };
+
+            if !parser[line.clone()].starts_with(b"P ") {
+                continue;
+            }
+
+            let name = (line.start + 2)..line.end;
+            let name_bytes = &parser[name.clone()];
+            if name_bytes == b".pack"
+                || !name_bytes.ends_with(b".pack")
+                || name_bytes.contains(&b'/')
+            {
+                return Err(ParseInfoPacksError::InvalidPackName(parse::lossy_utf8(
+                    name_bytes,
+                )));
+            }
+
+            packs.push(name);
+        }
+
+        Ok(InfoPacks {
+            data: parser.into_inner(),
+            packs,
+        })
+    }
+
+    /// Read an `objects/info/packs` file. If the file does not exist, the
+    /// list of packs is empty.
+    pub(in crate::object) fn read(path: &Path) -> Result<Self, ReadInfoPacksError> {
+        match fs_err::read(path) {
+            Ok(data) => Ok(InfoPacks::parse(data)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Ok(InfoPacks::parse(Bytes::new())?)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The file names of the listed packs, such as `pack-<id>.pack`.
+    pub fn packs<'a>(&'a self) -> impl ExactSizeIterator<Item = &'a BStr> + 'a {
+        self.packs
+            .iter()
+            .map(move |range| self.data[range.clone()].as_bstr())
+    }
+}
+
+impl fmt::Debug for InfoPacks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.packs()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::InfoPacks;
+
+    #[test]
+    fn test_parse_info_packs() {
+        let info_packs = InfoPacks::parse(Bytes::from_static(
+            b"P pack-570c9f2183ceba36aaf51e7f604467f6495a218e.pack\n\
+              \n\
+              P pack-0123456789012345678901234567890123456789.pack\n\
+              \n",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            info_packs.packs().collect::<Vec<_>>(),
+            vec![
+                "pack-570c9f2183ceba36aaf51e7f604467f6495a218e.pack",
+                "pack-0123456789012345678901234567890123456789.pack",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_info_packs_without_trailing_newline() {
+        let info_packs = InfoPacks::parse(Bytes::from_static(b"P pack-a.pack")).unwrap();
+        assert_eq!(info_packs.packs().collect::<Vec<_>>(), vec!["pack-a.pack"]);
+    }
+
+    #[test]
+    fn test_parse_info_packs_empty() {
+        assert_eq!(InfoPacks::parse(Bytes::new()).unwrap().packs().len(), 0);
+        assert_eq!(
+            InfoPacks::parse(Bytes::from_static(b"\n"))
+                .unwrap()
+                .packs()
+                .len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_parse_info_packs_invalid_name() {
+        let err = InfoPacks::parse(Bytes::from_static(b"P pack-a.idx\n")).unwrap_err();
+        assert_eq!(err.to_string(), "invalid pack name `pack-a.idx`");
+        assert!(InfoPacks::parse(Bytes::from_static(b"P ../pack-a.pack\n")).is_err());
+        assert!(InfoPacks::parse(Bytes::from_static(b"P .pack\n")).is_err());
+    }
+}
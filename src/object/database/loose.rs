@@ -8,7 +8,7 @@ use flate2::Compression;
 use thiserror::Error;
 
 use crate::object::database::ObjectReader;
-use crate::object::Id;
+use crate::object::{Id, ShortId};
 
 const OBJECTS_FOLDER: &str = "objects";
 
@@ -43,32 +43,130 @@ pub(in crate::object) enum WriteLooseError {
 
 impl LooseObjectDatabase {
     pub fn open(path: &Path) -> Self {
-        LooseObjectDatabase {
-            path: path.join(OBJECTS_FOLDER),
-        }
+        LooseObjectDatabase::open_objects_dir(path.join(OBJECTS_FOLDER))
+    }
+
+    /// Open the loose objects in an object directory, such as an alternate.
+    pub fn open_objects_dir(path: impl Into<PathBuf>) -> Self {
+        LooseObjectDatabase { path: path.into() }
+    }
+
+    pub(in crate::object::database) fn path(&self) -> &Path {
+        &self.path
     }
 
     pub(in crate::object::database) fn read_object(
         &self,
         id: &Id,
     ) -> Result<ObjectReader, ReadLooseError> {
-        let hex = id.to_hex();
-        let (dir, file) = object_path_parts(&hex);
-        let mut path = self.path.join(dir);
-        path.push(file);
-
-        match fs_err::File::open(path) {
+        match fs_err::File::open(self.object_path(id)) {
             Ok(file) => Ok(ObjectReader::from_file(None, file)),
             Err(err) if err.kind() == io::ErrorKind::NotFound => Err(ReadLooseError::NotFound),
             Err(err) => Err(err.into()),
         }
     }
 
+    /// Read the compressed contents of an object without blocking the thread.
+    #[cfg(feature = "async")]
+    pub(in crate::object::database) async fn read_compressed(
+        &self,
+        id: &Id,
+    ) -> Result<Vec<u8>, ReadLooseError> {
+        match tokio::fs::read(self.object_path(id)).await {
+            Ok(data) => Ok(data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Err(ReadLooseError::NotFound),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(in crate::object::database) fn contains(&self, id: &Id) -> Result<bool, ReadLooseError> {
+        match fs_err::metadata(self.object_path(id)) {
+            Ok(_) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(in crate::object::database) fn resolve(
+        &self,
+        short_id: &ShortId,
+    ) -> Result<Id, ReadLooseError> {
+        let hex = short_id.to_hex();
+        let (dir, file_prefix) = object_path_parts(&hex);
+
+        let entries = match fs_err::read_dir(self.path.join(dir)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(ReadLooseError::NotFound)
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut result = None;
+        for entry in entries {
+            let file_name = entry?.file_name();
+            // Ignore any files which are not objects, such as temporary files.
+            let file_name = match file_name.to_str() {
+                Some(file_name) if file_name.starts_with(file_prefix) => file_name,
+                _ => continue,
+            };
+            let id = match Id::from_hex(format!("{}{}", dir, file_name).as_bytes()) {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+
+            if result.is_some() {
+                return Err(ReadLooseError::Ambiguous);
+            }
+            result = Some(id);
+        }
+
+        result.ok_or(ReadLooseError::NotFound)
+    }
+
+    pub(in crate::object::database) fn object_ids(&self) -> Result<Vec<Id>, ReadLooseError> {
+        let dirs = match fs_err::read_dir(&self.path) {
+            Ok(dirs) => dirs,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut ids = Vec::new();
+        for dir in dirs {
+            let dir = dir?;
+            let dir_name = dir.file_name();
+            // Skip any directories which do not contain objects, such as `info` and `pack`.
+            let dir_name = match dir_name.to_str() {
+                Some(dir_name)
+                    if dir_name.len() == 2 && dir_name.bytes().all(|b| b.is_ascii_hexdigit()) =>
+                {
+                    dir_name
+                }
+                _ => continue,
+            };
+
+            for entry in fs_err::read_dir(dir.path())? {
+                let file_name = entry?.file_name();
+                // Ignore any files which are not objects, such as temporary files.
+                let file_name = match file_name.to_str() {
+                    Some(file_name) => file_name,
+                    None => continue,
+                };
+                if let Ok(id) = Id::from_hex(format!("{}{}", dir_name, file_name).as_bytes()) {
+                    ids.push(id);
+                }
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Write an object with the given id, which must be the hash of `bytes`.
     pub(in crate::object::database) fn write_object(
         &self,
+        id: Id,
         bytes: &[u8],
     ) -> Result<Id, WriteLooseError> {
-        let id = Id::from_hash(bytes);
         let hex = id.to_hex();
         let (dir, file) = object_path_parts(&hex);
 
@@ -95,6 +193,16 @@ impl LooseObjectDatabase {
     }
 }
 
+impl LooseObjectDatabase {
+    fn object_path(&self, id: &Id) -> PathBuf {
+        let hex = id.to_hex();
+        let (dir, file) = object_path_parts(&hex);
+        let mut path = self.path.join(dir);
+        path.push(file);
+        path
+    }
+}
+
 fn object_path_parts(hex: &str) -> (&str, &str) {
     hex.split_at(2)
 }
@@ -107,7 +215,12 @@ mod tests {
     use proptest::{arbitrary::any, collection::vec, prop_assert_eq, proptest};
     use tempdir::TempDir;
 
-    use super::{object_path_parts, LooseObjectDatabase, OBJECTS_FOLDER};
+    use super::{object_path_parts, LooseObjectDatabase, ReadLooseError, OBJECTS_FOLDER};
+    use crate::object::Id;
+
+    fn write_object(db: &LooseObjectDatabase, bytes: &[u8]) -> Id {
+        db.write_object(Id::from_hash(bytes), bytes).unwrap()
+    }
 
     proptest! {
         #[test]
@@ -117,7 +230,7 @@ mod tests {
 
             let db = LooseObjectDatabase::open(tempdir.path());
 
-            let id = db.write_object(&bytes).unwrap();
+            let id = write_object(&db, &bytes);
 
             let mut read_bytes = Vec::new();
             db.read_object(&id).unwrap().reader().read_to_end(&mut read_bytes).unwrap();
@@ -126,6 +239,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn contains_written_object() {
+        let tempdir = TempDir::new("rusty_git_odb_loose_tests").unwrap();
+        create_dir(tempdir.path().join(OBJECTS_FOLDER)).unwrap();
+        let db = LooseObjectDatabase::open(tempdir.path());
+
+        let id = write_object(&db, b"hello");
+        assert!(db.contains(&id).unwrap());
+        assert!(!db.contains(&Id::from_hash(b"goodbye")).unwrap());
+    }
+
+    #[test]
+    fn resolve_short_id() {
+        let tempdir = TempDir::new("rusty_git_odb_loose_tests").unwrap();
+        create_dir(tempdir.path().join(OBJECTS_FOLDER)).unwrap();
+        let db = LooseObjectDatabase::open(tempdir.path());
+
+        let id = write_object(&db, b"hello");
+        let hex = id.to_hex();
+        assert_eq!(db.resolve(&hex[..4].parse().unwrap()).unwrap(), id);
+        assert_eq!(db.resolve(&hex[..8].parse().unwrap()).unwrap(), id);
+        assert_eq!(db.resolve(&hex.parse().unwrap()).unwrap(), id);
+
+        let other_hex = Id::from_hash(b"goodbye").to_hex();
+        assert!(matches!(
+            db.resolve(&other_hex[..4].parse().unwrap()),
+            Err(ReadLooseError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn object_ids_lists_written_objects() {
+        let tempdir = TempDir::new("rusty_git_odb_loose_tests").unwrap();
+        create_dir(tempdir.path().join(OBJECTS_FOLDER)).unwrap();
+        create_dir(tempdir.path().join(OBJECTS_FOLDER).join("info")).unwrap();
+        let db = LooseObjectDatabase::open(tempdir.path());
+
+        assert!(db.object_ids().unwrap().is_empty());
+
+        let mut expected = vec![write_object(&db, b"hello"), write_object(&db, b"goodbye")];
+        expected.sort();
+
+        let mut ids = db.object_ids().unwrap();
+        ids.sort();
+        assert_eq!(ids, expected);
+    }
+
     #[test]
     fn updates_file_mtime_on_already_exists() {
         let tempdir = TempDir::new("rusty_git_odb_loose_tests").unwrap();
@@ -133,14 +293,14 @@ mod tests {
         create_dir(&odb_path).unwrap();
         let db = LooseObjectDatabase::open(tempdir.path());
 
-        let id = db.write_object(b"hello").unwrap();
+        let id = write_object(&db, b"hello");
         let hex = id.to_hex();
         let (dir, file) = object_path_parts(&hex);
         let path = odb_path.join(dir).join(file);
 
         let mtime1 = metadata(&path).unwrap().modified().unwrap();
 
-        assert_eq!(db.write_object(b"hello").unwrap(), id);
+        assert_eq!(write_object(&db, b"hello"), id);
         let mtime2 = metadata(&path).unwrap().modified().unwrap();
 
         assert_ne!(mtime1, mtime2);
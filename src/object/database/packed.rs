@@ -1,21 +1,35 @@
+mod bundle;
+mod cache;
 mod delta;
 mod index;
+mod midx;
 mod pack;
+mod rev;
 
-use std::io;
+pub use self::bundle::{Bundle, ReadBundleError};
+pub(in crate::object::database) use self::pack::{no_external_base, FindBase};
+
+use std::collections::HashSet;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+use bytes::Bytes;
 use dashmap::DashMap;
+use once_cell::sync::OnceCell;
 
+use self::cache::{ObjectCache, DEFAULT_CACHE_LIMIT};
 use self::index::{FindIndexOffsetError, IndexFile, ReadIndexFileError};
-use self::pack::{PackFile, ReadPackFileError};
+use self::midx::{MultiPackIndex, MULTI_PACK_INDEX_FILE};
+use self::pack::{PackFile, ReadPackFileError, DEFAULT_MAX_DELTA_DEPTH};
 use crate::object::database::ObjectReader;
-use crate::object::ShortId;
+use crate::object::{HashAlgorithm, Id, ObjectHeader, ReadObjectError, ShortId};
 use thiserror::Error;
 
 const PACKS_FOLDER: &str = "objects/pack";
+const OBJECTS_PACK_FOLDER: &str = "pack";
 const MAX_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug)]
@@ -23,7 +37,30 @@ pub struct PackedObjectDatabase {
     path: PathBuf,
     // last: Mutex<Arc<PackFile>>, why is this useful?
     packs: DashMap<PathBuf, Arc<Entry>>,
+    // The multi-pack-index covering some or all of the packs, if there is one.
+    multi_pack_index: Mutex<Option<Arc<MultiPackIndex>>>,
     last_refresh: Mutex<Option<Instant>>,
+    hash_algorithm: HashAlgorithm,
+    // The cache of reconstructed objects, shared by all packs in this database
+    // and those of its alternates.
+    cache: Arc<ObjectCache>,
+    // The maximum length of a delta chain in each pack.
+    max_delta_depth: AtomicUsize,
+    // Whether packs are memory mapped rather than read through a file handle.
+    mmap: AtomicBool,
+    // Whether the CRC32 of each object read from a pack is checked.
+    verify_crc: AtomicBool,
+}
+
+/// A summary of a pack in the object database, as returned by
+/// `ObjectDatabase::packs`.
+#[derive(Debug, Clone)]
+pub struct PackInfo {
+    name: String,
+    id: Id,
+    object_count: usize,
+    size: u64,
+    index_version: u32,
 }
 
 #[derive(Debug, Error)]
@@ -62,64 +99,392 @@ enum ReadEntryErrorKind {
     IdMismatch,
 }
 
+// A pack in the pack directory. The index and pack files are only read when
+// they are first needed, so packs which are never used cost almost nothing.
 #[derive(Debug)]
 struct Entry {
     name: String,
-    index: IndexFile,
-    pack: PackFile,
+    path: PathBuf,
+    index: OnceCell<IndexFile>,
+    pack: OnceCell<PackFile>,
 }
 
+// An object found in a pack, with its offset in the pack and its full id.
+type FoundObject = (Arc<Entry>, u64, Id);
+
+// The id, header and offset of an object in a pack.
+type PackObject = (Id, ObjectHeader, u64);
+
 impl PackedObjectDatabase {
-    pub fn open(path: &Path) -> Self {
+    pub fn open(path: &Path, hash_algorithm: HashAlgorithm) -> Self {
+        PackedObjectDatabase::open_pack_dir(path.join(PACKS_FOLDER), hash_algorithm)
+    }
+
+    /// Open the packs in an object directory, such as an alternate.
+    pub fn open_objects_dir(path: &Path, hash_algorithm: HashAlgorithm) -> Self {
+        PackedObjectDatabase::open_pack_dir(path.join(OBJECTS_PACK_FOLDER), hash_algorithm)
+    }
+
+    fn open_pack_dir(path: PathBuf, hash_algorithm: HashAlgorithm) -> Self {
         PackedObjectDatabase {
-            path: path.join(PACKS_FOLDER),
+            path,
             packs: DashMap::new(),
+            multi_pack_index: Mutex::new(None),
             last_refresh: Mutex::new(None),
+            hash_algorithm,
+            cache: Arc::new(ObjectCache::new(DEFAULT_CACHE_LIMIT)),
+            max_delta_depth: AtomicUsize::new(DEFAULT_MAX_DELTA_DEPTH),
+            mmap: AtomicBool::new(false),
+            verify_crc: AtomicBool::new(false),
         }
     }
 
+    /// Write a version 2 index for a pack, given the id, offset and CRC32 of
+    /// each object in the pack and the id of the pack itself.
+    pub fn write_index(
+        writer: impl Write,
+        entries: &[(Id, u64, u32)],
+        pack_id: Id,
+    ) -> io::Result<()> {
+        IndexFile::write(writer, entries, pack_id)
+    }
+
     pub(in crate::object::database) fn read_object(
         &self,
         short_id: &ShortId,
+        find_base: &FindBase<'_>,
     ) -> Result<ObjectReader, ReadPackedError> {
-        match self.try_read_object(short_id) {
-            Err(ReadPackedError::NotFound) if self.refresh()? => self.try_read_object(short_id),
+        self.read_entry(short_id, |pack, index, offset| {
+            pack.read_object(index, offset, find_base)
+        })
+    }
+
+    pub(in crate::object::database) fn open_object(
+        &self,
+        short_id: &ShortId,
+        find_base: &FindBase<'_>,
+    ) -> Result<ObjectReader, ReadPackedError> {
+        self.read_entry(short_id, |pack, index, offset| {
+            pack.open_object(index, offset, find_base)
+        })
+    }
+
+    pub(in crate::object::database) fn read_header(
+        &self,
+        short_id: &ShortId,
+        find_base: &FindBase<'_>,
+    ) -> Result<ObjectHeader, ReadPackedError> {
+        self.read_entry(short_id, |pack, index, offset| {
+            pack.read_header(index, offset, find_base)
+        })
+    }
+
+    pub(in crate::object::database) fn contains(
+        &self,
+        short_id: &ShortId,
+    ) -> Result<bool, ReadPackedError> {
+        match self.find_object(short_id) {
+            Ok(_) => Ok(true),
+            Err(ReadPackedError::NotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub(in crate::object::database) fn resolve(
+        &self,
+        short_id: &ShortId,
+    ) -> Result<Id, ReadPackedError> {
+        let (_, _, id) = self.find_object(short_id)?;
+        Ok(id)
+    }
+
+    /// List the ids of the objects in every pack, along with an error for each
+    /// pack whose index could not be read.
+    pub(in crate::object::database) fn object_ids(
+        &self,
+    ) -> Result<(Vec<Id>, Vec<ReadPackedError>), ReadPackedError> {
+        self.refresh()?;
+
+        let mut ids = Vec::new();
+        let mut errors = Vec::new();
+        for entry in self.all_entries() {
+            let entry_ids = self.index(&entry).and_then(|index| {
+                index
+                    .ids()
+                    .map_err(|err| entry.error(ReadEntryErrorKind::ReadIndexFile(err)))
+            });
+            match entry_ids {
+                Ok(entry_ids) => ids.extend(entry_ids),
+                Err(err) => errors.push(ReadPackedError::ReadEntry(err)),
+            }
+        }
+        Ok((ids, errors))
+    }
+
+    /// The path of the file which caused `err`, or the pack directory if the
+    /// error was not specific to one file.
+    pub(in crate::object::database) fn error_path(&self, err: &ReadPackedError) -> PathBuf {
+        match err {
+            ReadPackedError::ReadEntry(err) => self.path.join(&err.name),
+            _ => self.path.clone(),
+        }
+    }
+
+    /// Iterate over the id, header and offset of every object in the pack with
+    /// id `pack_id`, in the order of its index.
+    ///
+    /// Each header is read as the iterator advances, so other threads may read
+    /// objects from the pack while iterating.
+    pub(in crate::object::database) fn pack_objects<'a>(
+        &'a self,
+        pack_id: Id,
+        find_base: impl Fn(Id) -> Result<Option<(ObjectHeader, Bytes)>, ReadObjectError> + 'a,
+    ) -> Result<impl Iterator<Item = Result<PackObject, ReadPackedError>> + 'a, ReadPackedError>
+    {
+        let entry = self.find_pack(pack_id)?;
+        let count = self.index(&entry)?.count() as usize;
+        self.pack(&entry)?;
+
+        Ok((0..count).map(move |position| {
+            self.pack(&entry)?
+                .object_at_position(self.index(&entry)?, position, &find_base)
+                .map_err(|err| entry.read_pack_file_error(err))
+        }))
+    }
+
+    /// The id of the object at `offset` in the pack with id `pack_id`, or
+    /// `None` if no object starts at that offset.
+    pub(in crate::object::database) fn object_id_at_offset(
+        &self,
+        pack_id: Id,
+        offset: u64,
+    ) -> Result<Option<Id>, ReadPackedError> {
+        let entry = self.find_pack(pack_id)?;
+        Ok(self
+            .pack(&entry)?
+            .object_id_at_offset(self.index(&entry)?, offset))
+    }
+
+    /// Summarize each pack in the pack directory, sorted by name. The pack
+    /// directory is scanned first, so the list is up to date.
+    pub(in crate::object::database) fn packs(&self) -> Result<Vec<PackInfo>, ReadPackedError> {
+        self.force_refresh()?;
+
+        let mut packs = self
+            .all_entries()
+            .iter()
+            .map(|entry| {
+                let index = self.index(entry)?;
+                let path = entry.path.with_extension("pack");
+                Ok(PackInfo {
+                    size: fs_err::metadata(&path)?.len(),
+                    // The file has an extension so it must have a file name
+                    name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                    id: index.id(),
+                    object_count: index.count() as usize,
+                    index_version: index.version(),
+                })
+            })
+            .collect::<Result<Vec<_>, ReadPackedError>>()?;
+        packs.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+        Ok(packs)
+    }
+
+    /// Use the same object cache as `other`, so that the cache limit applies
+    /// to the packs of both databases together.
+    pub(in crate::object::database) fn share_cache(mut self, other: &PackedObjectDatabase) -> Self {
+        self.cache = other.cache.clone();
+        self
+    }
+
+    /// Set the maximum total size of the objects cached for all packs sharing
+    /// this database's cache.
+    pub(in crate::object::database) fn set_cache_limit(&self, limit: usize) {
+        self.cache.set_limit(limit);
+    }
+
+    /// Set the maximum length of a delta chain in packs opened after this call.
+    pub(in crate::object::database) fn set_max_delta_depth(&self, max_delta_depth: usize) {
+        self.max_delta_depth
+            .store(max_delta_depth, Ordering::Relaxed);
+    }
+
+    /// Set whether packs opened after this call are memory mapped.
+    pub(in crate::object::database) fn set_mmap(&self, mmap: bool) {
+        self.mmap.store(mmap, Ordering::Relaxed);
+    }
+
+    /// Set whether packs opened after this call check the CRC32 of each object.
+    pub(in crate::object::database) fn set_verify_crc(&self, verify_crc: bool) {
+        self.verify_crc.store(verify_crc, Ordering::Relaxed);
+    }
+
+    /// The total size of the objects cached for all packs sharing this
+    /// database's cache.
+    pub(in crate::object::database) fn cache_size(&self) -> usize {
+        self.cache.size()
+    }
+
+    /// Scan the pack directory for new or removed packs, even if it was
+    /// scanned recently.
+    pub(in crate::object::database) fn force_refresh(&self) -> Result<(), ReadPackedError> {
+        *self.last_refresh.lock().unwrap() = None;
+        self.refresh()?;
+        Ok(())
+    }
+
+    // Find the pack containing an object and read from it. If the pack has
+    // been removed since it was opened, for example by `git gc`, the object
+    // is looked for again in the packs which replaced it.
+    fn read_entry<T>(
+        &self,
+        short_id: &ShortId,
+        read: impl Fn(&PackFile, &IndexFile, u64) -> Result<T, ReadPackFileError>,
+    ) -> Result<T, ReadPackedError> {
+        match self.try_read_entry(short_id, &read) {
+            Err(ReadPackedError::ReadEntry(err)) if err.is_not_found() => {
+                self.force_refresh()?;
+                self.try_read_entry(short_id, &read)
+            }
             result => result,
         }
     }
 
-    fn try_read_object(&self, short_id: &ShortId) -> Result<ObjectReader, ReadPackedError> {
+    fn try_read_entry<T>(
+        &self,
+        short_id: &ShortId,
+        read: &impl Fn(&PackFile, &IndexFile, u64) -> Result<T, ReadPackFileError>,
+    ) -> Result<T, ReadPackedError> {
+        let (entry, offset, _) = self.find_object(short_id)?;
+        read(self.pack(&entry)?, self.index(&entry)?, offset)
+            .map_err(|err| entry.read_pack_file_error(err))
+    }
+
+    fn find_object(&self, short_id: &ShortId) -> Result<FoundObject, ReadPackedError> {
+        match self.try_find_object(short_id) {
+            Err(ReadPackedError::NotFound) if self.refresh()? => self.try_find_object(short_id),
+            Err(ReadPackedError::ReadEntry(err)) if err.is_not_found() => {
+                self.force_refresh()?;
+                self.try_find_object(short_id)
+            }
+            result => result,
+        }
+    }
+
+    fn try_find_object(&self, short_id: &ShortId) -> Result<FoundObject, ReadPackedError> {
         let mut result = None;
-        let mut found_id = None;
-        for entry in self.packs.iter() {
-            match entry.value().index.find_offset(short_id) {
-                Err(FindIndexOffsetError::Ambiguous) => return Err(ReadPackedError::Ambiguous),
-                Ok((_, id)) if found_id.is_some() && found_id != Some(id) => {
-                    return Err(ReadPackedError::Ambiguous)
-                }
-                Ok((offset, id)) => {
-                    found_id = Some(id);
-                    result = Some((entry.value().clone(), offset))
+
+        // Objects in packs covered by the multi-pack-index can be found with a
+        // single lookup, so only the remaining packs need to be searched.
+        let multi_pack_index = self.multi_pack_index.lock().unwrap().clone();
+        if let Some(multi_pack_index) = &multi_pack_index {
+            match multi_pack_index.find_offset(short_id) {
+                Ok((name, offset, id)) => {
+                    let entry = self.open_entry(self.path.join(name));
+                    if self.is_full_id(short_id) {
+                        return Ok((entry, offset, id));
+                    }
+                    result = Some((entry, offset, id));
                 }
-                Err(FindIndexOffsetError::NotFound) => continue,
+                Err(FindIndexOffsetError::NotFound) => (),
+                Err(FindIndexOffsetError::Ambiguous) => return Err(ReadPackedError::Ambiguous),
                 Err(FindIndexOffsetError::ReadIndexFile(err)) => {
                     return Err(ReadPackedError::ReadEntry(ReadEntryError {
-                        name: entry.name.clone(),
+                        name: MULTI_PACK_INDEX_FILE.to_owned(),
                         kind: ReadEntryErrorKind::ReadIndexFile(err),
                     }))
                 }
             }
         }
 
-        match result {
-            Some((entry, offset)) => match entry.pack.read_object(&entry.index, offset) {
-                Ok(reader) => Ok(reader),
-                Err(err) => Err(ReadPackedError::ReadEntry(ReadEntryError {
-                    name: entry.name.clone(),
-                    kind: ReadEntryErrorKind::ReadPackFile(err),
-                })),
-            },
-            None => Err(ReadPackedError::NotFound),
+        // Take a snapshot of the packs, so the map is not locked while their
+        // indexes are searched.
+        let entries: Vec<Arc<Entry>> = self
+            .packs
+            .iter()
+            .filter(|entry| match &multi_pack_index {
+                Some(multi_pack_index) => !multi_pack_index.contains_pack(&entry.name),
+                None => true,
+            })
+            .map(|entry| entry.value().clone())
+            .collect();
+
+        merge_found(result, self.search_packs(short_id, &entries)?)?
+            .ok_or(ReadPackedError::NotFound)
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    fn search_packs(
+        &self,
+        short_id: &ShortId,
+        entries: &[Arc<Entry>],
+    ) -> Result<Option<FoundObject>, ReadPackedError> {
+        if self.is_full_id(short_id) {
+            let mut error = None;
+            let found = entries
+                .iter()
+                .find_map(|entry| match self.search_pack(short_id, entry) {
+                    Ok(found) => found,
+                    Err(err) => {
+                        error.get_or_insert(err);
+                        None
+                    }
+                });
+            return first_found(found, error);
+        }
+
+        entries.iter().try_fold(None, |result, entry| {
+            merge_found(result, self.search_pack(short_id, entry)?)
+        })
+    }
+
+    #[cfg(feature = "rayon")]
+    fn search_packs(
+        &self,
+        short_id: &ShortId,
+        entries: &[Arc<Entry>],
+    ) -> Result<Option<FoundObject>, ReadPackedError> {
+        use rayon::prelude::*;
+
+        if self.is_full_id(short_id) {
+            let error = Mutex::new(None);
+            let found =
+                entries
+                    .par_iter()
+                    .find_map_any(|entry| match self.search_pack(short_id, entry) {
+                        Ok(found) => found,
+                        Err(err) => {
+                            error.lock().unwrap().get_or_insert(err);
+                            None
+                        }
+                    });
+            return first_found(found, error.into_inner().unwrap());
+        }
+
+        entries
+            .par_iter()
+            .map(|entry| self.search_pack(short_id, entry))
+            .try_reduce(|| None, merge_found)
+    }
+
+    // A full id cannot be ambiguous, so the search for it can stop at the
+    // first pack which contains it.
+    fn is_full_id(&self, short_id: &ShortId) -> bool {
+        short_id.len as usize == self.hash_algorithm.digest_len()
+    }
+
+    fn search_pack(
+        &self,
+        short_id: &ShortId,
+        entry: &Arc<Entry>,
+    ) -> Result<Option<FoundObject>, ReadPackedError> {
+        match self.index(entry)?.find_offset(short_id) {
+            Ok((offset, id)) => Ok(Some((entry.clone(), offset, id))),
+            Err(FindIndexOffsetError::NotFound) => Ok(None),
+            Err(FindIndexOffsetError::Ambiguous) => Err(ReadPackedError::Ambiguous),
+            Err(FindIndexOffsetError::ReadIndexFile(err)) => {
+                Err(entry.error(ReadEntryErrorKind::ReadIndexFile(err)).into())
+            }
         }
     }
 
@@ -134,59 +499,475 @@ impl PackedObjectDatabase {
             _ => (),
         }
 
-        for entry in fs_err::read_dir(&self.path)? {
+        let multi_pack_index = match MultiPackIndex::open(
+            self.path.join(MULTI_PACK_INDEX_FILE),
+            self.hash_algorithm,
+        ) {
+            Ok(multi_pack_index) => Some(Arc::new(multi_pack_index)),
+            Err(ReadIndexFileError::Io(err)) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(ReadPackedError::ReadEntry(ReadEntryError {
+                    name: MULTI_PACK_INDEX_FILE.to_owned(),
+                    kind: ReadEntryErrorKind::ReadIndexFile(err),
+                }))
+            }
+        };
+
+        let entries = match fs_err::read_dir(&self.path) {
+            Ok(entries) => Some(entries),
+            // An object directory without any packs, such as an alternate, may
+            // not have a pack directory.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        let mut index_paths = HashSet::new();
+        for entry in entries.into_iter().flatten() {
             let path = entry?.path();
             if path.extension() == Some("idx".as_ref()) {
-                self.packs
-                    .entry(path.clone())
-                    .or_try_insert_with(move || Entry::open(path).map(Arc::new))?;
+                // Packs covered by the multi-pack-index are only added once an
+                // object is found in them.
+                let covered = match (&multi_pack_index, path.file_name()) {
+                    (Some(multi_pack_index), Some(name)) => {
+                        multi_pack_index.contains_pack(&name.to_string_lossy())
+                    }
+                    _ => false,
+                };
+                if !covered {
+                    self.open_entry(path.clone());
+                }
+                index_paths.insert(path);
             }
         }
 
+        // Forget packs which have been removed, for example by `git gc`, so
+        // their objects are looked for in the packs which replaced them.
+        self.packs.retain(|path, _| index_paths.contains(path));
+        *self.multi_pack_index.lock().unwrap() = multi_pack_index;
+
         *last_refresh_guard = Some(Instant::now());
         Ok(true)
     }
+
+    // Get the entry for the pack with the given index file, adding it if necessary.
+    fn find_pack(&self, pack_id: Id) -> Result<Arc<Entry>, ReadPackedError> {
+        self.refresh()?;
+
+        for entry in self.all_entries() {
+            if self.index(&entry)?.id() == pack_id {
+                return Ok(entry);
+            }
+        }
+        Err(ReadPackedError::NotFound)
+    }
+
+    // Get every pack in the pack directory, adding those covered by the
+    // multi-pack-index which have not been used yet.
+    fn all_entries(&self) -> Vec<Arc<Entry>> {
+        let multi_pack_index = self.multi_pack_index.lock().unwrap().clone();
+        if let Some(multi_pack_index) = multi_pack_index {
+            for name in multi_pack_index.pack_names() {
+                self.open_entry(self.path.join(name));
+            }
+        }
+        self.packs
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+
+    fn open_entry(&self, path: PathBuf) -> Arc<Entry> {
+        self.packs
+            .entry(path.clone())
+            .or_insert_with(|| Arc::new(Entry::new(path)))
+            .value()
+            .clone()
+    }
+
+    // Get the index of a pack, reading it if this is its first use.
+    fn index<'a>(&self, entry: &'a Entry) -> Result<&'a IndexFile, ReadEntryError> {
+        entry.index.get_or_try_init(|| {
+            IndexFile::open(entry.path.clone(), self.hash_algorithm)
+                .map_err(|err| entry.error(ReadEntryErrorKind::ReadIndexFile(err)))
+        })
+    }
+
+    // Get a pack file, opening it and checking it matches its index if this is
+    // its first use.
+    fn pack<'a>(&self, entry: &'a Entry) -> Result<&'a PackFile, ReadEntryError> {
+        entry.pack.get_or_try_init(|| {
+            let index = self.index(entry)?;
+
+            let pack_path = entry.path.with_extension("pack");
+            let pack = if self.mmap.load(Ordering::Relaxed) {
+                PackFile::open_mmap(pack_path, self.hash_algorithm)
+            } else {
+                PackFile::open(pack_path, self.hash_algorithm)
+            };
+            let pack = pack
+                .map_err(|err| entry.error(ReadEntryErrorKind::ReadPackFile(err)))?
+                .with_max_delta_depth(self.max_delta_depth.load(Ordering::Relaxed))
+                .with_verify_crc(self.verify_crc.load(Ordering::Relaxed))
+                .with_cache(self.cache.clone());
+
+            if index.count() != pack.count() {
+                return Err(entry.error(ReadEntryErrorKind::CountMismatch));
+            }
+
+            if index.id() != pack.id() {
+                return Err(entry.error(ReadEntryErrorKind::IdMismatch));
+            }
+
+            Ok(pack)
+        })
+    }
+}
+
+impl PackInfo {
+    /// The file name of the pack, such as `pack-<id>.pack`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The id of the pack, which is the hash of its contents.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// The number of objects in the pack, as recorded in its index.
+    pub fn object_count(&self) -> usize {
+        self.object_count
+    }
+
+    /// The size of the pack file on disk in bytes, not including its index.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The version of the pack's index file format, which is either 1 or 2.
+    pub fn index_version(&self) -> u32 {
+        self.index_version
+    }
+}
+
+// Combine the objects found in two packs, which is ambiguous if they are
+// different objects.
+fn merge_found(
+    lhs: Option<FoundObject>,
+    rhs: Option<FoundObject>,
+) -> Result<Option<FoundObject>, ReadPackedError> {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) if lhs.2 != rhs.2 => Err(ReadPackedError::Ambiguous),
+        (Some(found), _) => Ok(Some(found)),
+        (None, found) => Ok(found),
+    }
+}
+
+// The result of searching every pack for a full id. A pack which could not be
+// read may not have contained the object anyway, so its error is only
+// reported if no other pack contains it.
+fn first_found(
+    found: Option<FoundObject>,
+    error: Option<ReadPackedError>,
+) -> Result<Option<FoundObject>, ReadPackedError> {
+    match (found, error) {
+        (Some(found), _) => Ok(Some(found)),
+        (None, Some(err)) => Err(err),
+        (None, None) => Ok(None),
+    }
+}
+
+impl ReadEntryError {
+    // Returns true if the index or pack file no longer exists on disk.
+    fn is_not_found(&self) -> bool {
+        match &self.kind {
+            ReadEntryErrorKind::ReadIndexFile(ReadIndexFileError::Io(err)) => {
+                err.kind() == io::ErrorKind::NotFound
+            }
+            ReadEntryErrorKind::ReadPackFile(err) => err.is_not_found(),
+            _ => false,
+        }
+    }
 }
 
 impl Entry {
-    fn open(path: PathBuf) -> Result<Self, ReadEntryError> {
+    fn read_pack_file_error(&self, err: ReadPackFileError) -> ReadPackedError {
+        ReadPackedError::ReadEntry(self.error(ReadEntryErrorKind::ReadPackFile(err)))
+    }
+
+    fn new(path: PathBuf) -> Self {
         // The file has an extension so it must have a file name
         let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        Entry {
+            name,
+            path,
+            index: OnceCell::new(),
+            pack: OnceCell::new(),
+        }
+    }
 
-        let index = match IndexFile::open(path.clone()) {
-            Ok(index) => index,
-            Err(err) => {
-                return Err(ReadEntryError {
-                    name,
-                    kind: ReadEntryErrorKind::ReadIndexFile(err),
-                })
-            }
-        };
+    fn error(&self, kind: ReadEntryErrorKind) -> ReadEntryError {
+        ReadEntryError {
+            name: self.name.clone(),
+            kind,
+        }
+    }
+}
 
-        let pack = match PackFile::open(path.with_extension("pack")) {
-            Ok(pack) => pack,
-            Err(err) => {
-                return Err(ReadEntryError {
-                    name,
-                    kind: ReadEntryErrorKind::ReadPackFile(err),
-                })
-            }
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::object::{ObjectDatabase, ObjectKind, ID_LEN};
+
+    const PACK_HEADER_LEN: u64 = 12;
+    const BLOB_HEADER: ObjectHeader = ObjectHeader {
+        kind: ObjectKind::Blob,
+        len: 4,
+    };
+    // The header of a blob with `BLOB_HEADER` in a pack.
+    const PACKED_BLOB_HEADER: &[u8] = &[0b0011_0100];
+
+    // Write a pack containing a single object, given its packed header and
+    // uncompressed data, along with an index for it.
+    fn write_pack(path: &Path, id: Id, header: &[u8], data: &[u8]) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+
+        let mut pack = Vec::new();
+        pack.extend_from_slice(b"PACK\x00\x00\x00\x02\x00\x00\x00\x01");
+        pack.extend_from_slice(header);
+        pack.extend_from_slice(&encoder.finish().unwrap());
+        let pack_id = HashAlgorithm::Sha1.hash(&pack);
+        pack.extend_from_slice(pack_id.as_bytes());
+        fs_err::write(path.with_extension("pack"), pack).unwrap();
+
+        let mut index = Vec::new();
+        IndexFile::write(&mut index, &[(id, PACK_HEADER_LEN, 0)], pack_id).unwrap();
+        fs_err::write(path.with_extension("idx"), index).unwrap();
+    }
+
+    // Create an object directory containing `n` packs named `pack-0`, `pack-1`
+    // and so on, each holding a different blob with `BLOB_HEADER`, and open it.
+    fn test_packs(n: usize) -> (TempDir, PackedObjectDatabase, Vec<Id>) {
+        let tempdir = TempDir::new("rusty_git_packed_tests").unwrap();
+        let pack_dir = tempdir.path().join(PACKS_FOLDER);
+        fs_err::create_dir_all(&pack_dir).unwrap();
+
+        let ids = (0..n)
+            .map(|i| {
+                let data = format!("{:04}", i);
+                let id = BLOB_HEADER.hash(HashAlgorithm::Sha1, data.as_bytes());
+                write_pack(
+                    &pack_dir.join(format!("pack-{}", i)),
+                    id,
+                    PACKED_BLOB_HEADER,
+                    data.as_bytes(),
+                );
+                id
+            })
+            .collect();
+
+        let database = PackedObjectDatabase::open(tempdir.path(), HashAlgorithm::Sha1);
+        (tempdir, database, ids)
+    }
+
+    #[test]
+    fn refresh_forgets_removed_packs() {
+        let (tempdir, database, ids) = test_packs(1);
+        let pack_dir = tempdir.path().join(PACKS_FOLDER);
+
+        assert!(database.contains(&ShortId::from(ids[0])).unwrap());
+        assert_eq!(database.packs.len(), 1);
+
+        fs_err::remove_file(pack_dir.join("pack-0.pack")).unwrap();
+        fs_err::remove_file(pack_dir.join("pack-0.idx")).unwrap();
+        database.force_refresh().unwrap();
+        assert_eq!(database.packs.len(), 0);
+        assert!(!database.contains(&ShortId::from(ids[0])).unwrap());
+    }
+
+    #[test]
+    fn packs_are_opened_on_first_use() {
+        let (tempdir, database, ids) = test_packs(4);
+        let pack_dir = tempdir.path().join(PACKS_FOLDER);
+        // The other packs are only found to be corrupt if an object is read from them.
+        for i in 1..4 {
+            fs_err::write(pack_dir.join(format!("pack-{}.pack", i)), b"not a pack").unwrap();
+        }
+
+        database.force_refresh().unwrap();
+        assert_eq!(database.packs.len(), 4);
+        assert!(database
+            .packs
+            .iter()
+            .all(|entry| entry.index.get().is_none() && entry.pack.get().is_none()));
+
+        assert!(database.contains(&ShortId::from(ids[0])).unwrap());
+        assert!(database
+            .packs
+            .iter()
+            .all(|entry| entry.pack.get().is_none()));
+
+        assert_eq!(
+            database
+                .read_header(&ShortId::from(ids[0]), &no_external_base)
+                .unwrap(),
+            BLOB_HEADER
+        );
+
+        // The indexes of other packs may have been searched, but their packs
+        // are never opened.
+        for entry in database.packs.iter() {
+            let contains_id = entry.path == pack_dir.join("pack-0.idx");
+            assert_eq!(entry.pack.get().is_some(), contains_id);
+        }
+
+        assert!(database
+            .read_header(&ShortId::from(ids[1]), &no_external_base)
+            .is_err());
+    }
+
+    #[test]
+    fn packs_covered_by_multi_pack_index_are_added_on_first_use() {
+        let (tempdir, database, ids) = test_packs(2);
+        let pack_dir = tempdir.path().join(PACKS_FOLDER);
+        fs_err::write(
+            pack_dir.join(MULTI_PACK_INDEX_FILE),
+            midx::tests::write(&["pack-0.idx"], &[(ids[0], 0, PACK_HEADER_LEN)]),
+        )
+        .unwrap();
+
+        database.force_refresh().unwrap();
+        assert_eq!(database.packs.len(), 1);
+
+        assert!(database.contains(&ShortId::from(ids[0])).unwrap());
+        assert_eq!(database.packs.len(), 2);
+
+        // Listing objects includes packs covered by the multi-pack-index.
+        let database = PackedObjectDatabase::open(tempdir.path(), HashAlgorithm::Sha1);
+        let (mut found, errors) = database.object_ids().unwrap();
+        assert!(errors.is_empty());
+        found.sort();
+        let mut expected = ids;
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn short_ids_are_ambiguous_across_packs() {
+        let (tempdir, database, _) = test_packs(0);
+        let pack_dir = tempdir.path().join(PACKS_FOLDER);
+
+        // The objects are never read, so their ids do not need to match their data.
+        let id = |last| {
+            let mut bytes = [0xab; ID_LEN];
+            bytes[ID_LEN - 1] = last;
+            Id::from_bytes(&bytes)
         };
+        for n in 0..4 {
+            write_pack(
+                &pack_dir.join(format!("pack-{}", n)),
+                id(n),
+                PACKED_BLOB_HEADER,
+                b"aaaa",
+            );
+        }
 
-        if index.count() != pack.count() {
-            return Err(ReadEntryError {
-                name,
-                kind: ReadEntryErrorKind::CountMismatch,
-            });
+        assert_eq!(database.resolve(&ShortId::from(id(2))).unwrap(), id(2));
+        assert!(matches!(
+            database.resolve(&ShortId::from_hex(b"abababab").unwrap()),
+            Err(ReadPackedError::Ambiguous)
+        ));
+        assert!(matches!(
+            database.resolve(&ShortId::from(id(4))),
+            Err(ReadPackedError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn full_ids_are_found_despite_unreadable_packs() {
+        let (tempdir, database, ids) = test_packs(5);
+        let pack_dir = tempdir.path().join(PACKS_FOLDER);
+        for i in 1..5 {
+            fs_err::write(pack_dir.join(format!("pack-{}.idx", i)), b"not an index").unwrap();
         }
 
-        if index.id() != pack.id() {
-            return Err(ReadEntryError {
-                name,
-                kind: ReadEntryErrorKind::IdMismatch,
-            });
+        for _ in 0..8 {
+            assert_eq!(database.resolve(&ShortId::from(ids[0])).unwrap(), ids[0]);
         }
+        // The other objects are only in packs whose index cannot be read.
+        assert!(matches!(
+            database.resolve(&ShortId::from(ids[1])),
+            Err(ReadPackedError::ReadEntry(_))
+        ));
+    }
+
+    #[test]
+    fn cache_limit_is_shared_by_all_packs() {
+        let (_tempdir, database, ids) = test_packs(4);
+        database.set_cache_limit(10);
+        for &id in &ids {
+            database
+                .read_object(&ShortId::from(id), &no_external_base)
+                .unwrap();
+        }
+        // Only two of the four byte objects fit in the cache at once.
+        assert_eq!(database.cache_size(), 8);
+
+        let alternate =
+            PackedObjectDatabase::open_objects_dir(Path::new("alternate"), HashAlgorithm::Sha1)
+                .share_cache(&database);
+        alternate.set_cache_limit(4);
+        assert_eq!(database.cache_size(), 4);
+    }
+
+    #[test]
+    fn ref_delta_base_in_other_pack_or_loose() {
+        let (tempdir, _, _) = test_packs(0);
+        let pack_dir = tempdir.path().join(PACKS_FOLDER);
+
+        let blob = |len| ObjectHeader {
+            kind: ObjectKind::Blob,
+            len,
+        };
+        let base = blob(4).hash(HashAlgorithm::Sha1, b"aaaa");
+        let id = blob(5).hash(HashAlgorithm::Sha1, b"aaaab");
+
+        // A thin pack containing only a delta which copies the base and appends a byte.
+        let mut delta_header = vec![0b0111_0110];
+        delta_header.extend_from_slice(base.as_bytes());
+        write_pack(
+            &pack_dir.join("pack-delta"),
+            id,
+            &delta_header,
+            b"\x04\x05\x90\x04\x01b",
+        );
+        write_pack(
+            &pack_dir.join("pack-base"),
+            base,
+            PACKED_BLOB_HEADER,
+            b"aaaa",
+        );
+
+        let database = ObjectDatabase::open(tempdir.path(), HashAlgorithm::Sha1);
+        assert_eq!(database.read_header(id).unwrap(), blob(5));
+        let (header, data) = database.read_object(id).unwrap().into_bytes().unwrap();
+        assert_eq!(header, blob(5));
+        assert_eq!(data, "aaaab");
 
-        Ok(Entry { pack, index, name })
+        // The base can also be a loose object.
+        fs_err::remove_file(pack_dir.join("pack-base.pack")).unwrap();
+        fs_err::remove_file(pack_dir.join("pack-base.idx")).unwrap();
+        let database = ObjectDatabase::open(tempdir.path(), HashAlgorithm::Sha1);
+        assert_eq!(database.write_object(b"blob 4\0aaaa").unwrap(), base);
+        assert_eq!(database.read_header(id).unwrap(), blob(5));
+        let (header, data) = database.read_object(id).unwrap().into_bytes().unwrap();
+        assert_eq!(header, blob(5));
+        assert_eq!(data, "aaaab");
     }
 }
@@ -0,0 +1,282 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use bstr::{BStr, ByteSlice};
+use bytes::Bytes;
+use fs_err::File;
+use thiserror::Error;
+
+use crate::object::database::packed::index::{FindIndexOffsetError, IndexFile};
+use crate::object::database::packed::pack::{
+    no_external_base, FindBase, PackFile, ReadPackFileError,
+};
+use crate::object::database::packed::{ReadEntryError, ReadEntryErrorKind, ReadPackedError};
+use crate::object::database::{ObjectReader, ReadError};
+use crate::object::{HashAlgorithm, Id, Object, ObjectHeader, ReadObjectError, ShortId};
+use crate::parse;
+
+const SIGNATURE_V2: &[u8] = b"# v2 git bundle\n";
+const SIGNATURE_V3: &[u8] = b"# v3 git bundle\n";
+const MAX_LINE_LEN: usize = 4096;
+
+/// A bundle file, as written by `git bundle create`, containing a list of
+/// references and a pack of the objects they need.
+pub struct Bundle {
+    name: String,
+    data: Bytes,
+    prerequisites: Vec<(Id, Range<usize>)>,
+    references: Vec<(Id, Range<usize>)>,
+    index: IndexFile,
+    pack: PackFile,
+    // The bases of deltas in a thin pack, which are not in the bundle.
+    external_bases: HashMap<Id, (ObjectHeader, Bytes)>,
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct ReadBundleError {
+    kind: ReadBundleErrorKind,
+}
+
+#[derive(Debug, Error)]
+enum ReadBundleErrorKind {
+    #[error("the signature of the bundle is invalid")]
+    InvalidSignature,
+    #[error("unsupported bundle capability `{0}`")]
+    UnsupportedCapability(String),
+    #[error("the pack in the bundle contains a delta against `{0}`, which is not in the bundle")]
+    MissingDeltaBase(Id),
+    #[error("{0}")]
+    Other(&'static str),
+    #[error(transparent)]
+    Parse(#[from] parse::Error),
+    #[error("failed to read the pack in the bundle")]
+    ReadPackFile(#[source] ReadPackFileError),
+    #[error("io error reading the bundle")]
+    Io(
+        #[source]
+        #[from]
+        io::Error,
+    ),
+}
+
+impl Bundle {
+    /// Open a bundle file and index the pack it contains.
+    ///
+    /// Bundles do not include a pack index, so every deltified object in the
+    /// pack must be reconstructed when the bundle is opened. Bundles with
+    /// prerequisites may contain deltas against objects which are not in the
+    /// bundle, in which case opening fails. Use `ObjectDatabase::open_bundle`
+    /// to read those bases from a repository.
+    ///
+    /// Both version 2 and version 3 bundles are supported, but the only
+    /// version 3 capability understood is `object-format`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ReadBundleError> {
+        Bundle::open_with(path.as_ref(), &no_external_base)
+    }
+
+    /// Open a bundle, finding the bases of any deltas which are not in the
+    /// bundle with `find_base`.
+    pub(in crate::object::database) fn open_with(
+        path: &Path,
+        find_base: &FindBase<'_>,
+    ) -> Result<Self, ReadBundleError> {
+        // The file name is only used for error messages
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let mut buffer = parse::Buffer::new(File::open(path)?);
+        let mut prerequisites = Vec::new();
+        let mut references = Vec::new();
+
+        let signature = buffer.read_exact(SIGNATURE_V2.len())?;
+        let is_v3 = match &buffer[signature] {
+            SIGNATURE_V2 => false,
+            SIGNATURE_V3 => true,
+            _ => return Err(ReadBundleErrorKind::InvalidSignature.into()),
+        };
+
+        // Version 2 bundles only support SHA-1 ids.
+        let mut hash_algorithm = HashAlgorithm::Sha1;
+        loop {
+            let line = buffer
+                .read_until_byte(b'\n', MAX_LINE_LEN)?
+                .ok_or(ReadBundleErrorKind::Other("bundle header line is too long"))?;
+            // Remove the trailing newline
+            let line = line.start..(line.end - 1);
+            if line.is_empty() {
+                break;
+            }
+
+            // Version 3 bundles list their capabilities before any references.
+            if is_v3 && buffer[line.clone()].first() == Some(&b'@') {
+                let capability = &buffer[(line.start + 1)..line.end];
+                hash_algorithm =
+                    match capability {
+                        b"object-format=sha1" => HashAlgorithm::Sha1,
+                        b"object-format=sha256" => HashAlgorithm::Sha256,
+                        _ => {
+                            return Err(ReadBundleErrorKind::UnsupportedCapability(
+                                parse::lossy_utf8(capability),
+                            )
+                            .into())
+                        }
+                    };
+                continue;
+            }
+
+            let (line, is_prerequisite) = match buffer[line.clone()].first() {
+                Some(b'-') => ((line.start + 1)..line.end, true),
+                _ => (line, false),
+            };
+
+            let hex_len = hash_algorithm.hex_len();
+            if line.len() < hex_len {
+                return Err(ReadBundleErrorKind::Other("invalid bundle header line").into());
+            }
+            let id = Id::from_hex(&buffer[line.start..][..hex_len]).map_err(parse::Error::from)?;
+
+            let rest = (line.start + hex_len)..line.end;
+            let name = match buffer[rest.clone()].first() {
+                Some(b' ') => (rest.start + 1)..rest.end,
+                // Prerequisites may omit the comment following the id.
+                None if is_prerequisite => rest,
+                _ => return Err(ReadBundleErrorKind::Other("invalid bundle header line").into()),
+            };
+
+            if is_prerequisite {
+                prerequisites.push((id, name));
+            } else {
+                references.push((id, name));
+            }
+        }
+
+        let header_len = buffer.pos();
+        let data = Bytes::copy_from_slice(&buffer[..header_len]);
+
+        // The pack follows the header, so reuse the same file handle to read it.
+        let file = buffer.into_inner();
+        let pack = PackFile::from_file(path.to_owned(), file, header_len as u64, hash_algorithm)
+            .map_err(ReadBundleErrorKind::ReadPackFile)?;
+
+        // Keep any bases found outside the bundle, since they are needed
+        // again whenever the deltas against them are read.
+        let external_bases = RefCell::new(HashMap::new());
+        let index = pack
+            .build_index(&|id| {
+                let base = find_base(id)?;
+                if let Some(base) = &base {
+                    external_bases.borrow_mut().insert(id, base.clone());
+                }
+                Ok(base)
+            })
+            .map_err(|err| match err {
+                ReadPackFileError::MissingDeltaBase { base } => {
+                    ReadBundleErrorKind::MissingDeltaBase(base)
+                }
+                err => ReadBundleErrorKind::ReadPackFile(err),
+            })?;
+
+        Ok(Bundle {
+            name,
+            data,
+            prerequisites,
+            references,
+            index,
+            pack,
+            external_bases: external_bases.into_inner(),
+        })
+    }
+
+    /// The objects which must already exist in a repository before this
+    /// bundle can be applied to it, along with an optional comment,
+    /// usually the subject line of the commit.
+    pub fn prerequisites<'a>(&'a self) -> impl ExactSizeIterator<Item = (Id, &'a BStr)> + 'a {
+        self.prerequisites
+            .iter()
+            .map(move |(id, range)| (*id, self.data[range.clone()].as_bstr()))
+    }
+
+    /// The references contained in the bundle, with their full names.
+    pub fn references<'a>(&'a self) -> impl ExactSizeIterator<Item = (Id, &'a BStr)> + 'a {
+        self.references
+            .iter()
+            .map(move |(id, range)| (*id, self.data[range.clone()].as_bstr()))
+    }
+
+    /// The ids of every object in the bundle, in sorted order.
+    pub fn object_ids(&self) -> impl Iterator<Item = Id> + '_ {
+        // The index is built when the bundle is opened, so it is always valid.
+        self.index.ids().expect("invalid bundle index")
+    }
+
+    pub fn parse_object(&self, id: Id) -> Result<Object, ReadObjectError> {
+        match self.read_object(id)?.parse(id.hash_algorithm()) {
+            Ok(data) => Ok(Object { id, data }),
+            Err(err) => Err(ReadObjectError::new(id, err)),
+        }
+    }
+
+    pub fn read_object(&self, id: Id) -> Result<ObjectReader, ReadObjectError> {
+        let offset = match self.index.find_offset(&ShortId::from(id)) {
+            Ok((offset, _)) => offset,
+            Err(FindIndexOffsetError::NotFound) => {
+                return Err(ReadObjectError::new(id, ReadError::NotFound))
+            }
+            Err(FindIndexOffsetError::Ambiguous) => {
+                return Err(ReadObjectError::new(id, ReadError::Ambiguous))
+            }
+            Err(FindIndexOffsetError::ReadIndexFile(err)) => {
+                return Err(self.read_error(id, ReadEntryErrorKind::ReadIndexFile(err)))
+            }
+        };
+
+        let find_base = |base| Ok(self.external_bases.get(&base).cloned());
+        self.pack
+            .read_object(&self.index, offset, &find_base)
+            .map_err(|err| self.read_error(id, ReadEntryErrorKind::ReadPackFile(err)))
+    }
+
+    fn read_error(&self, id: Id, kind: ReadEntryErrorKind) -> ReadObjectError {
+        let err = ReadPackedError::ReadEntry(ReadEntryError {
+            name: self.name.clone(),
+            kind,
+        });
+        ReadObjectError::new(id, ReadError::from(err))
+    }
+}
+
+impl From<io::Error> for ReadBundleError {
+    fn from(err: io::Error) -> Self {
+        ReadBundleErrorKind::from(err).into()
+    }
+}
+
+impl From<parse::Error> for ReadBundleError {
+    fn from(err: parse::Error) -> Self {
+        ReadBundleErrorKind::from(err).into()
+    }
+}
+
+impl From<ReadBundleErrorKind> for ReadBundleError {
+    fn from(kind: ReadBundleErrorKind) -> Self {
+        ReadBundleError { kind }
+    }
+}
+
+impl fmt::Debug for Bundle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bundle")
+            .field("prerequisites", &self.prerequisites().collect::<Vec<_>>())
+            .field("references", &self.references().collect::<Vec<_>>())
+            .field("pack", &self.pack)
+            .finish()
+    }
+}
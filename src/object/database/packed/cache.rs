@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+use crate::object::{Id, ObjectHeader};
+
+/// The default size of the cache, matching the default of git's
+/// `core.deltaBaseCacheLimit`.
+pub(in crate::object::database::packed) const DEFAULT_CACHE_LIMIT: usize = 96 * 1024 * 1024;
+
+/// A cache of reconstructed pack objects, keyed by the id of their pack and
+/// their offset in it. A single cache is shared by all the packs of an object
+/// database, so the limit bounds the memory used by all of them together.
+///
+/// When the total size of the cached objects exceeds the limit, the least
+/// recently used objects are evicted. Objects are reference counted, so an
+/// object being used as the base of a delta remains valid even if it is
+/// evicted while the delta is applied.
+#[derive(Debug)]
+pub(in crate::object::database::packed) struct ObjectCache {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    // The key of each entry, ordered from least to most recently used.
+    lru: BTreeMap<u64, Key>,
+    // Incremented on every access, to order entries in `lru`.
+    clock: u64,
+    size: usize,
+    limit: usize,
+}
+
+// The id of a pack and the offset of an object in it.
+type Key = (Id, u64);
+
+#[derive(Debug)]
+struct Entry {
+    header: ObjectHeader,
+    data: Bytes,
+    last_used: u64,
+}
+
+impl ObjectCache {
+    pub fn new(limit: usize) -> Self {
+        ObjectCache {
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+                size: 0,
+                limit,
+            }),
+        }
+    }
+
+    pub fn get(&self, pack: Id, offset: u64) -> Option<(ObjectHeader, Bytes)> {
+        let mut inner = self.inner.lock().unwrap();
+        let last_used = inner.tick();
+
+        let key = (pack, offset);
+        let entry = inner.entries.get_mut(&key)?;
+        let prev_used = entry.last_used;
+        entry.last_used = last_used;
+        let result = (entry.header, entry.data.clone());
+
+        inner.lru.remove(&prev_used);
+        inner.lru.insert(last_used, key);
+        Some(result)
+    }
+
+    /// Add an object to the cache, evicting older objects if necessary.
+    /// Objects larger than the limit are not cached.
+    pub fn insert(&self, pack: Id, offset: u64, header: ObjectHeader, data: Bytes) {
+        let mut inner = self.inner.lock().unwrap();
+        let key = (pack, offset);
+        if data.len() > inner.limit || inner.entries.contains_key(&key) {
+            return;
+        }
+
+        let last_used = inner.tick();
+        inner.size += data.len();
+        inner.lru.insert(last_used, key);
+        inner.entries.insert(
+            key,
+            Entry {
+                header,
+                data,
+                last_used,
+            },
+        );
+        inner.evict();
+    }
+
+    /// The total size of the cached objects, in bytes.
+    pub fn size(&self) -> usize {
+        self.inner.lock().unwrap().size
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.limit = limit;
+        inner.evict();
+    }
+}
+
+impl Inner {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.limit {
+            let (&last_used, &key) = match self.lru.iter().next() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            self.lru.remove(&last_used);
+            if let Some(entry) = self.entries.remove(&key) {
+                self.size -= entry.data.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::ObjectKind;
+
+    fn header(len: usize) -> ObjectHeader {
+        ObjectHeader {
+            kind: ObjectKind::Blob,
+            len,
+        }
+    }
+
+    fn pack() -> Id {
+        Id::from_hash(b"pack")
+    }
+
+    fn insert(cache: &ObjectCache, offset: u64, len: usize) {
+        cache.insert(pack(), offset, header(len), Bytes::from(vec![0; len]));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = ObjectCache::new(10);
+        insert(&cache, 1, 4);
+        insert(&cache, 2, 4);
+        assert_eq!(cache.size(), 8);
+
+        // Using the first entry makes the second the least recently used.
+        assert_eq!(cache.get(pack(), 1).unwrap().0, header(4));
+        insert(&cache, 3, 4);
+        assert_eq!(cache.size(), 8);
+        assert!(cache.get(pack(), 1).is_some());
+        assert!(cache.get(pack(), 2).is_none());
+        assert!(cache.get(pack(), 3).is_some());
+    }
+
+    #[test]
+    fn does_not_cache_objects_larger_than_limit() {
+        let cache = ObjectCache::new(10);
+        insert(&cache, 1, 4);
+        insert(&cache, 2, 11);
+        assert!(cache.get(pack(), 1).is_some());
+        assert!(cache.get(pack(), 2).is_none());
+        assert_eq!(cache.size(), 4);
+    }
+
+    #[test]
+    fn evicted_objects_remain_valid() {
+        let cache = ObjectCache::new(4);
+        insert(&cache, 1, 4);
+        let (_, base) = cache.get(pack(), 1).unwrap();
+        insert(&cache, 2, 4);
+        assert!(cache.get(pack(), 1).is_none());
+        assert_eq!(base.len(), 4);
+    }
+
+    #[test]
+    fn lowering_limit_evicts() {
+        let cache = ObjectCache::new(10);
+        insert(&cache, 1, 4);
+        insert(&cache, 2, 4);
+        cache.set_limit(5);
+        assert_eq!(cache.size(), 4);
+        assert!(cache.get(pack(), 1).is_none());
+        assert!(cache.get(pack(), 2).is_some());
+    }
+
+    #[test]
+    fn objects_are_keyed_by_pack() {
+        let cache = ObjectCache::new(10);
+        let other = Id::from_hash(b"other");
+        insert(&cache, 1, 4);
+        cache.insert(other, 1, header(5), Bytes::from(vec![0; 5]));
+        assert_eq!(cache.get(pack(), 1).unwrap().0, header(4));
+        assert_eq!(cache.get(other, 1).unwrap().0, header(5));
+        assert_eq!(cache.size(), 9);
+
+        // Both packs share the limit.
+        cache.insert(other, 2, header(4), Bytes::from(vec![0; 4]));
+        assert_eq!(cache.size(), 9);
+        assert!(cache.get(pack(), 1).is_none());
+    }
+}
@@ -48,21 +48,19 @@ where
 
     while let Some(cmd) = delta.read_command()? {
         let src = match cmd {
-            Command::CopyFromBase { offset, len } => {
-                base
-                    .get(offset..)
-                    .ok_or(DeltaError::InvalidCommand)?
-                    .get(..len)
-                    .ok_or(DeltaError::InvalidCommand)?
-            }
+            Command::CopyFromBase { offset, len } => base
+                .get(offset..)
+                .ok_or(DeltaError::InvalidCommand)?
+                .get(..len)
+                .ok_or(DeltaError::InvalidCommand)?,
             Command::CopyFromDelta { len } => {
                 let range = delta.read_exact(len)?;
                 &delta[range]
             }
         };
 
-        result.copy_from_slice(&src);
-        delta.clear_buffer();
+        result.extend_from_slice(src);
+        delta.discard_observed();
     }
 
     if header.result_len != result.len() {
@@ -78,9 +76,9 @@ where
     ))
 }
 
-struct DeltaHeader {
-    base_len: usize,
-    result_len: usize,
+pub(in crate::object::database::packed) struct DeltaHeader {
+    pub base_len: usize,
+    pub result_len: usize,
 }
 
 impl DeltaError {
@@ -91,7 +89,9 @@ impl<R> parse::Buffer<R>
 where
     R: Read,
 {
-    fn read_delta_header(&mut self) -> Result<DeltaHeader, DeltaError> {
+    pub(in crate::object::database::packed) fn read_delta_header(
+        &mut self,
+    ) -> Result<DeltaHeader, DeltaError> {
         let base_len = self.read_delta_header_len(DeltaError::MAX_VARINT_LEN * 2)?;
         let result_len = self.read_delta_header_len(DeltaError::MAX_VARINT_LEN)?;
 
@@ -132,7 +132,7 @@ where
             Err(err) => return Err(err.into()),
         };
 
-        if intersects(cmd, 0b1000_000) {
+        if intersects(cmd, 0b1000_0000) {
             let mut offset = 0;
             if intersects(cmd, 0b0000_0001) {
                 offset |= u64::from(self.read_byte()?) << 0;
@@ -1,23 +1,26 @@
 use std::convert::TryFrom;
 use std::fmt;
-use std::io;
-use std::mem::size_of;
-use std::ops::Range;
+use std::io::{self, Write};
 use std::path::PathBuf;
 
 use byteorder::NetworkEndian;
 use bytes::Bytes;
+use once_cell::sync::OnceCell;
 use thiserror::Error;
 use zerocopy::byteorder::{U32, U64};
 use zerocopy::{FromBytes, LayoutVerified};
 
-use crate::object::{Id, ShortId, ID_LEN};
+use crate::object::{HashAlgorithm, Id, ShortId};
 use crate::parse::Parser;
 
 pub(in crate::object::database::packed) struct IndexFile {
     data: Bytes,
     version: Version,
     count: usize,
+    hash_algorithm: HashAlgorithm,
+    // The offset and position of each entry, sorted by offset. This is only
+    // built when needed to look up an entry by its offset.
+    offset_positions: OnceCell<Vec<(u64, usize)>>,
 }
 
 #[derive(Debug, Error)]
@@ -44,40 +47,104 @@ pub(in crate::object::database::packed) enum FindIndexOffsetError {
     ReadIndexFile(ReadIndexFileError),
 }
 
+// The tables of 32-bit and 64-bit offsets in a version 2 index.
+type Offsets<'a> = (&'a [U32<NetworkEndian>], &'a [U64<NetworkEndian>]);
+
 #[derive(Debug, PartialEq)]
 enum Version {
+    // Entries are a 4-byte offset followed by an id.
     V1,
+    // Entries are just an id, and the offsets are stored in a separate table.
     V2,
 }
 
-#[repr(C)]
-#[derive(Debug, FromBytes)]
-struct EntryV1 {
-    offset: U32<NetworkEndian>,
-    id: Id,
-}
-
-#[repr(C)]
-#[derive(Debug, FromBytes)]
-struct EntryV2 {
-    id: Id,
-}
-
 impl IndexFile {
     const SIGNATURE: u32 = u32::from_be_bytes(*b"\xfftOc");
     const HEADER_LEN: usize = 8;
     const FAN_OUT_COUNT: usize = 256;
     const FAN_OUT_LEN: usize = IndexFile::FAN_OUT_COUNT * 4;
-    const ENTRY_LEN_V1: usize = size_of::<EntryV1>();
-    const ENTRY_LEN_V2: usize = size_of::<EntryV2>();
-    const TRAILER_LEN: usize = ID_LEN + ID_LEN;
 
-    pub fn open(path: PathBuf) -> Result<Self, ReadIndexFileError> {
+    pub fn open(path: PathBuf, hash_algorithm: HashAlgorithm) -> Result<Self, ReadIndexFileError> {
         let bytes = Bytes::from(fs_err::read(path)?);
-        IndexFile::parse(Parser::new(bytes))
+        IndexFile::parse(Parser::new(bytes).with_hash_algorithm(hash_algorithm))
+    }
+
+    /// Build a version 2 index in memory from the ids and offsets of the
+    /// objects in a pack. The CRC32 of each object is not computed.
+    pub fn from_entries(entries: &mut Vec<(Id, u64)>, pack_id: Id) -> Self {
+        let hash_algorithm = pack_id.hash_algorithm();
+        entries.sort_unstable();
+        entries.dedup_by_key(|&mut (id, _)| id);
+
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|&(id, offset)| (id, offset, 0))
+            .collect();
+        let mut data = Vec::new();
+        IndexFile::write(&mut data, &entries, pack_id).expect("failed to write to a vec");
+
+        IndexFile {
+            data: data.into(),
+            version: Version::V2,
+            count: entries.len(),
+            hash_algorithm,
+            offset_positions: OnceCell::new(),
+        }
+    }
+
+    /// Write a version 2 index for a pack, given the id, offset and CRC32 of
+    /// each object in the pack and the id of the pack itself. The entries do
+    /// not need to be sorted, but their ids must be unique.
+    pub fn write<W: Write>(
+        mut writer: W,
+        entries: &[(Id, u64, u32)],
+        pack_id: Id,
+    ) -> io::Result<()> {
+        let mut entries = entries.to_vec();
+        entries.sort_unstable_by_key(|&(id, _, _)| id);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&IndexFile::SIGNATURE.to_be_bytes());
+        data.extend_from_slice(&2u32.to_be_bytes());
+
+        let mut count = 0;
+        for first_byte in 0..IndexFile::FAN_OUT_COUNT {
+            while count < entries.len() && usize::from(entries[count].0.as_bytes()[0]) == first_byte
+            {
+                count += 1;
+            }
+            data.extend_from_slice(&(count as u32).to_be_bytes());
+        }
+
+        for (id, _, _) in &entries {
+            data.extend_from_slice(id.as_bytes());
+        }
+        for (_, _, crc) in &entries {
+            data.extend_from_slice(&crc.to_be_bytes());
+        }
+
+        let mut large_offsets = Vec::new();
+        for &(_, offset, _) in &entries {
+            let small_offset = match u32::try_from(offset) {
+                Ok(offset) if offset & 0x80000000 == 0 => offset,
+                _ => {
+                    large_offsets.extend_from_slice(&offset.to_be_bytes());
+                    0x80000000 | (large_offsets.len() / 8 - 1) as u32
+                }
+            };
+            data.extend_from_slice(&small_offset.to_be_bytes());
+        }
+        data.extend_from_slice(&large_offsets);
+
+        data.extend_from_slice(pack_id.as_bytes());
+        let checksum = pack_id.hash_algorithm().hash(&data);
+        data.extend_from_slice(checksum.as_bytes());
+
+        writer.write_all(&data)
     }
 
     fn parse(mut parser: Parser<Bytes>) -> Result<Self, ReadIndexFileError> {
+        let hash_algorithm = parser.hash_algorithm();
         let version = if parser.consume_u32(IndexFile::SIGNATURE) {
             let version = parser
                 .parse_u32()
@@ -105,9 +172,9 @@ impl IndexFile {
             usize::try_from(count).or(Err(ReadIndexFileError::Other("invalid index count")))?;
 
         let mut min_size = count
-            .checked_mul(version.entry_len())
+            .checked_mul(version.entry_len(hash_algorithm))
             .ok_or(ReadIndexFileError::Other("invalid index count"))?
-            .checked_add(IndexFile::TRAILER_LEN)
+            .checked_add(IndexFile::trailer_len(hash_algorithm))
             .ok_or(ReadIndexFileError::Other("invalid index count"))?;
         if version == Version::V2 {
             min_size = count
@@ -134,16 +201,25 @@ impl IndexFile {
                 "index length is an invalid length",
             ));
         }
+        // Any remaining space is the table of large offsets.
+        let large_offsets_len = parser.remaining() - min_size;
+        if large_offsets_len / 8 * 8 != large_offsets_len {
+            return Err(ReadIndexFileError::Other(
+                "index length is an invalid length",
+            ));
+        }
 
         Ok(IndexFile {
             data: parser.into_inner(),
             count,
             version,
+            hash_algorithm,
+            offset_positions: OnceCell::new(),
         })
     }
 
     pub fn find_offset(&self, short_id: &ShortId) -> Result<(u64, Id), FindIndexOffsetError> {
-        let fan_out = self.fan_out();
+        let fan_out = self.fan_out()?;
         let first_byte = short_id.first_byte() as usize;
         let index_end = fan_out[first_byte].get() as usize;
         let index_start = match first_byte.checked_sub(1) {
@@ -151,101 +227,216 @@ impl IndexFile {
             None => 0,
         };
 
-        fn binary_search<'a, T: Entry>(
-            entries: &'a [T],
-            short_id: &ShortId,
-        ) -> Result<(usize, &'a T), FindIndexOffsetError> {
-            match entries.binary_search_by(|entry| entry.id().cmp_short(short_id)) {
-                Ok(index) => Ok((index, &entries[index])),
-                Err(index) => {
-                    let mut matches = entries[index..]
-                        .iter()
-                        .take_while(|entry| entry.id().starts_with(short_id));
-                    let entry = matches
-                        .next()
-                        .ok_or_else(|| FindIndexOffsetError::NotFound)?;
-                    if matches.next().is_some() {
-                        return Err(FindIndexOffsetError::Ambiguous);
-                    }
-                    Ok((index, entry))
-                }
+        let entries = self
+            .entries()
+            .filter(|_| index_start <= index_end && index_end <= self.count)
+            .ok_or(FindIndexOffsetError::read_index_file("invalid offset"))?;
+        let entry_id = |index: usize| self.entry_id(entries, index);
+
+        // Find the first entry which is not less than the short id. Any
+        // matching entries follow it.
+        let (mut low, mut high) = (index_start, index_end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if entry_id(mid) < short_id.as_bytes() {
+                low = mid + 1;
+            } else {
+                high = mid;
             }
         }
 
-        let (offset, id) = match self.version {
-            Version::V1 => {
-                let (_, entry) = binary_search(self.entries_v1(index_start..index_end)?, short_id)?;
-                (u64::from(entry.offset.get()), entry.id)
-            }
-            Version::V2 => {
-                let (index, entry) =
-                    binary_search(self.entries_v2(index_start..index_end)?, short_id)?;
-                let (small_offsets, large_offsets) = self.offsets();
-                let small_offset = small_offsets[index_start + index].get();
-                let offset = if (small_offset & 0x80000000) == 0 {
-                    u64::from(small_offsets[index_start + index].get())
-                } else {
-                    let large_offset_index = usize::try_from(small_offset & 0x7fffffff)
-                        .map_err(|_| FindIndexOffsetError::read_index_file("invalid offset"))?;
-                    large_offsets
-                        .get(large_offset_index)
-                        .ok_or(FindIndexOffsetError::read_index_file("invalid offset"))?
-                        .get()
-                };
-                (offset, entry.id)
-            }
+        let matches =
+            |index: usize| index < index_end && entry_id(index).starts_with(short_id.as_bytes());
+        if !matches(low) {
+            return Err(FindIndexOffsetError::NotFound);
+        }
+        if matches(low + 1) {
+            return Err(FindIndexOffsetError::Ambiguous);
+        }
+        let id = Id::from_bytes(entry_id(low));
+
+        Ok((self.offset_at(entries, low)?, id))
+    }
+
+    /// The CRC32 of the packed representation of the object at `offset`, as
+    /// stored in a version 2 index. Returns `None` for version 1 indexes, which
+    /// do not store CRCs, or if there is no object at `offset`.
+    pub fn crc32_at_offset(&self, offset: u64) -> Result<Option<u32>, FindIndexOffsetError> {
+        if self.version != Version::V2 {
+            return Ok(None);
+        }
+
+        let offset_positions = self.offset_positions()?;
+        let position = match offset_positions.binary_search_by_key(&offset, |&(offset, _)| offset) {
+            Ok(index) => offset_positions[index].1,
+            Err(_) => return Ok(None),
         };
 
-        Ok((offset, id))
+        // The CRC table follows the ids.
+        let start = self.count * self.hash_algorithm.digest_len() + position * 4;
+        let crc = self
+            .data()
+            .get(IndexFile::FAN_OUT_LEN..)
+            .and_then(|data| data.get(start..(start + 4)))
+            .ok_or(FindIndexOffsetError::read_index_file("invalid crc table"))?;
+        Ok(Some(u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]])))
+    }
+
+    /// The ids of every object in the index, in sorted order.
+    pub fn ids(&self) -> Result<impl Iterator<Item = Id> + '_, ReadIndexFileError> {
+        self.entries()
+            .ok_or(ReadIndexFileError::Other("invalid entries"))?;
+        Ok((0..self.count).filter_map(move |index| self.id_at(index)))
+    }
+
+    /// The id of the entry at position `index` in sorted order, or `None` if
+    /// `index` is out of bounds.
+    pub fn id_at(&self, index: usize) -> Option<Id> {
+        if index >= self.count {
+            return None;
+        }
+        Some(Id::from_bytes(self.entry_id(self.entries()?, index)))
     }
 
     pub fn count(&self) -> u32 {
         self.count as u32
     }
 
-    fn fan_out(&self) -> &[U32<NetworkEndian>] {
-        LayoutVerified::new_slice(&self.data()[..IndexFile::FAN_OUT_LEN])
-            .unwrap()
-            .into_slice()
+    /// The version of the index file format, which is either 1 or 2.
+    pub fn version(&self) -> u32 {
+        match self.version {
+            Version::V1 => 1,
+            Version::V2 => 2,
+        }
+    }
+
+    /// The offset of the object following the one at `offset` in the pack, or
+    /// `None` if it is the last object.
+    pub fn next_offset(&self, offset: u64) -> Result<Option<u64>, FindIndexOffsetError> {
+        let offset_positions = self.offset_positions()?;
+        let index = match offset_positions.binary_search_by_key(&offset, |&(offset, _)| offset) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        };
+        Ok(offset_positions.get(index).map(|&(offset, _)| offset))
     }
 
-    fn entries_v1(&self, range: Range<usize>) -> Result<&[EntryV1], FindIndexOffsetError> {
-        Ok(LayoutVerified::<_, [EntryV1]>::new_slice(self.entries())
-            .unwrap()
-            .into_slice()
-            .get(range)
-            .ok_or(FindIndexOffsetError::read_index_file("invalid offset"))?)
+    /// The offset of the entry at position `index` in sorted order.
+    pub fn offset_at_position(&self, index: usize) -> Result<u64, FindIndexOffsetError> {
+        let entries = self
+            .entries()
+            .filter(|_| index < self.count)
+            .ok_or(FindIndexOffsetError::read_index_file("invalid entries"))?;
+        self.offset_at(entries, index)
     }
 
-    fn entries_v2(&self, range: Range<usize>) -> Result<&[EntryV2], FindIndexOffsetError> {
-        Ok(LayoutVerified::<_, [EntryV2]>::new_slice(self.entries())
-            .unwrap()
-            .into_slice()
-            .get(range)
-            .ok_or(FindIndexOffsetError::read_index_file("invalid offset"))?)
+    /// The position in sorted order of the entry at `offset`, or `None` if no
+    /// entry has that offset.
+    pub fn position_at_offset(&self, offset: u64) -> Result<Option<usize>, FindIndexOffsetError> {
+        let offset_positions = self.offset_positions()?;
+        Ok(offset_positions
+            .binary_search_by_key(&offset, |&(offset, _)| offset)
+            .ok()
+            .map(|index| offset_positions[index].1))
     }
 
-    fn entries(&self) -> &[u8] {
-        let data = self.data();
-        &data[IndexFile::FAN_OUT_LEN..][..(self.count * self.version.entry_len())]
+    fn offset_positions(&self) -> Result<&[(u64, usize)], FindIndexOffsetError> {
+        let offset_positions = self
+            .offset_positions
+            .get_or_try_init(|| self.build_offset_positions())?;
+        Ok(offset_positions)
     }
 
-    fn offsets(&self) -> (&[U32<NetworkEndian>], &[U64<NetworkEndian>]) {
-        debug_assert_eq!(self.version, Version::V2);
+    fn build_offset_positions(&self) -> Result<Vec<(u64, usize)>, FindIndexOffsetError> {
+        let entries = self
+            .entries()
+            .ok_or(FindIndexOffsetError::read_index_file("invalid entries"))?;
+        let mut offset_positions = (0..self.count)
+            .map(|position| Ok((self.offset_at(entries, position)?, position)))
+            .collect::<Result<Vec<_>, FindIndexOffsetError>>()?;
+        offset_positions.sort_unstable();
+        Ok(offset_positions)
+    }
+
+    // The lengths of each section are checked in `parse`, but the accessors below
+    // return errors rather than panicking if they are inconsistent.
 
-        let data = &self.data()[IndexFile::FAN_OUT_LEN..];
-        let start = self.count * (IndexFile::ENTRY_LEN_V2 + 4);
-        let mid = start + self.count * 4;
-        let end = data.len() - IndexFile::TRAILER_LEN;
+    fn fan_out(&self) -> Result<&[U32<NetworkEndian>], FindIndexOffsetError> {
+        slice(self.data().get(..IndexFile::FAN_OUT_LEN))
+            .ok_or(FindIndexOffsetError::read_index_file("invalid fan out"))
+    }
+
+    // The table of entries, which is always `count` entries long.
+    fn entries(&self) -> Option<&[u8]> {
+        let len = self
+            .count
+            .checked_mul(self.version.entry_len(self.hash_algorithm))?;
+        self.data().get(IndexFile::FAN_OUT_LEN..)?.get(..len)
+    }
 
-        (
-            LayoutVerified::new_slice(&data[start..mid])
-                .unwrap()
-                .into_slice(),
-            LayoutVerified::new_slice(&data[mid..end])
-                .unwrap()
-                .into_slice(),
-        )
+    // The id of the entry at `index` in the table returned by `entries`.
+    fn entry_id<'a>(&self, entries: &'a [u8], index: usize) -> &'a [u8] {
+        let id_len = self.hash_algorithm.digest_len();
+        let entry_len = self.version.entry_len(self.hash_algorithm);
+        &entries[index * entry_len..][(entry_len - id_len)..entry_len]
+    }
+
+    // The offset of the entry at `index` in the table returned by `entries`.
+    fn offset_at(&self, entries: &[u8], index: usize) -> Result<u64, FindIndexOffsetError> {
+        match self.version {
+            Version::V1 => {
+                let entry = &entries[index * self.version.entry_len(self.hash_algorithm)..];
+                Ok(u64::from(u32::from_be_bytes([
+                    entry[0], entry[1], entry[2], entry[3],
+                ])))
+            }
+            Version::V2 => {
+                let (small_offsets, large_offsets) = self.offsets()?;
+                let small_offset = small_offsets
+                    .get(index)
+                    .ok_or(FindIndexOffsetError::read_index_file("invalid offset"))?
+                    .get();
+                if (small_offset & 0x80000000) == 0 {
+                    Ok(u64::from(small_offset))
+                } else {
+                    let large_offset_index = usize::try_from(small_offset & 0x7fffffff)
+                        .map_err(|_| FindIndexOffsetError::read_index_file("invalid offset"))?;
+                    Ok(large_offsets
+                        .get(large_offset_index)
+                        .ok_or(FindIndexOffsetError::read_index_file("invalid offset"))?
+                        .get())
+                }
+            }
+        }
+    }
+
+    fn offsets(&self) -> Result<Offsets<'_>, FindIndexOffsetError> {
+        debug_assert_eq!(self.version, Version::V2);
+
+        let invalid = || FindIndexOffsetError::read_index_file("invalid offset table");
+
+        let data = self
+            .data()
+            .get(IndexFile::FAN_OUT_LEN..)
+            .ok_or_else(invalid)?;
+        let start = self
+            .count
+            .checked_mul(self.version.entry_len(self.hash_algorithm) + 4)
+            .ok_or_else(invalid)?;
+        let mid = self
+            .count
+            .checked_mul(4)
+            .and_then(|len| start.checked_add(len))
+            .ok_or_else(invalid)?;
+        let end = data
+            .len()
+            .checked_sub(IndexFile::trailer_len(self.hash_algorithm))
+            .ok_or_else(invalid)?;
+
+        Ok((
+            slice(data.get(start..mid)).ok_or_else(invalid)?,
+            slice(data.get(mid..end)).ok_or_else(invalid)?,
+        ))
     }
 
     fn data(&self) -> &[u8] {
@@ -255,44 +446,26 @@ impl IndexFile {
         }
     }
 
-    pub fn id(&self) -> Id {
-        let pos = self.data.len() - IndexFile::TRAILER_LEN;
-        Id::from_bytes(&self.data[pos..][..ID_LEN])
+    // The trailer is the id of the pack file followed by the checksum of the index.
+    fn trailer_len(hash_algorithm: HashAlgorithm) -> usize {
+        hash_algorithm.digest_len() * 2
     }
 
-    // TODO: check this
-    #[allow(unused)]
-    fn crc(&self) -> Id {
-        let pos = self.data.len() - IndexFile::TRAILER_LEN + ID_LEN;
-        Id::from_bytes(&self.data[pos..][..ID_LEN])
+    pub fn id(&self) -> Id {
+        let pos = self.data.len() - IndexFile::trailer_len(self.hash_algorithm);
+        Id::from_bytes(&self.data[pos..][..self.hash_algorithm.digest_len()])
     }
 }
 
 impl Version {
-    fn entry_len(&self) -> usize {
+    fn entry_len(&self, hash_algorithm: HashAlgorithm) -> usize {
         match self {
-            Version::V1 => IndexFile::ENTRY_LEN_V1,
-            Version::V2 => IndexFile::ENTRY_LEN_V2,
+            Version::V1 => 4 + hash_algorithm.digest_len(),
+            Version::V2 => hash_algorithm.digest_len(),
         }
     }
 }
 
-trait Entry {
-    fn id(&self) -> &Id;
-}
-
-impl Entry for EntryV1 {
-    fn id(&self) -> &Id {
-        &self.id
-    }
-}
-
-impl Entry for EntryV2 {
-    fn id(&self) -> &Id {
-        &self.id
-    }
-}
-
 impl fmt::Debug for IndexFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("IndexFile")
@@ -303,25 +476,32 @@ impl fmt::Debug for IndexFile {
     }
 }
 
+// Reinterpret bytes as a slice of `T`, if they have a valid length.
+fn slice<T: FromBytes>(bytes: Option<&[u8]>) -> Option<&[T]> {
+    Some(LayoutVerified::new_slice(bytes?)?.into_slice())
+}
+
 impl FindIndexOffsetError {
-    fn read_index_file(message: &'static str) -> Self {
+    pub(in crate::object::database::packed) fn read_index_file(message: &'static str) -> Self {
         FindIndexOffsetError::ReadIndexFile(ReadIndexFileError::Other(message))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::mem::{align_of, size_of};
     use std::str::FromStr;
 
+    use proptest::{arbitrary::any, collection::vec, proptest};
+
     use super::*;
+    use crate::object::ID_LEN;
 
     #[test]
-    fn test_entry_layout() {
-        assert_eq!(size_of::<EntryV1>(), IndexFile::ENTRY_LEN_V1);
-        assert_eq!(align_of::<EntryV1>(), 1);
-        assert_eq!(size_of::<EntryV2>(), IndexFile::ENTRY_LEN_V2);
-        assert_eq!(align_of::<EntryV2>(), 1);
+    fn test_entry_len() {
+        assert_eq!(Version::V1.entry_len(HashAlgorithm::Sha1), 24);
+        assert_eq!(Version::V2.entry_len(HashAlgorithm::Sha1), 20);
+        assert_eq!(Version::V1.entry_len(HashAlgorithm::Sha256), 36);
+        assert_eq!(Version::V2.entry_len(HashAlgorithm::Sha256), 32);
     }
 
     fn id(s: &str) -> Id {
@@ -408,6 +588,16 @@ mod tests {
             .find_offset(&ShortId::from_str("4048").unwrap())
             .unwrap_err()
             .is_not_found());
+
+        assert_eq!(
+            index.id_at(0),
+            Some(id("2057bab324290cc76e3669cd24ff7345e907fd13"))
+        );
+        assert_eq!(
+            index.id_at(2),
+            Some(id("4046d56282d07200068541199583f49c65f707f7"))
+        );
+        assert_eq!(index.id_at(3), None);
     }
 
     #[test]
@@ -479,5 +669,178 @@ mod tests {
             .find_offset(&ShortId::from_str("4048").unwrap())
             .unwrap_err()
             .is_not_found());
+
+        assert_eq!(
+            index.id_at(0),
+            Some(id("2057bab324290cc76e3669cd24ff7345e907fd13"))
+        );
+        assert_eq!(
+            index.id_at(2),
+            Some(id("4046d56282d07200068541199583f49c65f707f7"))
+        );
+        assert_eq!(index.id_at(3), None);
+    }
+
+    #[test]
+    fn from_entries() {
+        let pack_id = id("ea0e0aa8f197e86ba6d2c2203e280b26ecbadb76");
+        let mut entries = vec![
+            (id("4046d56282d07200068541199583f49c65f707f7"), 0x61),
+            (id("2057bab324290cc76e3669cd24ff7345e907fd13"), 0x24),
+            (
+                id("4046b3b7c67ec0dedab9c5952d630b241eebf820"),
+                0x1_0000_0042,
+            ),
+        ];
+
+        let built = IndexFile::from_entries(&mut entries, pack_id);
+        let index = IndexFile::parse(Parser::new(built.data)).unwrap();
+
+        assert_eq!(index.count, 3);
+        assert_eq!(index.id(), pack_id);
+        assert_eq!(
+            index
+                .find_offset(&short("2057bab324290cc76e3669cd24ff7345e907fd13"))
+                .unwrap(),
+            (0x24, id("2057bab324290cc76e3669cd24ff7345e907fd13"))
+        );
+        assert_eq!(
+            index
+                .find_offset(&short("4046b3b7c67ec0dedab9c5952d630b241eebf820"))
+                .unwrap(),
+            (
+                0x1_0000_0042,
+                id("4046b3b7c67ec0dedab9c5952d630b241eebf820")
+            )
+        );
+        assert_eq!(
+            index
+                .find_offset(&short("4046d56282d07200068541199583f49c65f707f7"))
+                .unwrap(),
+            (0x61, id("4046d56282d07200068541199583f49c65f707f7"))
+        );
+        assert!(index
+            .find_offset(&short("4048"))
+            .unwrap_err()
+            .is_not_found());
+    }
+
+    #[test]
+    fn write_v2() {
+        let pack_id = id("ea0e0aa8f197e86ba6d2c2203e280b26ecbadb76");
+        let entries = [
+            (
+                id("4046d56282d07200068541199583f49c65f707f7"),
+                0x61,
+                0x1111_1111,
+            ),
+            (
+                id("2057bab324290cc76e3669cd24ff7345e907fd13"),
+                0x8000_0000,
+                0x2222_2222,
+            ),
+            (
+                id("4046b3b7c67ec0dedab9c5952d630b241eebf820"),
+                0x1_0000_0042,
+                0x3333_3333,
+            ),
+            (
+                id("ff46b3b7c67ec0dedab9c5952d630b241eebf820"),
+                0x24,
+                0x4444_4444,
+            ),
+        ];
+
+        let mut bytes = Vec::new();
+        IndexFile::write(&mut bytes, &entries, pack_id).unwrap();
+
+        // The CRC32 table follows the header, fan out and ids, in sorted order.
+        let crc_start = IndexFile::HEADER_LEN + IndexFile::FAN_OUT_LEN + entries.len() * ID_LEN;
+        assert_eq!(
+            &bytes[crc_start..][..16],
+            b"\x22\x22\x22\x22\x33\x33\x33\x33\x11\x11\x11\x11\x44\x44\x44\x44"
+        );
+        // The trailer is the pack id followed by the checksum of the rest of the index.
+        let checksum_start = bytes.len() - ID_LEN;
+        assert_eq!(
+            &bytes[checksum_start..],
+            HashAlgorithm::Sha1
+                .hash(&bytes[..checksum_start])
+                .as_bytes()
+        );
+
+        let index = IndexFile::parse(Parser::new(bytes.into())).unwrap();
+        assert_eq!(index.count, 4);
+        assert_eq!(index.version, Version::V2);
+        assert_eq!(index.id(), pack_id);
+        for &(id, offset, crc) in &entries {
+            assert_eq!(index.find_offset(&ShortId::from(id)).unwrap(), (offset, id));
+            assert_eq!(index.crc32_at_offset(offset).unwrap(), Some(crc));
+        }
+        assert_eq!(index.crc32_at_offset(0x25).unwrap(), None);
+        assert_eq!(index.next_offset(0x24).unwrap(), Some(0x61));
+        assert_eq!(index.next_offset(0x25).unwrap(), Some(0x61));
+        assert_eq!(index.next_offset(0x8000_0000).unwrap(), Some(0x1_0000_0042));
+        assert_eq!(index.next_offset(0x1_0000_0042).unwrap(), None);
+        assert_eq!(index.ids().unwrap().collect::<Vec<_>>(), {
+            let mut ids: Vec<_> = entries.iter().map(|&(id, _, _)| id).collect();
+            ids.sort();
+            ids
+        });
+    }
+
+    // Exercise every accessor of an index which parsed successfully.
+    fn read_index(bytes: Vec<u8>) {
+        if let Ok(index) = IndexFile::parse(Parser::new(bytes.into())) {
+            index.id();
+            if let Ok(ids) = index.ids() {
+                ids.count();
+            }
+            for byte in 0..=255 {
+                index
+                    .find_offset(&ShortId::from(Id::from_bytes(&[byte; ID_LEN])))
+                    .ok();
+                index.find_offset(&short(&format!("{:02x}00", byte))).ok();
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_index_does_not_panic() {
+        let mut entries = (0..16u8)
+            .map(|i| (Id::from_hash(&[i]), u64::from(i) << 28))
+            .collect();
+        let index = IndexFile::from_entries(&mut entries, Id::default());
+
+        for len in 0..index.data.len() {
+            read_index(index.data[..len].to_vec());
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn garbage_index_does_not_panic(bytes in vec(any::<u8>(), ..2000)) {
+            read_index(bytes);
+        }
+
+        #[test]
+        fn garbage_entries_do_not_panic(
+            v2 in any::<bool>(),
+            count in 0..8u32,
+            tail in vec(any::<u8>(), ..400),
+        ) {
+            let mut bytes = Vec::new();
+            if v2 {
+                bytes.extend(b"\xff\x74\x4f\x63");
+                bytes.extend(b"\x00\x00\x00\x02");
+            }
+            for i in 0..IndexFile::FAN_OUT_COUNT {
+                let n = if i < 0x80 { count / 2 } else { count };
+                bytes.extend(&n.to_be_bytes());
+            }
+            bytes.extend(tail);
+
+            read_index(bytes);
+        }
     }
 }
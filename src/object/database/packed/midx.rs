@@ -0,0 +1,461 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use byteorder::NetworkEndian;
+use bytes::Bytes;
+use zerocopy::byteorder::{U32, U64};
+use zerocopy::{FromBytes, LayoutVerified};
+
+use crate::object::database::packed::index::{FindIndexOffsetError, ReadIndexFileError};
+use crate::object::{HashAlgorithm, Id, ShortId};
+
+/// The name of the multi-pack-index file in the pack directory.
+pub(in crate::object::database::packed) const MULTI_PACK_INDEX_FILE: &str = "multi-pack-index";
+
+/// A multi-pack-index, as written by `git multi-pack-index write`, which maps
+/// object ids to their offset in one of several packs.
+pub(in crate::object::database::packed) struct MultiPackIndex {
+    data: Bytes,
+    count: usize,
+    hash_algorithm: HashAlgorithm,
+    // The names of the index files of each pack, in sorted order. Entries
+    // refer to packs by their position in this list.
+    pack_names: Vec<String>,
+    fan_out: Range<usize>,
+    ids: Range<usize>,
+    offsets: Range<usize>,
+    large_offsets: Range<usize>,
+}
+
+impl MultiPackIndex {
+    const SIGNATURE: &'static [u8] = b"MIDX";
+    const HEADER_LEN: usize = 12;
+    const CHUNK_ENTRY_LEN: usize = 12;
+    const FAN_OUT_COUNT: usize = 256;
+    const FAN_OUT_LEN: usize = MultiPackIndex::FAN_OUT_COUNT * 4;
+    const OFFSET_ENTRY_LEN: usize = 8;
+
+    const PACK_NAMES: [u8; 4] = *b"PNAM";
+    const OID_FAN_OUT: [u8; 4] = *b"OIDF";
+    const OID_LOOKUP: [u8; 4] = *b"OIDL";
+    const OBJECT_OFFSETS: [u8; 4] = *b"OOFF";
+    const LARGE_OFFSETS: [u8; 4] = *b"LOFF";
+
+    pub fn open(path: PathBuf, hash_algorithm: HashAlgorithm) -> Result<Self, ReadIndexFileError> {
+        let bytes = Bytes::from(fs_err::read(path)?);
+        MultiPackIndex::parse(bytes, hash_algorithm)
+    }
+
+    fn parse(data: Bytes, hash_algorithm: HashAlgorithm) -> Result<Self, ReadIndexFileError> {
+        let header = data
+            .get(..MultiPackIndex::HEADER_LEN)
+            .ok_or(ReadIndexFileError::Other("file is too short"))?;
+        if &header[..4] != MultiPackIndex::SIGNATURE {
+            return Err(ReadIndexFileError::Other(
+                "the signature of the multi-pack-index is invalid",
+            ));
+        }
+        if header[4] != 1 {
+            return Err(ReadIndexFileError::UnknownVersion(u32::from(header[4])));
+        }
+        let expected_hash_version = match hash_algorithm {
+            HashAlgorithm::Sha1 => 1,
+            HashAlgorithm::Sha256 => 2,
+        };
+        if header[5] != expected_hash_version {
+            return Err(ReadIndexFileError::Other(
+                "the multi-pack-index uses a different hash algorithm",
+            ));
+        }
+        let chunk_count = usize::from(header[6]);
+        if header[7] != 0 {
+            return Err(ReadIndexFileError::Other(
+                "incremental multi-pack-indexes are not supported",
+            ));
+        }
+        let pack_count = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let pack_count =
+            usize::try_from(pack_count).or(Err(ReadIndexFileError::Other("invalid pack count")))?;
+
+        // The chunk table has an extra entry with an id of zero, marking the
+        // end of the last chunk.
+        let chunk_table = data
+            .get(MultiPackIndex::HEADER_LEN..)
+            .and_then(|data| data.get(..(chunk_count + 1) * MultiPackIndex::CHUNK_ENTRY_LEN))
+            .ok_or(ReadIndexFileError::Other("file is too short"))?;
+        let chunks_start = MultiPackIndex::HEADER_LEN + chunk_table.len();
+        let chunks_end = data
+            .len()
+            .checked_sub(hash_algorithm.digest_len())
+            .ok_or(ReadIndexFileError::Other("file is too short"))?;
+
+        let mut chunks = Vec::with_capacity(chunk_count);
+        let mut prev_offset = chunks_start;
+        for (index, entry) in chunk_table
+            .chunks_exact(MultiPackIndex::CHUNK_ENTRY_LEN)
+            .enumerate()
+        {
+            let mut offset = [0; 8];
+            offset.copy_from_slice(&entry[4..]);
+            let offset = usize::try_from(u64::from_be_bytes(offset))
+                .or(Err(ReadIndexFileError::Other("invalid chunk offset")))?;
+            if offset < prev_offset || offset > chunks_end {
+                return Err(ReadIndexFileError::Other("invalid chunk offset"));
+            }
+            if let Some((_, range)) = chunks.last_mut() {
+                *range = prev_offset..offset;
+            }
+            if index < chunk_count {
+                let mut id = [0; 4];
+                id.copy_from_slice(&entry[..4]);
+                chunks.push((id, offset..offset));
+            }
+            prev_offset = offset;
+        }
+
+        let chunk = |id: [u8; 4]| {
+            chunks
+                .iter()
+                .find(|(chunk_id, _)| *chunk_id == id)
+                .map(|(_, range)| range.clone())
+        };
+        let missing = || ReadIndexFileError::Other("the multi-pack-index is missing a chunk");
+
+        let fan_out = chunk(MultiPackIndex::OID_FAN_OUT).ok_or_else(missing)?;
+        let ids = chunk(MultiPackIndex::OID_LOOKUP).ok_or_else(missing)?;
+        let offsets = chunk(MultiPackIndex::OBJECT_OFFSETS).ok_or_else(missing)?;
+        let pack_names = chunk(MultiPackIndex::PACK_NAMES).ok_or_else(missing)?;
+        let large_offsets = chunk(MultiPackIndex::LARGE_OFFSETS).unwrap_or(0..0);
+
+        if fan_out.len() != MultiPackIndex::FAN_OUT_LEN {
+            return Err(ReadIndexFileError::Other("invalid fan out"));
+        }
+        let mut count = 0;
+        for n in slice::<U32<NetworkEndian>>(&data[fan_out.clone()]).unwrap() {
+            if n.get() < count {
+                return Err(ReadIndexFileError::Other("the fan out is not monotonic"));
+            }
+            count = n.get();
+        }
+        let count =
+            usize::try_from(count).or(Err(ReadIndexFileError::Other("invalid index count")))?;
+
+        if Some(ids.len()) != count.checked_mul(hash_algorithm.digest_len())
+            || Some(offsets.len()) != count.checked_mul(MultiPackIndex::OFFSET_ENTRY_LEN)
+            || large_offsets.len() % 8 != 0
+        {
+            return Err(ReadIndexFileError::Other("invalid chunk length"));
+        }
+
+        // The pack names are null-terminated, and may be followed by padding.
+        let pack_names = data[pack_names]
+            .split(|&b| b == 0)
+            .take(pack_count)
+            .map(|name| {
+                String::from_utf8(name.to_vec())
+                    .map_err(|_| ReadIndexFileError::Other("invalid pack name"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if pack_names.len() != pack_count || pack_names.iter().any(String::is_empty) {
+            return Err(ReadIndexFileError::Other("invalid pack names"));
+        }
+        if pack_names.windows(2).any(|names| names[0] >= names[1]) {
+            return Err(ReadIndexFileError::Other("the pack names are not sorted"));
+        }
+
+        Ok(MultiPackIndex {
+            data,
+            count,
+            hash_algorithm,
+            pack_names,
+            fan_out,
+            ids,
+            offsets,
+            large_offsets,
+        })
+    }
+
+    /// Whether the pack with the given index file name is covered by this
+    /// multi-pack-index.
+    pub fn contains_pack(&self, name: &str) -> bool {
+        self.pack_names
+            .binary_search_by(|pack_name| pack_name.as_str().cmp(name))
+            .is_ok()
+    }
+
+    /// The names of the index files of the packs covered by this
+    /// multi-pack-index.
+    pub fn pack_names(&self) -> impl Iterator<Item = &str> {
+        self.pack_names.iter().map(String::as_str)
+    }
+
+    /// Find an object, returning the name of the index file of the pack which
+    /// contains it, its offset in that pack and its full id.
+    pub fn find_offset(&self, short_id: &ShortId) -> Result<(&str, u64, Id), FindIndexOffsetError> {
+        let fan_out = slice::<U32<NetworkEndian>>(&self.data[self.fan_out.clone()])
+            .ok_or(FindIndexOffsetError::read_index_file("invalid fan out"))?;
+        let first_byte = short_id.first_byte() as usize;
+        let index_end = fan_out[first_byte].get() as usize;
+        let index_start = match first_byte.checked_sub(1) {
+            Some(prev) => fan_out[prev].get() as usize,
+            None => 0,
+        };
+        if index_start > index_end || index_end > self.count {
+            return Err(FindIndexOffsetError::read_index_file("invalid offset"));
+        }
+
+        // Find the first entry which is not less than the short id. Any
+        // matching entries follow it.
+        let (mut low, mut high) = (index_start, index_end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.id_bytes(mid) < short_id.as_bytes() {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        let matches = |index: usize| {
+            index < index_end && self.id_bytes(index).starts_with(short_id.as_bytes())
+        };
+        if !matches(low) {
+            return Err(FindIndexOffsetError::NotFound);
+        }
+        if matches(low + 1) {
+            return Err(FindIndexOffsetError::Ambiguous);
+        }
+        let id = Id::from_bytes(self.id_bytes(low));
+
+        let (pack, offset) = self.offset_at(low)?;
+        Ok((pack, offset, id))
+    }
+
+    // The id of the entry at `index`, which must be less than `count`.
+    fn id_bytes(&self, index: usize) -> &[u8] {
+        let id_len = self.hash_algorithm.digest_len();
+        &self.data[self.ids.clone()][index * id_len..][..id_len]
+    }
+
+    // The pack name and offset of the entry at `index`, which must be less than `count`.
+    fn offset_at(&self, index: usize) -> Result<(&str, u64), FindIndexOffsetError> {
+        let invalid = || FindIndexOffsetError::read_index_file("invalid offset");
+
+        let entry = slice::<U32<NetworkEndian>>(
+            &self.data[self.offsets.clone()][index * MultiPackIndex::OFFSET_ENTRY_LEN..]
+                [..MultiPackIndex::OFFSET_ENTRY_LEN],
+        )
+        .ok_or_else(invalid)?;
+        let pack = usize::try_from(entry[0].get()).map_err(|_| invalid())?;
+        let pack = self.pack_names.get(pack).ok_or_else(invalid)?;
+
+        let small_offset = entry[1].get();
+        if (small_offset & 0x80000000) == 0 {
+            Ok((pack, u64::from(small_offset)))
+        } else {
+            let large_offset_index =
+                usize::try_from(small_offset & 0x7fffffff).map_err(|_| invalid())?;
+            let large_offsets = slice::<U64<NetworkEndian>>(&self.data[self.large_offsets.clone()])
+                .ok_or_else(invalid)?;
+            Ok((
+                pack,
+                large_offsets
+                    .get(large_offset_index)
+                    .ok_or_else(invalid)?
+                    .get(),
+            ))
+        }
+    }
+}
+
+impl fmt::Debug for MultiPackIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MultiPackIndex")
+            .field("count", &self.count)
+            .field("pack_names", &self.pack_names)
+            .finish()
+    }
+}
+
+// Reinterpret bytes as a slice of `T`, if they have a valid length.
+fn slice<T: FromBytes>(bytes: &[u8]) -> Option<&[T]> {
+    Some(LayoutVerified::new_slice(bytes)?.into_slice())
+}
+
+#[cfg(test)]
+pub(in crate::object::database::packed) mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn id(s: &str) -> Id {
+        Id::from_str(s).unwrap()
+    }
+
+    fn short(s: &str) -> ShortId {
+        ShortId::from_str(s).unwrap()
+    }
+
+    // Write a multi-pack-index for the given sorted pack names and entries of
+    // (id, pack, offset), sorted by id.
+    pub(in crate::object::database::packed) fn write(
+        pack_names: &[&str],
+        entries: &[(Id, u32, u64)],
+    ) -> Bytes {
+        let mut names = Vec::new();
+        for name in pack_names {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        while names.len() % 4 != 0 {
+            names.push(0);
+        }
+
+        let mut fan_out = Vec::new();
+        let mut count = 0;
+        for first_byte in 0..MultiPackIndex::FAN_OUT_COUNT {
+            while count < entries.len() && usize::from(entries[count].0.as_bytes()[0]) == first_byte
+            {
+                count += 1;
+            }
+            fan_out.extend_from_slice(&(count as u32).to_be_bytes());
+        }
+
+        let mut ids = Vec::new();
+        let mut offsets = Vec::new();
+        let mut large_offsets = Vec::new();
+        for &(id, pack, offset) in entries {
+            ids.extend_from_slice(id.as_bytes());
+            offsets.extend_from_slice(&pack.to_be_bytes());
+            let small_offset = match u32::try_from(offset) {
+                Ok(offset) if offset & 0x80000000 == 0 => offset,
+                _ => {
+                    large_offsets.extend_from_slice(&offset.to_be_bytes());
+                    0x80000000 | (large_offsets.len() / 8 - 1) as u32
+                }
+            };
+            offsets.extend_from_slice(&small_offset.to_be_bytes());
+        }
+
+        let chunks = [
+            (MultiPackIndex::PACK_NAMES, names),
+            (MultiPackIndex::OID_FAN_OUT, fan_out),
+            (MultiPackIndex::OID_LOOKUP, ids),
+            (MultiPackIndex::OBJECT_OFFSETS, offsets),
+            (MultiPackIndex::LARGE_OFFSETS, large_offsets),
+        ];
+
+        let mut data = Vec::new();
+        data.extend_from_slice(MultiPackIndex::SIGNATURE);
+        data.extend_from_slice(&[1, 1, chunks.len() as u8, 0]);
+        data.extend_from_slice(&(pack_names.len() as u32).to_be_bytes());
+
+        let mut offset =
+            MultiPackIndex::HEADER_LEN + (chunks.len() + 1) * MultiPackIndex::CHUNK_ENTRY_LEN;
+        for (chunk_id, chunk) in &chunks {
+            data.extend_from_slice(chunk_id);
+            data.extend_from_slice(&(offset as u64).to_be_bytes());
+            offset += chunk.len();
+        }
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(offset as u64).to_be_bytes());
+
+        for (_, chunk) in &chunks {
+            data.extend_from_slice(chunk);
+        }
+        let checksum = HashAlgorithm::Sha1.hash(&data);
+        data.extend_from_slice(checksum.as_bytes());
+        data.into()
+    }
+
+    #[test]
+    fn find_offset() {
+        let midx = MultiPackIndex::parse(
+            write(
+                &["pack-a.idx", "pack-b.idx"],
+                &[
+                    (id("2057bab324290cc76e3669cd24ff7345e907fd13"), 1, 0x24),
+                    (id("4046b3b7c67ec0dedab9c5952d630b241eebf820"), 0, 0x42),
+                    (
+                        id("4046d56282d07200068541199583f49c65f707f7"),
+                        1,
+                        0x1_0000_0000,
+                    ),
+                ],
+            ),
+            HashAlgorithm::Sha1,
+        )
+        .unwrap();
+
+        assert_eq!(midx.pack_names, &["pack-a.idx", "pack-b.idx"]);
+        assert!(midx.contains_pack("pack-a.idx"));
+        assert!(!midx.contains_pack("pack-c.idx"));
+
+        assert_eq!(
+            midx.find_offset(&short("2057bab3")).unwrap(),
+            (
+                "pack-b.idx",
+                0x24,
+                id("2057bab324290cc76e3669cd24ff7345e907fd13")
+            )
+        );
+        assert_eq!(
+            midx.find_offset(&short("4046b3")).unwrap(),
+            (
+                "pack-a.idx",
+                0x42,
+                id("4046b3b7c67ec0dedab9c5952d630b241eebf820")
+            )
+        );
+        assert_eq!(
+            midx.find_offset(&short("4046d5")).unwrap(),
+            (
+                "pack-b.idx",
+                0x1_0000_0000,
+                id("4046d56282d07200068541199583f49c65f707f7")
+            )
+        );
+        assert!(matches!(
+            midx.find_offset(&short("4046")),
+            Err(FindIndexOffsetError::Ambiguous)
+        ));
+        assert!(matches!(
+            midx.find_offset(&short("3046")),
+            Err(FindIndexOffsetError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        let data = write(
+            &["pack-a.idx"],
+            &[(id("2057bab324290cc76e3669cd24ff7345e907fd13"), 0, 0x24)],
+        );
+        assert!(MultiPackIndex::parse(data.clone(), HashAlgorithm::Sha1).is_ok());
+        assert!(MultiPackIndex::parse(data.clone(), HashAlgorithm::Sha256).is_err());
+        assert!(MultiPackIndex::parse(data.slice(..40), HashAlgorithm::Sha1).is_err());
+
+        let mut bad_version = data.to_vec();
+        bad_version[4] = 2;
+        assert!(matches!(
+            MultiPackIndex::parse(bad_version.into(), HashAlgorithm::Sha1),
+            Err(ReadIndexFileError::UnknownVersion(2))
+        ));
+
+        // A reference to a pack which does not exist.
+        let midx = MultiPackIndex::parse(
+            write(
+                &["pack-a.idx"],
+                &[(id("2057bab324290cc76e3669cd24ff7345e907fd13"), 1, 0x24)],
+            ),
+            HashAlgorithm::Sha1,
+        )
+        .unwrap();
+        assert!(matches!(
+            midx.find_offset(&short("2057")),
+            Err(FindIndexOffsetError::ReadIndexFile(_))
+        ));
+    }
+}
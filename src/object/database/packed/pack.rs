@@ -1,31 +1,65 @@
+use std::cmp::Ordering;
 use std::fmt;
 use std::io::{self, Read, Seek, SeekFrom};
 use std::mem::size_of;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use byteorder::NetworkEndian;
 use bytes::Bytes;
-use dashmap::mapref::entry::Entry as DashMapEntry;
-use dashmap::DashMap;
+use flate2::Crc;
 use fs_err::File;
+use memmap::Mmap;
+use once_cell::sync::OnceCell;
 use smallvec::SmallVec;
 use thiserror::Error;
 use zerocopy::byteorder::U32;
 use zerocopy::FromBytes;
 
+use crate::object::database::packed::cache::{ObjectCache, DEFAULT_CACHE_LIMIT};
 use crate::object::database::packed::delta::{apply_delta, DeltaError};
 use crate::object::database::packed::index::{FindIndexOffsetError, IndexFile};
+use crate::object::database::packed::rev::ReverseIndex;
 use crate::object::database::ObjectReader;
-use crate::object::{Id, ObjectHeader, ObjectKind, ParseObjectError, ShortId, ID_LEN};
+use crate::object::{
+    HashAlgorithm, Id, ObjectHeader, ObjectKind, ParseObjectError, ReadObjectError, ShortId,
+};
 use crate::parse;
 
+/// The default maximum length of a delta chain, matching the largest depth
+/// `git pack-objects` will write.
+pub(in crate::object::database::packed) const DEFAULT_MAX_DELTA_DEPTH: usize = 4095;
+
+/// Finds the base of a `RefDelta` which is not in the same pack, returning its
+/// header and contents, or `None` if it does not exist.
+pub(in crate::object::database) type FindBase<'a> =
+    dyn Fn(Id) -> Result<Option<(ObjectHeader, Bytes)>, ReadObjectError> + 'a;
+
+/// A `FindBase` for packs which must be self-contained, such as bundles.
+pub(in crate::object::database) fn no_external_base(
+    _: Id,
+) -> Result<Option<(ObjectHeader, Bytes)>, ReadObjectError> {
+    Ok(None)
+}
+
 pub(in crate::object::database::packed) struct PackFile {
+    path: PathBuf,
+    // The offset of the start of the pack within the file, which is non-zero
+    // for packs embedded in another file such as a bundle.
+    start: u64,
     id: Id,
-    file: Mutex<parse::Buffer<File>>,
-    cache: DashMap<u64, (ObjectHeader, Bytes)>,
+    source: Source,
+    // The offset of the pack id following the last object, relative to `start`.
+    end: u64,
+    cache: Arc<ObjectCache>,
     version: PackFileVersion,
     count: u32,
+    hash_algorithm: HashAlgorithm,
+    max_delta_depth: usize,
+    verify_crc: bool,
+    // The reverse index read from the `.rev` file, if there is a valid one.
+    // This is only read when needed to look up an object by its offset.
+    reverse_index: OnceCell<Option<ReverseIndex>>,
 }
 
 #[derive(Debug, Error)]
@@ -36,6 +70,24 @@ pub(in crate::object::database::packed) enum ReadPackFileError {
     UnknownVersion(u32),
     #[error("cannot parse object type `{0}`")]
     UnknownType(u8),
+    #[error("the delta base object `{base}` is missing")]
+    MissingDeltaBase { base: Id },
+    #[error("failed to read the delta base object `{base}`")]
+    ReadDeltaBase {
+        base: Id,
+        #[source]
+        source: Box<ReadObjectError>,
+    },
+    #[error("the delta chain at pack offset {offset:#x} is longer than the maximum depth of {max_depth}")]
+    DeltaChainTooLong { offset: u64, max_depth: usize },
+    #[error("the CRC32 of the object at pack offset {offset:#x} does not match the index")]
+    CrcMismatch { offset: u64 },
+    #[error("failed to apply a delta to the base object `{base}`")]
+    InvalidDelta {
+        base: Id,
+        #[source]
+        source: DeltaError,
+    },
     #[error("error finding base object offset in pack index file")]
     FindIndexOffset(
         #[from]
@@ -54,8 +106,8 @@ pub(in crate::object::database::packed) enum ReadPackFileError {
         #[source]
         DeltaError,
     ),
-    #[error("{0}")]
-    Other(&'static str),
+    #[error("{message} at pack offset {offset:#x}")]
+    Corrupt { offset: u64, message: &'static str },
     #[error(transparent)]
     Parse(#[from] parse::Error),
     #[error("io error reading pack index file")]
@@ -66,6 +118,20 @@ pub(in crate::object::database::packed) enum ReadPackFileError {
     ),
 }
 
+// The contents of a pack file, which are either read from the file as needed
+// or memory mapped.
+enum Source {
+    File(Mutex<File>),
+    Mmap(Mmap),
+}
+
+// A reader over the contents of a pack file. Reading from the file holds its
+// lock, so the position of the file is not changed by other threads.
+enum Reader<'a> {
+    File(MutexGuard<'a, File>),
+    Mmap(io::Cursor<&'a [u8]>),
+}
+
 #[derive(Debug)]
 enum PackFileVersion {
     V2,
@@ -88,132 +154,625 @@ struct ChainEntry {
     key: u64,
     // The offset of the object data, following the header
     offset: u64,
-    header: ObjectHeader,
+    // The base object
+    base: DeltaBase,
+}
+
+// The object at the end of a delta chain.
+enum ChainBase {
+    Object(ObjectHeader, Bytes),
+    // A base outside the pack, as in a thin pack.
+    External(Id),
+}
+
+// The base of a delta, which is either in the same pack or must be found elsewhere.
+#[derive(Debug, Clone, Copy)]
+enum DeltaBase {
+    Offset(u64),
+    External(Id),
 }
 
 impl PackFile {
     const SIGNATURE: u32 = u32::from_be_bytes(*b"PACK");
 
-    pub fn open(path: PathBuf) -> Result<Self, ReadPackFileError> {
-        let mut file = Mutex::new(parse::Buffer::with_capacity(File::open(path)?, ID_LEN));
-        let buffer = file.get_mut().unwrap();
-        let header = buffer.read_pack_file_header()?;
+    pub fn open(path: PathBuf, hash_algorithm: HashAlgorithm) -> Result<Self, ReadPackFileError> {
+        PackFile::open_at(path, 0, hash_algorithm)
+    }
 
-        if header.signature.get() != PackFile::SIGNATURE {
-            return Err(ReadPackFileError::InvalidSignature);
-        }
+    /// Open a pack which starts at `start` bytes into the file and continues
+    /// until the end of the file. All object offsets are relative to `start`.
+    pub fn open_at(
+        path: PathBuf,
+        start: u64,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self, ReadPackFileError> {
+        let file = File::open(path.clone())?;
+        PackFile::from_file(path, file, start, hash_algorithm)
+    }
 
-        let version = match header.version.get() {
-            2 => PackFileVersion::V2,
-            3 => PackFileVersion::V3,
-            n => return Err(ReadPackFileError::UnknownVersion(n)),
-        };
+    /// Like `open_at`, but reuses an already open handle to the file at `path`.
+    pub fn from_file(
+        path: PathBuf,
+        file: File,
+        start: u64,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self, ReadPackFileError> {
+        PackFile::from_source(path, Source::File(Mutex::new(file)), start, hash_algorithm)
+    }
+
+    /// Open a pack file by memory mapping it, rather than reading it through a
+    /// shared file handle. Objects can then be read concurrently without
+    /// waiting for other threads.
+    pub fn open_mmap(
+        path: PathBuf,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self, ReadPackFileError> {
+        let file = File::open(path.clone())?;
+        // Safety: pack files are never modified once written, so the mapped
+        // memory does not change while it is borrowed.
+        let mmap = unsafe { Mmap::map(file.file())? };
+        PackFile::from_source(path, Source::Mmap(mmap), 0, hash_algorithm)
+    }
 
-        buffer.seek(SeekFrom::End(-(ID_LEN as i64)))?;
-        let id = buffer.read_id()?;
+    fn from_source(
+        path: PathBuf,
+        source: Source,
+        start: u64,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self, ReadPackFileError> {
+        let (header, version, end, id) = {
+            let mut buffer = source.buffer(hash_algorithm);
+            buffer.seek(SeekFrom::Start(start))?;
+            let header = buffer.read_pack_file_header()?;
+
+            if header.signature.get() != PackFile::SIGNATURE {
+                return Err(ReadPackFileError::InvalidSignature);
+            }
+
+            let version = match header.version.get() {
+                2 => PackFileVersion::V2,
+                3 => PackFileVersion::V3,
+                n => return Err(ReadPackFileError::UnknownVersion(n)),
+            };
+
+            let id_len = hash_algorithm.digest_len();
+            let end = buffer.seek(SeekFrom::End(-(id_len as i64)))? - start;
+            (header, version, end, buffer.read_id()?)
+        };
 
         Ok(PackFile {
+            path,
+            start,
             version,
-            cache: DashMap::new(),
+            cache: Arc::new(ObjectCache::new(DEFAULT_CACHE_LIMIT)),
             count: header.count.get(),
-            file,
+            source,
+            end,
             id,
+            hash_algorithm,
+            max_delta_depth: DEFAULT_MAX_DELTA_DEPTH,
+            verify_crc: false,
+            reverse_index: OnceCell::new(),
         })
     }
 
+    /// Set the maximum number of deltas which may be applied to reconstruct
+    /// an object. Reading an object with a longer delta chain fails with
+    /// `ReadPackFileError::DeltaChainTooLong`.
+    pub fn with_max_delta_depth(mut self, max_delta_depth: usize) -> Self {
+        self.max_delta_depth = max_delta_depth;
+        self
+    }
+
+    /// Cache reconstructed objects in `cache`, which may be shared with other
+    /// packs, rather than in a cache of this pack's own.
+    pub fn with_cache(mut self, cache: Arc<ObjectCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Set whether the CRC32 of each object read from the pack is checked
+    /// against the index. Reading an object whose packed data does not match
+    /// fails with `ReadPackFileError::CrcMismatch`.
+    ///
+    /// This requires reading each object twice, so it is disabled by default.
+    /// Only version 2 indexes store CRCs, so objects are not checked for
+    /// version 1 indexes.
+    pub fn with_verify_crc(mut self, verify_crc: bool) -> Self {
+        self.verify_crc = verify_crc;
+        self
+    }
+
+    /// Read an object, reconstructing it if it is a delta. The bases of
+    /// `RefDelta` objects which are not in this pack are found with `find_base`.
     pub fn read_object(
         &self,
         index: &IndexFile,
         offset: u64,
+        find_base: &FindBase<'_>,
     ) -> Result<ObjectReader, ReadPackFileError> {
-        let (chain, mut header, mut base) = self.find_chain(index, offset)?;
-        for entry in chain {
-            let (new_header, new_base) = self.apply_delta(base, entry)?;
-            header = new_header;
-            base = new_base;
+        let (header, data) = self.read_object_data(index, offset, find_base)?;
+        Ok(ObjectReader::from_bytes(header, data))
+    }
+
+    /// Open a reader over an object.
+    ///
+    /// Undeltified objects are decompressed from a separate handle to the
+    /// pack file as they are read, rather than being held in memory. Deltified
+    /// objects must be fully reconstructed, so they are read as in `read_object`.
+    pub fn open_object(
+        &self,
+        index: &IndexFile,
+        offset: u64,
+        find_base: &FindBase<'_>,
+    ) -> Result<ObjectReader, ReadPackFileError> {
+        if let Some((header, data)) = self.cache.get(self.id, offset) {
+            return Ok(ObjectReader::from_bytes(header, data));
         }
 
-        Ok(ObjectReader::from_bytes(header, base))
+        let (header, data_offset) = {
+            let mut buffer = self.buffer();
+            buffer.seek(SeekFrom::Start(self.start + offset))?;
+            let header = buffer.read_pack_object_header(offset)?;
+            let data_offset = offset + buffer.pos() as u64;
+            if self.verify_crc
+                && !matches!(header.kind, ObjectKind::OfsDelta | ObjectKind::RefDelta)
+            {
+                self.check_crc(&mut buffer, index, offset)?;
+            }
+            (header, data_offset)
+        };
+
+        match header.kind {
+            ObjectKind::OfsDelta | ObjectKind::RefDelta => {
+                self.read_object(index, offset, find_base)
+            }
+            _ => {
+                // Memory mapped packs are also read from a separate handle, so
+                // the object is not inflated into memory all at once.
+                let mut file = File::open(self.path.clone())?;
+                file.seek(SeekFrom::Start(self.start + data_offset))?;
+                Ok(ObjectReader::from_file(header, file))
+            }
+        }
     }
 
     pub fn count(&self) -> u32 {
         self.count
     }
 
-    fn find_chain(
-        &self,
-        index: &IndexFile,
-        mut offset: u64,
-    ) -> Result<(Chain, ObjectHeader, Bytes), ReadPackFileError> {
-        let mut chain = Chain::new();
+    /// Build an index for a pack which does not have an index file, by
+    /// hashing every object in the pack.
+    ///
+    /// Undeltified objects are hashed as the pack is scanned, so they are only
+    /// decompressed once. Deltified objects are then reconstructed, using
+    /// `find_base` for bases outside the pack, as in a thin pack. Fails with
+    /// `MissingDeltaBase` if a base cannot be found.
+    pub fn build_index(&self, find_base: &FindBase<'_>) -> Result<IndexFile, ReadPackFileError> {
+        let mut entries = Vec::with_capacity(self.count as usize);
+        let mut pending = Vec::new();
+        for (offset, id) in self.scan_objects()? {
+            match id {
+                Some(id) => entries.push((id, offset)),
+                None => pending.push(offset),
+            }
+        }
+        let mut index = IndexFile::from_entries(&mut entries, self.id);
 
-        let mut buffer = self.file.lock().unwrap();
+        // A delta may refer to a base which is itself a delta stored later in
+        // the pack, so retry any missing bases until no more can be resolved.
+        while !pending.is_empty() {
+            let mut unresolved = Vec::new();
+            let mut missing = None;
+            for &offset in &pending {
+                match self.read_object_data(&index, offset, find_base) {
+                    Ok((header, data)) => {
+                        entries.push((header.hash(self.hash_algorithm, &data), offset));
+                    }
+                    Err(err @ ReadPackFileError::MissingDeltaBase { .. }) => {
+                        unresolved.push(offset);
+                        missing.get_or_insert(err);
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
 
-        loop {
-            let cache_entry = match self.cache.entry(offset) {
-                DashMapEntry::Occupied(entry) => {
-                    return Ok((chain, entry.get().0, entry.get().1.clone()))
+            index = IndexFile::from_entries(&mut entries, self.id);
+            if let Some(err) = missing {
+                if unresolved.len() == pending.len() {
+                    return Err(err);
                 }
-                DashMapEntry::Vacant(entry) => entry,
-            };
+            }
+            pending = unresolved;
+        }
+        Ok(index)
+    }
 
-            buffer.seek(SeekFrom::Start(offset))?;
+    // Read the offset of every object in the pack, in the order they are
+    // stored, along with the id of each undeltified object.
+    fn scan_objects(&self) -> Result<Vec<(u64, Option<Id>)>, ReadPackFileError> {
+        let mut objects = Vec::with_capacity(self.count as usize);
+        let mut offset = PackFileHeader::LEN as u64;
 
-            let header = buffer.read_pack_object_header()?;
+        let mut buffer = self.buffer();
+        buffer.seek(SeekFrom::Start(self.start + offset))?;
 
-            let base_offset = match header.kind {
+        for _ in 0..self.count {
+            let header = buffer.read_pack_object_header(offset)?;
+            match header.kind {
                 ObjectKind::OfsDelta => {
-                    let delta_offset = buffer.read_delta_offset()?;
-                    offset
-                        .checked_sub(delta_offset)
-                        .ok_or(ReadPackFileError::Other("invalid delta offset"))?
+                    buffer.read_delta_offset(offset)?;
                 }
                 ObjectKind::RefDelta => {
-                    let id = buffer.read_delta_reference()?;
-                    let (offset, _) = index.find_offset(&ShortId::from(id))?;
-                    offset
+                    buffer.read_delta_reference()?;
                 }
-                _ => {
-                    let base = buffer.read_exact(header.len)?;
-                    let base = buffer.take_buffer(base);
-                    cache_entry.insert((header, base.clone()));
-                    return Ok((chain, header, base));
+                _ => (),
+            }
+            // The compressed length of the object is not stored, so the only way
+            // to find the next object is to decompress this one.
+            let data = buffer.decompress_to_end()?;
+            let id = match header.kind {
+                ObjectKind::OfsDelta | ObjectKind::RefDelta => None,
+                _ if data.len() != header.len => {
+                    return Err(ReadPackFileError::corrupt(
+                        offset,
+                        "the object length does not match its header",
+                    ))
                 }
+                _ => Some(header.hash(self.hash_algorithm, &data)),
             };
+            objects.push((offset, id));
+
+            offset += buffer.pos() as u64;
+            buffer.discard_observed();
+        }
+
+        Ok(objects)
+    }
+
+    fn read_object_data(
+        &self,
+        index: &IndexFile,
+        offset: u64,
+        find_base: &FindBase<'_>,
+    ) -> Result<(ObjectHeader, Bytes), ReadPackFileError> {
+        // The same buffer is used to read the whole chain, rather than
+        // reallocating it and locking the pack again for each delta.
+        let mut buffer = self.buffer();
+        let (chain, mut header, mut base) = match self.find_chain(&mut buffer, index, offset)? {
+            (chain, ChainBase::Object(header, base)) => (chain, header, base),
+            (chain, ChainBase::External(id)) => {
+                // Release the pack before reading another, which may itself be
+                // waiting to read a base from this pack.
+                drop(buffer);
+                let (header, base) = self.find_external_base(find_base, id)?;
+                buffer = self.buffer();
+                (chain, header, base)
+            }
+        };
+        // The chain is ordered from the requested object to the base, so deltas must be applied in reverse.
+        for entry in chain.into_iter().rev() {
+            let delta_base = entry.base;
+            let (new_header, new_base) = self
+                .apply_delta(&mut buffer, header, base, entry)
+                .map_err(|err| match err {
+                    // Name the base object if it is in the index.
+                    ReadPackFileError::ParseDeltaError(source) => {
+                        let base_id = match delta_base {
+                            DeltaBase::Offset(base_offset) => {
+                                self.object_id_at_offset(index, base_offset)
+                            }
+                            DeltaBase::External(id) => Some(id),
+                        };
+                        match base_id {
+                            Some(base) => ReadPackFileError::InvalidDelta { base, source },
+                            None => ReadPackFileError::ParseDeltaError(source),
+                        }
+                    }
+                    err => err,
+                })?;
+            header = new_header;
+            base = new_base;
+        }
+
+        Ok((header, base))
+    }
+
+    // Find the chain of deltas leading to the object at `offset`, along with
+    // the base object at the end of the chain, or the id of the base if it is
+    // not in this pack.
+    fn find_chain(
+        &self,
+        buffer: &mut parse::Buffer<Reader<'_>>,
+        index: &IndexFile,
+        mut offset: u64,
+    ) -> Result<(Chain, ChainBase), ReadPackFileError> {
+        let start_offset = offset;
+        let mut chain = Chain::new();
 
+        loop {
+            if let Some((header, base)) = self.cache.get(self.id, offset) {
+                return Ok((chain, ChainBase::Object(header, base)));
+            }
+
+            // Objects are only checked when read from the pack, not from the cache.
+            if self.verify_crc {
+                self.check_crc(buffer, index, offset)?;
+            }
+
+            buffer.seek(SeekFrom::Start(self.start + offset))?;
+
+            let header = buffer.read_pack_object_header(offset)?;
+
+            let base = match buffer.read_delta_base(index, offset, header)? {
+                Some(base) => base,
+                None => {
+                    let base = buffer.decompress().read_to_end(header.len)?;
+                    self.cache.insert(self.id, offset, header, base.clone());
+                    return Ok((chain, ChainBase::Object(header, base)));
+                }
+            };
+
+            if chain.len() == self.max_delta_depth {
+                return Err(self.delta_chain_too_long(start_offset));
+            }
             chain.push(ChainEntry {
                 key: offset,
                 offset: offset + buffer.pos() as u64,
-                header,
+                base,
             });
 
-            if base_offset == offset {
-                return Err(ReadPackFileError::Other("loop in deltas"));
+            offset = match base {
+                DeltaBase::Offset(base_offset) => base_offset,
+                DeltaBase::External(id) => return Ok((chain, ChainBase::External(id))),
+            };
+        }
+    }
+
+    fn find_external_base(
+        &self,
+        find_base: &FindBase<'_>,
+        id: Id,
+    ) -> Result<(ObjectHeader, Bytes), ReadPackFileError> {
+        match find_base(id) {
+            Ok(Some(base)) => Ok(base),
+            Ok(None) => Err(ReadPackFileError::MissingDeltaBase { base: id }),
+            Err(err) => Err(ReadPackFileError::ReadDeltaBase {
+                base: id,
+                source: Box::new(err),
+            }),
+        }
+    }
+
+    // Compare the CRC32 of the packed representation of the object at `offset`,
+    // including its header, with the CRC stored in the index. The packed data
+    // extends to the start of the next object, so it can be checked even if it
+    // cannot be decompressed.
+    fn check_crc(
+        &self,
+        buffer: &mut parse::Buffer<Reader<'_>>,
+        index: &IndexFile,
+        offset: u64,
+    ) -> Result<(), ReadPackFileError> {
+        const CHUNK_LEN: u64 = 64 * 1024;
+
+        let expected = match index.crc32_at_offset(offset)? {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+
+        let end = index.next_offset(offset)?.unwrap_or(self.end);
+        let mut remaining = end
+            .checked_sub(offset)
+            .ok_or(ReadPackFileError::corrupt(offset, "invalid object offset"))?;
+
+        buffer.seek(SeekFrom::Start(self.start + offset))?;
+        let mut crc = Crc::new();
+        while remaining != 0 {
+            let len = remaining.min(CHUNK_LEN);
+            let range = buffer.read_exact(len as usize)?;
+            crc.update(&buffer[range]);
+            buffer.discard_observed();
+            remaining -= len;
+        }
+
+        if crc.sum() != expected {
+            return Err(ReadPackFileError::CrcMismatch { offset });
+        }
+        Ok(())
+    }
+
+    /// Read the kind and length of an object without reconstructing it.
+    ///
+    /// The length of a deltified object is stored at the start of its delta,
+    /// and its kind is the kind of the base object at the end of the chain.
+    pub fn read_header(
+        &self,
+        index: &IndexFile,
+        mut offset: u64,
+        find_base: &FindBase<'_>,
+    ) -> Result<ObjectHeader, ReadPackFileError> {
+        let start_offset = offset;
+        let mut depth = 0;
+        let mut len = None;
+
+        let mut buffer = self.buffer();
+
+        loop {
+            if let Some((header, _)) = self.cache.get(self.id, offset) {
+                return Ok(ObjectHeader {
+                    kind: header.kind,
+                    len: len.unwrap_or(header.len),
+                });
+            }
+
+            buffer.seek(SeekFrom::Start(self.start + offset))?;
+
+            let header = buffer.read_pack_object_header(offset)?;
+
+            let base = match buffer.read_delta_base(index, offset, header)? {
+                Some(base) => base,
+                None => {
+                    return Ok(ObjectHeader {
+                        kind: header.kind,
+                        len: len.unwrap_or(header.len),
+                    })
+                }
+            };
+
+            if len.is_none() {
+                len = Some(buffer.decompress().read_delta_header()?.result_len);
             }
-            offset = base_offset;
+
+            if depth == self.max_delta_depth {
+                return Err(self.delta_chain_too_long(start_offset));
+            }
+            depth += 1;
+            offset = match base {
+                DeltaBase::Offset(base_offset) => base_offset,
+                DeltaBase::External(id) => {
+                    drop(buffer);
+                    let (header, _) = self.find_external_base(find_base, id)?;
+                    return Ok(ObjectHeader {
+                        kind: header.kind,
+                        len: len.unwrap_or(header.len),
+                    });
+                }
+            };
         }
     }
 
+    /// Read the id, header and offset of the object at `position` in the order
+    /// of `index`. A deltified object has the header of the object it
+    /// reconstructs.
+    pub fn object_at_position(
+        &self,
+        index: &IndexFile,
+        position: usize,
+        find_base: &FindBase<'_>,
+    ) -> Result<(Id, ObjectHeader, u64), ReadPackFileError> {
+        let id = index
+            .id_at(position)
+            .ok_or(FindIndexOffsetError::read_index_file("invalid entries"))?;
+        let offset = index.offset_at_position(position)?;
+        Ok((id, self.read_header(index, offset, find_base)?, offset))
+    }
+
     fn apply_delta(
         &self,
+        buffer: &mut parse::Buffer<Reader<'_>>,
+        base_header: ObjectHeader,
         base: Bytes,
         delta: ChainEntry,
     ) -> Result<(ObjectHeader, Bytes), ReadPackFileError> {
-        let mut buffer = self.file.lock().unwrap();
-
-        buffer.seek(SeekFrom::Start(delta.offset))?;
-
-        let result = apply_delta(delta.header.kind, &base, &mut buffer.decompress_exact(delta.header.len))?;
+        buffer.seek(SeekFrom::Start(self.start + delta.offset))?;
 
-        Ok(self
-            .cache
-            .insert(delta.key, result.clone())
-            .unwrap_or(result))
+        let (header, data) = apply_delta(base_header.kind, &base, &mut buffer.decompress())?;
+        self.cache.insert(self.id, delta.key, header, data.clone());
+        Ok((header, data))
     }
 
     pub fn id(&self) -> Id {
         self.id
     }
+
+    /// The id of the object at `offset`, or `None` if the index has no object
+    /// at that offset.
+    ///
+    /// Offsets are mapped to positions in the index using the pack's `.rev`
+    /// file if it has one, and otherwise by sorting the offsets in the index.
+    pub fn object_id_at_offset(&self, index: &IndexFile, offset: u64) -> Option<Id> {
+        let position = match self.reverse_index() {
+            Some(reverse_index) => {
+                let (mut low, mut high) = (0, reverse_index.count());
+                loop {
+                    if low >= high {
+                        return None;
+                    }
+                    let mid = low + (high - low) / 2;
+                    let position = reverse_index.position_at(mid)?;
+                    match index.offset_at_position(position).ok()?.cmp(&offset) {
+                        Ordering::Less => low = mid + 1,
+                        Ordering::Greater => high = mid,
+                        Ordering::Equal => break position,
+                    }
+                }
+            }
+            None => index.position_at_offset(offset).ok()??,
+        };
+        index.id_at(position)
+    }
+
+    fn reverse_index(&self) -> Option<&ReverseIndex> {
+        self.reverse_index
+            .get_or_init(|| {
+                // Packs embedded in another file, such as a bundle, have no
+                // reverse index. An invalid reverse index is ignored, since the
+                // mapping can always be rebuilt from the index.
+                if self.start != 0 {
+                    return None;
+                }
+                ReverseIndex::open(
+                    self.path.with_extension("rev"),
+                    self.hash_algorithm,
+                    self.id,
+                    self.count as usize,
+                )
+                .ok()
+            })
+            .as_ref()
+    }
+
+    fn buffer(&self) -> parse::Buffer<Reader<'_>> {
+        self.source.buffer(self.hash_algorithm)
+    }
+
+    fn delta_chain_too_long(&self, offset: u64) -> ReadPackFileError {
+        ReadPackFileError::DeltaChainTooLong {
+            offset,
+            max_depth: self.max_delta_depth,
+        }
+    }
+}
+
+impl Source {
+    fn buffer(&self, hash_algorithm: HashAlgorithm) -> parse::Buffer<Reader<'_>> {
+        let reader = match self {
+            Source::File(file) => Reader::File(file.lock().unwrap()),
+            Source::Mmap(mmap) => Reader::Mmap(io::Cursor::new(mmap)),
+        };
+        parse::Buffer::with_capacity(reader, hash_algorithm.digest_len())
+            .with_hash_algorithm(hash_algorithm)
+    }
+}
+
+impl<'a> Read for Reader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Reader::File(file) => file.read(buf),
+            Reader::Mmap(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl<'a> Seek for Reader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Reader::File(file) => file.seek(pos),
+            Reader::Mmap(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+impl ReadPackFileError {
+    fn corrupt(offset: u64, message: &'static str) -> Self {
+        ReadPackFileError::Corrupt { offset, message }
+    }
+
+    /// Returns true if the pack file no longer exists on disk.
+    pub(in crate::object::database::packed) fn is_not_found(&self) -> bool {
+        matches!(self, ReadPackFileError::Io(err) if err.kind() == io::ErrorKind::NotFound)
+    }
 }
 
 impl PackFileHeader {
@@ -232,7 +791,7 @@ impl<R: Read> parse::Buffer<R> {
         Ok(*parser.parse_struct::<PackFileHeader>()?)
     }
 
-    fn read_pack_object_header(&mut self) -> Result<ObjectHeader, ReadPackFileError> {
+    fn read_pack_object_header(&mut self, offset: u64) -> Result<ObjectHeader, ReadPackFileError> {
         let range = self
             .read_until(ObjectHeader::MAX_PACKED_LEN, |slice| {
                 slice
@@ -240,7 +799,7 @@ impl<R: Read> parse::Buffer<R> {
                     .position(|&byte| byte & 0b1000_0000 == 0)
                     .map(|offset| offset + 1)
             })?
-            .ok_or(ReadPackFileError::Other("invalid object size"))?;
+            .ok_or(ReadPackFileError::corrupt(offset, "invalid object size"))?;
         let parser = &mut self.parser(range);
 
         let mut byte = parser.parse_byte()?;
@@ -260,14 +819,14 @@ impl<R: Read> parse::Buffer<R> {
             byte = parser.parse_byte()?;
             len |= usize::from(byte & 0b0111_1111)
                 .checked_shl(shift)
-                .ok_or(ReadPackFileError::Other("invalid object size"))?;
+                .ok_or(ReadPackFileError::corrupt(offset, "invalid object size"))?;
             shift += 7;
         }
 
         Ok(ObjectHeader { len, kind })
     }
 
-    fn read_delta_offset(&mut self) -> Result<u64, ReadPackFileError> {
+    fn read_delta_offset(&mut self, offset: u64) -> Result<u64, ReadPackFileError> {
         let range = self
             .read_until(ObjectHeader::MAX_DELTA_OFFSET_LEN, |slice| {
                 slice
@@ -275,27 +834,67 @@ impl<R: Read> parse::Buffer<R> {
                     .position(|&byte| byte & 0b1000_0000 == 0)
                     .map(|offset| offset + 1)
             })?
-            .ok_or(ReadPackFileError::Other("invalid delta offset"))?;
+            .ok_or(ReadPackFileError::corrupt(offset, "invalid delta offset"))?;
         let parser = &mut self.parser(range);
 
-        let mut offset: u64 = 0;
+        // Each continuation byte implicitly adds one before shifting, so that
+        // encodings of different lengths never overlap.
+        let mut delta_offset = u64::from(parser.parse_byte()? & 0b0111_1111);
         while parser.remaining() != 0 {
             let byte = parser.parse_byte()?;
-            offset <<= 7;
-            offset += u64::from(byte & 0b0111_1111);
+            delta_offset = delta_offset
+                .checked_add(1)
+                .and_then(|delta_offset| delta_offset.checked_mul(1 << 7))
+                .ok_or(ReadPackFileError::corrupt(offset, "invalid delta offset"))?
+                | u64::from(byte & 0b0111_1111);
         }
 
-        Ok(offset)
+        Ok(delta_offset)
     }
 
     fn read_delta_reference(&mut self) -> Result<Id, ReadPackFileError> {
         Ok(self.read_id()?)
     }
+
+    // If the object is a delta, returns its base object.
+    fn read_delta_base(
+        &mut self,
+        index: &IndexFile,
+        offset: u64,
+        header: ObjectHeader,
+    ) -> Result<Option<DeltaBase>, ReadPackFileError> {
+        let base_offset = match header.kind {
+            ObjectKind::OfsDelta => {
+                let delta_offset = self.read_delta_offset(offset)?;
+                offset
+                    .checked_sub(delta_offset)
+                    .ok_or(ReadPackFileError::corrupt(offset, "invalid delta offset"))?
+            }
+            ObjectKind::RefDelta => {
+                let id = self.read_delta_reference()?;
+                match index.find_offset(&ShortId::from(id)) {
+                    Ok((offset, _)) => offset,
+                    Err(FindIndexOffsetError::NotFound) => {
+                        return Ok(Some(DeltaBase::External(id)))
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        if base_offset == offset {
+            return Err(ReadPackFileError::corrupt(offset, "loop in deltas"));
+        }
+        Ok(Some(DeltaBase::Offset(base_offset)))
+    }
 }
 
 impl fmt::Debug for PackFile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("PackFile")
+            .field("path", &self.path)
+            .field("start", &self.start)
             .field("version", &self.version)
             .finish()
     }
@@ -303,9 +902,21 @@ impl fmt::Debug for PackFile {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
     use bstr::B;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use tempdir::TempDir;
 
     use super::*;
+    use crate::object::ID_LEN;
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
 
     #[cfg(target_pointer_width = "64")]
     #[test]
@@ -313,16 +924,179 @@ mod tests {
         let max_len_header = b"\x9F\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\x0F";
         assert_eq!(max_len_header.len(), ObjectHeader::MAX_PACKED_LEN);
         let mut buffer = parse::Buffer::new(io::Cursor::new(B(max_len_header)));
-        let parsed_header = buffer.read_pack_object_header().unwrap();
+        let parsed_header = buffer.read_pack_object_header(0).unwrap();
         assert_eq!(parsed_header.kind, ObjectKind::Commit);
         assert_eq!(parsed_header.len, usize::MAX);
     }
 
     #[test]
     fn pack_object_header_max_delta_offset_len() {
-        let max_len_header = b"\x81\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF\x7F";
+        let max_len_header = b"\x80\xFE\xFE\xFE\xFE\xFE\xFE\xFE\xFE\x7F";
         assert_eq!(max_len_header.len(), ObjectHeader::MAX_DELTA_OFFSET_LEN);
         let mut buffer = parse::Buffer::new(io::Cursor::new(B(max_len_header)));
-        assert_eq!(buffer.read_delta_offset().unwrap(), u64::MAX);
+        assert_eq!(buffer.read_delta_offset(0).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn pack_object_header_delta_offset() {
+        let mut buffer = parse::Buffer::new(io::Cursor::new(B(b"\x91\x2E")));
+        assert_eq!(
+            buffer.read_delta_offset(0).unwrap(),
+            ((0x11 + 1) << 7) | 0x2E
+        );
+    }
+
+    #[test]
+    fn pack_object_header_delta_offset_overflow() {
+        let header = b"\x80\xFE\xFE\xFE\xFE\xFE\xFE\xFE\xFF\x00";
+        let mut buffer = parse::Buffer::new(io::Cursor::new(B(header)));
+        assert_eq!(
+            buffer.read_delta_offset(0x1234).unwrap_err().to_string(),
+            "invalid delta offset at pack offset 0x1234"
+        );
+    }
+
+    #[test]
+    fn delta_chain_too_long() {
+        let tempdir = TempDir::new("rusty_git_pack_tests").unwrap();
+
+        // A blob followed by a chain of deltas, each copying the previous object.
+        let mut pack = Vec::new();
+        pack.extend_from_slice(b"PACK\x00\x00\x00\x02\x00\x00\x00\x06");
+        let mut offsets = vec![pack.len() as u64];
+        pack.push(0b0011_0001);
+        pack.extend_from_slice(&compress(b"a"));
+        for _ in 0..5 {
+            let offset = pack.len() as u64;
+            pack.push(0b0110_0100);
+            pack.push((offset - offsets.last().unwrap()) as u8);
+            pack.extend_from_slice(&compress(b"\x01\x01\x90\x01"));
+            offsets.push(offset);
+        }
+        pack.extend_from_slice(&[0; ID_LEN]);
+        fs_err::write(tempdir.path().join("pack.pack"), pack).unwrap();
+
+        let index = IndexFile::from_entries(&mut Vec::new(), Id::from_hash(b"pack"));
+        let pack = PackFile::open(tempdir.path().join("pack.pack"), HashAlgorithm::Sha1)
+            .unwrap()
+            .with_max_delta_depth(3);
+
+        match pack.read_header(&index, offsets[5], &no_external_base) {
+            Err(ReadPackFileError::DeltaChainTooLong { offset, max_depth }) => {
+                assert_eq!(offset, offsets[5]);
+                assert_eq!(max_depth, 3);
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        match pack.read_object(&index, offsets[5], &no_external_base) {
+            Err(ReadPackFileError::DeltaChainTooLong { offset, max_depth }) => {
+                assert_eq!(offset, offsets[5]);
+                assert_eq!(max_depth, 3);
+            }
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+
+        let (header, data) = pack
+            .read_object_data(&index, offsets[3], &no_external_base)
+            .unwrap();
+        assert_eq!(header.kind, ObjectKind::Blob);
+        assert_eq!(data, "a");
+    }
+
+    #[test]
+    fn invalid_delta_names_base_object() {
+        let tempdir = TempDir::new("rusty_git_pack_tests").unwrap();
+
+        // A blob followed by a delta which expects a base of the wrong length.
+        let mut pack = Vec::new();
+        pack.extend_from_slice(b"PACK\x00\x00\x00\x02\x00\x00\x00\x02");
+        let base_offset = pack.len() as u64;
+        pack.push(0b0011_0001);
+        pack.extend_from_slice(&compress(b"a"));
+        let delta_offset = pack.len() as u64;
+        pack.push(0b0110_0100);
+        pack.push((delta_offset - base_offset) as u8);
+        pack.extend_from_slice(&compress(b"\x02\x01\x90\x01"));
+        pack.extend_from_slice(&[0; ID_LEN]);
+        fs_err::write(tempdir.path().join("pack.pack"), pack).unwrap();
+
+        let base = Id::from_hash(b"base");
+        let delta = Id::from_hash(b"delta");
+        let pack = PackFile::open(tempdir.path().join("pack.pack"), HashAlgorithm::Sha1).unwrap();
+        let index = IndexFile::from_entries(
+            &mut vec![(base, base_offset), (delta, delta_offset)],
+            pack.id(),
+        );
+
+        assert!(pack.reverse_index().is_none());
+        assert_eq!(pack.object_id_at_offset(&index, base_offset), Some(base));
+        assert_eq!(pack.object_id_at_offset(&index, delta_offset), Some(delta));
+        assert_eq!(pack.object_id_at_offset(&index, delta_offset + 1), None);
+
+        match pack.read_object(&index, delta_offset, &no_external_base) {
+            Err(ReadPackFileError::InvalidDelta {
+                base: id,
+                source: DeltaError::BaseLengthMismatch,
+            }) => assert_eq!(id, base),
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
+
+        // The same mapping is read from a reverse index when there is one.
+        let mut positions: Vec<u32> = vec![0, 1];
+        if delta < base {
+            positions.reverse();
+        }
+        let mut rev = Vec::new();
+        rev.extend_from_slice(b"RIDX\x00\x00\x00\x01\x00\x00\x00\x01");
+        for position in positions {
+            rev.extend_from_slice(&position.to_be_bytes());
+        }
+        rev.extend_from_slice(pack.id().as_bytes());
+        rev.extend_from_slice(&[0; ID_LEN]);
+        fs_err::write(tempdir.path().join("pack.rev"), rev).unwrap();
+
+        let pack = PackFile::open(tempdir.path().join("pack.pack"), HashAlgorithm::Sha1).unwrap();
+        assert!(pack.reverse_index().is_some());
+        assert_eq!(pack.object_id_at_offset(&index, base_offset), Some(base));
+        assert_eq!(pack.object_id_at_offset(&index, delta_offset), Some(delta));
+        assert_eq!(pack.object_id_at_offset(&index, delta_offset + 1), None);
+    }
+
+    #[test]
+    fn missing_ref_delta_base() {
+        let tempdir = TempDir::new("rusty_git_pack_tests").unwrap();
+        let id = Id::from_hash(b"object");
+        let base = Id::from_hash(b"base");
+
+        let mut pack = Vec::new();
+        pack.extend_from_slice(b"PACK\x00\x00\x00\x02\x00\x00\x00\x01");
+        let offset = pack.len() as u64;
+        pack.push(0b0111_0100);
+        pack.extend_from_slice(base.as_bytes());
+        // A delta with an empty base, which inserts a single byte.
+        pack.extend_from_slice(&compress(b"\x00\x01\x01a"));
+        pack.extend_from_slice(&[0; ID_LEN]);
+        fs_err::write(tempdir.path().join("pack.pack"), pack).unwrap();
+
+        // A version 1 index containing only the deltified object.
+        let mut index = Vec::new();
+        for i in 0..=255 {
+            let count: u32 = if i < id.as_bytes()[0] { 0 } else { 1 };
+            index.extend_from_slice(&count.to_be_bytes());
+        }
+        index.extend_from_slice(&(offset as u32).to_be_bytes());
+        index.extend_from_slice(id.as_bytes());
+        index.extend_from_slice(&[0; ID_LEN * 2]);
+        fs_err::write(tempdir.path().join("pack.idx"), index).unwrap();
+
+        let index = IndexFile::open(tempdir.path().join("pack.idx"), HashAlgorithm::Sha1).unwrap();
+        let pack = PackFile::open(tempdir.path().join("pack.pack"), HashAlgorithm::Sha1).unwrap();
+
+        match pack.read_object(&index, offset, &no_external_base) {
+            Err(ReadPackFileError::MissingDeltaBase { base: missing }) => {
+                assert_eq!(missing, base)
+            }
+            result => panic!("unexpected result {:?}", result.map(|_| ())),
+        }
     }
 }
@@ -0,0 +1,157 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
+
+use bytes::Bytes;
+
+use crate::object::database::packed::index::ReadIndexFileError;
+use crate::object::{HashAlgorithm, Id};
+
+/// A reverse index, as written by `git index-pack --rev-index`, which stores
+/// the position in the pack index of each object, ordered by its offset in
+/// the pack.
+pub(in crate::object::database::packed) struct ReverseIndex {
+    data: Bytes,
+    count: usize,
+}
+
+impl ReverseIndex {
+    const SIGNATURE: &'static [u8] = b"RIDX";
+    const HEADER_LEN: usize = 12;
+
+    /// Open the reverse index of a pack, checking that it belongs to the pack
+    /// with id `pack_id` containing `count` objects.
+    pub fn open(
+        path: PathBuf,
+        hash_algorithm: HashAlgorithm,
+        pack_id: Id,
+        count: usize,
+    ) -> Result<Self, ReadIndexFileError> {
+        let bytes = Bytes::from(fs_err::read(path)?);
+        ReverseIndex::parse(bytes, hash_algorithm, pack_id, count)
+    }
+
+    fn parse(
+        data: Bytes,
+        hash_algorithm: HashAlgorithm,
+        pack_id: Id,
+        count: usize,
+    ) -> Result<Self, ReadIndexFileError> {
+        let header = data
+            .get(..ReverseIndex::HEADER_LEN)
+            .ok_or(ReadIndexFileError::Other("file is too short"))?;
+        if &header[..4] != ReverseIndex::SIGNATURE {
+            return Err(ReadIndexFileError::Other(
+                "the signature of the reverse index is invalid",
+            ));
+        }
+        let version = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        if version != 1 {
+            return Err(ReadIndexFileError::UnknownVersion(version));
+        }
+        let expected_hash_version = match hash_algorithm {
+            HashAlgorithm::Sha1 => 1,
+            HashAlgorithm::Sha256 => 2,
+        };
+        if u32::from_be_bytes([header[8], header[9], header[10], header[11]])
+            != expected_hash_version
+        {
+            return Err(ReadIndexFileError::Other(
+                "the reverse index uses a different hash algorithm",
+            ));
+        }
+
+        let trailer_len = hash_algorithm.digest_len() * 2;
+        let expected_len = count
+            .checked_mul(4)
+            .and_then(|len| len.checked_add(ReverseIndex::HEADER_LEN + trailer_len))
+            .ok_or(ReadIndexFileError::Other("invalid index count"))?;
+        if data.len() != expected_len {
+            return Err(ReadIndexFileError::Other(
+                "reverse index is an invalid length",
+            ));
+        }
+
+        let id_pos = data.len() - trailer_len;
+        if Id::from_bytes(&data[id_pos..][..hash_algorithm.digest_len()]) != pack_id {
+            return Err(ReadIndexFileError::Other(
+                "the reverse index is for a different pack",
+            ));
+        }
+
+        Ok(ReverseIndex { data, count })
+    }
+
+    /// The number of objects in the pack.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The position in the pack index of the object which is at position
+    /// `rank` when the objects are sorted by their offset in the pack.
+    pub fn position_at(&self, rank: usize) -> Option<usize> {
+        if rank >= self.count {
+            return None;
+        }
+        let entry = &self.data[ReverseIndex::HEADER_LEN + rank * 4..][..4];
+        usize::try_from(u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]])).ok()
+    }
+}
+
+impl fmt::Debug for ReverseIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReverseIndex")
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(positions: &[u32], pack_id: Id) -> Bytes {
+        let mut data = Vec::new();
+        data.extend_from_slice(ReverseIndex::SIGNATURE);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        for position in positions {
+            data.extend_from_slice(&position.to_be_bytes());
+        }
+        data.extend_from_slice(pack_id.as_bytes());
+        let checksum = HashAlgorithm::Sha1.hash(&data);
+        data.extend_from_slice(checksum.as_bytes());
+        data.into()
+    }
+
+    #[test]
+    fn parse() {
+        let pack_id = Id::from_hash(b"pack");
+        let data = write(&[2, 0, 1], pack_id);
+
+        let reverse_index =
+            ReverseIndex::parse(data.clone(), HashAlgorithm::Sha1, pack_id, 3).unwrap();
+        assert_eq!(reverse_index.count(), 3);
+        assert_eq!(reverse_index.position_at(0), Some(2));
+        assert_eq!(reverse_index.position_at(1), Some(0));
+        assert_eq!(reverse_index.position_at(2), Some(1));
+        assert_eq!(reverse_index.position_at(3), None);
+
+        assert!(ReverseIndex::parse(data.clone(), HashAlgorithm::Sha1, pack_id, 4).is_err());
+        assert!(ReverseIndex::parse(
+            data.clone(),
+            HashAlgorithm::Sha1,
+            Id::from_hash(b"other"),
+            3
+        )
+        .is_err());
+        assert!(ReverseIndex::parse(data.clone(), HashAlgorithm::Sha256, pack_id, 3).is_err());
+
+        let mut bad_version = data.to_vec();
+        bad_version[7] = 2;
+        assert!(matches!(
+            ReverseIndex::parse(bad_version.into(), HashAlgorithm::Sha1, pack_id, 3),
+            Err(ReadIndexFileError::UnknownVersion(2))
+        ));
+    }
+}
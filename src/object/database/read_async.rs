@@ -0,0 +1,61 @@
+use std::io::{Cursor, Read};
+use std::panic;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use flate2::read::ZlibDecoder;
+use tokio::io::AsyncRead;
+
+use crate::object::database::loose::ReadLooseError;
+use crate::object::database::reader::read_body_error;
+use crate::object::database::{ObjectDatabase, ReadError};
+use crate::object::parse::ParseObjectError;
+use crate::object::{Id, ObjectHeader, ReadObjectError};
+use crate::parse;
+
+impl ObjectDatabase {
+    /// Read an object from within a tokio runtime, returning its header and a
+    /// reader over its body.
+    ///
+    /// Loose objects are read with `tokio::fs` and then decompressed on tokio's
+    /// blocking thread pool. Packed objects, and objects in alternate object
+    /// directories, are read with `read_object` on the blocking thread pool,
+    /// which is why the database must be shared through an `Arc`. In both cases
+    /// the whole body is read into memory first.
+    pub async fn read_object_async(
+        self: &Arc<Self>,
+        id: Id,
+    ) -> Result<(ObjectHeader, impl AsyncRead + Unpin + Send), ReadObjectError> {
+        self.check_hash_algorithm(id)?;
+
+        let (header, body) = match self.loose.read_compressed(&id).await {
+            Ok(data) => tokio::task::spawn_blocking(move || decompress(&data))
+                .await
+                .unwrap_or_else(|err| panic::resume_unwind(err.into_panic()))
+                .map_err(|err| ReadObjectError::new(id, err))?,
+            Err(ReadLooseError::NotFound) => {
+                let database = Arc::clone(self);
+                tokio::task::spawn_blocking(move || {
+                    database
+                        .read_object(id)?
+                        .into_bytes()
+                        .map_err(|err| ReadObjectError::new(id, err))
+                })
+                .await
+                .unwrap_or_else(|err| panic::resume_unwind(err.into_panic()))?
+            }
+            Err(err) => return Err(ReadObjectError::new(id, ReadError::from(err))),
+        };
+
+        Ok((header, Cursor::new(body)))
+    }
+}
+
+// Decompress a loose object which has already been read into memory.
+fn decompress(data: &[u8]) -> Result<(ObjectHeader, Bytes), ParseObjectError> {
+    let mut object = Vec::new();
+    ZlibDecoder::new(data)
+        .read_to_end(&mut object)
+        .map_err(|err| read_body_error(parse::Error::Io(err)))?;
+    ObjectHeader::split_framed(object.into())
+}
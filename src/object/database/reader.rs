@@ -1,20 +1,31 @@
-use std::io::{self, BufRead, BufReader, Read};
+use std::io::{self, BufReader, Read, Write};
 
 use bytes::buf::ext::BufExt;
 use bytes::Bytes;
 use flate2::bufread::ZlibDecoder;
 
-use crate::object::parse::ParseObjectError;
-use crate::object::{ObjectData, ObjectHeader};
-use crate::parse;
+use crate::object::parse::{ParseHeaderError, ParseObjectError};
+use crate::object::{
+    HashAlgorithm, Id, ObjectData, ObjectHeader, ReadObjectError, ReadObjectErrorKind,
+};
+use crate::parse::{self, Parser};
 
 pub struct ObjectReader {
     header: Option<ObjectHeader>,
-    reader: ZlibDecoder<ReaderKind>,
+    reader: ReaderKind,
+}
+
+// Reads exactly the number of bytes given in an object's header, failing if
+// the underlying reader ends too soon.
+struct BodyReader<R> {
+    reader: R,
+    remaining: u64,
 }
 
 enum ReaderKind {
-    File(BufReader<fs_err::File>),
+    // A zlib compressed loose object, including its header.
+    File(ZlibDecoder<BufReader<fs_err::File>>),
+    // The decompressed body of a packed object.
     Bytes(bytes::buf::ext::Reader<Bytes>),
 }
 
@@ -25,17 +36,14 @@ impl ObjectReader {
     ) -> Self {
         ObjectReader {
             header: header.into(),
-            reader: ZlibDecoder::new(ReaderKind::File(BufReader::new(file))),
+            reader: ReaderKind::File(ZlibDecoder::new(BufReader::new(file))),
         }
     }
 
-    pub(in crate::object) fn from_bytes(
-        header: impl Into<Option<ObjectHeader>>,
-        bytes: Bytes,
-    ) -> Self {
+    pub(in crate::object) fn from_bytes(header: ObjectHeader, bytes: Bytes) -> Self {
         ObjectReader {
-            header: header.into(),
-            reader: ZlibDecoder::new(ReaderKind::Bytes(bytes.reader())),
+            header: Some(header),
+            reader: ReaderKind::Bytes(bytes.reader()),
         }
     }
 
@@ -43,7 +51,114 @@ impl ObjectReader {
         &mut self.reader
     }
 
-    pub(in crate::object) fn parse(self) -> Result<ObjectData, ParseObjectError> {
+    /// Parse the object, reading any ids it contains with the given hash algorithm.
+    pub(in crate::object) fn parse(
+        self,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<ObjectData, ParseObjectError> {
+        let (header, parser) = self.into_parser(hash_algorithm)?;
+        parser.parse_object_body(header.kind)
+    }
+
+    /// Parse the object, checking that its header and body hash to `id`.
+    pub(in crate::object) fn parse_verified(self, id: Id) -> Result<ObjectData, ReadObjectError> {
+        let hash_algorithm = id.hash_algorithm();
+        let (header, parser) = self
+            .into_parser(hash_algorithm)
+            .map_err(|err| ReadObjectError::new(id, err))?;
+
+        let actual = header.hash(hash_algorithm, parser.remaining_buffer());
+        if actual != id {
+            return Err(ReadObjectError::new(
+                id,
+                ReadObjectErrorKind::Corrupt {
+                    expected: id,
+                    actual,
+                },
+            ));
+        }
+
+        parser
+            .parse_object_body(header.kind)
+            .map_err(|err| ReadObjectError::new(id, err))
+    }
+
+    /// Read the whole object into memory, returning its header and body.
+    pub(in crate::object) fn into_bytes(self) -> Result<(ObjectHeader, Bytes), ParseObjectError> {
+        // The body is not parsed, so the hash algorithm is unused.
+        let (header, parser) = self.into_parser(HashAlgorithm::default())?;
+        let pos = parser.pos();
+        Ok((header, parser.into_inner().slice(pos..)))
+    }
+
+    // Read the whole object into memory, returning its header and a parser
+    // positioned at the start of its body.
+    fn into_parser(
+        self,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<(ObjectHeader, Parser<Bytes>), ParseObjectError> {
+        if let (Some(header), ReaderKind::Bytes(reader)) = (self.header, &self.reader) {
+            // The body is already in memory so it can be parsed without copying.
+            let bytes = reader.get_ref().clone();
+            if bytes.len() != header.len {
+                return Err(ParseHeaderError::LengthMismatch.into());
+            }
+            return Ok((
+                header,
+                Parser::new(bytes).with_hash_algorithm(hash_algorithm),
+            ));
+        }
+
+        let mut buffer = parse::Buffer::new(self.reader).with_hash_algorithm(hash_algorithm);
+
+        let header = match self.header {
+            Some(header) => header,
+            None => buffer.read_object_header()?,
+        };
+
+        let parser = buffer
+            .read_to_end_into_parser(header.len)
+            .map_err(read_body_error)?;
+        Ok((header, parser))
+    }
+
+    /// Read the header of the object, without reading its body.
+    pub(in crate::object) fn read_header(self) -> Result<ObjectHeader, ParseObjectError> {
+        match self.header {
+            Some(header) => Ok(header),
+            None => Ok(parse::Buffer::new(self.reader).read_object_header()?),
+        }
+    }
+
+    /// Read the header of the object, returning it along with a reader over its body.
+    ///
+    /// Unlike `read_header`, nothing past the header is read, so the body can be
+    /// streamed from the returned reader.
+    pub(in crate::object) fn into_body_reader(
+        self,
+    ) -> Result<(ObjectHeader, impl Read), ParseObjectError> {
+        let mut reader = self.reader;
+        let header = match self.header {
+            Some(header) => header,
+            None => ObjectHeader::read_unbuffered(&mut reader)?,
+        };
+        Ok((
+            header,
+            BodyReader {
+                reader,
+                remaining: header.len as u64,
+            },
+        ))
+    }
+
+    /// Write the body of the object to `writer`, without its header.
+    ///
+    /// Loose objects are decompressed and written incrementally, so
+    /// the object is never fully held in memory.
+    pub(in crate::object) fn copy_body<W>(self, writer: &mut W) -> Result<(), ParseObjectError>
+    where
+        W: Write,
+    {
         let mut buffer = parse::Buffer::new(self.reader);
 
         let header = match self.header {
@@ -51,7 +166,42 @@ impl ObjectReader {
             None => buffer.read_object_header()?,
         };
 
-        buffer.read_object_body(header)
+        buffer
+            .copy_to_end(header.len, writer)
+            .map_err(read_body_error)?;
+        Ok(())
+    }
+}
+
+// If the body ends before the length given in the header, as when the zlib
+// stream of a loose object is truncated, the object is corrupt rather than
+// unreadable.
+pub(in crate::object::database) fn read_body_error(err: parse::Error) -> ParseHeaderError {
+    match err {
+        parse::Error::UnexpectedEof => ParseHeaderError::LengthMismatch,
+        parse::Error::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            ParseHeaderError::LengthMismatch
+        }
+        err => ParseHeaderError::from(err),
+    }
+}
+
+impl<R: Read> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(self.remaining as usize);
+        let read = self.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the object body is shorter than the length in its header",
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
     }
 }
 
@@ -64,18 +214,41 @@ impl Read for ReaderKind {
     }
 }
 
-impl BufRead for ReaderKind {
-    fn fill_buf(&mut self) -> io::Result<&[u8]> {
-        match self {
-            ReaderKind::File(file) => file.fill_buf(),
-            ReaderKind::Bytes(bytes) => bytes.fill_buf(),
-        }
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use bytes::Bytes;
+
+    use super::ObjectReader;
+    use crate::object::{ObjectHeader, ObjectKind};
+
+    #[test]
+    fn body_reader_rejects_short_body() {
+        let header = ObjectHeader {
+            kind: ObjectKind::Blob,
+            len: 10,
+        };
+        let reader = ObjectReader::from_bytes(header, Bytes::from_static(b"short"));
+        let (_, mut body) = reader.into_body_reader().unwrap();
+
+        let mut data = Vec::new();
+        let err = body.read_to_end(&mut data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(data, b"short");
     }
 
-    fn consume(&mut self, amt: usize) {
-        match self {
-            ReaderKind::File(file) => file.consume(amt),
-            ReaderKind::Bytes(bytes) => bytes.consume(amt),
-        }
+    #[test]
+    fn body_reader_stops_at_header_len() {
+        let header = ObjectHeader {
+            kind: ObjectKind::Blob,
+            len: 5,
+        };
+        let reader = ObjectReader::from_bytes(header, Bytes::from_static(b"short and long"));
+        let (_, mut body) = reader.into_body_reader().unwrap();
+
+        let mut data = Vec::new();
+        body.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"short");
     }
 }
@@ -5,8 +5,8 @@ use bytes::Bytes;
 use thiserror::Error;
 
 use crate::object::{
-    Blob, Commit, ObjectData, ObjectHeader, ObjectKind, ParseBlobError, ParseCommitError,
-    ParseTagError, ParseTreeError, Tag, Tree,
+    Blob, Commit, HashAlgorithm, Id, ObjectData, ObjectHeader, ObjectKind, ParseBlobError,
+    ParseCommitError, ParseTagError, ParseTreeError, Tag, Tree,
 };
 use crate::parse::{self, Buffer, Parser};
 
@@ -46,9 +46,14 @@ pub(in crate::object) enum ParseObjectError {
     ),
 }
 
+/// An error when parsing an object from bytes already in memory.
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct ParseObjectDataError(#[from] ParseObjectError);
+
 #[derive(Debug, Error)]
 #[error("unknown object type `{0}`")]
-pub(in crate::object) struct ParseObjectKindError(String);
+pub struct ParseObjectKindError(String);
 
 #[derive(Debug, Error)]
 pub(in crate::object) enum ParseHeaderError {
@@ -68,6 +73,63 @@ pub(in crate::object) enum ParseHeaderError {
 
 impl ObjectHeader {
     const MAX_LEN: usize = 28;
+
+    /// Split an object preceded by its header in git's `<kind> <len>\0`
+    /// format into its header and body, checking the length of the body.
+    pub(in crate::object) fn split_framed(
+        data: Bytes,
+    ) -> Result<(ObjectHeader, Bytes), ParseObjectError> {
+        let mut parser = Parser::new(data);
+        let header = parser.parse_object_header()?;
+        if parser.remaining() != header.len {
+            return Err(ParseHeaderError::LengthMismatch.into());
+        }
+
+        let pos = parser.pos();
+        Ok((header, parser.into_inner().slice(pos..)))
+    }
+
+    /// Read a header directly from `reader` a byte at a time, so that nothing
+    /// past the end of the header is consumed.
+    pub(in crate::object) fn read_unbuffered<R: Read>(
+        reader: &mut R,
+    ) -> Result<Self, ParseObjectError> {
+        let mut header = Vec::with_capacity(ObjectHeader::MAX_LEN);
+        loop {
+            let mut byte = [0];
+            reader.read_exact(&mut byte)?;
+            header.push(byte[0]);
+            if byte[0] == b'\0' {
+                break;
+            }
+            if header.len() == ObjectHeader::MAX_LEN {
+                return Err(ParseObjectError::InvalidHeader(ParseHeaderError::Other(
+                    "the end of the header was not found",
+                )));
+            }
+        }
+
+        Parser::new(header.as_slice())
+            .parse_object_header()
+            .map_err(ParseObjectError::InvalidHeader)
+    }
+
+    /// Compute the id of an object with this header and the given body, which
+    /// is hashed with the header in git's `<kind> <len>\0` format.
+    pub(in crate::object) fn hash(&self, hash_algorithm: HashAlgorithm, body: &[u8]) -> Id {
+        hash_algorithm
+            .hasher()
+            .chain(format!("{} {}\0", self.kind.as_str(), self.len))
+            .chain(body)
+            .finish()
+    }
+
+    /// Prepend a header in git's `<kind> <len>\0` format to an object body.
+    pub(in crate::object) fn frame(kind: ObjectKind, body: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("{} {}\0", kind.as_str(), body.len()).into_bytes();
+        bytes.extend_from_slice(body);
+        bytes
+    }
 }
 
 impl<R: Read> Buffer<R> {
@@ -84,20 +146,13 @@ impl<R: Read> Buffer<R> {
         debug_assert!(parser.finished());
         Ok(header)
     }
-
-    pub(in crate::object) fn read_object_body(
-        self,
-        header: ObjectHeader,
-    ) -> Result<ObjectData, ParseObjectError> {
-        let parser = self
-            .read_to_end_into_parser(header.len)
-            .map_err(ParseHeaderError::from)?;
-        parser.parse_object_body(header.kind)
-    }
 }
 
 impl Parser<Bytes> {
-    fn parse_object_body(self, kind: ObjectKind) -> Result<ObjectData, ParseObjectError> {
+    pub(in crate::object) fn parse_object_body(
+        self,
+        kind: ObjectKind,
+    ) -> Result<ObjectData, ParseObjectError> {
         match kind {
             ObjectKind::Blob => Blob::parse(self)
                 .map(ObjectData::Blob)
@@ -118,6 +173,22 @@ impl Parser<Bytes> {
     }
 }
 
+impl ObjectData {
+    /// Parse the body of an object of the given kind. Any ids the object
+    /// contains are parsed as SHA-1 ids.
+    pub fn from_bytes(kind: ObjectKind, data: Bytes) -> Result<Self, ParseObjectDataError> {
+        Ok(Parser::new(data).parse_object_body(kind)?)
+    }
+
+    /// Parse an object preceded by its header in git's `<kind> <len>\0`
+    /// format, as in a decompressed loose object. Any ids the object contains
+    /// are parsed as SHA-1 ids.
+    pub fn from_framed_bytes(data: Bytes) -> Result<Self, ParseObjectDataError> {
+        let (header, body) = ObjectHeader::split_framed(data)?;
+        ObjectData::from_bytes(header.kind, body)
+    }
+}
+
 impl<B: AsRef<[u8]>> Parser<B> {
     pub(in crate::object) fn parse_object_header(
         &mut self,
@@ -141,17 +212,42 @@ impl<B: AsRef<[u8]>> Parser<B> {
 }
 
 impl ObjectKind {
+    // The names used for each kind when parsing and formatting.
+    const NAMES: [(ObjectKind, &'static str); 6] = [
+        (ObjectKind::Commit, "commit"),
+        (ObjectKind::Tree, "tree"),
+        (ObjectKind::Blob, "blob"),
+        (ObjectKind::Tag, "tag"),
+        (ObjectKind::OfsDelta, "ofs-delta"),
+        (ObjectKind::RefDelta, "ref-delta"),
+    ];
+
+    /// Parse the name of any kind, including the delta kinds.
+    pub(in crate::object) fn from_name(input: &[u8]) -> Result<Self, ParseObjectKindError> {
+        ObjectKind::NAMES
+            .iter()
+            .find(|(_, name)| name.as_bytes() == input)
+            .map(|&(kind, _)| kind)
+            .ok_or_else(|| ParseObjectKindError(parse::lossy_utf8(input)))
+    }
+
+    /// Parse the kind of an object header, which is never a delta.
     pub(in crate::object) fn from_bytes(input: &[u8]) -> Result<Self, ParseObjectKindError> {
-        match input {
-            b"commit" => Ok(ObjectKind::Commit),
-            b"tree" => Ok(ObjectKind::Tree),
-            b"blob" => Ok(ObjectKind::Blob),
-            b"tag" => Ok(ObjectKind::Tag),
-            input => Err(ParseObjectKindError(
-                String::from_utf8_lossy(input).into_owned(),
-            )),
+        match ObjectKind::from_name(input)? {
+            ObjectKind::OfsDelta | ObjectKind::RefDelta => {
+                Err(ParseObjectKindError(parse::lossy_utf8(input)))
+            }
+            kind => Ok(kind),
         }
     }
+
+    pub(in crate::object) fn as_str(&self) -> &'static str {
+        ObjectKind::NAMES
+            .iter()
+            .find(|(kind, _)| kind == self)
+            .map(|&(_, name)| name)
+            .expect("every kind has a name")
+    }
 }
 
 impl From<ParseHeaderError> for ParseObjectError {
@@ -203,4 +299,42 @@ fn test_parse_header() {
     assert!(parse_header(b"commit 333333333333333333333\0abc").is_err(),);
     assert!(parse_header(b"blob 3").is_err(),);
     assert!(parse_header(b"blob3\0abc").is_err(),);
+    assert_eq!(
+        parse_header(b"bl\xffb 3\0abc").unwrap_err().to_string(),
+        "unknown object type `bl\u{fffd}b`"
+    );
+}
+
+#[test]
+fn test_object_data_from_bytes() {
+    let commit = b"\
+tree a552334b3ba0630d8f82ac9f27ab55625085d9bd
+author Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+committer Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100
+
+message";
+
+    match ObjectData::from_bytes(ObjectKind::Commit, Bytes::from_static(commit)).unwrap() {
+        ObjectData::Commit(commit) => assert_eq!(commit.message(), "message"),
+        data => panic!("expected a commit, got {:?}", data.kind()),
+    }
+    match ObjectData::from_framed_bytes(Bytes::from(ObjectHeader::frame(
+        ObjectKind::Commit,
+        commit,
+    )))
+    .unwrap()
+    {
+        ObjectData::Commit(commit) => assert_eq!(commit.message(), "message"),
+        data => panic!("expected a commit, got {:?}", data.kind()),
+    }
+    match ObjectData::from_framed_bytes(Bytes::from_static(b"blob 3\0abc")).unwrap() {
+        ObjectData::Blob(blob) => assert_eq!(blob.data(), "abc"),
+        data => panic!("expected a blob, got {:?}", data.kind()),
+    }
+
+    assert!(ObjectData::from_bytes(ObjectKind::Commit, Bytes::from_static(b"abc")).is_err());
+    assert!(ObjectData::from_bytes(ObjectKind::OfsDelta, Bytes::from_static(b"abc")).is_err());
+    assert!(ObjectData::from_framed_bytes(Bytes::from_static(b"blob 4\0abc")).is_err());
+    assert!(ObjectData::from_framed_bytes(Bytes::from_static(b"blob 2\0abc")).is_err());
+    assert!(ObjectData::from_framed_bytes(Bytes::from_static(b"blob 3")).is_err());
 }
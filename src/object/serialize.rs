@@ -0,0 +1,389 @@
+//! `serde::Serialize` implementations for parsed objects.
+//!
+//! Ids are serialized as hex strings, and text fields such as names and
+//! messages are serialized as strings, with invalid UTF-8 replaced. Ids,
+//! abbreviated ids and object kinds can also be deserialized from the same
+//! strings.
+
+use std::fmt;
+
+use bstr::{BStr, ByteSlice};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::ser::{Serialize, SerializeSeq, SerializeStruct, Serializer};
+
+use crate::object::{
+    Blob, Commit, HashAlgorithm, Id, Object, ObjectData, ObjectKind, ShortId, Signature, Tag, Tree,
+    TreeEntry,
+};
+
+impl Serialize for Id {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Id {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(IdVisitor)
+    }
+}
+
+struct IdVisitor;
+
+impl<'de> Visitor<'de> for IdVisitor {
+    type Value = Id;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a hex object id of {} or {} characters",
+            HashAlgorithm::Sha1.hex_len(),
+            HashAlgorithm::Sha256.hex_len()
+        )
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        if value.len() != HashAlgorithm::Sha1.hex_len()
+            && value.len() != HashAlgorithm::Sha256.hex_len()
+        {
+            return Err(E::invalid_length(value.len(), &self));
+        }
+        value.parse().map_err(E::custom)
+    }
+}
+
+// Abbreviated ids are serialized with only the digits they contain, so they
+// keep their length when deserialized.
+impl Serialize for ShortId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for ShortId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(ShortIdVisitor)
+    }
+}
+
+struct ShortIdVisitor;
+
+impl<'de> Visitor<'de> for ShortIdVisitor {
+    type Value = ShortId;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a hex object id, which may be abbreviated")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl Serialize for ObjectKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(ObjectKindVisitor)
+    }
+}
+
+struct ObjectKindVisitor;
+
+impl<'de> Visitor<'de> for ObjectKindVisitor {
+    type Value = ObjectKind;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object type")
+    }
+
+    // Unlike `FromStr`, this accepts the delta kinds, so that every
+    // serialized kind can be read back.
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        ObjectKind::from_name(value.as_bytes()).map_err(E::custom)
+    }
+}
+
+impl Serialize for Object {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Object", 2)?;
+        state.serialize_field("id", self.id())?;
+        state.serialize_field("data", self.data())?;
+        state.end()
+    }
+}
+
+impl Serialize for ObjectData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ObjectData::Commit(commit) => {
+                serializer.serialize_newtype_variant("ObjectData", 0, "commit", commit)
+            }
+            ObjectData::Tree(tree) => {
+                serializer.serialize_newtype_variant("ObjectData", 1, "tree", tree)
+            }
+            ObjectData::Blob(blob) => {
+                serializer.serialize_newtype_variant("ObjectData", 2, "blob", blob)
+            }
+            ObjectData::Tag(tag) => {
+                serializer.serialize_newtype_variant("ObjectData", 3, "tag", tag)
+            }
+        }
+    }
+}
+
+impl Serialize for Commit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Commit", 6)?;
+        state.serialize_field("tree", &self.tree())?;
+        state.serialize_field("parents", &self.parents().collect::<Vec<_>>())?;
+        state.serialize_field("author", &self.author())?;
+        state.serialize_field("committer", &self.committer())?;
+        state.serialize_field("encoding", &self.encoding().map(Text))?;
+        state.serialize_field("message", &Text(self.message()))?;
+        state.end()
+    }
+}
+
+impl Serialize for Tree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entries = self.entries();
+        let mut state = serializer.serialize_seq(Some(entries.len()))?;
+        for entry in entries {
+            state.serialize_element(&entry)?;
+        }
+        state.end()
+    }
+}
+
+impl<'a> Serialize for TreeEntry<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("TreeEntry", 4)?;
+        state.serialize_field("mode", &format!("{:06o}", self.mode()))?;
+        state.serialize_field("type", &self.file_mode().object_kind())?;
+        state.serialize_field("id", &self.id())?;
+        state.serialize_field("name", &Text(self.filename()))?;
+        state.end()
+    }
+}
+
+impl Serialize for Blob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Blobs may contain binary data, so they are not converted to strings.
+        serializer.serialize_bytes(self.data())
+    }
+}
+
+impl Serialize for Tag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Tag", 5)?;
+        state.serialize_field("object", &self.object())?;
+        state.serialize_field("type", &self.kind())?;
+        state.serialize_field("tag", &Text(self.tag()))?;
+        state.serialize_field("tagger", &self.tagger())?;
+        state.serialize_field("message", &self.message().map(Text))?;
+        state.end()
+    }
+}
+
+impl<'a> Serialize for Signature<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let time = self.time().map(|time| time.seconds());
+
+        let mut state = serializer.serialize_struct("Signature", 4)?;
+        state.serialize_field("name", &Text(self.name()))?;
+        state.serialize_field("email", &Text(self.email()))?;
+        state.serialize_field("time", &time)?;
+        state.serialize_field("tz", &self.timezone().map(Text))?;
+        state.end()
+    }
+}
+
+// Serializes bytes as a string, replacing invalid UTF-8.
+struct Text<'a>(&'a BStr);
+
+impl<'a> Serialize for Text<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_str_lossy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bytes::Bytes;
+    use serde_json::json;
+
+    use crate::object::{Blob, Commit, Id, ObjectKind, Parser, ShortId, Tag, Tree};
+
+    #[test]
+    fn test_serialize_commit() {
+        let commit = Commit::parse(Parser::new(Bytes::from_static(
+            b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+parent 90012941912143fcf042590f8e152c41b13d5520\n\
+author A U Thor <author@example.com> 1234567890 +0100\n\
+committer C O Mitter <committer@example.com> 1234567891 -0200\n\
+\n\
+Message\n",
+        )))
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&commit).unwrap(),
+            json!({
+                "tree": "4b825dc642cb6eb9a060e54bf8d69288fbee4904",
+                "parents": ["90012941912143fcf042590f8e152c41b13d5520"],
+                "author": {
+                    "name": "A U Thor",
+                    "email": "author@example.com",
+                    "time": 1234567890,
+                    "tz": "+0100",
+                },
+                "committer": {
+                    "name": "C O Mitter",
+                    "email": "committer@example.com",
+                    "time": 1234567891,
+                    "tz": "-0200",
+                },
+                "encoding": null,
+                "message": "Message\n",
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_tree() {
+        let tree = Tree::parse(Parser::new(Bytes::from_static(
+            b"\
+40000 dir\0\x49\x19\x89\xb9\x30\xc1\xe5\xd0\x83\xa4\xd2\xa1\xf7\xfa\x42\xaa\xa8\x6c\x13\x75\
+100644 file\0\x69\x36\x99\x04\x2b\x1a\x8c\xcf\x69\x76\x36\xd3\xcd\x34\xb2\x00\xf3\xa8\x27\x8b",
+        )))
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&tree).unwrap(),
+            json!([
+                {
+                    "mode": "040000",
+                    "type": "tree",
+                    "id": "491989b930c1e5d083a4d2a1f7fa42aaa86c1375",
+                    "name": "dir",
+                },
+                {
+                    "mode": "100644",
+                    "type": "blob",
+                    "id": "693699042b1a8ccf697636d3cd34b200f3a8278b",
+                    "name": "file",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_serialize_tag() {
+        let tag = Tag::parse(Parser::new(Bytes::from_static(
+            b"object 90012941912143fcf042590f8e152c41b13d5520\n\
+type commit\n\
+tag v1.0\n\
+tagger A U Thor <author@example.com> 1234567890 +0000\n\
+\n\
+Version 1.0\n",
+        )))
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&tag).unwrap(),
+            json!({
+                "object": "90012941912143fcf042590f8e152c41b13d5520",
+                "type": "commit",
+                "tag": "v1.0",
+                "tagger": {
+                    "name": "A U Thor",
+                    "email": "author@example.com",
+                    "time": 1234567890,
+                    "tz": "+0000",
+                },
+                "message": "Version 1.0\n",
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_blob() {
+        let blob = Blob::parse(Parser::new(Bytes::from_static(b"a\xff"))).unwrap();
+
+        assert_eq!(serde_json::to_value(&blob).unwrap(), json!([0x61, 0xff]));
+    }
+
+    #[test]
+    fn test_serialize_id() {
+        let id = Id::from_hash(b"blob 0\0");
+
+        assert_eq!(
+            serde_json::to_value(id).unwrap(),
+            json!("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391")
+        );
+    }
+
+    #[test]
+    fn test_deserialize_id() {
+        let id = Id::from_hash(b"blob 0\0");
+        let value = serde_json::to_value(id).unwrap();
+        assert_eq!(serde_json::from_value::<Id>(value).unwrap(), id);
+
+        let sha256 =
+            Id::from_str("473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813")
+                .unwrap();
+        let value = serde_json::to_value(sha256).unwrap();
+        assert_eq!(serde_json::from_value::<Id>(value).unwrap(), sha256);
+
+        let err = serde_json::from_value::<Id>(json!("e69de29b")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid length 8, expected a hex object id of 40 or 64 characters"
+        );
+        let err = serde_json::from_value::<Id>(json!("g69de29bb2d1d6434b8b29ae775ad8c2e48c5391"))
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid character"));
+    }
+
+    #[test]
+    fn test_short_id_round_trip() {
+        let short_id = ShortId::from_str("e69de29b").unwrap();
+        let value = serde_json::to_value(short_id).unwrap();
+        assert_eq!(value, json!("e69de29b"));
+        assert_eq!(
+            serde_json::from_value::<ShortId>(value).unwrap().to_hex(),
+            "e69de29b"
+        );
+
+        let err = serde_json::from_value::<ShortId>(json!("e6")).unwrap_err();
+        assert_eq!(err.to_string(), "ids must be at least 4 characters long");
+    }
+
+    #[test]
+    fn test_object_kind_round_trip() {
+        let value = serde_json::to_value(ObjectKind::Tree).unwrap();
+        assert_eq!(value, json!("tree"));
+        assert_eq!(
+            serde_json::from_value::<ObjectKind>(value).unwrap(),
+            ObjectKind::Tree
+        );
+
+        let value = serde_json::to_value(ObjectKind::RefDelta).unwrap();
+        assert_eq!(value, json!("ref-delta"));
+        assert_eq!(
+            serde_json::from_value::<ObjectKind>(value).unwrap(),
+            ObjectKind::RefDelta
+        );
+
+        let err = serde_json::from_value::<ObjectKind>(json!("delta")).unwrap_err();
+        assert_eq!(err.to_string(), "unknown object type `delta`");
+        assert!(serde_json::from_value::<ObjectKind>(json!(1)).is_err());
+    }
+}
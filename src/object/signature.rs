@@ -1,8 +1,10 @@
+use std::fmt;
+use std::io::{self, Write};
 use std::ops::Range;
 
 use bstr::{BStr, ByteSlice};
-use once_cell::sync::Lazy;
-use regex::bytes::{Captures, Regex};
+use bytes::Bytes;
+use memchr::memrchr;
 use thiserror::Error;
 
 use crate::parse::Parser;
@@ -13,7 +15,25 @@ pub struct SignatureRaw {
 }
 
 pub struct Signature<'a> {
-    captures: Captures<'a>,
+    raw: &'a [u8],
+    name: &'a [u8],
+    email: &'a [u8],
+    timestamp: Option<&'a [u8]>,
+    timezone: Option<&'a [u8]>,
+}
+
+/// A signature that owns its underlying buffer.
+#[derive(Clone)]
+pub struct SignatureOwned {
+    data: Bytes,
+    raw: SignatureRaw,
+}
+
+/// The time of a signature, with its timezone offset.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Time {
+    seconds: i64,
+    offset: i32,
 }
 
 #[derive(Debug, Error)]
@@ -21,46 +41,270 @@ pub struct Signature<'a> {
 pub struct ParseSignatureError;
 
 impl<'a> Signature<'a> {
-    fn regex() -> &'static Regex {
-        const PADDING_CHARS: &str = "[\x00-\x32.,:;<>\"\\\\']*";
+    // Split a signature line in the same way as libgit2. The email is
+    // delimited by the last `<` and `>` in the line, so everything before it,
+    // including any angle brackets, is part of the name. The name and email
+    // are trimmed of surrounding whitespace and punctuation, and either may be
+    // empty. If the rest of the line is not a valid time, it is kept as part
+    // of the email rather than being discarded.
+    fn split(raw: &'a [u8]) -> Option<Self> {
+        let email_start = memrchr(b'<', raw)?;
+        let email_end = memrchr(b'>', raw)?;
+        if email_end < email_start {
+            return None;
+        }
 
-        static REGEX: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(&format!(
-                r"{pad}(.*){pad} <{pad}(.*){pad}>(?: (\d+)(?: ([+\-]\d+))?)?",
-                pad = PADDING_CHARS
-            ))
-            .unwrap()
-        });
+        let (email_end, timestamp, timezone) = match split_time(&raw[email_end + 1..]) {
+            Some((timestamp, timezone)) => (email_end, timestamp, timezone),
+            None => (raw.len(), None, None),
+        };
 
-        &*REGEX
+        Some(Signature {
+            raw,
+            name: trim(&raw[..email_start]),
+            email: trim(&raw[email_start + 1..email_end]),
+            timestamp,
+            timezone,
+        })
     }
 
     fn is_valid(input: &[u8]) -> bool {
-        Signature::regex().is_match(input)
+        Signature::split(input).is_some()
     }
 
     pub(in crate::object) fn new(input: &'a [u8], raw: &SignatureRaw) -> Self {
-        Signature {
-            captures: Signature::regex()
-                .captures(&input[raw.range.clone()])
-                .expect("invalid signature"),
+        Signature::split(&input[raw.range.clone()]).expect("invalid signature")
+    }
+
+    /// Parse a standalone signature line of the form `Name <email> timestamp timezone`.
+    ///
+    /// A single trailing newline is permitted.
+    pub fn parse_line(bytes: &[u8]) -> Result<SignatureOwned, ParseSignatureError> {
+        let line = match bytes.last() {
+            Some(b'\n') => &bytes[..(bytes.len() - 1)],
+            _ => bytes,
+        };
+        if Signature::is_valid(line) {
+            Ok(SignatureOwned {
+                data: Bytes::copy_from_slice(line),
+                raw: SignatureRaw {
+                    range: 0..line.len(),
+                },
+            })
+        } else {
+            Err(ParseSignatureError)
         }
     }
 
+    /// The raw bytes of the signature line, excluding any prefix and the trailing newline.
+    pub fn as_bytes(&self) -> &'a BStr {
+        self.raw.as_bstr()
+    }
+
+    /// Copy the signature line, exactly as it was parsed.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.raw.to_vec()
+    }
+
     pub fn name(&self) -> &'a BStr {
-        self.captures.get(1).unwrap().as_bytes().as_bstr()
+        self.name.as_bstr()
     }
 
     pub fn email(&self) -> &'a BStr {
-        self.captures.get(2).unwrap().as_bytes().as_bstr()
+        self.email.as_bstr()
+    }
+
+    /// Returns `true` if the signature ends with a timestamp, which may be
+    /// followed by a timezone.
+    pub fn has_time(&self) -> bool {
+        self.timestamp.is_some()
     }
 
     pub fn timestamp(&self) -> Option<&'a BStr> {
-        self.captures.get(3).map(|mat| mat.as_bytes().as_bstr())
+        self.timestamp.map(ByteSlice::as_bstr)
     }
 
     pub fn timezone(&self) -> Option<&'a BStr> {
-        self.captures.get(4).map(|mat| mat.as_bytes().as_bstr())
+        self.timezone.map(ByteSlice::as_bstr)
+    }
+
+    /// Parse the timestamp and timezone of the signature.
+    ///
+    /// Returns `None` if the signature has no valid timestamp. If the
+    /// timezone is missing or invalid, the offset is zero.
+    pub fn time(&self) -> Option<Time> {
+        let seconds = self.timestamp()?.to_str().ok()?.parse().ok()?;
+        let offset = self
+            .timezone()
+            .and_then(|timezone| parse_timezone_offset(timezone))
+            .unwrap_or(0);
+        Some(Time { seconds, offset })
+    }
+}
+
+impl Time {
+    /// Create a time from the number of seconds since the Unix epoch and
+    /// the offset of its timezone from UTC, in minutes.
+    pub fn new(seconds: i64, offset: i32) -> Self {
+        Time { seconds, offset }
+    }
+
+    /// The number of seconds since the Unix epoch.
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    /// The offset of the timezone from UTC, in minutes.
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+}
+
+/// Formats the time as git does in signatures, as the number of seconds
+/// followed by the timezone, e.g. `1596907199 +0100`.
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.unsigned_abs();
+        write!(
+            f,
+            "{} {}{:02}{:02}",
+            self.seconds,
+            sign,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+// The timestamp and timezone of a signature line.
+type TimeParts<'a> = (Option<&'a [u8]>, Option<&'a [u8]>);
+
+// Split the suffix of a signature line following the email, which may be empty,
+// ` timestamp` or ` timestamp timezone`, ignoring any trailing whitespace.
+// Returns `None` if the suffix is not of this form.
+fn split_time(bytes: &[u8]) -> Option<TimeParts<'_>> {
+    let (timestamp, rest) = match bytes.trim_end_with(|c| c.is_ascii_whitespace()) {
+        [] => return Some((None, None)),
+        [b' ', rest @ ..] => split_digits(rest),
+        _ => return None,
+    };
+    if timestamp.is_empty() {
+        return None;
+    }
+
+    match rest {
+        [] => Some((Some(timestamp), None)),
+        [b' ', timezone @ ..] => match timezone {
+            [b'+' | b'-', digits @ ..] if is_digits(digits) => {
+                Some((Some(timestamp), Some(timezone)))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Split a slice after its leading ASCII digits.
+fn split_digits(bytes: &[u8]) -> (&[u8], &[u8]) {
+    let len = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    bytes.split_at(len)
+}
+
+fn is_digits(bytes: &[u8]) -> bool {
+    !bytes.is_empty() && bytes.iter().all(u8::is_ascii_digit)
+}
+
+// The characters git considers "crud" at the ends of a name or email.
+fn is_crud(b: u8) -> bool {
+    b <= b' ' || b".,:;<>\"\\'".contains(&b)
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|&b| !is_crud(b))
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|&b| !is_crud(b))
+        .map_or(start, |pos| pos + 1);
+    &bytes[start..end]
+}
+
+// Parse a timezone of the form `+HHMM` or `-HHMM` into an offset in minutes.
+fn parse_timezone_offset(timezone: &[u8]) -> Option<i32> {
+    let (sign, digits) = match timezone.split_first()? {
+        (b'+', digits) => (1, digits),
+        (b'-', digits) => (-1, digits),
+        _ => return None,
+    };
+    let value: i32 = digits.to_str().ok()?.parse().ok()?;
+    Some(sign * (value / 100 * 60 + value % 100))
+}
+
+impl SignatureOwned {
+    /// Create a signature of the form `Name <email> timestamp timezone`.
+    ///
+    /// Returns an error if the name or email contain `<`, `>` or a newline, if
+    /// the timezone offset cannot be written as `+HHMM`, or if the signature
+    /// would otherwise not parse back to the same name, email and time.
+    pub fn new(
+        name: impl AsRef<[u8]>,
+        email: impl AsRef<[u8]>,
+        time: Time,
+    ) -> Result<Self, ParseSignatureError> {
+        let (name, email) = (name.as_ref(), email.as_ref());
+        if name
+            .iter()
+            .chain(email)
+            .any(|&b| matches!(b, b'<' | b'>' | b'\n'))
+            || time.offset.unsigned_abs() / 60 > 99
+        {
+            return Err(ParseSignatureError);
+        }
+
+        let mut line = Vec::with_capacity(name.len() + email.len() + 32);
+        line.extend_from_slice(name);
+        line.extend_from_slice(b" <");
+        line.extend_from_slice(email);
+        line.extend_from_slice(b"> ");
+        line.extend_from_slice(time.to_string().as_bytes());
+
+        let owned = Signature::parse_line(&line)?;
+        let signature = owned.signature();
+        if signature.name() != name || signature.email() != email || signature.time() != Some(time)
+        {
+            return Err(ParseSignatureError);
+        }
+        Ok(owned)
+    }
+
+    pub fn signature(&self) -> Signature<'_> {
+        Signature::new(&self.data, &self.raw)
+    }
+
+    /// Write the signature line, without a trailing newline.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.signature().as_bytes())
+    }
+}
+
+impl<'a> From<Signature<'a>> for SignatureOwned {
+    fn from(signature: Signature<'a>) -> Self {
+        let data = Bytes::copy_from_slice(signature.as_bytes());
+        SignatureOwned {
+            raw: SignatureRaw {
+                range: 0..data.len(),
+            },
+            data,
+        }
+    }
+}
+
+impl fmt::Debug for SignatureOwned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.signature(), f)
     }
 }
 
@@ -87,9 +331,33 @@ impl<B: AsRef<[u8]>> Parser<B> {
 #[cfg(test)]
 mod tests {
     use bstr::B;
+    use proptest::{arbitrary::any, collection::vec, prop_assert, prop_assert_eq, proptest};
 
     use super::*;
 
+    // Check that every byte of the line that is not crud belongs to one of the
+    // parts of the signature.
+    fn is_lossless(signature: &Signature) -> bool {
+        let mut covered = vec![false; signature.raw.len()];
+        let parts = [
+            Some(signature.name),
+            Some(signature.email),
+            signature.timestamp,
+            signature.timezone,
+        ];
+        for part in parts.iter().flatten() {
+            let start = part.as_ptr() as usize - signature.raw.as_ptr() as usize;
+            for covered in &mut covered[start..][..part.len()] {
+                *covered = true;
+            }
+        }
+        signature
+            .raw
+            .iter()
+            .zip(covered)
+            .all(|(&b, covered)| covered || is_crud(b))
+    }
+
     #[test]
     fn test_parse_signature() {
         let mut parser = Parser::new(B(
@@ -103,6 +371,24 @@ mod tests {
         assert_eq!(signature.email(), "me@andrewhickman.dev");
         assert_eq!(signature.timestamp(), Some(b"1596907199".as_bstr()));
         assert_eq!(signature.timezone(), Some(b"+0100".as_bstr()));
+        assert_eq!(
+            signature.time(),
+            Some(Time {
+                seconds: 1596907199,
+                offset: 60
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_signature_negative_timezone() {
+        let owned =
+            Signature::parse_line(b"Andrew Hickman <me@andrewhickman.dev> 1596907199 -0830")
+                .unwrap();
+        let time = owned.signature().time().unwrap();
+
+        assert_eq!(time.seconds(), 1596907199);
+        assert_eq!(time.offset(), -510);
     }
 
     #[test]
@@ -118,6 +404,13 @@ mod tests {
         assert_eq!(signature.email(), "me@andrewhickman.dev");
         assert_eq!(signature.timestamp(), Some(b"1596907199".as_bstr()));
         assert_eq!(signature.timezone(), None);
+        assert_eq!(
+            signature.time(),
+            Some(Time {
+                seconds: 1596907199,
+                offset: 0
+            })
+        );
     }
 
     #[test]
@@ -131,5 +424,161 @@ mod tests {
         assert_eq!(signature.email(), "me@andrewhickman.dev");
         assert_eq!(signature.timestamp(), None);
         assert_eq!(signature.timezone(), None);
+        assert_eq!(signature.time(), None);
+    }
+
+    #[test]
+    fn test_parse_signature_line() {
+        let owned =
+            Signature::parse_line(b"Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100\n")
+                .unwrap();
+        let signature = owned.signature();
+
+        assert_eq!(
+            signature.as_bytes(),
+            "Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100"
+        );
+        assert_eq!(signature.name(), "Andrew Hickman");
+        assert_eq!(signature.email(), "me@andrewhickman.dev");
+        assert_eq!(signature.timestamp(), Some(b"1596907199".as_bstr()));
+        assert_eq!(signature.timezone(), Some(b"+0100".as_bstr()));
+
+        assert!(Signature::parse_line(b"Andrew Hickman").is_err());
+    }
+
+    #[test]
+    fn test_parse_signature_angle_brackets() {
+        let owned = Signature::parse_line(b"Foo <Bar> <foo@bar> 1596907199 +0100").unwrap();
+        let signature = owned.signature();
+        assert_eq!(signature.name(), "Foo <Bar");
+        assert_eq!(signature.email(), "foo@bar");
+        assert_eq!(signature.time(), Some(Time::new(1596907199, 60)));
+
+        let owned = Signature::parse_line(b"Foo <> 1596907199 +0100").unwrap();
+        let signature = owned.signature();
+        assert_eq!(signature.name(), "Foo");
+        assert_eq!(signature.email(), "");
+        assert_eq!(signature.timezone(), Some(b"+0100".as_bstr()));
+
+        let owned = Signature::parse_line(b"  Foo.\t<< foo@bar >>").unwrap();
+        let signature = owned.signature();
+        assert_eq!(signature.name(), "Foo");
+        assert_eq!(signature.email(), "foo@bar");
+        assert_eq!(signature.timestamp(), None);
+
+        assert!(Signature::parse_line(b"Foo > foo@bar <").is_err());
+    }
+
+    #[test]
+    fn test_parse_signature_invalid_time() {
+        for line in &[
+            &b"Foo <foo@bar> 1596907199abc"[..],
+            b"Foo <foo@bar> 1596907199 +01x0",
+            b"Foo <foo@bar> 1596907199 0100",
+            b"Foo <foo@bar> 1596907199 +0100 trailing",
+            b"Foo <foo@bar> garbage",
+        ] {
+            let owned = Signature::parse_line(line).unwrap();
+            let signature = owned.signature();
+            assert_eq!(signature.name(), "Foo");
+            assert_eq!(signature.email().as_bytes(), &line[5..]);
+            assert!(!signature.has_time());
+            assert_eq!(signature.timezone(), None);
+        }
+
+        let owned = Signature::parse_line(b"Foo <foo@bar> 1596907199 \r").unwrap();
+        let signature = owned.signature();
+        assert_eq!(signature.email(), "foo@bar");
+        assert!(signature.has_time());
+        assert_eq!(signature.timestamp(), Some(b"1596907199".as_bstr()));
+        assert_eq!(signature.timezone(), None);
+    }
+
+    proptest! {
+        #[test]
+        fn randomized_signature_does_not_lose_data(line in vec(any::<u8>(), ..100)) {
+            if let Some(signature) = Signature::split(&line) {
+                prop_assert!(is_lossless(&signature));
+            }
+        }
+
+        #[test]
+        fn randomized_identity_does_not_lose_data(line in "[a-z0-9 <>+-]{0,40}") {
+            if let Some(signature) = Signature::split(line.as_bytes()) {
+                prop_assert!(is_lossless(&signature));
+            }
+        }
+
+        #[test]
+        fn randomized_time_round_trips(
+            name in "[a-z]{1,10}",
+            email in "[a-z][a-z@.]{0,10}[a-z]",
+            seconds in 0..i64::from(u32::MAX),
+            offset in -1439..1440,
+        ) {
+            let time = Time::new(seconds, offset);
+            let line = format!("{} <{}> {}", name, email, time);
+            let owned = Signature::parse_line(line.as_bytes()).unwrap();
+            let signature = owned.signature();
+            prop_assert_eq!(signature.name(), name.as_str());
+            prop_assert_eq!(signature.email(), email.as_str());
+            prop_assert!(signature.has_time());
+            prop_assert_eq!(signature.time(), Some(time));
+        }
+    }
+
+    #[test]
+    fn test_write_signature() {
+        let write = |owned: &SignatureOwned| {
+            let mut line = Vec::new();
+            owned.write_to(&mut line).unwrap();
+            String::from_utf8(line).unwrap()
+        };
+
+        let owned = SignatureOwned::new(
+            "Andrew Hickman",
+            "me@andrewhickman.dev",
+            Time::new(1596907199, 60),
+        )
+        .unwrap();
+        assert_eq!(
+            write(&owned),
+            "Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100"
+        );
+        assert_eq!(
+            owned.signature().to_bytes(),
+            b"Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100"
+        );
+
+        let owned = SignatureOwned::new("A", "a@b", Time::new(0, 0)).unwrap();
+        assert_eq!(write(&owned), "A <a@b> 0 +0000");
+
+        let owned = SignatureOwned::new("A", "a@b", Time::new(1596907199, -510)).unwrap();
+        assert_eq!(write(&owned), "A <a@b> 1596907199 -0830");
+        assert_eq!(owned.signature().time(), Some(Time::new(1596907199, -510)));
+
+        assert!(SignatureOwned::new("A <b>", "a@b", Time::new(0, 0)).is_err());
+        assert!(SignatureOwned::new("A", "a@b\n", Time::new(0, 0)).is_err());
+
+        // Offsets which cannot be written as `+HHMM` are rejected.
+        assert_eq!(Time::new(0, i32::MIN).to_string(), "0 -3579139408");
+        assert!(SignatureOwned::new("A", "a@b", Time::new(0, i32::MIN)).is_err());
+        assert!(SignatureOwned::new("A", "a@b", Time::new(0, 100 * 60)).is_err());
+        assert!(SignatureOwned::new("A", "a@b", Time::new(0, -(99 * 60 + 59))).is_ok());
+    }
+
+    #[test]
+    fn test_signature_to_owned() {
+        let mut parser = Parser::new(B(
+            "author Andrew Hickman <me@andrewhickman.dev> 1596907199\n",
+        ));
+        let signature_raw = parser.parse_signature(b"author ").unwrap().unwrap();
+        let buf = parser.into_inner();
+        let owned = SignatureOwned::from(Signature::new(buf, &signature_raw));
+
+        let mut line = Vec::new();
+        owned.write_to(&mut line).unwrap();
+        assert_eq!(line, b"Andrew Hickman <me@andrewhickman.dev> 1596907199");
+        assert_eq!(owned.signature().name(), "Andrew Hickman");
     }
 }
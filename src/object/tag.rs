@@ -1,17 +1,20 @@
 use std::fmt;
 use std::ops::Range;
 
-use bstr::{BStr, ByteSlice};
+use bstr::{BStr, BString, ByteSlice};
 use bytes::Bytes;
 use thiserror::Error;
 
 use crate::object::parse::ParseObjectKindError;
 use crate::object::signature::{ParseSignatureError, Signature, SignatureRaw};
-use crate::object::{Id, ObjectKind, Parser, ID_HEX_LEN};
+use crate::object::{HashAlgorithm, Id, ObjectHeader, ObjectKind, Parser, SignatureOwned};
 
 #[derive(Clone)]
 pub struct Tag {
     data: Bytes,
+    // The position of the start of the body in `data`.
+    start: usize,
+    hash_algorithm: HashAlgorithm,
     tag: Range<usize>,
     object: usize,
     kind: ObjectKind,
@@ -19,8 +22,20 @@ pub struct Tag {
     message: Option<usize>,
 }
 
+/// The fields of an annotated tag to be written to the object database.
+#[derive(Debug, Clone)]
+pub struct TagData {
+    object: Id,
+    kind: ObjectKind,
+    tag: BString,
+    tagger: Option<SignatureOwned>,
+    message: BString,
+}
+
+/// An error when parsing a tag object, such as one embedded in a commit's
+/// `mergetag` header.
 #[derive(Debug, Error)]
-pub(in crate::object) enum ParseTagError {
+pub enum ParseTagError {
     #[error("{0}")]
     Other(&'static str),
     #[error(transparent)]
@@ -31,6 +46,7 @@ pub(in crate::object) enum ParseTagError {
 
 impl Tag {
     pub(in crate::object) fn parse(mut parser: Parser<Bytes>) -> Result<Self, ParseTagError> {
+        let start = parser.pos();
         let object = parser
             .parse_hex_id_line(b"object ")
             .map_err(|_| ParseTagError::Other("object field not found"))?
@@ -56,7 +72,9 @@ impl Tag {
         };
 
         Ok(Tag {
+            hash_algorithm: parser.hash_algorithm(),
             data: parser.into_inner(),
+            start,
             object,
             kind,
             tag,
@@ -70,7 +88,8 @@ impl Tag {
     }
 
     pub fn object(&self) -> Id {
-        Id::from_hex(&self.data[self.object..][..ID_HEX_LEN]).expect("id already validated")
+        Id::from_hex(&self.data[self.object..][..self.hash_algorithm.hex_len()])
+            .expect("id already validated")
     }
 
     pub fn kind(&self) -> ObjectKind {
@@ -86,6 +105,52 @@ impl Tag {
     pub fn message(&self) -> Option<&BStr> {
         self.message.map(|message| self.data[message..].as_bstr())
     }
+
+    /// The length of the object's body, as declared in its header.
+    pub(in crate::object) fn body_len(&self) -> usize {
+        self.data.len() - self.start
+    }
+}
+
+impl TagData {
+    pub fn new(
+        object: Id,
+        kind: ObjectKind,
+        tag: impl Into<BString>,
+        message: impl Into<BString>,
+    ) -> Self {
+        TagData {
+            object,
+            kind,
+            tag: tag.into(),
+            tagger: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn with_tagger(mut self, tagger: SignatureOwned) -> Self {
+        self.tagger = Some(tagger);
+        self
+    }
+
+    /// Serialize the tag in git's canonical format, including its header.
+    pub(in crate::object) fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(format!("object {}\n", self.object).as_bytes());
+        body.extend_from_slice(format!("type {}\n", self.kind.as_str()).as_bytes());
+        body.extend_from_slice(b"tag ");
+        body.extend_from_slice(&self.tag);
+        body.push(b'\n');
+        if let Some(tagger) = &self.tagger {
+            body.extend_from_slice(b"tagger ");
+            body.extend_from_slice(tagger.signature().as_bytes());
+            body.push(b'\n');
+        }
+        body.push(b'\n');
+        body.extend_from_slice(&self.message);
+
+        ObjectHeader::frame(ObjectKind::Tag, &body)
+    }
 }
 
 impl fmt::Debug for Tag {
@@ -1,17 +1,23 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::ops::Range;
 use std::str;
 use std::sync::Arc;
 
-use bstr::{BStr, ByteSlice};
+use bstr::{BStr, BString, ByteSlice};
 use bytes::Bytes;
 use thiserror::Error;
 
-use crate::object::{Id, Parser, ID_LEN};
+use crate::object::{
+    HashAlgorithm, Id, ObjectDatabase, ObjectHeader, ObjectKind, Parser, WriteError, ID_LEN,
+};
 
 #[derive(Clone)]
 pub struct Tree {
     data: Bytes,
+    // The position of the start of the body in `data`.
+    start: usize,
     entries: Arc<[TreeEntryRaw]>,
 }
 
@@ -20,52 +26,245 @@ pub struct TreeEntry<'a> {
     entry: TreeEntryRaw,
 }
 
+/// The kind of a tree entry, as determined by its mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FileMode {
+    /// A subdirectory, with mode `040000`.
+    Tree,
+    /// A regular file, with mode `100644`.
+    Blob,
+    /// An executable file, with mode `100755`.
+    ExecutableBlob,
+    /// A symbolic link, with mode `120000`.
+    Symlink,
+    /// A submodule commit, with mode `160000`.
+    Gitlink,
+}
+
+/// Builds a tree from entries with unique names, and writes it to the object database.
+#[derive(Debug, Clone, Default)]
+pub struct TreeBuilder {
+    entries: BTreeMap<BString, (FileMode, Id)>,
+}
+
+#[derive(Debug, Error)]
+pub enum InsertTreeEntryError {
+    #[error("invalid tree entry name `{0}`")]
+    InvalidName(BString),
+    #[error("the tree already has an entry named `{0}`")]
+    DuplicateName(BString),
+}
+
+/// A tree entry that owns its underlying buffer.
+#[derive(Clone)]
+pub struct TreeEntryOwned {
+    data: Bytes,
+    entry: TreeEntryRaw,
+}
+
 #[derive(Debug, Error)]
-#[error("{0}")]
-pub(in crate::object) struct ParseTreeError(&'static str);
+pub(in crate::object) enum ParseTreeError {
+    #[error("invalid mode `{0}`")]
+    InvalidMode(String),
+    #[error("invalid filename `{0}`")]
+    InvalidFilename(String),
+    #[error("{0}")]
+    Other(&'static str),
+}
 
 #[derive(Clone)]
 struct TreeEntryRaw {
     mode: u16,
+    hash_algorithm: HashAlgorithm,
     id: usize,
     filename: Range<usize>,
 }
 
+impl TreeEntryRaw {
+    // The shortest mode git writes is `40000` for trees, and the longest is
+    // `100644` for blobs.
+    const MIN_MODE_LEN: usize = 5;
+    const MAX_MODE_LEN: usize = 6;
+    const MIN_FILENAME_LEN: usize = 1;
+    // An entry is `<mode> <filename>\0<id>`, and the shortest ids are SHA-1.
+    const MIN_LEN: usize =
+        TreeEntryRaw::MIN_MODE_LEN + 1 + TreeEntryRaw::MIN_FILENAME_LEN + 1 + ID_LEN;
+}
+
 impl Tree {
     pub(in crate::object) fn parse(mut parser: Parser<Bytes>) -> Result<Self, ParseTreeError> {
-        let mut entries = Vec::with_capacity(parser.remaining() / 140);
+        let start = parser.pos();
+        // Sized for the shortest entries git writes, so the vector never needs
+        // to grow for trees written by git.
+        let mut entries = Vec::with_capacity(parser.remaining() / TreeEntryRaw::MIN_LEN);
 
         while !parser.finished() {
-            let mode = parser
-                .consume_until(b' ')
-                .ok_or(ParseTreeError("invalid mode"))?;
-            let mode = str::from_utf8(&parser[mode]).map_err(|_| ParseTreeError("invalid mode"))?;
-            let mode = u16::from_str_radix(mode, 8).map_err(|_| ParseTreeError("invalid mode"))?;
+            // Stop at the end of the filename so a missing space is not
+            // searched for in the following entries.
+            let mode_range = match parser.consume_until_any(b" \0") {
+                Some((mode_range, b' ')) => mode_range,
+                _ => return Err(ParseTreeError::Other("invalid mode")),
+            };
+            let invalid_mode =
+                || ParseTreeError::InvalidMode(parser.lossy_utf8(mode_range.clone()));
+            let mode = str::from_utf8(&parser[mode_range.clone()]).map_err(|_| invalid_mode())?;
+            let mode = u16::from_str_radix(mode, 8).map_err(|_| invalid_mode())?;
 
             let filename = parser
                 .consume_until(0)
-                .ok_or(ParseTreeError("invalid filename"))?;
+                .ok_or(ParseTreeError::Other("invalid filename"))?;
+            if !is_valid_name(&parser[filename.clone()]) {
+                return Err(ParseTreeError::InvalidFilename(parser.lossy_utf8(filename)));
+            }
 
+            let hash_algorithm = parser.hash_algorithm();
             let id = parser.pos();
-            if !parser.advance(ID_LEN) {
-                return Err(ParseTreeError("invalid id"));
+            if !parser.advance(hash_algorithm.digest_len()) {
+                return Err(ParseTreeError::Other("invalid id"));
             }
 
-            entries.push(TreeEntryRaw { mode, filename, id })
+            entries.push(TreeEntryRaw {
+                mode,
+                hash_algorithm,
+                filename,
+                id,
+            })
         }
 
         Ok(Tree {
             data: parser.into_inner(),
+            start,
             entries: Arc::from(entries.as_slice()),
         })
     }
 
+    /// The length of the object's body, as declared in its header.
+    pub(in crate::object) fn body_len(&self) -> usize {
+        self.data.len() - self.start
+    }
+
     pub fn entries(&self) -> impl ExactSizeIterator<Item = TreeEntry> {
         self.entries.iter().cloned().map(move |entry| TreeEntry {
             data: &self.data,
             entry,
         })
     }
+
+    /// Find the entry with the given filename.
+    pub fn entry(&self, name: &[u8]) -> Option<TreeEntry<'_>> {
+        self.find_entry(name).map(|entry| TreeEntry {
+            data: &self.data,
+            entry: entry.clone(),
+        })
+    }
+
+    pub(in crate::object) fn entry_owned(&self, name: &[u8]) -> Option<TreeEntryOwned> {
+        self.find_entry(name).map(|entry| TreeEntryOwned {
+            data: self.data.clone(),
+            entry: entry.clone(),
+        })
+    }
+
+    fn find_entry(&self, name: &[u8]) -> Option<&TreeEntryRaw> {
+        // Entries are sorted as if trees had a trailing slash, so the position
+        // of the entry depends on whether it is a tree.
+        [false, true].iter().find_map(|&is_tree| {
+            let index = self
+                .entries
+                .binary_search_by(|entry| {
+                    compare_names(
+                        &self.data[entry.filename.clone()],
+                        entry.is_tree(),
+                        name,
+                        is_tree,
+                    )
+                })
+                .ok()?;
+            let entry = &self.entries[index];
+            // Names containing `/` or nul bytes may compare equal to a different name.
+            if &self.data[entry.filename.clone()] != name {
+                return None;
+            }
+            Some(entry)
+        })
+    }
+}
+
+impl TreeEntryRaw {
+    fn is_tree(&self) -> bool {
+        FileMode::from_raw(self.mode) == FileMode::Tree
+    }
+}
+
+impl FileMode {
+    const TYPE_MASK: u16 = 0o170000;
+    const EXECUTABLE_MASK: u16 = 0o111;
+
+    /// Classify a raw mode in the same way as git.
+    ///
+    /// Only the file type bits are significant, except for regular files
+    /// which are executable if any execute bit is set. Unrecognized types
+    /// are treated as regular files, since git reads them as blobs.
+    pub fn from_raw(mode: u16) -> Self {
+        match mode & FileMode::TYPE_MASK {
+            0o040000 => FileMode::Tree,
+            0o100000 if mode & FileMode::EXECUTABLE_MASK != 0 => FileMode::ExecutableBlob,
+            0o120000 => FileMode::Symlink,
+            0o160000 => FileMode::Gitlink,
+            _ => FileMode::Blob,
+        }
+    }
+
+    /// The kind of object an entry with this mode refers to. Gitlinks refer
+    /// to commits in a submodule.
+    pub fn object_kind(self) -> ObjectKind {
+        match self {
+            FileMode::Tree => ObjectKind::Tree,
+            FileMode::Gitlink => ObjectKind::Commit,
+            FileMode::Blob | FileMode::ExecutableBlob | FileMode::Symlink => ObjectKind::Blob,
+        }
+    }
+
+    /// The mode git writes for entries of this kind.
+    pub fn to_raw(self) -> u16 {
+        match self {
+            FileMode::Tree => 0o040000,
+            FileMode::Blob => 0o100644,
+            FileMode::ExecutableBlob => 0o100755,
+            FileMode::Symlink => 0o120000,
+            FileMode::Gitlink => 0o160000,
+        }
+    }
+}
+
+// Git forbids empty names, names containing `/` or NUL, and the special `.`
+// and `..` names, which would make paths through the tree ambiguous.
+fn is_valid_name(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name != b"."
+        && name != b".."
+        && !name.contains(&b'/')
+        && !name.contains(&b'\0')
+}
+
+// Compare filenames in the order git sorts tree entries.
+fn compare_names(lhs: &[u8], lhs_is_tree: bool, rhs: &[u8], rhs_is_tree: bool) -> Ordering {
+    let len = lhs.len().min(rhs.len());
+    match lhs[..len].cmp(&rhs[..len]) {
+        Ordering::Equal => (),
+        ordering => return ordering,
+    }
+
+    let terminator = |is_tree| if is_tree { b'/' } else { b'\0' };
+    let lhs_next = lhs
+        .get(len)
+        .copied()
+        .unwrap_or_else(|| terminator(lhs_is_tree));
+    let rhs_next = rhs
+        .get(len)
+        .copied()
+        .unwrap_or_else(|| terminator(rhs_is_tree));
+    lhs_next.cmp(&rhs_next)
 }
 
 impl<'a> TreeEntry<'a> {
@@ -73,8 +272,38 @@ impl<'a> TreeEntry<'a> {
         self.entry.mode
     }
 
+    pub fn file_mode(&self) -> FileMode {
+        FileMode::from_raw(self.entry.mode)
+    }
+
+    pub fn is_tree(&self) -> bool {
+        self.file_mode() == FileMode::Tree
+    }
+
+    /// Returns `true` for both regular and executable files.
+    pub fn is_blob(&self) -> bool {
+        matches!(self.file_mode(), FileMode::Blob | FileMode::ExecutableBlob)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_mode() == FileMode::Symlink
+    }
+
+    /// Returns `true` for submodules, whose id is a commit in the submodule's
+    /// repository rather than an object in this one.
+    pub fn is_submodule(&self) -> bool {
+        self.file_mode() == FileMode::Gitlink
+    }
+
     pub fn id(&self) -> Id {
-        Id::from_bytes(&self.data[self.entry.id..][..ID_LEN])
+        Id::from_bytes(self.id_bytes())
+    }
+
+    /// The raw bytes of the id, borrowed from the tree's buffer.
+    ///
+    /// This avoids copying the id when it only needs to be compared.
+    pub fn id_bytes(&self) -> &'a [u8] {
+        &self.data[self.entry.id..][..self.entry.hash_algorithm.digest_len()]
     }
 
     pub fn filename(&self) -> &'a BStr {
@@ -82,16 +311,103 @@ impl<'a> TreeEntry<'a> {
     }
 }
 
+impl TreeBuilder {
+    pub fn new() -> Self {
+        TreeBuilder::default()
+    }
+
+    /// Add an entry to the tree. Entries may be added in any order.
+    ///
+    /// Returns an error if the name is empty, `.` or `..`, or contains `/` or a
+    /// nul byte, or if the tree already has an entry with the same name, even
+    /// if it is a different kind.
+    pub fn insert(
+        &mut self,
+        name: &[u8],
+        mode: FileMode,
+        id: Id,
+    ) -> Result<(), InsertTreeEntryError> {
+        if !is_valid_name(name) {
+            return Err(InsertTreeEntryError::InvalidName(name.into()));
+        }
+        if self.entries.contains_key(name.as_bstr()) {
+            return Err(InsertTreeEntryError::DuplicateName(name.into()));
+        }
+
+        self.entries.insert(name.into(), (mode, id));
+        Ok(())
+    }
+
+    /// Returns `true` if no entries have been added.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the tree to `db` in git's canonical order, returning its id.
+    pub fn write(self, db: &ObjectDatabase) -> Result<Id, WriteError> {
+        db.write_tree(&self)
+    }
+
+    /// Serialize the tree in git's canonical format, including its header.
+    pub(in crate::object) fn encode(&self) -> Vec<u8> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|(lhs, (lhs_mode, _)), (rhs, (rhs_mode, _))| {
+            compare_names(
+                lhs,
+                *lhs_mode == FileMode::Tree,
+                rhs,
+                *rhs_mode == FileMode::Tree,
+            )
+        });
+
+        // Sized for the longest modes, so the body is allocated once.
+        let mut body = Vec::with_capacity(
+            entries
+                .iter()
+                .map(|(filename, (_, id))| {
+                    TreeEntryRaw::MAX_MODE_LEN + 1 + filename.len() + 1 + id.as_bytes().len()
+                })
+                .sum(),
+        );
+        for (filename, (mode, id)) in entries {
+            body.extend_from_slice(format!("{:o} ", mode.to_raw()).as_bytes());
+            body.extend_from_slice(filename);
+            body.push(b'\0');
+            body.extend_from_slice(id.as_bytes());
+        }
+
+        ObjectHeader::frame(ObjectKind::Tree, &body)
+    }
+}
+
+impl TreeEntryOwned {
+    pub fn entry(&self) -> TreeEntry<'_> {
+        TreeEntry {
+            data: &self.data,
+            entry: self.entry.clone(),
+        }
+    }
+}
+
 impl fmt::Debug for Tree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_list().entries(self.entries()).finish()
     }
 }
 
+impl fmt::Debug for TreeEntryOwned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.entry(), f)
+    }
+}
+
 impl<'a> fmt::Debug for TreeEntry<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("TreeEntry")
-            .field("mode", &self.mode())
+            .field(
+                "mode",
+                &format_args!("{:06o} ({:?})", self.mode(), self.file_mode()),
+            )
             .field("id", &self.id())
             .field("filename", &self.filename())
             .finish()
@@ -100,7 +416,11 @@ impl<'a> fmt::Debug for TreeEntry<'a> {
 
 #[cfg(test)]
 mod tests {
-    use crate::object::{Parser, Tree};
+    use bstr::BString;
+    use bytes::Bytes;
+
+    use crate::object::tree::{InsertTreeEntryError, TreeBuilder, TreeEntryRaw};
+    use crate::object::{FileMode, HashAlgorithm, Id, ObjectKind, Parser, Tree, ID_LEN};
 
     #[test]
     fn test_parse_tree() {
@@ -117,16 +437,221 @@ mod tests {
         let entries: Vec<_> = tree.entries().collect();
 
         assert_eq!(entries[0].mode(), 16384);
+        assert_eq!(entries[0].file_mode(), FileMode::Tree);
         assert_eq!(
             entries[0].id().to_hex(),
             "491989b930c1e5d083a4d2a1f7fa42aaa86c1375"
         );
         assert_eq!(entries[0].filename(), ".github");
         assert_eq!(entries[1].mode(), 33188);
+        assert_eq!(entries[1].file_mode(), FileMode::Blob);
         assert_eq!(
             entries[1].id().to_hex(),
             "693699042b1a8ccf697636d3cd34b200f3a8278b"
         );
         assert_eq!(entries[1].filename(), ".gitignore");
+        assert_eq!(
+            entries[1].id_bytes(),
+            b"\x69\x36\x99\x04\x2b\x1a\x8c\xcf\x69\x76\x36\xd3\xcd\x34\xb2\x00\xf3\xa8\x27\x8b"
+        );
+    }
+
+    #[test]
+    fn test_parse_sha256_tree() {
+        let mut data = Vec::new();
+        for name in &["a", "b"] {
+            data.extend_from_slice(format!("100644 {}\0", name).as_bytes());
+            data.extend_from_slice(HashAlgorithm::Sha256.hash(name.as_bytes()).as_bytes());
+        }
+        let parser = Parser::new(data.into()).with_hash_algorithm(HashAlgorithm::Sha256);
+
+        let tree = Tree::parse(parser).unwrap();
+        let entries: Vec<_> = tree.entries().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].filename(), "a");
+        assert_eq!(entries[0].id(), HashAlgorithm::Sha256.hash(b"a"));
+        assert_eq!(entries[1].filename(), "b");
+        assert_eq!(entries[1].id(), HashAlgorithm::Sha256.hash(b"b"));
+    }
+
+    #[test]
+    fn test_parse_tree_invalid_mode() {
+        let parser = Parser::new(Bytes::from_static(b"10z644 file\0aaaaaaaaaaaaaaaaaaaa"));
+        let err = Tree::parse(parser).unwrap_err();
+        assert_eq!(err.to_string(), "invalid mode `10z644`");
+
+        let parser = Parser::new(Bytes::from_static(b"10\xff644 file\0aaaaaaaaaaaaaaaaaaaa"));
+        let err = Tree::parse(parser).unwrap_err();
+        assert_eq!(err.to_string(), "invalid mode `10\u{fffd}644`");
+
+        let parser = Parser::new(Bytes::from_static(b"100644file\0a aaaaaaaaaaaaaaaaaa"));
+        let err = Tree::parse(parser).unwrap_err();
+        assert_eq!(err.to_string(), "invalid mode");
+    }
+
+    #[test]
+    fn test_parse_tree_invalid_filename() {
+        for (data, message) in &[
+            (
+                &b"100644 a/b\0aaaaaaaaaaaaaaaaaaaa"[..],
+                "invalid filename `a/b`",
+            ),
+            (b"40000 .\0aaaaaaaaaaaaaaaaaaaa", "invalid filename `.`"),
+            (b"40000 ..\0aaaaaaaaaaaaaaaaaaaa", "invalid filename `..`"),
+            (b"100644 \0aaaaaaaaaaaaaaaaaaaa", "invalid filename ``"),
+        ] {
+            let err = Tree::parse(Parser::new(Bytes::from_static(data))).unwrap_err();
+            assert_eq!(err.to_string(), *message);
+        }
+
+        // Names which only start with a dot are allowed.
+        let tree = Tree::parse(Parser::new(Bytes::from_static(
+            b"100644 .gitignore\0aaaaaaaaaaaaaaaaaaaa",
+        )))
+        .unwrap();
+        assert_eq!(tree.entries().next().unwrap().filename(), ".gitignore");
+    }
+
+    #[test]
+    fn test_find_entry() {
+        let mut data = Vec::new();
+        for (mode, name) in &[
+            ("100644", "a"),
+            ("100644", "foo-bar"),
+            ("100644", "foo.txt"),
+            ("40000", "foo"),
+            ("100644", "foo0"),
+            ("160000", "sub"),
+        ] {
+            data.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+            data.extend_from_slice(Id::from_hash(name.as_bytes()).as_bytes());
+        }
+        let tree = Tree::parse(Parser::new(data.into())).unwrap();
+
+        for name in &["a", "foo-bar", "foo.txt", "foo", "foo0", "sub"] {
+            let entry = tree.entry(name.as_bytes()).unwrap();
+            assert_eq!(entry.filename(), name);
+            assert_eq!(entry.id(), Id::from_hash(name.as_bytes()));
+        }
+        assert_eq!(tree.entry(b"foo").unwrap().mode(), 0o040000);
+        assert!(tree.entry(b"foo").unwrap().is_tree());
+        assert!(tree.entry(b"foo.txt").unwrap().is_blob());
+        assert_eq!(tree.entry(b"sub").unwrap().file_mode(), FileMode::Gitlink);
+        assert!(tree.entry(b"fo").is_none());
+        assert!(tree.entry(b"foo/").is_none());
+        assert!(tree.entry(b"a\0").is_none());
+        assert!(tree.entry(b"b").is_none());
+        assert!(tree.entry(b"").is_none());
+    }
+
+    #[test]
+    fn test_file_mode() {
+        assert_eq!(FileMode::from_raw(0o40000), FileMode::Tree);
+        assert_eq!(FileMode::from_raw(0o100644), FileMode::Blob);
+        assert_eq!(FileMode::from_raw(0o100664), FileMode::Blob);
+        assert_eq!(FileMode::from_raw(0o100755), FileMode::ExecutableBlob);
+        assert_eq!(FileMode::from_raw(0o120000), FileMode::Symlink);
+        assert_eq!(FileMode::from_raw(0o160000), FileMode::Gitlink);
+        // Unknown types are read as blobs, as in git.
+        assert_eq!(FileMode::from_raw(0o060644), FileMode::Blob);
+        assert_eq!(FileMode::from_raw(0), FileMode::Blob);
+
+        for &mode in &[
+            FileMode::Tree,
+            FileMode::Blob,
+            FileMode::ExecutableBlob,
+            FileMode::Symlink,
+            FileMode::Gitlink,
+        ] {
+            assert_eq!(FileMode::from_raw(mode.to_raw()), mode);
+
+            let mode_len = format!("{:o}", mode.to_raw()).len();
+            assert!(mode_len >= TreeEntryRaw::MIN_MODE_LEN);
+            assert!(mode_len <= TreeEntryRaw::MAX_MODE_LEN);
+        }
+
+        assert_eq!(FileMode::Tree.object_kind(), ObjectKind::Tree);
+        assert_eq!(FileMode::Symlink.object_kind(), ObjectKind::Blob);
+        assert_eq!(FileMode::Gitlink.object_kind(), ObjectKind::Commit);
+    }
+
+    #[test]
+    fn test_submodule_entry() {
+        let commit = Id::from_hash(b"commit");
+        let mut builder = TreeBuilder::new();
+        builder.insert(b"lib", FileMode::Gitlink, commit).unwrap();
+        builder
+            .insert(b"src", FileMode::Tree, Id::from_hash(b"tree"))
+            .unwrap();
+        let bytes = builder.encode();
+        let body = bytes.splitn(2, |&b| b == 0).nth(1).unwrap();
+        let tree = Tree::parse(Parser::new(Bytes::from(body.to_vec()))).unwrap();
+        let entries: Vec<_> = tree.entries().collect();
+
+        assert!(entries[0].is_submodule());
+        assert!(!entries[0].is_tree() && !entries[0].is_blob());
+        assert_eq!(entries[0].id(), commit);
+        assert!(!entries[1].is_submodule());
+
+        assert_eq!(
+            format!("{:?}", entries[1]),
+            format!(
+                "TreeEntry {{ mode: 040000 (Tree), id: {:?}, filename: \"src\" }}",
+                Id::from_hash(b"tree")
+            )
+        );
+    }
+
+    #[test]
+    fn test_encode_tree() {
+        let id = Id::from_hash(b"blob 0\0");
+        let mut builder = TreeBuilder::new();
+        builder.insert(b"a", FileMode::Tree, id).unwrap();
+        builder.insert(b"a.txt", FileMode::Blob, id).unwrap();
+        builder
+            .insert(b"a-b", FileMode::ExecutableBlob, id)
+            .unwrap();
+        let bytes = builder.encode();
+
+        let header = b"tree 92\0";
+        assert_eq!(&bytes[..header.len()], header);
+        let tree = Tree::parse(Parser::new(Bytes::from(bytes[header.len()..].to_vec()))).unwrap();
+        let entries: Vec<_> = tree
+            .entries()
+            .map(|entry| (entry.filename().to_owned(), entry.mode(), entry.id()))
+            .collect();
+        // Trees are sorted as if their name ended with `/`.
+        assert_eq!(
+            entries,
+            vec![
+                (BString::from("a-b"), 0o100755, id),
+                (BString::from("a.txt"), 0o100644, id),
+                (BString::from("a"), 0o040000, id),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tree_builder_rejects_invalid_names() {
+        let id = Id::from_hash(b"blob 0\0");
+        let mut builder = TreeBuilder::new();
+        builder.insert(b"a", FileMode::Blob, id).unwrap();
+
+        for name in &[&b""[..], b"a/b", b"a\0", b".", b".."] {
+            assert!(matches!(
+                builder.insert(name, FileMode::Blob, id),
+                Err(InsertTreeEntryError::InvalidName(_))
+            ));
+        }
+        // Names must be unique even if the entries have different kinds.
+        assert!(matches!(
+            builder.insert(b"a", FileMode::Tree, id),
+            Err(InsertTreeEntryError::DuplicateName(name)) if name == "a"
+        ));
+    }
+
+    #[test]
+    fn test_entry_min_len() {
+        assert_eq!(TreeEntryRaw::MIN_LEN, b"40000 a\0".len() + ID_LEN);
     }
 }
@@ -31,3 +31,9 @@ pub(crate) enum Error {
         io::Error,
     ),
 }
+
+/// Convert bytes to a string for use in an error message, replacing any
+/// invalid UTF-8 sequences.
+pub(crate) fn lossy_utf8(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
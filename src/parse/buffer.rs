@@ -1,13 +1,13 @@
-use std::io::{self, Read, Seek, SeekFrom};
-use std::mem;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::ops::{Index, Range};
 use std::slice::SliceIndex;
 
 use bytes::{Bytes, BytesMut};
 use flate2::read::ZlibDecoder;
+use flate2::{Decompress, FlushDecompress, Status};
 use memchr::memchr;
 
-use crate::object::{Id, ID_LEN};
+use crate::object::{HashAlgorithm, Id};
 use crate::parse::{Error, Parser};
 
 /// Similar to std::io::BufReader, but with a variable sized buffer
@@ -17,15 +17,12 @@ pub(crate) struct Buffer<R> {
     reader: R,
     // Marks the first byte not yet observed by the user.
     pos: usize,
+    hash_algorithm: HashAlgorithm,
 }
 
 impl<R: Read> Buffer<R> {
     pub fn new(reader: R) -> Self {
-        Buffer {
-            reader,
-            buffer: BytesMut::new(),
-            pos: 0,
-        }
+        Buffer::with_capacity(reader, 0)
     }
 
     pub fn with_capacity(reader: R, capacity: usize) -> Self {
@@ -33,25 +30,33 @@ impl<R: Read> Buffer<R> {
             reader,
             buffer: BytesMut::with_capacity(capacity),
             pos: 0,
+            hash_algorithm: HashAlgorithm::default(),
         }
     }
 
+    /// Set the hash algorithm of the ids being read, which is SHA-1 by default.
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
     pub fn pos(&self) -> usize {
         self.pos
     }
 
-    /// Empty the internal buffer and return it.
+    /// Unwrap the buffer, returning the underlying reader.
     ///
-    /// The position of the reader is unspecified after this operation.
-    pub fn take_buffer(&mut self, range: Range<usize>) -> Bytes {
-        self.pos = 0;
-        mem::take(&mut self.buffer).freeze().slice(range)
+    /// Any data which has been read ahead into the buffer is lost, so the
+    /// position of the reader may be past `pos`.
+    pub fn into_inner(self) -> R {
+        self.reader
     }
 
-    /// Clear the internal buffer
-    pub fn clear_buffer(&mut self) {
+    /// Discard the bytes already observed from the internal buffer, keeping
+    /// any data which has been read ahead.
+    pub fn discard_observed(&mut self) {
+        let _ = self.buffer.split_to(self.pos);
         self.pos = 0;
-        self.buffer.clear();
     }
 
     /// Create a parser for the given range of bytes.
@@ -59,18 +64,54 @@ impl<R: Read> Buffer<R> {
     where
         I: SliceIndex<[u8], Output = [u8]>,
     {
-        Parser::new(&self[range])
+        Parser::new(&self[range]).with_hash_algorithm(self.hash_algorithm)
     }
 
-    /// Return a buffer that decompresses exactly `compressed_size` bytes from this buffer.
-    pub fn decompress_exact<'a>(
-        &'a mut self,
-        compressed_size: usize,
-    ) -> Buffer<ZlibDecoder<impl Read + 'a>> {
-        debug_assert_eq!(self.pos, self.buffer.len()); // Ensure there is no buffered data
+    /// Return a buffer that decompresses the zlib stream starting at the
+    /// current position, including any data which has been read ahead.
+    ///
+    /// The position of the reader is unspecified afterwards, so this buffer
+    /// must be seeked before it is read from again.
+    pub fn decompress<'a>(&'a mut self) -> Buffer<ZlibDecoder<impl Read + 'a>> {
+        let buffered = &self.buffer[self.pos..];
+        let reader = ZlibDecoder::new(buffered.chain(self.reader.by_ref()));
+        Buffer::new(reader).with_hash_algorithm(self.hash_algorithm)
+    }
 
-        let reader = ZlibDecoder::new(self.reader.by_ref().take(compressed_size as u64));
-        Buffer::new(reader)
+    /// Inflate the zlib stream starting at the current position until the end
+    /// of the stream, returning the decompressed bytes.
+    ///
+    /// Unlike `decompress`, the length of the stream does not need to be known.
+    /// The position is advanced past the compressed data, so any data following
+    /// the stream can still be read. Returns `UnexpectedEof` if the reader ends
+    /// before the stream is complete.
+    pub fn decompress_to_end(&mut self) -> Result<Bytes, Error> {
+        const CHUNK_LEN: usize = 8 * 1024;
+
+        let mut decompress = Decompress::new(true);
+        let mut output = Vec::new();
+        loop {
+            let input = match self.fill_buf_to(self.pos + CHUNK_LEN) {
+                Ok(&[]) => return Err(Error::UnexpectedEof),
+                Ok(input) => input,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(Error::Io(err)),
+            };
+
+            if output.len() == output.capacity() {
+                output.reserve(CHUNK_LEN);
+            }
+
+            let total_in = decompress.total_in();
+            let status = decompress
+                .decompress_vec(input, &mut output, FlushDecompress::None)
+                .map_err(io::Error::from)?;
+            self.pos += (decompress.total_in() - total_in) as usize;
+
+            if status == Status::StreamEnd {
+                return Ok(output.into());
+            }
+        }
     }
 
     /// Read an exact number of bytes and create a parser.
@@ -82,8 +123,9 @@ impl<R: Read> Buffer<R> {
     /// Read into an owned parser, .
     pub fn read_to_end_into_parser(self, size: usize) -> Result<Parser<Bytes>, Error> {
         let pos = self.pos;
+        let hash_algorithm = self.hash_algorithm;
         let buffer = self.read_to_end(size)?;
-        Ok(Parser::with_position(buffer, pos))
+        Ok(Parser::with_position(buffer, pos).with_hash_algorithm(hash_algorithm))
     }
 
     /// Read from the reader, calling `pred` on each byte slice until it returns the offset of the end.
@@ -164,16 +206,43 @@ impl<R: Read> Buffer<R> {
         // Read::read_to_end will grow the buffer unnecessarily for the
         // final zero-sized read call. Since we know the buffer size
         // ahead of time, we can avoid this.
+        self.read_eof()?;
+
+        Ok(self.buffer.freeze())
+    }
+
+    /// Write the rest of the reader to `writer` without buffering it. If
+    /// the number of bytes remaining is not `size`, returns an error.
+    ///
+    /// Data may have been written to `writer` before an error is returned.
+    pub fn copy_to_end<W>(mut self, size: usize, writer: &mut W) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        let buffered = &self.buffer[self.pos..];
+        let remaining = size
+            .checked_sub(buffered.len())
+            .ok_or(Error::InvalidLength)?;
+        writer.write_all(buffered)?;
+
+        let copied = io::copy(&mut self.reader.by_ref().take(remaining as u64), writer)?;
+        if copied != remaining as u64 {
+            return Err(Error::InvalidLength);
+        }
+
+        self.read_eof()
+    }
+
+    /// Check that the reader has no more data.
+    fn read_eof(&mut self) -> Result<(), Error> {
         loop {
             match self.reader.read(&mut [0]) {
-                Ok(0) => break,
+                Ok(0) => return Ok(()),
                 Ok(_) => return Err(Error::InvalidLength),
                 Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
                 Err(err) => return Err(Error::Io(err)),
             }
         }
-
-        Ok(self.buffer.freeze())
     }
 
     /// Reads up to the byte at `end`, starting from `self.pos`, from the reader.
@@ -200,9 +269,10 @@ impl<R: Read> Buffer<R> {
         }
     }
 
-    /// Read a 20-byte object id from the reader
+    /// Read a 20 or 32-byte object id from the reader, depending on the hash algorithm.
     pub fn read_id(&mut self) -> Result<Id, Error> {
-        self.read_exact_as_parser(ID_LEN)?.parse_id()
+        self.read_exact_as_parser(self.hash_algorithm.digest_len())?
+            .parse_id()
     }
 }
 
@@ -305,6 +375,7 @@ mod tests {
             reader,
             buffer: bytes[..buffered].into(),
             pos,
+            hash_algorithm: HashAlgorithm::default(),
         };
 
         assert_eq!(
@@ -330,6 +401,7 @@ mod tests {
             reader,
             buffer: bytes[..buffered].into(),
             pos,
+            hash_algorithm: HashAlgorithm::default(),
         };
 
         assert_eq!(
@@ -349,6 +421,7 @@ mod tests {
             reader,
             buffer: bytes.as_ref().into(),
             pos: 0,
+            hash_algorithm: HashAlgorithm::default(),
         };
 
         assert_eq!(buffer.fill_buf_to(size).unwrap(), &bytes[..size]);
@@ -639,4 +712,118 @@ mod tests {
 
         assert_eq!(buffer.read_to_end(size).unwrap().as_ref(), bytes);
     }
+
+    fn compress(bytes: &[u8]) -> Vec<u8> {
+        use flate2::write::ZlibEncoder;
+        use flate2::Compression;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decompress_to_end() {
+        let bytes: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let mut input = compress(&bytes);
+        let compressed_len = input.len();
+        input.extend_from_slice(b"trailer");
+
+        let mut buffer = Buffer::new(io::Cursor::new(input));
+        assert_eq!(
+            buffer.decompress_to_end().unwrap().as_ref(),
+            bytes.as_slice()
+        );
+        assert_eq!(buffer.pos(), compressed_len);
+        let trailer = buffer.read_exact(7).unwrap();
+        assert_eq!(&buffer[trailer], b"trailer");
+    }
+
+    #[test]
+    fn decompress_to_end_empty() {
+        let mut buffer = Buffer::new(io::Cursor::new(compress(b"")));
+        assert!(buffer.decompress_to_end().unwrap().is_empty());
+    }
+
+    #[test]
+    fn decompress_to_end_truncated() {
+        let input = compress(b"abcdefghijklznmnopqrstuvwxyza");
+
+        let mut buffer = Buffer::new(io::Cursor::new(&input[..(input.len() - 1)]));
+        match buffer.decompress_to_end() {
+            Err(Error::UnexpectedEof) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn decompress_to_end_invalid() {
+        let mut buffer = Buffer::new(io::Cursor::new(b"not a zlib stream"));
+        match buffer.decompress_to_end() {
+            Err(Error::Io(_)) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn decompress_after_read_ahead() {
+        let mut input = b"header".to_vec();
+        input.extend_from_slice(&compress(b"abcdefghijklznmnopqrstuvwxyza"));
+
+        // Reading the header also reads part of the compressed data.
+        let mut buffer = Buffer::with_capacity(io::Cursor::new(input), 16);
+        let range = buffer.read_until_byte(b'r', 16).unwrap().unwrap();
+        assert_eq!(&buffer[range], b"header");
+        assert!(buffer.buffer.len() > buffer.pos());
+
+        let data = buffer.decompress().read_to_end(29).unwrap();
+        assert_eq!(data, &b"abcdefghijklznmnopqrstuvwxyza"[..]);
+    }
+
+    #[test]
+    fn copy_to_end() {
+        let bytes = b"abcdefghijklznmnopqrstuvwxyza";
+
+        let mut buffer = Buffer::new(io::Cursor::new(bytes));
+        let range = buffer.read_until_byte(b'z', bytes.len()).unwrap().unwrap();
+        assert_eq!(range, 0..13);
+
+        let mut output = Vec::new();
+        buffer
+            .copy_to_end(bytes.len() - range.end, &mut output)
+            .unwrap();
+        assert_eq!(output, &bytes[range.end..]);
+    }
+
+    #[test]
+    fn copy_to_end_invalid_length() {
+        let bytes = b"abcdefghijklznmnopqrstuvwxyza";
+
+        let buffer = Buffer::new(io::Cursor::new(bytes));
+        match buffer.copy_to_end(bytes.len() - 1, &mut Vec::new()) {
+            Err(Error::InvalidLength) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let buffer = Buffer::new(io::Cursor::new(bytes));
+        match buffer.copy_to_end(bytes.len() + 1, &mut Vec::new()) {
+            Err(Error::InvalidLength) => (),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn into_inner() {
+        let bytes = b"abcdefghijklznmnopqrstuvwxyza";
+
+        let mut buffer = Buffer::new(io::Cursor::new(bytes));
+        let range = buffer.read_exact(4).unwrap();
+        assert_eq!(&buffer[range], b"abcd");
+
+        let mut reader = buffer.into_inner();
+        reader.seek(SeekFrom::Start(13)).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, &bytes[13..]);
+    }
 }
@@ -3,25 +3,40 @@ use std::ops::{Index, Range};
 use std::slice::SliceIndex;
 
 use byteorder::{ByteOrder, NetworkEndian};
-use memchr::memchr;
+use memchr::{memchr, memchr2, memchr3};
 use zerocopy::byteorder::U32;
 use zerocopy::{FromBytes, LayoutVerified};
 
-use crate::object::{Id, ID_HEX_LEN, ID_LEN};
-use crate::parse::Error;
+use crate::object::{HashAlgorithm, Id};
+use crate::parse::{self, Error};
 
 pub(crate) struct Parser<B> {
     buffer: B,
     pos: usize,
+    hash_algorithm: HashAlgorithm,
 }
 
 impl<B> Parser<B> {
     pub fn new(buffer: B) -> Self {
-        Parser { buffer, pos: 0 }
+        Parser::with_position(buffer, 0)
     }
 
     pub fn with_position(buffer: B, pos: usize) -> Self {
-        Parser { buffer, pos }
+        Parser {
+            buffer,
+            pos,
+            hash_algorithm: HashAlgorithm::default(),
+        }
+    }
+
+    /// Set the hash algorithm of the ids being parsed, which is SHA-1 by default.
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    pub fn hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
     }
 }
 
@@ -58,6 +73,11 @@ where
         self.buffer
     }
 
+    /// Convert the given range of bytes to a string for use in an error message.
+    pub fn lossy_utf8(&self, range: Range<usize>) -> String {
+        parse::lossy_utf8(&self[range])
+    }
+
     pub fn consume_bytes(&mut self, bytes: &[u8]) -> bool {
         if self.remaining_buffer().starts_with(bytes) {
             self.pos += bytes.len();
@@ -79,6 +99,24 @@ where
         }
     }
 
+    /// Consume bytes up to and including the first occurrence of any of
+    /// `delims`, returning the range before it and the delimiter found.
+    pub fn consume_until_any(&mut self, delims: &[u8]) -> Option<(Range<usize>, u8)> {
+        let remaining = self.remaining_buffer();
+        let ch_pos = match *delims {
+            [a] => memchr(a, remaining),
+            [a, b] => memchr2(a, b, remaining),
+            [a, b, c] => memchr3(a, b, c, remaining),
+            _ => remaining.iter().position(|ch| delims.contains(ch)),
+        }?;
+
+        let delim = remaining[ch_pos];
+        let start = self.pos;
+        let end = start + ch_pos;
+        self.pos = end + 1;
+        Some((start..end, delim))
+    }
+
     pub fn consume_u32(&mut self, value: u32) -> bool {
         let len = size_of::<u32>();
         if self.remaining() < len || NetworkEndian::read_u32(self.remaining_buffer()) != value {
@@ -108,10 +146,10 @@ where
         }
     }
 
-    // Consume 20 bytes and interpret them as an id
+    // Consume 20 or 32 bytes, depending on the hash algorithm, and interpret them as an id
     pub fn parse_id(&mut self) -> Result<Id, Error> {
         let start = self.pos;
-        if self.advance(ID_LEN) {
+        if self.advance(self.hash_algorithm.digest_len()) {
             Ok(Id::from_bytes(&self[start..self.pos]))
         } else {
             Err(Error::UnexpectedEof)
@@ -154,11 +192,12 @@ where
         }
 
         let start = self.pos();
-        if !self.advance(ID_HEX_LEN) || !self.consume_bytes(b"\n") {
+        let len = self.hash_algorithm.hex_len();
+        if !self.advance(len) || !self.consume_bytes(b"\n") {
             return Err(Error::UnexpectedEof);
         }
 
-        let _ = Id::from_hex(&self[start..][..ID_HEX_LEN])?;
+        let _ = Id::from_hex(&self[start..][..len])?;
 
         Ok(Some(start))
     }
@@ -175,3 +214,26 @@ where
         &self.buffer.as_ref()[idx]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consume_until_any() {
+        let mut parser = Parser::new(&b"ab cd\nef\0gh"[..]);
+        assert_eq!(parser.consume_until_any(b" \n"), Some((0..2, b' ')));
+        assert_eq!(parser.consume_until_any(b" \n"), Some((3..5, b'\n')));
+        assert_eq!(parser.consume_until_any(b" \n\0"), Some((6..8, b'\0')));
+        assert_eq!(parser.consume_until_any(b" \n\0h"), Some((9..10, b'h')));
+        assert!(parser.finished());
+    }
+
+    #[test]
+    fn consume_until_any_not_found() {
+        let mut parser = Parser::new(&b"abcdef"[..]);
+        assert_eq!(parser.consume_until_any(b" \n"), None);
+        assert_eq!(parser.consume_until_any(b""), None);
+        assert_eq!(parser.pos(), 0);
+    }
+}
@@ -1,18 +1,20 @@
 mod database;
 mod direct;
 mod parser;
+mod reflog;
 mod symbolic;
 
-use bstr::ByteSlice;
+use bstr::{BStr, ByteSlice};
 use std::io::{self, Cursor};
 use thiserror::Error;
 
-use crate::object::{self, Object};
+use crate::object::{self, Id, Object, ObjectData, ObjectKind, Tag};
 use crate::repository::Repository;
 
 pub use self::database::ReferenceDatabase;
 pub use self::direct::Direct;
 use self::parser::{ParseError, Parser};
+pub use self::reflog::{ParseReflogError, Reflog, ReflogEntry};
 pub use self::symbolic::Symbolic;
 
 #[derive(Debug, PartialEq)]
@@ -21,21 +23,46 @@ pub enum ReferenceTarget {
     Symbolic(Symbolic),
 }
 
+/// The kind of object a reference points to, as far as it can be found without
+/// parsing the object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TargetKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+    Unknown,
+}
+
 #[derive(Debug)]
 pub struct Reference {
     target: ReferenceTarget,
 }
 
+/// A branch or tag, along with the kind of object it points to.
+#[derive(Debug)]
+pub struct NamedReference {
+    name: Vec<u8>,
+    reference: Reference,
+    kind: TargetKind,
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("reference not found")]
     ReferenceNotFound,
+    #[error("`{0}` is not a valid reference name")]
+    InvalidReferenceName(String),
+    #[error("reference `{name}` not found, tried {}", .tried.join(", "))]
+    ShortNameNotFound { name: String, tried: Vec<String> },
     #[error(
         "reference was stored as invalid Utf16, on windows reference names must be valid utf16"
     )]
     ReferenceNameInvalidUtf16,
-    #[error("reference was given as invalid Utf8")]
-    ReferenceNameInvalidUtf8,
+    #[error("reference `{0}` was given as invalid Utf8")]
+    ReferenceNameInvalidUtf8(String),
+    #[error("symbolic reference `{0}` is part of a cycle")]
+    SymbolicReferenceCycle(String),
     #[error("failed to dereference to an object")]
     DereferencingFailed(
         #[source]
@@ -48,6 +75,12 @@ pub enum Error {
         #[from]
         ParseError,
     ),
+    #[error("the reflog is invalid")]
+    InvalidReflog(
+        #[source]
+        #[from]
+        ParseReflogError,
+    ),
     #[error("io error in reference database")]
     Io(
         #[source]
@@ -63,6 +96,36 @@ impl ReferenceTarget {
             ReferenceTarget::Direct(d) => d.object(repo),
         }
     }
+
+    /// Find the kind of object the reference points to without parsing it.
+    ///
+    /// A packed reference with a peeled id is known to point to an annotated
+    /// tag. The kind of any other direct reference is read from the object
+    /// header, and is `Unknown` if the object does not exist, as in a shallow
+    /// or partial clone. Symbolic references are not followed, so their kind
+    /// is `Unknown`.
+    pub fn kind(&self, repo: &Repository) -> Result<TargetKind, Error> {
+        match self {
+            ReferenceTarget::Direct(d) if d.peeled_id().is_some() => Ok(TargetKind::Tag),
+            ReferenceTarget::Direct(d) => match d.kind(repo) {
+                Err(Error::DereferencingFailed(err)) if err.is_not_found() => {
+                    Ok(TargetKind::Unknown)
+                }
+                result => result,
+            },
+            ReferenceTarget::Symbolic(_) => Ok(TargetKind::Unknown),
+        }
+    }
+
+    /// Peel the reference one level, returning the tag object it points to if
+    /// it is an annotated tag. Lightweight tags point directly at another
+    /// object, so `None` is returned for them.
+    pub fn peel_to_annotated_tag(&self, repo: &Repository) -> Result<Option<Tag>, Error> {
+        match self.peel(repo)?.into_data() {
+            ObjectData::Tag(tag) => Ok(Some(tag)),
+            _ => Ok(None),
+        }
+    }
 }
 
 impl Reference {
@@ -89,11 +152,87 @@ impl Reference {
         }
     }
 
+    /// Returns whether the reference points to another reference, like
+    /// `HEAD` does when a branch is checked out.
+    pub fn is_symbolic(&self) -> bool {
+        matches!(self.target, ReferenceTarget::Symbolic(_))
+    }
+
+    /// Returns whether the reference points directly to an object.
+    pub fn is_direct(&self) -> bool {
+        matches!(self.target, ReferenceTarget::Direct(_))
+    }
+
     pub fn peel(&self, repo: &Repository) -> Result<Object, Error> {
         self.target().peel(repo)
     }
 
+    pub fn peel_to_annotated_tag(&self, repo: &Repository) -> Result<Option<Tag>, Error> {
+        self.target().peel_to_annotated_tag(repo)
+    }
+
     pub fn target(&self) -> &ReferenceTarget {
         &self.target
     }
 }
+
+impl NamedReference {
+    pub(crate) fn new(
+        repo: &Repository,
+        name: Vec<u8>,
+        reference: Reference,
+    ) -> Result<Self, Error> {
+        let kind = reference.target().kind(repo)?;
+        Ok(NamedReference {
+            name,
+            reference,
+            kind,
+        })
+    }
+
+    /// The full name of the reference, such as `refs/heads/master`.
+    pub fn name(&self) -> &BStr {
+        self.name.as_bstr()
+    }
+
+    /// The name of the reference without its `refs/heads/` or `refs/tags/`
+    /// prefix, such as `master`.
+    pub fn short_name(&self) -> &BStr {
+        self.name
+            .splitn_str(3, "/")
+            .nth(2)
+            .unwrap_or(&self.name)
+            .as_bstr()
+    }
+
+    pub fn reference(&self) -> &Reference {
+        &self.reference
+    }
+
+    /// The kind of object the reference points to, found as described for
+    /// `ReferenceTarget::kind`.
+    pub fn kind(&self) -> TargetKind {
+        self.kind
+    }
+
+    /// The id of the object an annotated tag peels to, if it is known without
+    /// reading the tag.
+    pub fn peeled_id(&self) -> Option<Id> {
+        match self.reference.target() {
+            ReferenceTarget::Direct(direct) => direct.peeled_id(),
+            ReferenceTarget::Symbolic(_) => None,
+        }
+    }
+}
+
+impl From<ObjectKind> for TargetKind {
+    fn from(kind: ObjectKind) -> Self {
+        match kind {
+            ObjectKind::Commit => TargetKind::Commit,
+            ObjectKind::Tree => TargetKind::Tree,
+            ObjectKind::Blob => TargetKind::Blob,
+            ObjectKind::Tag => TargetKind::Tag,
+            ObjectKind::OfsDelta | ObjectKind::RefDelta => TargetKind::Unknown,
+        }
+    }
+}
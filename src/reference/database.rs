@@ -1,71 +1,212 @@
+use std::collections::HashSet;
 use std::fs::{self};
-use std::io;
+use std::io::{self, Read};
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
 
+use bstr::ByteSlice;
+
 #[cfg(unix)]
 use std::ffi::OsStr;
 #[cfg(unix)]
 use std::os::unix::ffi::OsStrExt;
 
-use crate::reference::{Error, Reference};
+use crate::parse::lossy_utf8;
+use crate::reference::{Direct, Error, Parser, Reference, ReferenceTarget, Reflog};
 
 const REFS: &[u8] = b"refs";
 const HEADS: &[u8] = b"heads";
 const TAGS: &[u8] = b"tags";
 const REMOTES: &[u8] = b"remotes";
 const HEAD: &[u8] = b"HEAD";
+const HEAD_ALIAS: &[u8] = b"@";
+const FETCH_HEAD: &[u8] = b"FETCH_HEAD";
+const PACKED_REFS: &[u8] = b"packed-refs";
+const LOGS: &[u8] = b"logs";
+const REFS_PREFIX: &[u8] = b"refs/";
+const WORKTREE_REFS_PREFIXES: &[&[u8]] = &[b"refs/worktree/", b"refs/bisect/", b"refs/rewritten/"];
 
 #[derive(Debug)]
 pub struct ReferenceDatabase {
     path: PathBuf,
+    common_path: PathBuf,
 }
 
 impl ReferenceDatabase {
     pub fn open(path: impl Into<PathBuf>) -> Self {
-        ReferenceDatabase { path: path.into() }
+        let path = path.into();
+        ReferenceDatabase {
+            common_path: path.clone(),
+            path,
+        }
+    }
+
+    /// Open the references of a linked worktree, whose `HEAD` is stored in
+    /// `path` while most references are shared with the main repository in
+    /// `common_path`.
+    pub(crate) fn open_with_common_dir(
+        path: impl Into<PathBuf>,
+        common_path: impl Into<PathBuf>,
+    ) -> Self {
+        ReferenceDatabase {
+            path: path.into(),
+            common_path: common_path.into(),
+        }
     }
 
     pub fn head(&self) -> Result<Reference, Error> {
         Ok(Reference::from_reader(self.read_head()?)?)
     }
 
+    /// Returns whether `HEAD` points directly to a commit instead of to a branch.
+    pub fn head_is_detached(&self) -> Result<bool, Error> {
+        Ok(self.head()?.is_direct())
+    }
+
+    /// Read a reference, falling back to the `packed-refs` file if there is
+    /// no loose reference with the given name.
     pub fn reference(&self, name: &[u8]) -> Result<Reference, Error> {
-        Ok(Reference::from_reader(self.read_reference_file(name)?)?)
+        match self.read_reference_file(name) {
+            Ok(file) => Ok(Reference::from_reader(file)?),
+            Err(Error::ReferenceNotFound) => self
+                .packed_references()?
+                .into_iter()
+                .find(|(packed_name, _)| packed_name == name)
+                .map(|(_, target)| Reference { target })
+                .ok_or(Error::ReferenceNotFound),
+            Err(err) => Err(err),
+        }
     }
 
-    pub fn reference_names(&self) -> Result<Vec<Vec<u8>>, Error> {
-        let mut refs = self.head_reference_names()?;
-        refs.append(&mut self.tag_reference_names()?);
-        refs.append(&mut self.remote_reference_names()?);
+    /// Find a reference from a name as it would be given to git, such as
+    /// `master` or `origin/main`.
+    ///
+    /// Like git, the first of these that exists is used:
+    ///
+    /// 1. `<name>`, if it starts with `refs/` or is a pseudo-reference such as
+    ///    `HEAD`, `FETCH_HEAD` or `ORIG_HEAD`
+    /// 2. `refs/<name>`
+    /// 3. `refs/tags/<name>`
+    /// 4. `refs/heads/<name>`
+    /// 5. `refs/remotes/<name>`
+    /// 6. `refs/remotes/<name>/HEAD`
+    ///
+    /// Returns `Error::InvalidReferenceName` if `name` does not follow git's
+    /// rules for reference names, such as containing a `..` component.
+    pub fn find(&self, name: &[u8]) -> Result<Reference, Error> {
+        if name != HEAD_ALIAS && !is_valid_reference_name(name) {
+            return Err(Error::InvalidReferenceName(lossy_utf8(name)));
+        }
+
+        let candidates = ReferenceDatabase::short_name_candidates(name);
+        let mut packed = self.packed_references()?;
+        for candidate in &candidates {
+            let result = if candidate == FETCH_HEAD {
+                self.read_fetch_head()
+            } else {
+                self.read_reference_file(candidate)
+                    .and_then(Reference::from_reader)
+            };
+            match result {
+                Ok(reference) => return Ok(reference),
+                Err(Error::ReferenceNotFound) => (),
+                Err(err) => return Err(err),
+            }
+
+            if let Some(pos) = packed
+                .iter()
+                .position(|(packed_name, _)| packed_name == candidate)
+            {
+                let (_, target) = packed.swap_remove(pos);
+                return Ok(Reference { target });
+            }
+        }
+
+        Err(Error::ShortNameNotFound {
+            name: lossy_utf8(name),
+            tried: candidates
+                .iter()
+                .map(|candidate| lossy_utf8(candidate))
+                .collect(),
+        })
+    }
+
+    /// Read the references in the `packed-refs` file. If the file does not
+    /// exist, there are no packed references.
+    ///
+    /// A packed reference may be out of date if there is also a loose
+    /// reference with the same name.
+    pub fn packed_references(&self) -> Result<Vec<(Vec<u8>, ReferenceTarget)>, Error> {
+        match fs_err::File::open(
+            self.common_path
+                .join(ReferenceDatabase::bytes_to_path(PACKED_REFS)?),
+        ) {
+            Ok(file) => Ok(Parser::new(file).parse_packed()?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Read every reference under `refs/heads`, `refs/tags` and `refs/remotes`,
+    /// along with its name.
+    ///
+    /// Each loose reference is parsed as the directories are walked, so this is
+    /// cheaper than calling `reference` for each of `reference_names`. Packed
+    /// references without a loose reference of the same name follow the loose
+    /// references.
+    pub fn references(&self) -> Result<Vec<(Vec<u8>, Reference)>, Error> {
+        self.references_of_kinds(&[HEADS, TAGS, REMOTES])
+    }
+
+    /// Read every reference under `refs/heads`, as described for `references`.
+    pub fn head_references(&self) -> Result<Vec<(Vec<u8>, Reference)>, Error> {
+        self.references_of_kinds(&[HEADS])
+    }
+
+    /// Read every reference under `refs/tags`, as described for `references`.
+    pub fn tag_references(&self) -> Result<Vec<(Vec<u8>, Reference)>, Error> {
+        self.references_of_kinds(&[TAGS])
+    }
+
+    fn references_of_kinds(&self, kinds: &[&[u8]]) -> Result<Vec<(Vec<u8>, Reference)>, Error> {
+        let mut refs = Vec::new();
+        for kind in kinds {
+            for path in self.get_all_file_paths_from_dir(&self.reference_dir(kind)?)? {
+                let reference = Reference::from_reader(fs_err::File::open(&path)?)?;
+                refs.push((self.reference_name_from_file(&path)?, reference));
+            }
+        }
+
+        let prefixes: Vec<Vec<u8>> = kinds
+            .iter()
+            .map(|kind| [REFS, b"/", kind, b"/"].concat())
+            .collect();
+        let loose_names: HashSet<Vec<u8>> = refs.iter().map(|(name, _)| name.clone()).collect();
+        for (name, target) in self.packed_references()? {
+            if prefixes.iter().any(|prefix| name.starts_with(prefix))
+                && !loose_names.contains(&name)
+            {
+                refs.push((name, Reference { target }));
+            }
+        }
+
         Ok(refs)
     }
 
+    pub fn reference_names(&self) -> Result<Vec<Vec<u8>>, Error> {
+        self.reference_names_of_kinds(&[HEADS, TAGS, REMOTES])
+    }
+
     pub fn head_reference_names(&self) -> Result<Vec<Vec<u8>>, Error> {
-        self.reference_names_from_dir(
-            &self
-                .path
-                .join(ReferenceDatabase::bytes_to_path(REFS)?)
-                .join(ReferenceDatabase::bytes_to_path(HEADS)?),
-        )
+        self.reference_names_of_kinds(&[HEADS])
     }
 
     pub fn tag_reference_names(&self) -> Result<Vec<Vec<u8>>, Error> {
-        self.reference_names_from_dir(
-            &self
-                .path
-                .join(ReferenceDatabase::bytes_to_path(REFS)?)
-                .join(ReferenceDatabase::bytes_to_path(TAGS)?),
-        )
+        self.reference_names_of_kinds(&[TAGS])
     }
 
     pub fn remote_reference_names(&self) -> Result<Vec<Vec<u8>>, Error> {
-        self.reference_names_from_dir(
-            &self
-                .path
-                .join(ReferenceDatabase::bytes_to_path(REFS)?)
-                .join(ReferenceDatabase::bytes_to_path(REMOTES)?),
-        )
+        self.reference_names_of_kinds(&[REMOTES])
     }
 
     pub fn read_head(&self) -> Result<impl io::Read, Error> {
@@ -73,18 +214,123 @@ impl ReferenceDatabase {
     }
 
     pub fn read_reference_file(&self, name: &[u8]) -> Result<impl io::Read, Error> {
-        match fs_err::File::open(self.path.join(ReferenceDatabase::bytes_to_path(name)?)) {
+        // A bare `@` is an alias for `HEAD`.
+        let name = if name == HEAD_ALIAS { HEAD } else { name };
+        match fs_err::File::open(
+            self.reference_root(name)
+                .join(ReferenceDatabase::bytes_to_path(name)?),
+        ) {
+            // A directory of references, such as `refs/heads`, is not itself a reference.
+            Ok(file) if file.metadata()?.is_dir() => Err(Error::ReferenceNotFound),
             Ok(file) => Ok(file),
             Err(err) if err.kind() == io::ErrorKind::NotFound => Err(Error::ReferenceNotFound),
             Err(err) => Err(err.into()),
         }
     }
 
+    /// Read the reflog of a reference. If the reference has no reflog, it is
+    /// empty.
+    pub fn reflog(&self, name: &[u8]) -> Result<Reflog, Error> {
+        let name = if name == HEAD_ALIAS { HEAD } else { name };
+        match fs_err::read(
+            self.reference_root(name)
+                .join(ReferenceDatabase::bytes_to_path(LOGS)?)
+                .join(ReferenceDatabase::bytes_to_path(name)?),
+        ) {
+            Ok(data) => Ok(Reflog::parse(&data)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Reflog::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     pub fn parse_reference(&self, name: &[u8]) -> Result<Reference, Error> {
         // TODO: use name to get reference.
         Ok(Reference::from_reader(self.read_reference_file(name)?)?)
     }
 
+    // The full names a short name may refer to, in order of precedence. The
+    // name itself is only included if it is a full name or a pseudo-reference,
+    // which consist of capital letters and underscores, so that other files in
+    // the git directory are not mistaken for references.
+    fn short_name_candidates(name: &[u8]) -> Vec<Vec<u8>> {
+        let mut candidates = Vec::new();
+        if name.starts_with(REFS_PREFIX)
+            || name == HEAD_ALIAS
+            || (!name.is_empty() && name.iter().all(|&b| matches!(b, b'A'..=b'Z' | b'_')))
+        {
+            candidates.push(name.to_vec());
+        }
+        candidates.push([REFS, b"/", name].concat());
+        for kind in &[TAGS, HEADS, REMOTES] {
+            candidates.push([REFS, b"/", kind, b"/", name].concat());
+        }
+        candidates.push([REFS, b"/", REMOTES, b"/", name, b"/", HEAD].concat());
+        candidates
+    }
+
+    // `FETCH_HEAD` lists everything fetched by the last `git fetch`, one per
+    // line, but only the object id at the start of the first line is used as
+    // its target.
+    fn read_fetch_head(&self) -> Result<Reference, Error> {
+        let mut data = Vec::new();
+        self.read_reference_file(FETCH_HEAD)?
+            .read_to_end(&mut data)?;
+        let id_len = data
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .unwrap_or(data.len());
+        Ok(Reference {
+            target: ReferenceTarget::Direct(Direct::from_bytes(&data[..id_len])?),
+        })
+    }
+
+    // Find the names of loose and packed references under `refs/<kind>/` for
+    // each kind in turn, reading the `packed-refs` file once. Within each kind
+    // loose references come first, and packed references with the same name
+    // as a loose reference are skipped.
+    fn reference_names_of_kinds(&self, kinds: &[&[u8]]) -> Result<Vec<Vec<u8>>, Error> {
+        let packed = self.packed_references()?;
+
+        let mut names = Vec::new();
+        for kind in kinds {
+            let mut kind_names = self.reference_names_from_dir(&self.reference_dir(kind)?)?;
+
+            let prefix = [REFS, b"/", kind, b"/"].concat();
+            let loose_names: HashSet<Vec<u8>> = kind_names.iter().cloned().collect();
+            for (name, _) in &packed {
+                if name.starts_with(&prefix) && !loose_names.contains(name) {
+                    kind_names.push(name.clone());
+                }
+            }
+
+            names.append(&mut kind_names);
+        }
+
+        Ok(names)
+    }
+
+    // Pseudo-references such as `HEAD`, and a few kinds of reference used by
+    // commands in progress, belong to a single worktree. Other references are
+    // shared by every worktree of the repository.
+    fn reference_root(&self, name: &[u8]) -> &Path {
+        if name.starts_with(REFS_PREFIX)
+            && !WORKTREE_REFS_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        {
+            &self.common_path
+        } else {
+            &self.path
+        }
+    }
+
+    fn reference_dir(&self, kind: &[u8]) -> Result<PathBuf, Error> {
+        Ok(self
+            .common_path
+            .join(ReferenceDatabase::bytes_to_path(REFS)?)
+            .join(ReferenceDatabase::bytes_to_path(kind)?))
+    }
+
     fn reference_names_from_dir(&self, path: &Path) -> Result<Vec<Vec<u8>>, Error> {
         let files = self.get_all_file_paths_from_dir(&path)?;
         files
@@ -108,13 +354,15 @@ impl ReferenceDatabase {
                     Err(error) => vec![Err(Error::Io(error))],
                 })
                 .collect(),
+            // A missing refs directory just means there are no references of that kind yet.
+            Err(error) if error.kind() == io::ErrorKind::NotFound => vec![],
             Err(error) => vec![Err(Error::Io(error))],
         }
     }
 
     fn reference_name_from_file(&self, path: &Path) -> Result<Vec<u8>, Error> {
-        ReferenceDatabase::path_to_bytes(&pathdiff::diff_paths(path, &self.path).unwrap()).map(
-            |bytes| {
+        ReferenceDatabase::path_to_bytes(&pathdiff::diff_paths(path, &self.common_path).unwrap())
+            .map(|bytes| {
                 bytes
                     .iter()
                     .map(|b| match b {
@@ -122,8 +370,7 @@ impl ReferenceDatabase {
                         _ => *b,
                     })
                     .collect()
-            },
-        )
+            })
     }
 
     #[cfg(windows)]
@@ -142,9 +389,9 @@ impl ReferenceDatabase {
 
     #[cfg(windows)]
     fn bytes_to_path(bytes: &[u8]) -> Result<&Path, Error> {
-        Ok(Path::new(
-            std::str::from_utf8(bytes).map_err(|_| Error::ReferenceNameInvalidUtf8)?,
-        ))
+        Ok(Path::new(std::str::from_utf8(bytes).map_err(|_| {
+            Error::ReferenceNameInvalidUtf8(crate::parse::lossy_utf8(bytes))
+        })?))
     }
 
     #[cfg(unix)]
@@ -152,3 +399,301 @@ impl ReferenceDatabase {
         Ok(OsStr::from_bytes(bytes).as_ref())
     }
 }
+
+// Check a reference name against the rules of `git check-ref-format`, so it
+// cannot escape the git directory when used as a path.
+fn is_valid_reference_name(name: &[u8]) -> bool {
+    const INVALID_CHARS: &[u8] = b" ~^:?*[\\\x7f";
+
+    !name.is_empty()
+        && !name.ends_with(b".")
+        && !name.contains_str("..")
+        && !name.contains_str("@{")
+        && name != b"@"
+        && !name.iter().any(|&b| b < b' ' || INVALID_CHARS.contains(&b))
+        && name.split_str("/").all(|component| {
+            !component.is_empty() && !component.starts_with(b".") && !component.ends_with(b".lock")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::create_dir_all;
+
+    use bstr::ByteSlice;
+    use tempdir::TempDir;
+
+    use super::*;
+    use crate::reference::Direct;
+
+    #[test]
+    fn missing_refs_directory_has_no_references() {
+        let tempdir = TempDir::new("rusty_git_refdb_tests").unwrap();
+        create_dir_all(tempdir.path().join("refs").join("heads")).unwrap();
+
+        let db = ReferenceDatabase::open(tempdir.path());
+
+        assert_eq!(db.tag_reference_names().unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(db.remote_reference_names().unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(db.reference_names().unwrap(), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn at_sign_is_an_alias_for_head() {
+        let tempdir = TempDir::new("rusty_git_refdb_tests").unwrap();
+        fs::write(tempdir.path().join("HEAD"), "ref: refs/heads/master\n").unwrap();
+
+        let db = ReferenceDatabase::open(tempdir.path());
+
+        assert_eq!(
+            db.reference(b"@").unwrap().name(),
+            Some("refs/heads/master")
+        );
+        assert_eq!(
+            db.parse_reference(b"@").unwrap().name(),
+            Some("refs/heads/master")
+        );
+    }
+
+    #[test]
+    fn missing_reflog_is_empty() {
+        let tempdir = TempDir::new("rusty_git_refdb_tests").unwrap();
+        let db = ReferenceDatabase::open(tempdir.path());
+
+        assert!(db.reflog(b"HEAD").unwrap().is_empty());
+        assert!(db.reflog(b"refs/heads/master").unwrap().is_empty());
+    }
+
+    #[test]
+    fn detached_head_is_direct() {
+        let tempdir = TempDir::new("rusty_git_refdb_tests").unwrap();
+        let db = ReferenceDatabase::open(tempdir.path());
+
+        fs::write(tempdir.path().join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        assert!(!db.head_is_detached().unwrap());
+        assert!(db.head().unwrap().is_symbolic());
+
+        fs::write(
+            tempdir.path().join("HEAD"),
+            "90012941912143fcf042590f8e152c41b13d5520\n",
+        )
+        .unwrap();
+        assert!(db.head_is_detached().unwrap());
+        assert!(db.head().unwrap().is_direct());
+    }
+
+    #[test]
+    fn packed_references_are_merged_with_loose_references() {
+        let tempdir = TempDir::new("rusty_git_refdb_tests").unwrap();
+        create_dir_all(tempdir.path().join("refs").join("heads")).unwrap();
+        fs::write(
+            tempdir.path().join("refs").join("heads").join("master"),
+            "dbaac6ca0b9ec8ff358224e7808cd5a21395b88c\n",
+        )
+        .unwrap();
+        fs::write(
+            tempdir.path().join("packed-refs"),
+            "# pack-refs with: peeled fully-peeled sorted \n\
+             da1a5d18c0ab0c03b20fdd91581bc90acd10d512 refs/heads/master\n\
+             da1a5d18c0ab0c03b20fdd91581bc90acd10d512 refs/heads/packed\n\
+             90012941912143fcf042590f8e152c41b13d5520 refs/tags/v1.0\n\
+             ^da1a5d18c0ab0c03b20fdd91581bc90acd10d512\n",
+        )
+        .unwrap();
+
+        let db = ReferenceDatabase::open(tempdir.path());
+
+        assert_eq!(db.packed_references().unwrap().len(), 3);
+        assert_eq!(
+            db.head_reference_names().unwrap(),
+            vec![b"refs/heads/master".to_vec(), b"refs/heads/packed".to_vec()]
+        );
+        assert_eq!(
+            db.tag_reference_names().unwrap(),
+            vec![b"refs/tags/v1.0".to_vec()]
+        );
+        assert_eq!(db.reference_names().unwrap().len(), 3);
+
+        let direct = |hex: &[u8]| ReferenceTarget::Direct(Direct::from_bytes(hex).unwrap());
+        assert_eq!(
+            db.reference(b"refs/heads/master").unwrap().target(),
+            &direct(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c")
+        );
+        assert_eq!(
+            db.reference(b"refs/heads/packed").unwrap().target(),
+            &direct(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512")
+        );
+        match db.reference(b"refs/tags/v1.0").unwrap().target() {
+            ReferenceTarget::Direct(direct) => {
+                assert_eq!(
+                    direct.id().to_hex(),
+                    "90012941912143fcf042590f8e152c41b13d5520"
+                );
+                assert_eq!(
+                    direct.peeled_id().unwrap().to_hex(),
+                    "da1a5d18c0ab0c03b20fdd91581bc90acd10d512"
+                );
+            }
+            target => panic!("unexpected target {:?}", target),
+        }
+        assert!(matches!(
+            db.reference(b"refs/heads/missing"),
+            Err(Error::ReferenceNotFound)
+        ));
+    }
+
+    #[test]
+    fn short_names_are_resolved_in_order() {
+        let tempdir = TempDir::new("rusty_git_refdb_tests").unwrap();
+        let refs = tempdir.path().join("refs");
+        create_dir_all(refs.join("heads")).unwrap();
+        create_dir_all(refs.join("tags")).unwrap();
+        create_dir_all(refs.join("remotes").join("origin")).unwrap();
+        fs::write(tempdir.path().join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        fs::write(
+            tempdir.path().join("FETCH_HEAD"),
+            "da1a5d18c0ab0c03b20fdd91581bc90acd10d512\t\tbranch 'master' of ../origin\n\
+             dbaac6ca0b9ec8ff358224e7808cd5a21395b88c\tnot-for-merge\tbranch 'other' of ../origin\n",
+        )
+        .unwrap();
+        fs::write(
+            refs.join("heads").join("master"),
+            "dbaac6ca0b9ec8ff358224e7808cd5a21395b88c\n",
+        )
+        .unwrap();
+        fs::write(
+            refs.join("tags").join("master"),
+            "90012941912143fcf042590f8e152c41b13d5520\n",
+        )
+        .unwrap();
+        fs::write(
+            refs.join("remotes").join("origin").join("HEAD"),
+            "ref: refs/remotes/origin/main\n",
+        )
+        .unwrap();
+        fs::write(
+            tempdir.path().join("packed-refs"),
+            "da1a5d18c0ab0c03b20fdd91581bc90acd10d512 refs/heads/packed\n\
+             da1a5d18c0ab0c03b20fdd91581bc90acd10d512 refs/remotes/origin/main\n",
+        )
+        .unwrap();
+        fs::write(tempdir.path().join("config"), "[core]\n").unwrap();
+
+        let db = ReferenceDatabase::open(tempdir.path());
+
+        let direct = |hex: &[u8]| ReferenceTarget::Direct(Direct::from_bytes(hex).unwrap());
+        assert_eq!(db.find(b"HEAD").unwrap().name(), Some("refs/heads/master"));
+        assert_eq!(
+            db.find(b"FETCH_HEAD").unwrap().target(),
+            &direct(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512")
+        );
+        // Tags take precedence over branches with the same name.
+        assert_eq!(
+            db.find(b"master").unwrap().target(),
+            &direct(b"90012941912143fcf042590f8e152c41b13d5520")
+        );
+        assert_eq!(
+            db.find(b"heads/master").unwrap().target(),
+            &direct(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c")
+        );
+        assert_eq!(
+            db.find(b"refs/heads/master").unwrap().target(),
+            &direct(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c")
+        );
+        assert_eq!(
+            db.find(b"packed").unwrap().target(),
+            &direct(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512")
+        );
+        assert_eq!(
+            db.find(b"origin/main").unwrap().target(),
+            &direct(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512")
+        );
+        assert_eq!(
+            db.find(b"origin").unwrap().name(),
+            Some("refs/remotes/origin/main")
+        );
+
+        let err = db.find(b"config").unwrap_err();
+        assert!(matches!(err, Error::ShortNameNotFound { .. }));
+        assert_eq!(
+            err.to_string(),
+            "reference `config` not found, tried refs/config, refs/tags/config, \
+             refs/heads/config, refs/remotes/config, refs/remotes/config/HEAD"
+        );
+        assert!(matches!(
+            db.find(b"ORIG_HEAD"),
+            Err(Error::ShortNameNotFound { .. })
+        ));
+
+        for name in &[
+            &b"../HEAD"[..],
+            b"../config",
+            b"/master",
+            b"heads//master",
+            b"master/",
+            b"master.",
+            b"master.lock",
+            b".hidden",
+            b"heads/.hidden",
+            b"master@{1}",
+            b"mas ter",
+            b"mas\x01ter",
+            b"mas:ter",
+            b"",
+        ] {
+            assert!(
+                matches!(db.find(name), Err(Error::InvalidReferenceName(_))),
+                "{}",
+                name.as_bstr()
+            );
+        }
+    }
+
+    #[test]
+    fn references_are_parsed_with_their_names() {
+        let tempdir = TempDir::new("rusty_git_refdb_tests").unwrap();
+        let remote = tempdir.path().join("refs").join("remotes").join("origin");
+        create_dir_all(tempdir.path().join("refs").join("heads")).unwrap();
+        create_dir_all(&remote).unwrap();
+        fs::write(
+            tempdir.path().join("refs").join("heads").join("master"),
+            "dbaac6ca0b9ec8ff358224e7808cd5a21395b88c\n",
+        )
+        .unwrap();
+        fs::write(remote.join("HEAD"), "ref: refs/remotes/origin/master\n").unwrap();
+        fs::write(
+            tempdir.path().join("packed-refs"),
+            "da1a5d18c0ab0c03b20fdd91581bc90acd10d512 refs/heads/master\n\
+             da1a5d18c0ab0c03b20fdd91581bc90acd10d512 refs/remotes/origin/master\n",
+        )
+        .unwrap();
+
+        let db = ReferenceDatabase::open(tempdir.path());
+
+        let mut references = db.references().unwrap();
+        references.sort_by(|a, b| a.0.cmp(&b.0));
+        let names: Vec<_> = references.iter().map(|(name, _)| name.as_bstr()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "refs/heads/master",
+                "refs/remotes/origin/HEAD",
+                "refs/remotes/origin/master",
+            ]
+        );
+
+        assert_eq!(
+            references[0].1.target(),
+            &ReferenceTarget::Direct(
+                Direct::from_bytes(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c").unwrap()
+            )
+        );
+        assert_eq!(references[1].1.name(), Some("refs/remotes/origin/master"));
+        assert_eq!(
+            references[2].1.target(),
+            &ReferenceTarget::Direct(
+                Direct::from_bytes(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512").unwrap()
+            )
+        );
+    }
+}
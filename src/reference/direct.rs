@@ -1,19 +1,47 @@
 use crate::object::{Id, Object};
-use crate::reference::{Error, ParseError};
+use crate::reference::{Error, ParseError, TargetKind};
 use crate::repository::Repository;
 
 #[derive(Debug, PartialEq)]
 pub struct Direct {
     id: Id,
+    peeled: Option<Id>,
 }
 
 impl Direct {
     pub fn from_bytes(bytes: &[u8]) -> Result<Direct, ParseError> {
         Ok(Direct {
             id: Id::from_hex(bytes).map_err(ParseError::InvalidDirectIdentifier)?,
+            peeled: None,
         })
     }
 
+    pub(in crate::reference) fn with_peeled(self, peeled: Id) -> Self {
+        Direct {
+            peeled: Some(peeled),
+            ..self
+        }
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// The id of the object this reference peels to, if it points to an
+    /// annotated tag and the peeled id was recorded in the `packed-refs` file.
+    pub fn peeled_id(&self) -> Option<Id> {
+        self.peeled
+    }
+
+    /// Read the kind of the object from its header, without parsing it.
+    pub fn kind(&self, repo: &Repository) -> Result<TargetKind, Error> {
+        let header = repo
+            .object_database()
+            .read_header(self.id)
+            .map_err(Error::DereferencingFailed)?;
+        Ok(header.kind.into())
+    }
+
     pub fn object(&self, repo: &Repository) -> Result<Object, Error> {
         repo.object_database()
             .parse_object(self.id)
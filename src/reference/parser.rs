@@ -5,11 +5,14 @@ use std::ops::Range;
 use memchr::memchr;
 use thiserror::Error;
 
-use crate::object::ParseIdError;
+use crate::object::{Id, ParseIdError};
+use crate::parse::lossy_utf8;
 use crate::reference::{Direct, ReferenceTarget, Symbolic};
 
 const SYMBOLIC_PREFIX: &[u8] = b"ref: ";
-const INVALID_REFERENCE_START: &[u8] = b"\n #";
+const PEELED_PREFIX: u8 = b'^';
+const INVALID_REFERENCE_START: &[u8] = b"\n#";
+const LEADING_WHITESPACE: &[u8] = b" \t";
 
 pub struct Parser<R> {
     buffer: Vec<u8>,
@@ -25,10 +28,10 @@ pub enum ParseError {
     Empty,
     #[error("no symbolic reference found")]
     EmptySymbolic,
-    #[error("reference data was invalid")]
-    InvalidReference,
-    #[error("peel object id was invalid")]
-    InvalidPeelIdentifier,
+    #[error("invalid reference line `{0}`")]
+    InvalidReference(String),
+    #[error("invalid peel object id `{0}`")]
+    InvalidPeelIdentifier(String),
     #[error("direct reference object id was invalid")]
     InvalidDirectIdentifier(
         #[from]
@@ -92,6 +95,52 @@ impl<R: Read> Parser<R> {
         Ok(target)
     }
 
+    /// Parse the contents of a `packed-refs` file, returning the name and
+    /// target of each reference.
+    ///
+    /// Lines starting with `^` give the object an annotated tag on the
+    /// previous line peels to, which is returned as its peeled id.
+    pub fn parse_packed(mut self) -> Result<Vec<(Vec<u8>, ReferenceTarget)>, ParseError> {
+        self.reader
+            .read_to_end(&mut self.buffer)
+            .map_err(ParseError::Io)?;
+
+        let mut references = Vec::new();
+        while let Some(range) = self.read_until_valid_reference_line()? {
+            let line = self.buffer[range].trim_end();
+
+            if line.first() == Some(&PEELED_PREFIX) {
+                let peel_id = &line[1..];
+                let peeled = Id::from_hex(peel_id)
+                    .map_err(|_| ParseError::InvalidPeelIdentifier(lossy_utf8(peel_id)))?;
+                match references.pop() {
+                    Some((name, ReferenceTarget::Direct(direct))) => {
+                        references.push((name, ReferenceTarget::Direct(direct.with_peeled(peeled))))
+                    }
+                    _ => return Err(ParseError::InvalidPeelIdentifier(lossy_utf8(peel_id))),
+                }
+                continue;
+            }
+
+            let ch_pos =
+                memchr(b' ', line).ok_or_else(|| ParseError::InvalidReference(lossy_utf8(line)))?;
+            let target = Direct::from_bytes(&line[..ch_pos])?;
+            let name = line[(ch_pos + 1)..].trim_start();
+            if name.is_empty() {
+                return Err(ParseError::InvalidReference(lossy_utf8(line)));
+            }
+
+            references.push((name.to_owned(), ReferenceTarget::Direct(target)));
+        }
+
+        Ok(references)
+    }
+
+    /// Find the next line containing a reference, skipping blank lines and
+    /// comments starting with `#`.
+    ///
+    /// git never writes leading whitespace, but it may be introduced by
+    /// editing the file by hand, so it is trimmed from the returned line.
     pub fn read_until_valid_reference_line(&mut self) -> Result<Option<Range<usize>>, ParseError> {
         while !self.finished() {
             let start = self.pos;
@@ -100,7 +149,12 @@ impl<R: Read> Parser<R> {
                 None => self.buffer.len(),
             };
 
-            if self.reference_line_is_valid(&self.buffer[start..self.pos]) {
+            let start = start
+                + self.buffer[start..self.pos]
+                    .iter()
+                    .take_while(|b| LEADING_WHITESPACE.contains(b))
+                    .count();
+            if start < self.pos && self.reference_line_is_valid(&self.buffer[start..self.pos]) {
                 return Ok(Some(start..self.pos));
             }
         }
@@ -127,7 +181,7 @@ impl<R: Read> Parser<R> {
 #[cfg(test)]
 mod tests {
     use super::{ParseError, Parser};
-    use crate::object::ParseIdError;
+    use crate::object::{Id, ParseIdError};
     use crate::reference::{Direct, ReferenceTarget, Symbolic};
     use proptest::prelude::*;
     use proptest::{arbitrary::any, collection::vec, proptest};
@@ -190,10 +244,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_direct_reference_uppercase() {
+        assert_eq!(
+            parse_ref(b"DBAAC6CA0B9EC8FF358224E7808CD5A21395B88C\n").unwrap(),
+            ReferenceTarget::Direct(
+                Direct::from_bytes(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c").unwrap()
+            )
+        );
+        assert_eq!(
+            parse_packed(b"DBAAC6CA0B9EC8FF358224E7808CD5A21395B88C refs/heads/master\n").unwrap(),
+            vec![(
+                b"refs/heads/master".to_vec(),
+                ReferenceTarget::Direct(
+                    Direct::from_bytes(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c").unwrap()
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_trims_leading_whitespace() {
+        assert_eq!(
+            parse_ref(b" dbaac6ca0b9ec8ff358224e7808cd5a21395b88c\n").unwrap(),
+            ReferenceTarget::Direct(
+                Direct::from_bytes(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c").unwrap()
+            )
+        );
+        assert_eq!(
+            parse_ref(b"\t ref: refs/heads/master\n").unwrap(),
+            ReferenceTarget::Symbolic(Symbolic::from_bytes(b"refs/heads/master", None).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_parse_skips_blank_lines() {
+        assert_eq!(
+            parse_ref(b"\n  \ndbaac6ca0b9ec8ff358224e7808cd5a21395b88c\n").unwrap(),
+            ReferenceTarget::Direct(
+                Direct::from_bytes(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c").unwrap()
+            )
+        );
+    }
+
     #[test]
     fn test_parse_fails_on_empty_input() {
         assert_display_eq!(ParseError::Empty, parse_ref(b"").err().unwrap());
         assert_display_eq!(ParseError::Empty, parse_ref(b" ").err().unwrap());
+        assert_display_eq!(ParseError::Empty, parse_ref(b" \n\t\n").err().unwrap());
+        assert_display_eq!(ParseError::Empty, parse_ref(b"  # stuff").err().unwrap());
         assert_display_eq!(ParseError::Empty, parse_ref(b"\n").err().unwrap());
         assert_display_eq!(ParseError::Empty, parse_ref(b"# stuff").err().unwrap());
         assert_display_eq!(
@@ -220,6 +319,68 @@ mod tests {
         );
     }
 
+    fn parse_packed(bytes: &[u8]) -> Result<Vec<(Vec<u8>, ReferenceTarget)>, ParseError> {
+        Parser::new(io::Cursor::new(bytes)).parse_packed()
+    }
+
+    #[test]
+    fn test_parse_packed_references() {
+        let references = parse_packed(
+            b"# pack-refs with: peeled fully-peeled sorted \n\
+              da1a5d18c0ab0c03b20fdd91581bc90acd10d512 refs/heads/master\n\
+              dbaac6ca0b9ec8ff358224e7808cd5a21395b88c refs/tags/v1.0\n\
+              ^da1a5d18c0ab0c03b20fdd91581bc90acd10d512\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            references,
+            vec![
+                (
+                    b"refs/heads/master".to_vec(),
+                    ReferenceTarget::Direct(
+                        Direct::from_bytes(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512").unwrap()
+                    )
+                ),
+                (
+                    b"refs/tags/v1.0".to_vec(),
+                    ReferenceTarget::Direct(
+                        Direct::from_bytes(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c")
+                            .unwrap()
+                            .with_peeled(
+                                Id::from_hex(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512").unwrap()
+                            )
+                    )
+                ),
+            ]
+        );
+        assert!(parse_packed(b"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_packed_references_fails_on_invalid_lines() {
+        assert_display_eq!(
+            ParseError::InvalidReference("da1a5d18c0ab0c03b20fdd91581bc90acd10d512".to_owned()),
+            parse_packed(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512")
+                .err()
+                .unwrap()
+        );
+        assert_display_eq!(
+            ParseError::InvalidPeelIdentifier(
+                "da1a5d18c0ab0c03b20fdd91581bc90acd10d512".to_owned()
+            ),
+            parse_packed(b"^da1a5d18c0ab0c03b20fdd91581bc90acd10d512")
+                .err()
+                .unwrap()
+        );
+        assert_display_eq!(
+            ParseError::InvalidPeelIdentifier("badid".to_owned()),
+            parse_packed(b"da1a5d18c0ab0c03b20fdd91581bc90acd10d512 refs/heads/master\n^badid")
+                .err()
+                .unwrap()
+        );
+    }
+
     proptest! {
         #![proptest_config(ProptestConfig {
           cases: 10000, .. ProptestConfig::default()
@@ -227,6 +388,7 @@ mod tests {
         #[test]
         fn randomized_data_does_not_panic(bytes in vec(any::<u8>(), ..200)) {
             parse_ref(&bytes).ok();
+            parse_packed(&bytes).ok();
         }
     }
 }
@@ -0,0 +1,187 @@
+use std::slice;
+use std::vec;
+
+use bstr::{BStr, ByteSlice};
+use memchr::memchr;
+use thiserror::Error;
+
+use crate::object::{Id, ParseIdError, ParseSignatureError, Signature, SignatureOwned};
+
+/// The log of changes made to a reference, as stored in `logs/<name>`.
+///
+/// Entries are ordered from oldest to newest, as they appear in the file.
+#[derive(Debug, Default)]
+pub struct Reflog {
+    entries: Vec<ReflogEntry>,
+}
+
+/// A single change to a reference, recorded in its reflog.
+#[derive(Debug)]
+pub struct ReflogEntry {
+    old: Id,
+    new: Id,
+    committer: SignatureOwned,
+    message: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+pub enum ParseReflogError {
+    #[error("reflog entry has an invalid object id")]
+    InvalidId(
+        #[source]
+        #[from]
+        ParseIdError,
+    ),
+    #[error("reflog entry has an invalid signature")]
+    InvalidSignature(
+        #[source]
+        #[from]
+        ParseSignatureError,
+    ),
+    #[error("reflog entry is missing its object ids")]
+    MissingId,
+}
+
+impl Reflog {
+    pub(in crate::reference) fn parse(data: &[u8]) -> Result<Self, ParseReflogError> {
+        let entries = data
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(ReflogEntry::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Reflog { entries })
+    }
+
+    /// Returns the number of entries in the reflog.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over the entries of the reflog, from oldest to newest.
+    pub fn iter(&self) -> slice::Iter<'_, ReflogEntry> {
+        self.entries.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Reflog {
+    type Item = &'a ReflogEntry;
+    type IntoIter = slice::Iter<'a, ReflogEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for Reflog {
+    type Item = ReflogEntry;
+    type IntoIter = vec::IntoIter<ReflogEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl ReflogEntry {
+    // Parse a line of the form `<old-id> <new-id> <committer>\t<message>`.
+    // The message may be omitted along with the tab.
+    fn parse(line: &[u8]) -> Result<Self, ParseReflogError> {
+        let (line, message) = match memchr(b'\t', line) {
+            Some(pos) => (&line[..pos], &line[pos + 1..]),
+            None => (line, &b""[..]),
+        };
+
+        let (old, line) = parse_id(line)?;
+        let (new, line) = parse_id(line)?;
+        let committer = Signature::parse_line(line)?;
+
+        Ok(ReflogEntry {
+            old,
+            new,
+            committer,
+            message: message.to_vec(),
+        })
+    }
+
+    /// The object id the reference pointed to before the change. This is the
+    /// zero id if the reference was created by the change.
+    pub fn old_id(&self) -> Id {
+        self.old
+    }
+
+    /// The object id the reference pointed to after the change. This is the
+    /// zero id if the reference was deleted by the change.
+    pub fn new_id(&self) -> Id {
+        self.new
+    }
+
+    /// The identity of the user who made the change, and when it was made.
+    pub fn committer(&self) -> Signature<'_> {
+        self.committer.signature()
+    }
+
+    /// A description of the change, such as `commit: Initial commit.`.
+    pub fn message(&self) -> &BStr {
+        self.message.as_bstr()
+    }
+}
+
+fn parse_id(line: &[u8]) -> Result<(Id, &[u8]), ParseReflogError> {
+    let pos = memchr(b' ', line).ok_or(ParseReflogError::MissingId)?;
+    Ok((Id::from_hex(&line[..pos])?, &line[pos + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reflog() {
+        let reflog = Reflog::parse(
+            b"0000000000000000000000000000000000000000 dbaac6ca0b9ec8ff358224e7808cd5a21395b88c \
+              Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100\tcommit (initial): Initial commit.\n\
+              dbaac6ca0b9ec8ff358224e7808cd5a21395b88c da1a5d18c0ab0c03b20fdd91581bc90acd10d512 \
+              Andrew Hickman <me@andrewhickman.dev> 1596907299 +0100\n",
+        )
+        .unwrap();
+
+        assert_eq!(reflog.len(), 2);
+        let entries: Vec<_> = reflog.iter().collect();
+
+        assert!(entries[0].old_id().is_zero());
+        assert_eq!(
+            entries[0].new_id(),
+            Id::from_hex(b"dbaac6ca0b9ec8ff358224e7808cd5a21395b88c").unwrap()
+        );
+        assert_eq!(entries[0].committer().name(), "Andrew Hickman");
+        assert_eq!(entries[0].committer().email(), "me@andrewhickman.dev");
+        assert_eq!(entries[0].committer().time().unwrap().seconds(), 1596907199);
+        assert_eq!(entries[0].message(), "commit (initial): Initial commit.");
+
+        assert_eq!(entries[1].old_id(), entries[0].new_id());
+        assert_eq!(entries[1].committer().timezone(), Some(b"+0100".as_bstr()));
+        assert_eq!(entries[1].message(), "");
+    }
+
+    #[test]
+    fn parse_invalid_reflog() {
+        assert!(matches!(
+            Reflog::parse(b"0000000000000000000000000000000000000000\n"),
+            Err(ParseReflogError::MissingId)
+        ));
+        assert!(matches!(
+            Reflog::parse(b"00000 00000 Andrew Hickman <me@andrewhickman.dev> 1596907199 +0100\n"),
+            Err(ParseReflogError::InvalidId(_))
+        ));
+        assert!(matches!(
+            Reflog::parse(
+                b"0000000000000000000000000000000000000000 \
+                  0000000000000000000000000000000000000000 Andrew Hickman\tmessage\n"
+            ),
+            Err(ParseReflogError::InvalidSignature(_))
+        ));
+    }
+}
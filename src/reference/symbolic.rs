@@ -1,8 +1,10 @@
 use bstr::{BStr, ByteSlice};
+use std::collections::HashSet;
 use std::fmt;
 
 use crate::object::Object;
-use crate::reference::{Direct, Error, ParseError};
+use crate::parse::lossy_utf8;
+use crate::reference::{Direct, Error, ParseError, ReferenceTarget};
 use crate::repository::Repository;
 
 #[derive(PartialEq)]
@@ -30,10 +32,32 @@ impl Symbolic {
         self.data.as_bstr()
     }
 
+    /// Returns whether the id of the peeled object is stored alongside the reference.
+    pub fn is_peeled(&self) -> bool {
+        self.direct_peel.is_some()
+    }
+
+    /// Follow the chain of symbolic references to the object at its end.
+    ///
+    /// Returns `Error::SymbolicReferenceCycle` if a reference in the chain
+    /// refers back to an earlier one.
     pub fn peel(&self, repo: &Repository) -> Result<Object, Error> {
-        match &self.direct_peel {
-            Some(direct) => direct.object(repo),
-            None => repo.reference_database().reference(&self.data)?.peel(repo),
+        let mut visited = HashSet::new();
+        let mut reference;
+        let mut symbolic = self;
+        loop {
+            if let Some(direct) = &symbolic.direct_peel {
+                return direct.object(repo);
+            }
+            if !visited.insert(symbolic.data.clone()) {
+                return Err(Error::SymbolicReferenceCycle(lossy_utf8(&symbolic.data)));
+            }
+
+            reference = repo.reference_database().reference(&symbolic.data)?;
+            symbolic = match reference.target() {
+                ReferenceTarget::Direct(direct) => return direct.object(repo),
+                ReferenceTarget::Symbolic(symbolic) => symbolic,
+            };
         }
     }
 }
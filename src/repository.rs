@@ -1,19 +1,41 @@
-use std::io;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BinaryHeap, HashSet};
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
+use bstr::{BStr, BString, ByteSlice};
 use thiserror::Error;
 
-use crate::object::ObjectDatabase;
-use crate::reference::ReferenceDatabase;
+use crate::config::{Config, ConfigValueError, ReadConfigError};
+use crate::index::{Index, ReadIndexError};
+use crate::object::{
+    Blob, Commit, FileMode, HashAlgorithm, Id, ObjectData, ObjectDatabase, ObjectKind,
+    ReadObjectError, RefreshError, Tag, Tree, TreeBuilder, WriteError,
+};
+use crate::reference::{self, NamedReference, Reference, ReferenceDatabase, TargetKind};
 
 const DOTGIT_FOLDER: &str = ".git";
+const INDEX_FILE: &str = "index";
+const EMPTY_TREE: &[u8] = b"tree 0\0";
+const COMMONDIR_FILE: &str = "commondir";
+const CONFIG_FILE: &str = "config";
+const HEAD_FILE: &str = "HEAD";
+const OBJECTS_FOLDER: &str = "objects";
+const REFS_FOLDER: &str = "refs";
+const GITDIR_PREFIX: &[u8] = b"gitdir: ";
+const GIT_DIR_ENV: &str = "GIT_DIR";
+const GIT_WORK_TREE_ENV: &str = "GIT_WORK_TREE";
+// The number of commits older than the target to visit before giving up on an
+// ancestry walk, to allow for clock skew. This matches git's `SLOP`.
+const ANCESTRY_SLOP: u32 = 5;
 
 #[derive(Debug)]
 pub struct Repository {
-    workdir: PathBuf,
+    workdir: Option<PathBuf>,
     dotgit: PathBuf,
     object_database: ObjectDatabase,
     reference_database: ReferenceDatabase,
+    config: Config,
 }
 
 #[derive(Debug, Error)]
@@ -21,6 +43,25 @@ pub struct Repository {
 pub enum OpenError {
     #[error("repository not found at `{0}`")]
     NotFound(PathBuf),
+    #[error("permission denied opening repository at `{0}`")]
+    PermissionDenied(PathBuf),
+    #[error("invalid repository at `{path}`: `{missing}` is missing")]
+    InvalidRepository {
+        path: PathBuf,
+        missing: &'static str,
+    },
+    #[error("failed to read the repository config")]
+    ReadConfig(
+        #[source]
+        #[from]
+        ReadConfigError,
+    ),
+    #[error("invalid repository config")]
+    InvalidConfig(
+        #[source]
+        #[from]
+        ConfigValueError,
+    ),
     #[error("io error opening repository")]
     Io(
         #[source]
@@ -29,7 +70,75 @@ pub enum OpenError {
     ),
 }
 
+/// The paths which differ between the index and the tree of `HEAD`, i.e. the
+/// changes which are staged to be committed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Status {
+    added: Vec<BString>,
+    modified: Vec<BString>,
+    deleted: Vec<BString>,
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum StatusError {
+    #[error("failed to read the index")]
+    ReadIndex(
+        #[source]
+        #[from]
+        ReadIndexError,
+    ),
+    #[error("failed to read HEAD")]
+    ReadHead(
+        #[source]
+        #[from]
+        reference::Error,
+    ),
+    #[error(transparent)]
+    ReadObject(#[from] ReadObjectError),
+}
+
+/// The result of a three-way merge of trees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeResult {
+    tree: Id,
+    conflicts: Vec<MergeConflict>,
+}
+
+/// A path which was changed differently on both sides of a merge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    path: BString,
+    base: Option<Id>,
+    ours: Option<Id>,
+    theirs: Option<Id>,
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MergeError {
+    #[error(transparent)]
+    ReadObject(#[from] ReadObjectError),
+    #[error("failed to write the merged tree")]
+    Write(
+        #[source]
+        #[from]
+        WriteError,
+    ),
+}
+
 impl Repository {
+    /// Open the repository with a working directory at `path`.
+    ///
+    /// The `.git` folder may also be a file containing `gitdir: <path>`, as
+    /// used by linked worktrees and submodules. If the git directory has a
+    /// `commondir` file, objects and shared references are read from the
+    /// directory it names.
+    ///
+    /// Returns `OpenError::InvalidRepository` if the git directory does not
+    /// contain `objects`, `refs` and `HEAD`. The repository config is read when
+    /// it is opened, so an invalid config or an unknown `extensions.objectFormat`
+    /// is also an error.
     pub fn open<P>(path: P) -> Result<Repository, OpenError>
     where
         P: Into<PathBuf>,
@@ -37,26 +146,177 @@ impl Repository {
         let path = path.into();
 
         let dotgit = path.join(DOTGIT_FOLDER);
-        match fs_err::metadata(&dotgit) {
-            Ok(metadata) if metadata.is_dir() => (),
+        let dotgit = match fs_err::metadata(&dotgit) {
+            Ok(metadata) if metadata.is_dir() => dotgit,
+            Ok(metadata) if metadata.is_file() => match read_gitdir_file(&path, &dotgit) {
+                Ok(Some(gitdir)) => gitdir,
+                Ok(None) => return Err(OpenError::NotFound(path)),
+                Err(err) => return Err(OpenError::from_io(&path, err)),
+            },
             Ok(_) => return Err(OpenError::NotFound(path)),
             Err(err) if err.kind() == io::ErrorKind::NotFound => {
                 return Err(OpenError::NotFound(path))
             }
-            Err(err) => return Err(OpenError::from(err)),
+            Err(err) => return Err(OpenError::from_io(&path, err)),
         };
 
-        let object_database = ObjectDatabase::open(&dotgit);
-        let reference_database = ReferenceDatabase::open(dotgit.clone());
+        Repository::open_git_dir(Some(path), dotgit)
+    }
+
+    /// Open a bare repository, where `path` is the git directory itself.
+    pub fn open_bare<P>(path: P) -> Result<Repository, OpenError>
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+
+        match fs_err::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => (),
+            Ok(_) => return Err(OpenError::NotFound(path)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(OpenError::NotFound(path))
+            }
+            Err(err) => return Err(OpenError::from_io(&path, err)),
+        }
+
+        Repository::open_git_dir(None, path)
+    }
+
+    /// Open a repository using the `GIT_DIR` and `GIT_WORK_TREE` environment
+    /// variables, as git does.
+    ///
+    /// If `GIT_DIR` is set, it is used as the git directory, and the repository
+    /// is bare unless `GIT_WORK_TREE` is also set. Otherwise the repository is
+    /// discovered from the current directory, and `GIT_WORK_TREE` overrides its
+    /// working directory. Relative paths are resolved against the current
+    /// directory.
+    pub fn open_from_env() -> Result<Repository, OpenError> {
+        let current_dir = env::current_dir()?;
+        let work_tree = env::var_os(GIT_WORK_TREE_ENV).map(|path| current_dir.join(path));
+        match env::var_os(GIT_DIR_ENV) {
+            Some(git_dir) => {
+                let mut repo = Repository::open_bare(current_dir.join(git_dir))?;
+                repo.workdir = work_tree;
+                Ok(repo)
+            }
+            None => {
+                let mut repo = Repository::discover(current_dir)?;
+                if work_tree.is_some() {
+                    repo.workdir = work_tree;
+                }
+                Ok(repo)
+            }
+        }
+    }
+
+    fn open_git_dir(workdir: Option<PathBuf>, dotgit: PathBuf) -> Result<Repository, OpenError> {
+        let common_dir =
+            read_commondir_file(&dotgit).map_err(|err| OpenError::from_io(&dotgit, err))?;
+
+        // Check for the minimal layout of a git directory, as created by `git init`.
+        let required = [
+            (&common_dir, OBJECTS_FOLDER, true),
+            (&common_dir, REFS_FOLDER, true),
+            (&dotgit, HEAD_FILE, false),
+        ];
+        for &(dir, name, is_dir) in &required {
+            let exists = match fs_err::metadata(dir.join(name)) {
+                Ok(metadata) => metadata.is_dir() == is_dir,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+                Err(err) => return Err(OpenError::from_io(dir, err)),
+            };
+            if !exists {
+                return Err(OpenError::InvalidRepository {
+                    path: dir.clone(),
+                    missing: name,
+                });
+            }
+        }
+
+        let config = Config::read(&common_dir.join(CONFIG_FILE))?;
+        let object_database =
+            ObjectDatabase::open(&common_dir, HashAlgorithm::from_config(&config)?);
+        let reference_database =
+            ReferenceDatabase::open_with_common_dir(dotgit.clone(), common_dir);
 
         Ok(Repository {
-            workdir: path,
+            workdir,
             dotgit,
             object_database,
             reference_database,
+            config,
         })
     }
 
+    /// Open the repository containing `start`, by looking for a `.git` folder
+    /// in `start` and each of its parent directories.
+    ///
+    /// Directories whose `.git` is not a valid git directory are skipped, as git
+    /// does. The search stops before crossing into a different file system.
+    pub fn discover<P>(start: P) -> Result<Repository, OpenError>
+    where
+        P: Into<PathBuf>,
+    {
+        Repository::discover_with_ceiling_dirs(start, &[])
+    }
+
+    /// Like [`Repository::discover`], but the search also stops before
+    /// reaching any of `ceiling_dirs`, like `GIT_CEILING_DIRECTORIES`. The
+    /// ceiling directories themselves are not searched.
+    ///
+    /// `start` is always searched, even if it is one of the ceiling directories.
+    pub fn discover_with_ceiling_dirs<P>(
+        start: P,
+        ceiling_dirs: &[PathBuf],
+    ) -> Result<Repository, OpenError>
+    where
+        P: Into<PathBuf>,
+    {
+        let start = start.into();
+        // Resolve `..` components and symbolic links, so that each parent is
+        // really the next directory up and can be compared to the ceilings.
+        let absolute_start = match fs_err::canonicalize(&start) {
+            Ok(path) => path,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(OpenError::NotFound(start))
+            }
+            Err(err) => return Err(OpenError::from_io(&start, err)),
+        };
+        // Ceilings which do not exist can never be reached, so they are ignored.
+        let ceiling_dirs: Vec<PathBuf> = ceiling_dirs
+            .iter()
+            .filter_map(|dir| fs_err::canonicalize(dir).ok())
+            .collect();
+
+        let mut dir = absolute_start.as_path();
+        loop {
+            match Repository::open(dir) {
+                Err(OpenError::NotFound(_)) | Err(OpenError::InvalidRepository { .. }) => (),
+                result => return result,
+            }
+
+            dir = match dir.parent() {
+                Some(parent)
+                    if !ceiling_dirs.iter().any(|ceiling| ceiling == parent)
+                        && same_file_system(&absolute_start, parent)? =>
+                {
+                    parent
+                }
+                _ => return Err(OpenError::NotFound(start)),
+            };
+        }
+    }
+
+    /// The working directory of the repository, or `None` if it is bare.
+    pub fn workdir(&self) -> Option<&Path> {
+        self.workdir.as_deref()
+    }
+
+    /// The git directory of the repository, such as the `.git` folder.
+    pub fn git_dir(&self) -> &Path {
+        &self.dotgit
+    }
+
     pub fn object_database(&self) -> &ObjectDatabase {
         &self.object_database
     }
@@ -64,4 +324,562 @@ impl Repository {
     pub fn reference_database(&self) -> &ReferenceDatabase {
         &self.reference_database
     }
+
+    /// Find a reference from a short name such as `master` or `origin/main`.
+    ///
+    /// See [`ReferenceDatabase::find`] for the names that are tried.
+    pub fn find_reference(&self, name: &[u8]) -> Result<Reference, reference::Error> {
+        self.reference_database.find(name)
+    }
+
+    /// The repository config, read from the `config` file in the git directory
+    /// when the repository is opened. Global and system config files are not read.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Pick up changes made to the repository by other processes.
+    ///
+    /// Packs written since the repository was opened are otherwise only found
+    /// when an object is missing, at most once every couple of seconds.
+    /// References and loose objects are not cached, so they are always read
+    /// from disk.
+    pub fn refresh(&self) -> Result<(), RefreshError> {
+        self.object_database.refresh_packs()
+    }
+
+    /// Write the contents of an object to `writer`, without its header.
+    ///
+    /// See [`ObjectDatabase::stream_object`] for details on memory usage.
+    pub fn stream_object<W>(&self, id: Id, writer: &mut W) -> Result<(), ReadObjectError>
+    where
+        W: Write,
+    {
+        self.object_database.stream_object(id, writer)
+    }
+
+    /// Read a commit object, returning an error if the object is a different kind.
+    pub fn find_commit(&self, id: Id) -> Result<Commit, ReadObjectError> {
+        self.object_database.parse_object(id)?.into_commit()
+    }
+
+    /// Read a tree object, returning an error if the object is a different kind.
+    pub fn find_tree(&self, id: Id) -> Result<Tree, ReadObjectError> {
+        self.object_database.parse_object(id)?.into_tree()
+    }
+
+    /// Read a blob object, returning an error if the object is a different kind.
+    pub fn find_blob(&self, id: Id) -> Result<Blob, ReadObjectError> {
+        self.object_database.parse_object(id)?.into_blob()
+    }
+
+    /// Read a tag object, returning an error if the object is a different kind.
+    pub fn find_tag(&self, id: Id) -> Result<Tag, ReadObjectError> {
+        self.object_database.parse_object(id)?.into_tag()
+    }
+
+    /// Returns whether a commit has the same tree as its first parent, as created
+    /// by `git commit --allow-empty`. A root commit is empty if its tree is empty.
+    ///
+    /// Only the tree ids are compared, so no trees are read.
+    pub fn is_empty_commit(&self, id: Id) -> Result<bool, ReadObjectError> {
+        let commit = self.find_commit(id)?;
+        let parent_tree = match commit.parents().next() {
+            Some(parent) => self.find_commit(parent)?.tree(),
+            None => self.object_database.hash_algorithm().hash(EMPTY_TREE),
+        };
+        Ok(commit.tree() == parent_tree)
+    }
+
+    /// Returns whether `ancestor` can be reached from `descendant` by following
+    /// commit parents, i.e. whether `descendant` can be fast-forwarded from `ancestor`.
+    ///
+    /// A commit is considered to be its own ancestor. Like git, the walk stops once
+    /// a few commits in a row are older than `ancestor`, which tolerates small
+    /// amounts of clock skew between commits.
+    pub fn is_ancestor(&self, ancestor: Id, descendant: Id) -> Result<bool, ReadObjectError> {
+        if ancestor == descendant {
+            return Ok(true);
+        }
+
+        let min_time = commit_time(&self.find_commit(ancestor)?).unwrap_or(i64::MIN);
+
+        let mut seen = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        seen.insert(descendant);
+        queue.push(self.read_ancestry_entry(descendant)?);
+
+        // Visit the most recent commits first. Once the most recent remaining
+        // commit is older than `ancestor`, so is every other queued commit, but
+        // keep going for a few more in case of a skewed clock.
+        let mut slop = ANCESTRY_SLOP;
+        while let Some((time, _, parents)) = queue.pop() {
+            if time < min_time {
+                slop -= 1;
+                if slop == 0 {
+                    break;
+                }
+            } else {
+                slop = ANCESTRY_SLOP;
+            }
+
+            for parent in parents {
+                if parent == ancestor {
+                    return Ok(true);
+                }
+                if seen.insert(parent) {
+                    queue.push(self.read_ancestry_entry(parent)?);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Returns the ids of all objects reachable from `root`, including `root` itself.
+    pub fn reachable_objects(&self, root: Id) -> Result<Vec<Id>, ReadObjectError> {
+        self.walk_reachable_objects(root, None)
+    }
+
+    /// Returns the ids of all objects of the given kind reachable from `root`,
+    /// including `root` itself.
+    ///
+    /// Objects which cannot lead to an object of `kind` are not read. For
+    /// example, when searching for commits the trees of each commit are skipped.
+    pub fn reachable_objects_of_kind(
+        &self,
+        root: Id,
+        kind: ObjectKind,
+    ) -> Result<Vec<Id>, ReadObjectError> {
+        self.walk_reachable_objects(root, Some(kind))
+    }
+
+    /// Call `f` with the name of each reference under `refs/`, along with the
+    /// kind of object it points to.
+    ///
+    /// The kind is found without parsing any objects, and is `Unknown` if it
+    /// cannot be determined cheaply.
+    pub fn for_each_ref<F>(&self, mut f: F) -> Result<(), reference::Error>
+    where
+        F: FnMut(&BStr, &Reference, TargetKind),
+    {
+        for (name, reference) in self.reference_database.references()? {
+            let kind = reference.target().kind(self)?;
+            f(name.as_bstr(), &reference, kind);
+        }
+        Ok(())
+    }
+
+    /// Iterate over the branches under `refs/heads`, along with the kind of
+    /// object each points to, as found by `for_each_ref`.
+    pub fn branches(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<NamedReference, reference::Error>> + '_, reference::Error>
+    {
+        let references = self.reference_database.head_references()?;
+        Ok(references
+            .into_iter()
+            .map(move |(name, reference)| NamedReference::new(self, name, reference)))
+    }
+
+    /// Iterate over the tags under `refs/tags`, along with the kind of object
+    /// each points to, as found by `for_each_ref`. Annotated tags which have
+    /// been packed by `git pack-refs` also have the id they peel to.
+    pub fn tags(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<NamedReference, reference::Error>> + '_, reference::Error>
+    {
+        let references = self.reference_database.tag_references()?;
+        Ok(references
+            .into_iter()
+            .map(move |(name, reference)| NamedReference::new(self, name, reference)))
+    }
+
+    /// Read the index file, also known as the staging area. If the repository
+    /// has no index yet, it is empty.
+    pub fn index(&self) -> Result<Index, ReadIndexError> {
+        Index::read(
+            &self.dotgit.join(INDEX_FILE),
+            self.object_database.hash_algorithm(),
+        )
+    }
+
+    /// Compare the index to the tree of `HEAD`, without looking at the working directory.
+    ///
+    /// If `HEAD` does not point to a commit yet, every path in the index is
+    /// added. Paths with merge conflicts are reported as added or modified.
+    pub fn status(&self) -> Result<Status, StatusError> {
+        let index = self.index()?;
+
+        let mut head_entries = BTreeMap::new();
+        match self.reference_database.head()?.peel(self) {
+            Ok(object) => {
+                let tree = object.into_commit()?.tree();
+                self.read_tree_recursive(tree, &mut BString::from(""), &mut head_entries)?;
+            }
+            // The current branch has no commits yet.
+            Err(reference::Error::ReferenceNotFound) => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        let mut status = Status::default();
+        let mut entries = index.entries().peekable();
+        while let Some(entry) = entries.next() {
+            // Conflicted paths have an entry for each stage, report them once.
+            while entries
+                .peek()
+                .filter(|next| next.path() == entry.path())
+                .is_some()
+            {
+                entries.next();
+            }
+
+            // Files are compared by id, so unchanged files are found without
+            // reading any blobs.
+            let path = BString::from(entry.path().as_bytes());
+            match head_entries.remove(&path) {
+                None => status.added.push(path),
+                Some(_) if entry.stage() != 0 => status.modified.push(path),
+                Some((mode, id)) if mode != entry.mode() || id != entry.id() => {
+                    status.modified.push(path)
+                }
+                Some(_) => (),
+            }
+        }
+        status.deleted.extend(head_entries.into_keys());
+
+        Ok(status)
+    }
+
+    /// Perform a three-way merge of the trees `ours` and `theirs`, where `base`
+    /// is the tree of their common ancestor, and write the merged tree.
+    ///
+    /// Paths changed on only one side take the version from that side, and
+    /// paths changed in the same way on both sides are kept. Directories are
+    /// merged recursively, and any other path changed on both sides is reported
+    /// as a conflict. The merged tree contains our version of conflicting paths.
+    pub fn merge_trees(&self, base: Id, ours: Id, theirs: Id) -> Result<MergeResult, MergeError> {
+        let mut conflicts = Vec::new();
+        let tree = if ours == theirs || base == theirs {
+            ours
+        } else if base == ours {
+            theirs
+        } else {
+            match self.merge_tree_entries(
+                [Some(base), Some(ours), Some(theirs)],
+                &mut BString::from(""),
+                &mut conflicts,
+            )? {
+                Some(tree) => tree,
+                None => self.object_database.write_object(EMPTY_TREE)?,
+            }
+        };
+
+        Ok(MergeResult { tree, conflicts })
+    }
+
+    // Merge the base, our and their versions of a directory, any of which may be
+    // missing, and write the result. Returns `None` if the merged tree is empty.
+    fn merge_tree_entries(
+        &self,
+        trees: [Option<Id>; 3],
+        prefix: &mut BString,
+        conflicts: &mut Vec<MergeConflict>,
+    ) -> Result<Option<Id>, MergeError> {
+        let mut entries = BTreeMap::<BString, [Option<(u16, Id)>; 3]>::new();
+        for (side, tree) in trees.iter().enumerate() {
+            if let Some(tree) = tree {
+                for entry in self.find_tree(*tree)?.entries() {
+                    let name = BString::from(entry.filename().as_bytes());
+                    entries.entry(name).or_default()[side] = Some((entry.mode(), entry.id()));
+                }
+            }
+        }
+
+        let is_tree = |entry: Option<(u16, Id)>| match entry {
+            Some((mode, _)) => FileMode::from_raw(mode) == FileMode::Tree,
+            None => true,
+        };
+        let id = |entry: Option<(u16, Id)>| entry.map(|(_, id)| id);
+
+        let mut merged = TreeBuilder::new();
+        for (name, [base, ours, theirs]) in entries {
+            let result = if ours == theirs || base == theirs {
+                ours
+            } else if base == ours {
+                theirs
+            } else {
+                let len = prefix.len();
+                prefix.extend_from_slice(&name);
+                let result = if is_tree(base) && is_tree(ours) && is_tree(theirs) {
+                    prefix.push(b'/');
+                    self.merge_tree_entries([id(base), id(ours), id(theirs)], prefix, conflicts)?
+                        .map(|tree| (FileMode::Tree.to_raw(), tree))
+                } else {
+                    conflicts.push(MergeConflict {
+                        path: prefix.clone(),
+                        base: id(base),
+                        ours: id(ours),
+                        theirs: id(theirs),
+                    });
+                    ours
+                };
+                prefix.truncate(len);
+                result
+            };
+
+            if let Some((mode, id)) = result {
+                merged
+                    .insert(&name, FileMode::from_raw(mode), id)
+                    .expect("names of parsed tree entries are valid and unique");
+            }
+        }
+
+        if merged.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.object_database.write_tree(&merged)?))
+    }
+
+    // Add all non-tree entries below `tree` to `entries`, keyed by their full path.
+    fn read_tree_recursive(
+        &self,
+        tree: Id,
+        prefix: &mut BString,
+        entries: &mut BTreeMap<BString, (u32, Id)>,
+    ) -> Result<(), ReadObjectError> {
+        let tree = self.find_tree(tree)?;
+        for entry in tree.entries() {
+            let len = prefix.len();
+            prefix.extend_from_slice(entry.filename());
+            if entry.is_tree() {
+                prefix.push(b'/');
+                self.read_tree_recursive(entry.id(), prefix, entries)?;
+            } else {
+                entries.insert(prefix.clone(), (u32::from(entry.mode()), entry.id()));
+            }
+            prefix.truncate(len);
+        }
+        Ok(())
+    }
+
+    fn walk_reachable_objects(
+        &self,
+        root: Id,
+        filter: Option<ObjectKind>,
+    ) -> Result<Vec<Id>, ReadObjectError> {
+        // Only tags can point to other tags, and only trees can point to blobs.
+        let follow_parents = filter != Some(ObjectKind::Tag);
+        let follow_trees = filter != Some(ObjectKind::Commit) && filter != Some(ObjectKind::Tag);
+        let is_wanted = |kind| filter.is_none() || filter == Some(kind);
+
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![(root, None)];
+        seen.insert(root);
+
+        while let Some((id, kind)) = pending.pop() {
+            // Blobs have no children, so there is no need to read them.
+            if kind == Some(ObjectKind::Blob) {
+                if is_wanted(ObjectKind::Blob) {
+                    result.push(id);
+                }
+                continue;
+            }
+
+            let object = self.object_database.parse_object(id)?;
+            if is_wanted(object.data().kind()) {
+                result.push(id);
+            }
+
+            let mut children = Vec::new();
+            match object.data() {
+                ObjectData::Commit(commit) => {
+                    if follow_trees {
+                        children.push((commit.tree(), ObjectKind::Tree));
+                    }
+                    if follow_parents {
+                        children.extend(commit.parents().map(|id| (id, ObjectKind::Commit)));
+                    }
+                }
+                ObjectData::Tree(tree) if follow_trees => {
+                    for entry in tree.entries() {
+                        match entry.file_mode() {
+                            FileMode::Tree => children.push((entry.id(), ObjectKind::Tree)),
+                            // Submodule commits are not stored in this repository.
+                            FileMode::Gitlink => (),
+                            _ => children.push((entry.id(), ObjectKind::Blob)),
+                        }
+                    }
+                }
+                ObjectData::Tag(tag) => children.push((tag.object(), tag.kind())),
+                _ => (),
+            }
+
+            for (id, kind) in children {
+                if seen.insert(id) {
+                    pending.push((id, Some(kind)));
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn read_ancestry_entry(&self, id: Id) -> Result<(i64, Id, Vec<Id>), ReadObjectError> {
+        let commit = self.find_commit(id)?;
+        // Commits without a valid time are never skipped.
+        let time = commit_time(&commit).unwrap_or(i64::MAX);
+        Ok((time, id, commit.parents().collect()))
+    }
+}
+
+impl MergeResult {
+    /// The id of the merged tree.
+    pub fn tree(&self) -> Id {
+        self.tree
+    }
+
+    /// The paths which were changed differently on both sides.
+    pub fn conflicts(&self) -> &[MergeConflict] {
+        &self.conflicts
+    }
+
+    /// Returns `true` if the trees were merged without any conflicts.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+impl MergeConflict {
+    /// The full path of the conflicting entry.
+    pub fn path(&self) -> &BStr {
+        self.path.as_bstr()
+    }
+
+    /// The id of the entry in the base tree, or `None` if it did not exist.
+    pub fn base(&self) -> Option<Id> {
+        self.base
+    }
+
+    /// The id of our version of the entry, or `None` if we deleted it.
+    pub fn ours(&self) -> Option<Id> {
+        self.ours
+    }
+
+    /// The id of their version of the entry, or `None` if they deleted it.
+    pub fn theirs(&self) -> Option<Id> {
+        self.theirs
+    }
+}
+
+impl OpenError {
+    // Distinguish repositories the user cannot access from other io errors.
+    fn from_io(path: &Path, err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            OpenError::PermissionDenied(path.to_owned())
+        } else {
+            OpenError::Io(err)
+        }
+    }
+}
+
+impl Status {
+    /// Paths which are in the index but not in `HEAD`.
+    pub fn added(&self) -> &[BString] {
+        &self.added
+    }
+
+    /// Paths whose id or mode in the index differs from `HEAD`.
+    pub fn modified(&self) -> &[BString] {
+        &self.modified
+    }
+
+    /// Paths which are in `HEAD` but not in the index.
+    pub fn deleted(&self) -> &[BString] {
+        &self.deleted
+    }
+
+    /// Returns `true` if the index matches `HEAD`.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.modified.is_empty() && self.deleted.is_empty()
+    }
+}
+
+fn commit_time(commit: &Commit) -> Option<i64> {
+    commit.committer().time().map(|time| time.seconds())
+}
+
+// Read a `.git` file of the form `gitdir: <path>`. Relative paths are resolved
+// against the working directory. Returns `None` if the file is invalid or does
+// not name a directory.
+fn read_gitdir_file(workdir: &Path, file: &Path) -> Result<Option<PathBuf>, io::Error> {
+    let contents = fs_err::read(file)?;
+    let gitdir = match contents
+        .strip_prefix(GITDIR_PREFIX)
+        .and_then(|gitdir| gitdir.trim_end().to_path().ok())
+    {
+        Some(gitdir) if !gitdir.as_os_str().is_empty() => workdir.join(gitdir),
+        _ => return Ok(None),
+    };
+
+    match fs_err::metadata(&gitdir) {
+        Ok(metadata) if metadata.is_dir() => Ok(Some(gitdir)),
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// Find the directory containing the objects and references shared between
+// worktrees. This is named by the `commondir` file of a linked worktree,
+// relative to its git directory, and is the git directory itself otherwise.
+fn read_commondir_file(dotgit: &Path) -> Result<PathBuf, io::Error> {
+    match fs_err::read(dotgit.join(COMMONDIR_FILE)) {
+        Ok(contents) => match contents.trim_end().to_path() {
+            Ok(common_dir) => Ok(dotgit.join(common_dir)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the commondir file is not a valid path",
+            )),
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(dotgit.to_owned()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(unix)]
+fn same_file_system(lhs: &Path, rhs: &Path) -> Result<bool, io::Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(fs_err::metadata(lhs)?.dev() == fs_err::metadata(rhs)?.dev())
+}
+
+#[cfg(not(unix))]
+fn same_file_system(_: &Path, _: &Path) -> Result<bool, io::Error> {
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Permission errors cannot be provoked when running as root, so the
+    // mapping is tested directly.
+    #[test]
+    fn permission_denied_errors_are_mapped() {
+        let path = Path::new("repo/.git");
+
+        let err = io::Error::from(io::ErrorKind::PermissionDenied);
+        match OpenError::from_io(path, err) {
+            OpenError::PermissionDenied(denied) => assert_eq!(denied, path),
+            err => panic!("expected PermissionDenied, got {:?}", err),
+        }
+
+        let err = io::Error::from(io::ErrorKind::Other);
+        match OpenError::from_io(path, err) {
+            OpenError::Io(_) => (),
+            err => panic!("expected Io, got {:?}", err),
+        }
+    }
 }
@@ -6,7 +6,7 @@ use std::io::prelude::*;
 use std::panic;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
-use std::str;
+use std::str::{self, FromStr as _};
 
 use tempdir::TempDir;
 
@@ -42,6 +42,24 @@ pub fn git_log(cwd: &Path, args: &[&str]) -> Output {
     output
 }
 
+// The id of the commit at `HEAD`.
+pub fn head_id(cwd: &Path) -> rusty_git::object::Id {
+    let id = String::from_utf8(git_log(cwd, &["-1", "--format=%H"]).stdout)
+        .expect("failed to parse commit hash as utf8");
+    rusty_git::object::Id::from_str(id.trim()).expect("failed to read object ID using rusty_git")
+}
+
+pub fn git_config(cwd: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("config")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
 pub fn git_branch(cwd: &Path, name: &str) {
     assert!(Command::new("git")
         .current_dir(cwd)
@@ -52,6 +70,32 @@ pub fn git_branch(cwd: &Path, name: &str) {
         .success())
 }
 
+pub fn git_checkout(cwd: &Path, name: &str) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .current_dir(cwd)
+        .arg("checkout")
+        .arg(name)
+        .status()
+        .unwrap()
+        .success())
+}
+
+pub fn git_worktree_add(cwd: &Path, path: &Path, branch: &str) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        .arg("worktree")
+        .arg("add")
+        .arg("-b")
+        .arg(branch)
+        .arg(path)
+        .status()
+        .unwrap()
+        .success())
+}
+
 pub fn git_commit(cwd: &Path, message: &str) {
     assert!(Command::new("git")
         .stderr(Stdio::null())
@@ -59,7 +103,68 @@ pub fn git_commit(cwd: &Path, message: &str) {
         .current_dir(cwd)
         .arg("-c")
         .arg("user.name=test")
+        .arg("-c")
+        .arg("user.email=test@example.com")
+        .arg("commit")
+        .arg("--message")
+        .arg(message)
+        .status()
+        .unwrap()
+        .success())
+}
+
+// Commit with both the author and committer dates set to `date`.
+pub fn git_commit_at(cwd: &Path, message: &str, date: &str) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        .env("GIT_AUTHOR_DATE", date)
+        .env("GIT_COMMITTER_DATE", date)
+        .arg("-c")
+        .arg("user.name=test")
+        .arg("-c")
+        .arg("user.email=test@example.com")
+        .arg("commit")
+        .arg("--message")
+        .arg(message)
+        .status()
+        .unwrap()
+        .success())
+}
+
+// Merge `branch` into the current branch, keeping the current tree so that
+// the merge cannot conflict.
+pub fn git_merge(cwd: &Path, branch: &str) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        .arg("-c")
+        .arg("user.name=test")
+        .arg("-c")
+        .arg("user.email=test@example.com")
+        .arg("merge")
+        .arg("--no-ff")
+        .arg("--strategy=ours")
+        .arg("--no-edit")
+        .arg(branch)
+        .status()
+        .unwrap()
+        .success())
+}
+
+pub fn git_commit_empty(cwd: &Path, message: &str) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        .arg("-c")
+        .arg("user.name=test")
+        .arg("-c")
+        .arg("user.email=test@example.com")
         .arg("commit")
+        .arg("--allow-empty")
         .arg("--message")
         .arg(message)
         .status()
@@ -73,6 +178,8 @@ pub fn git_tag(cwd: &Path, name: &str, message: Option<&str>) {
     cmd.stdout(Stdio::null());
     cmd.arg("-c");
     cmd.arg("user.name=test");
+    cmd.arg("-c");
+    cmd.arg("user.email=test@example.com");
     cmd.current_dir(cwd).arg("tag").arg(name);
     if let Some(message) = message {
         cmd.arg("--annotate");
@@ -82,6 +189,25 @@ pub fn git_tag(cwd: &Path, name: &str, message: Option<&str>) {
     assert!(cmd.status().unwrap().success());
 }
 
+pub fn git_tag_object(cwd: &Path, name: &str, target: &str, message: &str) {
+    assert!(Command::new("git")
+        .current_dir(cwd)
+        .stdout(Stdio::null())
+        .arg("-c")
+        .arg("user.name=test")
+        .arg("-c")
+        .arg("user.email=test@example.com")
+        .arg("tag")
+        .arg("--annotate")
+        .arg("--message")
+        .arg(message)
+        .arg(name)
+        .arg(target)
+        .status()
+        .unwrap()
+        .success());
+}
+
 pub fn git_add_file(cwd: &Path, file: &Path) {
     assert!(Command::new("git")
         .current_dir(cwd)
@@ -92,6 +218,341 @@ pub fn git_add_file(cwd: &Path, file: &Path) {
         .success());
 }
 
+pub fn git_rm_cached(cwd: &Path, file: &Path) {
+    assert!(Command::new("git")
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        .arg("rm")
+        .arg("--cached")
+        .arg(file)
+        .status()
+        .unwrap()
+        .success());
+}
+
+pub fn git_update_index_version(cwd: &Path, version: u32) {
+    assert!(Command::new("git")
+        .current_dir(cwd)
+        .arg("update-index")
+        .arg("--index-version")
+        .arg(version.to_string())
+        .status()
+        .unwrap()
+        .success());
+}
+
+// List the entries of the index as `(mode, id, stage, path)`.
+pub fn git_ls_files_stage(cwd: &Path) -> Vec<(String, String, String, String)> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("ls-files")
+        .arg("--stage")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut parts = line.splitn(2, '\t');
+            let fields: Vec<_> = parts.next().unwrap().split(' ').collect();
+            (
+                fields[0].to_owned(),
+                fields[1].to_owned(),
+                fields[2].to_owned(),
+                parts.next().unwrap().to_owned(),
+            )
+        })
+        .collect()
+}
+
+pub fn git_diff_cached_name_status(cwd: &Path) -> Vec<(String, String)> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("diff")
+        .arg("--cached")
+        .arg("--name-status")
+        .arg("--no-renames")
+        .output()
+        .expect("failed to read git status using diff");
+    assert!(output.status.success());
+
+    str::from_utf8(output.stdout.as_slice())
+        .expect("failed to parse output from git diff")
+        .lines()
+        .map(|line| {
+            let mut parts = line.splitn(2, '\t');
+            let status = parts.next().unwrap().to_owned();
+            let path = parts.next().unwrap().to_owned();
+            (status, path)
+        })
+        .collect()
+}
+
+pub fn git_add_gitlink(cwd: &Path, id: &str, path: &str) {
+    assert!(Command::new("git")
+        .current_dir(cwd)
+        .arg("update-index")
+        .arg("--add")
+        .arg("--cacheinfo")
+        .arg(format!("160000,{},{}", id, path))
+        .status()
+        .unwrap()
+        .success());
+}
+
+pub fn git_rev_parse(cwd: &Path, rev: &str) -> String {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("rev-parse")
+        .arg(rev)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    str::from_utf8(output.stdout.as_slice())
+        .unwrap()
+        .trim()
+        .to_owned()
+}
+
+// Compute the id of a file as an object of the given kind, without writing it.
+pub fn git_hash_object(cwd: &Path, kind: &str, file: &Path) -> String {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("hash-object")
+        .arg("-t")
+        .arg(kind)
+        .arg("--literally")
+        .arg(file)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    str::from_utf8(output.stdout.as_slice())
+        .unwrap()
+        .trim()
+        .to_owned()
+}
+
+// Write a file to the object database as an object of the given kind.
+pub fn git_write_object(cwd: &Path, kind: &str, file: &Path) -> String {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("hash-object")
+        .arg("-t")
+        .arg(kind)
+        .arg("-w")
+        .arg(file)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    str::from_utf8(output.stdout.as_slice())
+        .unwrap()
+        .trim()
+        .to_owned()
+}
+
+pub fn git_cat_file(cwd: &Path, kind: &str, id: &str) -> Vec<u8> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("cat-file")
+        .arg(kind)
+        .arg(id)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    output.stdout
+}
+
+// Merge two commits with `git merge-tree`, returning the id of the merged tree
+// and whether the merge was clean.
+pub fn git_merge_tree(cwd: &Path, ours: &str, theirs: &str) -> (String, bool) {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("merge-tree")
+        .arg("--write-tree")
+        .arg(ours)
+        .arg(theirs)
+        .output()
+        .unwrap();
+
+    let tree = str::from_utf8(output.stdout.as_slice())
+        .unwrap()
+        .lines()
+        .next()
+        .unwrap()
+        .to_owned();
+    (tree, output.status.success())
+}
+
+// List the objects in each pack as `(pack path, id, type, offset)`.
+pub fn git_verify_pack(cwd: &Path) -> Vec<(PathBuf, String, String, u64)> {
+    let pack_dir = cwd.join(".git/objects/pack");
+    let mut objects = Vec::new();
+    for entry in std::fs::read_dir(&pack_dir).unwrap() {
+        let idx_path = entry.unwrap().path();
+        if idx_path.extension() != Some("idx".as_ref()) {
+            continue;
+        }
+
+        let output = Command::new("git")
+            .current_dir(cwd)
+            .arg("verify-pack")
+            .arg("-v")
+            .arg(&idx_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        for line in str::from_utf8(&output.stdout).unwrap().lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            // Skip the summary lines following the objects.
+            if fields.len() >= 5 && fields[0].len() == 40 {
+                objects.push((
+                    idx_path.with_extension("pack"),
+                    fields[0].to_owned(),
+                    fields[1].to_owned(),
+                    fields[4].parse().unwrap(),
+                ));
+            }
+        }
+    }
+    objects
+}
+
+// Find the length of the longest delta chain in any pack.
+pub fn git_max_delta_depth(cwd: &Path) -> u32 {
+    let pack_dir = cwd.join(".git/objects/pack");
+    let mut max_depth = 0;
+    for entry in std::fs::read_dir(&pack_dir).unwrap() {
+        let idx_path = entry.unwrap().path();
+        if idx_path.extension() != Some("idx".as_ref()) {
+            continue;
+        }
+
+        let output = Command::new("git")
+            .current_dir(cwd)
+            .arg("verify-pack")
+            .arg("-v")
+            .arg(&idx_path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        for line in str::from_utf8(&output.stdout).unwrap().lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            // Deltified objects are followed by their depth and base.
+            if fields.len() == 7 && fields[0].len() == 40 {
+                max_depth = max_depth.max(fields[5].parse().unwrap());
+            }
+        }
+    }
+    max_depth
+}
+
+pub fn git_repack(cwd: &Path) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        .arg("repack")
+        .arg("-a")
+        .arg("-d")
+        .status()
+        .unwrap()
+        .success());
+}
+
+// Repack every object from scratch, allowing delta chains up to `depth` long.
+pub fn git_repack_with_depth(cwd: &Path, depth: u32) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        // The delta search is split between threads, which would break up chains.
+        .arg("-c")
+        .arg("pack.threads=1")
+        .arg("repack")
+        .arg("-a")
+        .arg("-d")
+        .arg("-f")
+        .arg(format!("--depth={}", depth))
+        .arg("--window=250")
+        .status()
+        .unwrap()
+        .success());
+}
+
+// Pack the loose objects into a new pack, leaving existing packs unchanged.
+pub fn git_repack_incremental(cwd: &Path) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        .arg("repack")
+        .arg("-d")
+        .status()
+        .unwrap()
+        .success());
+}
+
+pub fn git_pack_refs(cwd: &Path) {
+    assert!(Command::new("git")
+        .current_dir(cwd)
+        .arg("pack-refs")
+        .arg("--all")
+        .status()
+        .unwrap()
+        .success());
+}
+
+pub fn git_multi_pack_index_write(cwd: &Path) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .current_dir(cwd)
+        .arg("multi-pack-index")
+        .arg("write")
+        .status()
+        .unwrap()
+        .success());
+}
+
+pub fn git_bundle_create(cwd: &Path, file: &Path, revs: &[&str]) {
+    assert!(Command::new("git")
+        .current_dir(cwd)
+        .arg("bundle")
+        .arg("create")
+        .arg(file)
+        .args(revs)
+        .status()
+        .unwrap()
+        .success());
+}
+
+pub fn git_bundle_list_heads(cwd: &Path, file: &Path) -> Vec<(String, String)> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("bundle")
+        .arg("list-heads")
+        .arg(file)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    str::from_utf8(output.stdout.as_slice())
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut parts = line.splitn(2, ' ');
+            let id = parts.next().unwrap().to_owned();
+            let name = parts.next().unwrap().to_owned();
+            (id, name)
+        })
+        .collect()
+}
+
 pub fn git_get_objects(cwd: &Path) -> Vec<String> {
     let output = Command::new("git")
         .current_dir(cwd)
@@ -110,10 +571,82 @@ pub fn git_get_objects(cwd: &Path) -> Vec<String> {
         .collect()
 }
 
+pub fn git_get_object_headers(cwd: &Path) -> Vec<(String, String, usize)> {
+    let output = Command::new("git")
+        .current_dir(cwd)
+        .arg("cat-file")
+        .arg("--batch-check")
+        .arg("--batch-all-objects")
+        .output()
+        .expect("failed to read git objects using cat-file");
+
+    str::from_utf8(output.stdout.as_slice())
+        .expect("failed to parse output from git cat-file")
+        .lines()
+        .map(|line| {
+            let mut parts = line.split(' ');
+            let id = parts.next().unwrap().to_owned();
+            let kind = parts.next().unwrap().to_owned();
+            let len = parts.next().unwrap().parse().unwrap();
+            (id, kind, len)
+        })
+        .collect()
+}
+
+pub fn git_rev_list_objects(cwd: &Path, rev: &str) -> Vec<(String, String)> {
+    let rev_list = Command::new("git")
+        .current_dir(cwd)
+        .arg("rev-list")
+        .arg("--objects")
+        .arg("--no-object-names")
+        .arg(rev)
+        .output()
+        .expect("failed to list git objects using rev-list");
+    assert!(rev_list.status.success());
+
+    let mut cat_file = Command::new("git")
+        .current_dir(cwd)
+        .arg("cat-file")
+        .arg("--batch-check=%(objectname) %(objecttype)")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to read git objects using cat-file");
+    cat_file
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&rev_list.stdout)
+        .unwrap();
+    let output = cat_file.wait_with_output().unwrap();
+
+    str::from_utf8(output.stdout.as_slice())
+        .expect("failed to parse output from git cat-file")
+        .lines()
+        .map(|line| {
+            let mut parts = line.split(' ');
+            let id = parts.next().unwrap().to_owned();
+            let kind = parts.next().unwrap().to_owned();
+            (id, kind)
+        })
+        .collect()
+}
+
 pub fn git_init(cwd: &Path) -> Result<Output, io::Error> {
     Command::new("git").current_dir(cwd).arg("init").output()
 }
 
+pub fn git_init_sha256(cwd: &Path) {
+    assert!(Command::new("git")
+        .current_dir(cwd)
+        .arg("init")
+        .arg("--object-format=sha256")
+        .output()
+        .unwrap()
+        .status
+        .success());
+}
+
 pub fn git_clone(cwd: &Path, src: &str) {
     assert!(Command::new("git")
         .current_dir(cwd)
@@ -126,6 +659,20 @@ pub fn git_clone(cwd: &Path, src: &str) {
         .success());
 }
 
+// Clone a repository into `dest`, sharing its objects through `objects/info/alternates`.
+pub fn git_clone_shared(cwd: &Path, src: &Path, dest: &Path) {
+    assert!(Command::new("git")
+        .stderr(Stdio::null())
+        .current_dir(cwd)
+        .arg("clone")
+        .arg("--shared")
+        .arg(src)
+        .arg(dest)
+        .status()
+        .unwrap()
+        .success());
+}
+
 pub fn run_test<T>(test: T)
 where
     T: FnOnce(&Path) + panic::UnwindSafe,
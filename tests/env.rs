@@ -0,0 +1,69 @@
+mod common;
+
+use std::env;
+use std::str::FromStr;
+
+use self::common::*;
+
+use rusty_git::object::Id;
+use rusty_git::repository::Repository;
+
+// Environment variables and the current directory are shared by the whole
+// process, so this is the only test in this file, and git is not run while
+// they are changed.
+#[test]
+fn opening_repository_from_env() {
+    run_test_in_new_repo(|path| {
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+        let git_dir = path.join(".git");
+
+        env::set_var("GIT_DIR", &git_dir);
+        env::remove_var("GIT_WORK_TREE");
+        let bare = Repository::open_from_env();
+
+        env::set_var("GIT_WORK_TREE", path);
+        let with_work_tree = Repository::open_from_env();
+
+        env::set_var("GIT_DIR", path.join("missing"));
+        let missing = Repository::open_from_env();
+
+        // Relative paths are resolved against the current directory.
+        let current_dir = env::current_dir().unwrap();
+        env::set_current_dir(path).unwrap();
+        env::set_var("GIT_DIR", ".git");
+        env::set_var("GIT_WORK_TREE", ".");
+        let relative = Repository::open_from_env();
+        let relative_path = env::current_dir().unwrap();
+        env::set_current_dir(current_dir).unwrap();
+
+        env::remove_var("GIT_DIR");
+        env::remove_var("GIT_WORK_TREE");
+
+        let bare = bare.unwrap();
+        assert_eq!(bare.workdir(), None);
+        assert_eq!(
+            bare.find_commit(head).unwrap().message(),
+            "Initial commit.\n"
+        );
+        assert_eq!(
+            bare.reference_database()
+                .head()
+                .unwrap()
+                .peel(&bare)
+                .unwrap()
+                .id(),
+            &head
+        );
+
+        let with_work_tree = with_work_tree.unwrap();
+        assert_eq!(with_work_tree.workdir(), Some(path));
+        assert!(with_work_tree.status().unwrap().is_empty());
+
+        assert!(missing.is_err());
+
+        let relative = relative.unwrap();
+        assert_eq!(relative.workdir(), Some(relative_path.as_path()));
+        assert!(relative.workdir().unwrap().is_absolute());
+        assert_eq!(relative.git_dir(), relative_path.join(".git"));
+    });
+}
@@ -1,9 +1,21 @@
 mod common;
 
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
 
-use common::run_test_in_repo;
-use rusty_git::object::Id;
+use common::{
+    git_add_file, git_bundle_create, git_bundle_list_heads, git_commit, git_get_object_headers,
+    git_get_objects, git_init_sha256, git_log, git_max_delta_depth, git_multi_pack_index_write,
+    git_repack, git_repack_incremental, git_repack_with_depth, git_rev_list_objects, git_rev_parse,
+    git_verify_pack, run_test, run_test_in_new_repo, run_test_in_repo, test_write_file,
+};
+use rusty_git::object::{Bundle, HashAlgorithm, Id, ObjectDatabase, ObjectKind, ShortId};
 use rusty_git::repository::Repository;
 
 #[test]
@@ -16,3 +28,892 @@ fn test_pack_file() {
             .unwrap();
     });
 }
+
+#[test]
+fn streaming_packed_objects_produces_same_result_as_libgit2() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let lg2_odb = lg2_repo.odb().unwrap();
+
+        // Includes both deltified and undeltified objects.
+        for id in git_get_objects(path).iter().filter(|id| !id.is_empty()) {
+            let mut data = Vec::new();
+            repo.stream_object(Id::from_str(id).unwrap(), &mut data)
+                .unwrap();
+
+            let lg2_object = lg2_odb.read(git2::Oid::from_str(id).unwrap()).unwrap();
+            assert_eq!(data, lg2_object.data());
+        }
+    });
+}
+
+#[test]
+fn pack_cache_stays_within_limit() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let lg2_odb = lg2_repo.odb().unwrap();
+        let ids: Vec<_> = git_get_objects(path)
+            .into_iter()
+            .filter(|id| !id.is_empty())
+            .collect();
+
+        let odb = repo.object_database();
+        for id in &ids {
+            odb.parse_object(Id::from_str(id).unwrap()).unwrap();
+        }
+        assert!(odb.pack_cache_size() > 0);
+
+        odb.set_pack_cache_limit(0);
+        assert_eq!(odb.pack_cache_size(), 0);
+
+        // Deltified objects are still reconstructed correctly with a small cache.
+        let limit = 1024;
+        odb.set_pack_cache_limit(limit);
+        for id in &ids {
+            let mut data = Vec::new();
+            repo.stream_object(Id::from_str(id).unwrap(), &mut data)
+                .unwrap();
+
+            let lg2_object = lg2_odb.read(git2::Oid::from_str(id).unwrap()).unwrap();
+            assert_eq!(data, lg2_object.data());
+            assert!(odb.pack_cache_size() <= limit);
+        }
+    });
+}
+
+#[test]
+fn reading_mmapped_packs_concurrently_produces_same_result_as_libgit2() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let lg2_odb = lg2_repo.odb().unwrap();
+        let expected: Vec<_> = git_get_objects(path)
+            .iter()
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                let lg2_object = lg2_odb.read(git2::Oid::from_str(id).unwrap()).unwrap();
+                (Id::from_str(id).unwrap(), lg2_object.data().to_vec())
+            })
+            .collect();
+
+        repo.object_database().set_mmap_packs(true);
+        let repo = Arc::new(repo);
+        let expected = Arc::new(expected);
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let repo = repo.clone();
+                let expected = expected.clone();
+                thread::spawn(move || {
+                    for (id, lg2_data) in expected.iter() {
+                        let mut data = Vec::new();
+                        repo.object_database()
+                            .stream_object(*id, &mut data)
+                            .unwrap();
+                        assert_eq!(&data, lg2_data);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    });
+}
+
+#[test]
+fn verifying_packed_objects() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+
+        // Includes both deltified and undeltified objects.
+        for id in git_get_objects(path).iter().filter(|id| !id.is_empty()) {
+            let id = Id::from_str(id).unwrap();
+            let object = repo.object_database().read_object_verified(id).unwrap();
+            assert_eq!(*object.id(), id);
+        }
+    });
+}
+
+#[test]
+fn verifying_pack_crc_detects_corruption() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let odb = repo.object_database();
+        odb.set_verify_pack_crc(true);
+        let ids: Vec<_> = git_get_objects(path)
+            .iter()
+            .filter(|id| !id.is_empty())
+            .map(|id| Id::from_str(id).unwrap())
+            .collect();
+        for &id in &ids {
+            let mut data = Vec::new();
+            odb.stream_object(id, &mut data).unwrap();
+            assert_eq!(odb.parse_object(id).unwrap().id(), &id);
+        }
+
+        // Flip a bit in the compressed data of an undeltified blob.
+        let (pack_path, blob, offset) = git_verify_pack(path)
+            .into_iter()
+            .find(|(_, _, kind, _)| kind == "blob")
+            .map(|(pack_path, id, _, offset)| (pack_path, Id::from_str(&id).unwrap(), offset))
+            .unwrap();
+        let mut pack = fs::read(&pack_path).unwrap();
+        pack[offset as usize + 4] ^= 0x01;
+        // The pack may be hard linked to the cloned repository, so replace it
+        // rather than writing to it.
+        let new_path = pack_path.with_extension("tmp");
+        fs::write(&new_path, pack).unwrap();
+        fs::rename(&new_path, &pack_path).unwrap();
+
+        let repo = Repository::open(path).unwrap();
+        let odb = repo.object_database();
+        odb.set_verify_pack_crc(true);
+        let err = odb.parse_object(blob).unwrap_err();
+        assert!(error_chain(&err).contains("CRC32"), "{}", error_chain(&err));
+        let err = odb.stream_object(blob, &mut Vec::new()).unwrap_err();
+        assert!(error_chain(&err).contains("CRC32"), "{}", error_chain(&err));
+    });
+}
+
+#[test]
+fn writing_pack_index_produces_same_result_as_git() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        git_repack(path);
+
+        let mut objects = git_verify_pack(path);
+        objects.sort_by_key(|&(_, _, _, offset)| offset);
+        let pack_path = objects[0].0.clone();
+        assert!(objects.iter().all(|(path, _, _, _)| *path == pack_path));
+
+        // Each object extends to the start of the next, or to the pack's trailer.
+        let pack = fs::read(&pack_path).unwrap();
+        let trailer_start = pack.len() - 20;
+        let pack_id = Id::from_bytes(&pack[trailer_start..]);
+        let entries: Vec<_> = objects
+            .iter()
+            .enumerate()
+            .map(|(i, (_, id, _, offset))| {
+                let end = objects
+                    .get(i + 1)
+                    .map_or(trailer_start as u64, |&(_, _, _, next)| next);
+                let mut crc = flate2::Crc::new();
+                crc.update(&pack[*offset as usize..end as usize]);
+                (Id::from_str(id).unwrap(), *offset, crc.sum())
+            })
+            .collect();
+
+        let mut index = Vec::new();
+        ObjectDatabase::write_pack_index(&mut index, &entries, pack_id).unwrap();
+        assert_eq!(index, fs::read(pack_path.with_extension("idx")).unwrap());
+    });
+}
+
+#[test]
+fn is_ancestor_walks_packed_history() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+
+        let log = String::from_utf8(git_log(path, &["--format=%H"]).stdout).unwrap();
+        let commits: Vec<Id> = log.lines().map(|id| Id::from_str(id).unwrap()).collect();
+        let head = commits[0];
+        let root = commits[commits.len() - 1];
+
+        assert!(repo.is_ancestor(root, head).unwrap());
+        assert!(!repo.is_ancestor(head, root).unwrap());
+    });
+}
+
+#[test]
+fn reachable_objects_produces_same_result_as_git() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+
+        let log = String::from_utf8(git_log(path, &["-1", "--format=%H"]).stdout).unwrap();
+        let head = Id::from_str(log.trim()).unwrap();
+
+        let git_objects = git_rev_list_objects(path, "HEAD");
+        let expected = |kind: Option<&str>| -> BTreeSet<Id> {
+            git_objects
+                .iter()
+                .filter(|(_, object_kind)| kind.is_none() || kind == Some(object_kind.as_str()))
+                .map(|(id, _)| Id::from_str(id).unwrap())
+                .collect()
+        };
+
+        let actual = repo.reachable_objects(head).unwrap();
+        assert_eq!(actual.len(), git_objects.len());
+        assert_eq!(actual.into_iter().collect::<BTreeSet<_>>(), expected(None));
+
+        for &(kind, name) in &[
+            (ObjectKind::Commit, "commit"),
+            (ObjectKind::Tree, "tree"),
+            (ObjectKind::Blob, "blob"),
+            (ObjectKind::Tag, "tag"),
+        ] {
+            let actual = repo.reachable_objects_of_kind(head, kind).unwrap();
+            assert_eq!(
+                actual.into_iter().collect::<BTreeSet<_>>(),
+                expected(Some(name))
+            );
+        }
+    });
+}
+
+#[test]
+fn reading_packed_object_headers_produces_same_result_as_git() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+
+        // Includes both deltified and undeltified objects.
+        for (id, kind, len) in git_get_object_headers(path) {
+            let header = repo
+                .object_database()
+                .read_header(Id::from_str(&id).unwrap())
+                .unwrap();
+            assert_eq!(header.kind, kind.parse::<ObjectKind>().unwrap());
+            assert_eq!(header.len, len);
+        }
+    });
+}
+
+#[test]
+fn listing_pack_objects_produces_same_result_as_git() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let lens: HashMap<String, usize> = git_get_object_headers(path)
+            .into_iter()
+            .map(|(id, _, len)| (id, len))
+            .collect();
+
+        let mut expected = git_verify_pack(path);
+        expected.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        let mut objects = Vec::new();
+        for pack_path in expected.iter().map(|(pack_path, _, _, _)| pack_path) {
+            if objects.iter().any(|(path, _, _, _)| path == pack_path) {
+                continue;
+            }
+            let name = pack_path.file_stem().unwrap().to_str().unwrap();
+            let pack_id = Id::from_str(name.trim_start_matches("pack-")).unwrap();
+            for object in repo.object_database().pack_objects(pack_id).unwrap() {
+                let (id, header, offset) = object.unwrap();
+                assert_eq!(header.len, lens[&id.to_string()]);
+                objects.push((pack_path.clone(), id.to_string(), header.kind, offset));
+            }
+        }
+
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(pack_path, id, kind, offset)| {
+                (pack_path, id, kind.parse::<ObjectKind>().unwrap(), offset)
+            })
+            .collect();
+        assert_eq!(objects, expected);
+
+        let err = match repo.object_database().pack_objects(Id::zero()) {
+            Err(err) => err,
+            Ok(_) => panic!("expected an error for an unknown pack"),
+        };
+        assert!(err.is_not_found());
+        assert_eq!(err.id(), Id::zero());
+    });
+}
+
+#[test]
+fn finding_pack_objects_by_offset() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let database = repo.object_database();
+
+        for pack in database.packs().unwrap() {
+            for object in database.pack_objects(pack.id()).unwrap() {
+                let (id, _, offset) = object.unwrap();
+                assert_eq!(
+                    database
+                        .pack_object_id_at_offset(pack.id(), offset)
+                        .unwrap(),
+                    Some(id)
+                );
+                assert_eq!(
+                    database
+                        .pack_object_id_at_offset(pack.id(), offset + 1)
+                        .unwrap(),
+                    None
+                );
+            }
+        }
+
+        assert!(database
+            .pack_object_id_at_offset(Id::zero(), 12)
+            .unwrap_err()
+            .is_not_found());
+    });
+}
+
+#[test]
+fn reading_packed_blobs_produces_same_result_as_libgit2() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let lg2_odb = lg2_repo.odb().unwrap();
+
+        for (id, kind, len) in git_get_object_headers(path) {
+            let result = repo
+                .object_database()
+                .open_blob_reader(Id::from_str(&id).unwrap());
+            if kind != "blob" {
+                assert!(result.err().unwrap().is_wrong_kind());
+                continue;
+            }
+
+            let mut data = Vec::new();
+            io::copy(&mut result.unwrap(), &mut data).unwrap();
+            assert_eq!(data.len(), len);
+
+            let lg2_object = lg2_odb.read(git2::Oid::from_str(&id).unwrap()).unwrap();
+            assert_eq!(data, lg2_object.data());
+        }
+    });
+}
+
+// Format an error along with all of its sources.
+fn error_chain(err: &dyn Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message = format!("{}: {}", message, err);
+        source = err.source();
+    }
+    message
+}
+
+#[test]
+fn contains_packed_and_loose_objects() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+
+        for id in git_get_objects(path).iter().filter(|id| !id.is_empty()) {
+            assert!(repo
+                .object_database()
+                .contains(Id::from_str(id).unwrap())
+                .unwrap());
+        }
+
+        assert!(!repo.object_database().contains(Id::zero()).unwrap());
+    });
+}
+
+#[test]
+fn reading_info_packs() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+
+        let info_packs = repo.object_database().read_info_packs().unwrap();
+        assert_eq!(
+            info_packs.packs().collect::<Vec<_>>(),
+            vec!["pack-570c9f2183ceba36aaf51e7f604467f6495a218e.pack"]
+        );
+    });
+}
+
+#[test]
+fn resolving_abbreviated_ids() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+
+        let ids: Vec<String> = git_get_objects(path)
+            .into_iter()
+            .filter(|id| !id.is_empty())
+            .collect();
+
+        for id in &ids {
+            let short_id = ShortId::from_str(&id[..8]).unwrap();
+            let resolved = repo.object_database().resolve(&short_id).unwrap();
+            assert_eq!(resolved.to_hex(), *id);
+        }
+
+        // With this many objects, some share their first two bytes.
+        let ambiguous = ids
+            .iter()
+            .find(|id| ids.iter().filter(|other| other[..4] == id[..4]).count() > 1)
+            .unwrap();
+        let short_id = ShortId::from_str(&ambiguous[..4]).unwrap();
+        assert!(repo
+            .object_database()
+            .resolve(&short_id)
+            .unwrap_err()
+            .is_ambiguous());
+
+        let short_id = ShortId::from_str("0000000000").unwrap();
+        assert!(repo
+            .object_database()
+            .resolve(&short_id)
+            .unwrap_err()
+            .is_not_found());
+    });
+}
+
+#[test]
+fn listing_object_ids_produces_same_result_as_git() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+
+        let expected: Vec<String> = git_get_objects(path)
+            .into_iter()
+            .filter(|id| !id.is_empty())
+            .collect();
+        let ids: Vec<String> = repo
+            .object_database()
+            .object_ids()
+            .map(|id| id.unwrap().to_hex())
+            .collect();
+
+        assert_eq!(ids, expected);
+    });
+}
+
+#[test]
+fn listing_object_ids_reports_unreadable_packs() {
+    run_test_in_new_repo(|path| {
+        git_repack(path);
+        let expected: Vec<String> = git_get_objects(path)
+            .into_iter()
+            .filter(|id| !id.is_empty())
+            .collect();
+        assert!(!expected.is_empty());
+
+        // An index with an unknown version, as if it had been corrupted.
+        let index_path = path
+            .join(".git/objects/pack")
+            .join(format!("pack-{}.idx", "0".repeat(40)));
+        let mut index = b"\xfftOc\x00\x00\x00\x09".to_vec();
+        index.resize(4096, 0);
+        fs::write(&index_path, index).unwrap();
+
+        let repo = Repository::open(path).unwrap();
+        let (ids, errors): (Vec<_>, Vec<_>) =
+            repo.object_database().object_ids().partition(Result::is_ok);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].as_ref().unwrap_err().path(), index_path);
+
+        // The objects in the readable pack are still listed.
+        let ids: Vec<String> = ids.into_iter().map(|id| id.unwrap().to_hex()).collect();
+        assert_eq!(ids, expected);
+    });
+}
+
+#[test]
+fn reading_full_id_despite_unreadable_pack() {
+    run_test_in_new_repo(|path| {
+        git_repack(path);
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+
+        // An index with an unknown version, as if it had been corrupted.
+        let mut index = b"\xfftOc\x00\x00\x00\x09".to_vec();
+        index.resize(4096, 0);
+        fs::write(
+            path.join(".git/objects/pack")
+                .join(format!("pack-{}.idx", "0".repeat(40))),
+            index,
+        )
+        .unwrap();
+
+        // Packs may be searched in any order, so read the object repeatedly.
+        for _ in 0..8 {
+            let repo = Repository::open(path).unwrap();
+            assert_eq!(
+                repo.find_commit(head).unwrap().message(),
+                "Initial commit.\n"
+            );
+        }
+    });
+}
+
+#[test]
+fn refresh_finds_new_packs() {
+    run_test_in_new_repo(|path| {
+        let repo = Repository::open(path).unwrap();
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+
+        // Scan the (empty) pack directory before any packs exist.
+        assert!(!repo.object_database().contains(Id::zero()).unwrap());
+
+        // Move every object into a new pack and remove the loose copies.
+        git_repack(path);
+
+        repo.refresh().unwrap();
+        assert!(repo.object_database().contains(head).unwrap());
+        assert_eq!(
+            repo.find_commit(head).unwrap().message(),
+            "Initial commit.\n"
+        );
+    });
+}
+
+#[test]
+fn reading_object_after_pack_is_removed() {
+    run_test_in_new_repo(|path| {
+        git_repack(path);
+
+        let repo = Repository::open(path).unwrap();
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+        let mut expected = Vec::new();
+        repo.object_database()
+            .stream_object(head, &mut expected)
+            .unwrap();
+
+        // Consolidate into a new pack, which deletes the pack that is open.
+        let file = test_write_file(path, b"new", "new.txt");
+        git_add_file(path, &file);
+        git_commit(path, "New commit");
+        git_repack(path);
+
+        let mut data = Vec::new();
+        repo.object_database()
+            .stream_object(head, &mut data)
+            .unwrap();
+        assert_eq!(data, expected);
+    });
+}
+
+#[test]
+fn listing_packs_produces_same_result_as_git() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        git_repack_incremental(path);
+        let file = test_write_file(path, b"new", "new.txt");
+        git_add_file(path, &file);
+        git_commit(path, "New commit");
+        git_repack_incremental(path);
+
+        let repo = Repository::open(path).unwrap();
+        let mut counts: BTreeMap<PathBuf, usize> = BTreeMap::new();
+        for (pack_path, _, _, _) in git_verify_pack(path) {
+            *counts.entry(pack_path).or_default() += 1;
+        }
+
+        let packs = repo.object_database().packs().unwrap();
+        assert_eq!(packs.len(), counts.len());
+        for (pack, (pack_path, count)) in packs.iter().zip(&counts) {
+            assert_eq!(pack.name(), pack_path.file_name().unwrap());
+            assert_eq!(pack.name(), format!("pack-{}.pack", pack.id()).as_str());
+            assert_eq!(pack.object_count(), *count);
+            assert_eq!(pack.size(), fs::metadata(pack_path).unwrap().len());
+            assert_eq!(pack.index_version(), 2);
+        }
+    });
+}
+
+#[test]
+fn reading_multi_pack_index_produces_same_result_as_libgit2() {
+    run_test_in_new_repo(|path| {
+        git_repack_incremental(path);
+        for n in 0..3 {
+            let file = test_write_file(path, n.to_string().as_bytes(), &format!("{}.txt", n));
+            git_add_file(path, &file);
+            git_commit(path, &format!("Commit {}", n));
+            git_repack_incremental(path);
+        }
+        git_multi_pack_index_write(path);
+
+        // This pack is not covered by the multi-pack-index.
+        let file = test_write_file(path, b"not indexed", "not_indexed.txt");
+        git_add_file(path, &file);
+        git_commit(path, "Not indexed");
+        git_repack_incremental(path);
+
+        let repo = Repository::open(path).unwrap();
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let lg2_odb = lg2_repo.odb().unwrap();
+
+        let ids = git_get_objects(path);
+        for id in ids.iter().filter(|id| !id.is_empty()) {
+            let mut data = Vec::new();
+            repo.stream_object(Id::from_str(id).unwrap(), &mut data)
+                .unwrap();
+
+            let lg2_object = lg2_odb.read(git2::Oid::from_str(id).unwrap()).unwrap();
+            assert_eq!(data, lg2_object.data());
+
+            let short_id = ShortId::from_str(&id[..12]).unwrap();
+            assert_eq!(
+                repo.object_database().resolve(&short_id).unwrap(),
+                Id::from_str(id).unwrap()
+            );
+        }
+
+        // A corrupt multi-pack-index is reported rather than ignored.
+        let midx_path = path.join(".git/objects/pack/multi-pack-index");
+        fs::remove_file(&midx_path).unwrap();
+        fs::write(&midx_path, b"MIDX\x02\x01\x00\x00").unwrap();
+        let err = repo.refresh().unwrap_err();
+        assert!(error_chain(&err).contains("multi-pack-index"));
+    });
+}
+
+#[test]
+fn writing_packed_object_does_not_create_loose_copy() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let objects = path.join(".git").join("objects");
+        let loose_path = |id: &str| objects.join(&id[..2]).join(&id[2..]);
+
+        let (id, kind, len) = git_get_object_headers(path)
+            .into_iter()
+            .find(|(id, _, _)| !loose_path(id).exists())
+            .unwrap();
+
+        let mut bytes = format!("{} {}\0", kind, len).into_bytes();
+        repo.stream_object(Id::from_str(&id).unwrap(), &mut bytes)
+            .unwrap();
+
+        let written = repo.object_database().write_object(&bytes).unwrap();
+        assert_eq!(written.to_hex(), id);
+        assert!(!loose_path(&id).exists());
+    });
+}
+
+#[test]
+fn reading_bundle_produces_same_result_as_git() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let repo = Repository::open(path).unwrap();
+        let bundle_path = path.join("repo.bundle");
+        git_bundle_create(path, &bundle_path, &["--all"]);
+
+        let bundle = Bundle::open(&bundle_path).unwrap();
+        assert_eq!(bundle.prerequisites().len(), 0);
+
+        let references: Vec<(String, String)> = bundle
+            .references()
+            .map(|(id, name)| (id.to_hex(), name.to_string()))
+            .collect();
+        assert_eq!(references, git_bundle_list_heads(path, &bundle_path));
+
+        let expected: BTreeSet<Id> = git_rev_list_objects(path, "--all")
+            .iter()
+            .map(|(id, _)| Id::from_str(id).unwrap())
+            .collect();
+        let ids: BTreeSet<Id> = bundle.object_ids().collect();
+        assert_eq!(ids, expected);
+
+        for &id in &ids {
+            let mut expected = Vec::new();
+            repo.stream_object(id, &mut expected).unwrap();
+
+            let mut data = Vec::new();
+            io::copy(bundle.read_object(id).unwrap().reader(), &mut data).unwrap();
+            assert_eq!(data, expected);
+
+            bundle.parse_object(id).unwrap();
+        }
+    });
+}
+
+#[test]
+fn reading_bundle_with_prerequisites() {
+    run_test_in_repo("tests/resources/repo.git", |path| {
+        let bundle_path = path.join("repo.bundle");
+        git_bundle_create(path, &bundle_path, &["HEAD~1..HEAD"]);
+
+        let bundle = Bundle::open(&bundle_path).unwrap();
+        let prerequisites: Vec<Id> = bundle.prerequisites().map(|(id, _)| id).collect();
+        assert_eq!(
+            prerequisites,
+            vec![Id::from_str(&git_rev_parse(path, "HEAD~1")).unwrap()]
+        );
+
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+        assert_eq!(
+            bundle.references().collect::<Vec<_>>(),
+            vec![(head, "HEAD".into())]
+        );
+        assert!(bundle.parse_object(head).unwrap().data().kind() == ObjectKind::Commit);
+        let prerequisite = Id::from_str(&git_rev_parse(path, "HEAD~1")).unwrap();
+        assert!(bundle
+            .parse_object(prerequisite)
+            .unwrap_err()
+            .is_not_found());
+    });
+}
+
+#[test]
+fn reading_thin_bundle() {
+    run_test_in_new_repo(|path| {
+        // A small change to a large file is stored as a delta against the
+        // version in the prerequisite commit.
+        let lines: String = (0..2000).map(|line| format!("line {}\n", line)).collect();
+        let file = test_write_file(path, lines.as_bytes(), "lines.txt");
+        git_add_file(path, &file);
+        git_commit(path, "Add lines");
+        let file = test_write_file(path, format!("{}more\n", lines).as_bytes(), "lines.txt");
+        git_add_file(path, &file);
+        git_commit(path, "Add more lines");
+
+        let bundle_path = path.join("thin.bundle");
+        git_bundle_create(path, &bundle_path, &["HEAD~1..HEAD"]);
+
+        let base = Id::from_str(&git_rev_parse(path, "HEAD~1:lines.txt")).unwrap();
+        let err = Bundle::open(&bundle_path).unwrap_err();
+        assert!(
+            error_chain(&err).contains(&base.to_hex()),
+            "{}",
+            error_chain(&err)
+        );
+
+        let repo = Repository::open(path).unwrap();
+        let bundle = repo.object_database().open_bundle(&bundle_path).unwrap();
+        let expected: BTreeSet<Id> = git_rev_list_objects(path, "HEAD~1..HEAD")
+            .iter()
+            .map(|(id, _)| Id::from_str(id).unwrap())
+            .collect();
+        let ids: BTreeSet<Id> = bundle.object_ids().collect();
+        assert_eq!(ids, expected);
+
+        for &id in &ids {
+            let mut expected = Vec::new();
+            repo.stream_object(id, &mut expected).unwrap();
+
+            let mut data = Vec::new();
+            io::copy(bundle.read_object(id).unwrap().reader(), &mut data).unwrap();
+            assert_eq!(data, expected);
+        }
+    });
+}
+
+#[test]
+fn reading_sha256_bundle() {
+    run_test(|path| {
+        git_init_sha256(path);
+        let file = test_write_file(path, b"Hello world!", "hello_world.txt");
+        git_add_file(path, &file);
+        git_commit(path, "Initial commit.");
+
+        // Git writes version 3 bundles for SHA-256 repositories.
+        let bundle_path = path.join("repo.bundle");
+        git_bundle_create(path, &bundle_path, &["--all"]);
+        assert!(fs::read(&bundle_path)
+            .unwrap()
+            .starts_with(b"# v3 git bundle\n@object-format=sha256\n"));
+
+        let bundle = Bundle::open(&bundle_path).unwrap();
+        let references: Vec<(String, String)> = bundle
+            .references()
+            .map(|(id, name)| (id.to_hex(), name.to_string()))
+            .collect();
+        assert_eq!(references, git_bundle_list_heads(path, &bundle_path));
+
+        let blob = Id::from_str(&git_rev_parse(path, "HEAD:hello_world.txt")).unwrap();
+        assert_eq!(blob.hash_algorithm(), HashAlgorithm::Sha256);
+        let mut data = Vec::new();
+        io::copy(bundle.read_object(blob).unwrap().reader(), &mut data).unwrap();
+        assert_eq!(data, b"Hello world!");
+
+        // Capabilities other than the object format are rejected.
+        let mut filtered = fs::read(&bundle_path).unwrap();
+        let capability = b"@object-format=sha256\n".len();
+        let signature = b"# v3 git bundle\n".len();
+        filtered.splice(
+            signature..(signature + capability),
+            b"@filter=blob:none\n".to_vec(),
+        );
+        fs::write(&bundle_path, filtered).unwrap();
+        let err = Bundle::open(&bundle_path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unsupported bundle capability `filter=blob:none`"
+        );
+    });
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn reading_objects_async_produces_same_result_as_libgit2() {
+    use tokio::io::AsyncReadExt;
+
+    run_test_in_new_repo(|path| {
+        // Read a mix of packed and loose objects.
+        git_repack(path);
+        let file = test_write_file(path, b"new", "new.txt");
+        git_add_file(path, &file);
+        git_commit(path, "New commit");
+
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let lg2_odb = lg2_repo.odb().unwrap();
+        let odb = Arc::new(ObjectDatabase::open(
+            &path.join(".git"),
+            HashAlgorithm::Sha1,
+        ));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        for (id, kind, len) in git_get_object_headers(path) {
+            let lg2_object = lg2_odb.read(git2::Oid::from_str(&id).unwrap()).unwrap();
+
+            let (header, data) = runtime.block_on(async {
+                let (header, mut reader) = odb
+                    .read_object_async(Id::from_str(&id).unwrap())
+                    .await
+                    .unwrap();
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data).await.unwrap();
+                (header, data)
+            });
+
+            assert_eq!(header.kind, kind.parse::<ObjectKind>().unwrap());
+            assert_eq!(header.len, len);
+            assert_eq!(data, lg2_object.data());
+        }
+    });
+}
+
+#[test]
+fn reading_objects_with_long_delta_chains() {
+    run_test_in_new_repo(|path| {
+        // Each version shares most of its lines with the previous one, but few
+        // with older versions, so git deltifies them into a single long chain.
+        // Versions also grow in size so git considers them in a fixed order.
+        for i in 0..80 {
+            let content: String = (i * 5..i * 6 + 200)
+                .map(|line| format!("line {}\n", line))
+                .collect();
+            let file = test_write_file(path, content.as_bytes(), "file.txt");
+            git_add_file(path, &file);
+            git_commit(path, &format!("Commit {}", i));
+        }
+        git_repack_with_depth(path, 100);
+        assert!(git_max_delta_depth(path) > 50);
+
+        let repo = Repository::open(path).unwrap();
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let lg2_odb = lg2_repo.odb().unwrap();
+        for id in git_get_objects(path).iter().filter(|id| !id.is_empty()) {
+            let lg2_object = lg2_odb.read(git2::Oid::from_str(id).unwrap()).unwrap();
+            let mut data = Vec::new();
+            repo.object_database()
+                .stream_object(Id::from_str(id).unwrap(), &mut data)
+                .unwrap();
+            assert_eq!(data, lg2_object.data());
+        }
+
+        let repo = Repository::open(path).unwrap();
+        repo.object_database().set_max_delta_depth(10);
+        let errors: Vec<_> = git_get_objects(path)
+            .iter()
+            .filter(|id| !id.is_empty())
+            .filter_map(|id| {
+                repo.object_database()
+                    .stream_object(Id::from_str(id).unwrap(), &mut Vec::new())
+                    .err()
+            })
+            .collect();
+        assert!(!errors.is_empty());
+        for err in &errors {
+            assert!(
+                error_chain(err).contains("maximum depth of 10"),
+                "{}",
+                error_chain(err)
+            );
+        }
+    });
+}
@@ -5,7 +5,7 @@ use std::string::ToString;
 
 use self::common::*;
 
-use rusty_git::object::{self, ObjectData};
+use rusty_git::object;
 use rusty_git::repository::Repository;
 
 #[test]
@@ -21,11 +21,7 @@ fn reading_tag_produces_same_result_as_libgit2() {
         let rg_id = object::Id::from_str(&lg_id.to_string()).unwrap();
 
         let lg_tag = lg_repo.find_tag(lg_id).unwrap();
-        let rg_obj = rg_repo.object_database().parse_object(rg_id).unwrap();
-        let rg_tag = match rg_obj.data() {
-            ObjectData::Tag(tag) => tag,
-            _ => panic!("expected tag"),
-        };
+        let rg_tag = rg_repo.find_tag(rg_id).unwrap();
 
         assert_eq!(lg_tag.name_bytes(), rg_tag.tag());
         assert_eq!(lg_tag.target_id().to_string(), rg_tag.object().to_string());
@@ -36,3 +32,57 @@ fn reading_tag_produces_same_result_as_libgit2() {
         );
     })
 }
+
+#[test]
+fn peeling_to_annotated_tag() {
+    run_test_in_new_repo(|path| {
+        git_tag(path, "annotated", Some("my message"));
+        git_tag(path, "lightweight", None);
+
+        let repo = Repository::open(path).unwrap();
+        let references = repo.reference_database();
+
+        let tag = references
+            .reference(b"refs/tags/annotated")
+            .unwrap()
+            .peel_to_annotated_tag(&repo)
+            .unwrap()
+            .unwrap();
+        assert_eq!(tag.tag(), "annotated");
+
+        assert!(references
+            .reference(b"refs/tags/lightweight")
+            .unwrap()
+            .peel_to_annotated_tag(&repo)
+            .unwrap()
+            .is_none());
+    })
+}
+
+#[test]
+fn peeling_nested_tags() {
+    run_test_in_new_repo(|path| {
+        git_tag(path, "inner", Some("inner message"));
+        git_tag_object(path, "outer", "inner", "outer message");
+
+        let repo = Repository::open(path).unwrap();
+        let outer = repo
+            .reference_database()
+            .reference(b"refs/tags/outer")
+            .unwrap()
+            .peel(&repo)
+            .unwrap();
+        assert_eq!(outer.data().kind(), object::ObjectKind::Tag);
+
+        let commit = outer.peel_to(&repo, object::ObjectKind::Commit).unwrap();
+        assert_eq!(commit.id().to_hex(), git_rev_parse(path, "HEAD"));
+
+        let tag = outer.peel_to(&repo, object::ObjectKind::Tag).unwrap();
+        assert_eq!(tag.id(), outer.id());
+
+        assert!(outer
+            .peel_to(&repo, object::ObjectKind::Blob)
+            .unwrap_err()
+            .is_wrong_kind());
+    })
+}
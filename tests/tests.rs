@@ -1,12 +1,19 @@
 mod common;
 
+use std::fs;
+use std::io::Read as _;
 use std::panic;
 use std::path::Path;
 use std::str;
 use std::str::FromStr as _;
 
-use rusty_git::object::{ObjectData, TreeEntry};
-use rusty_git::repository::Repository;
+use rusty_git::index::{ParseIndexError, ReadIndexError};
+use rusty_git::object::{
+    CommitData, FileMode, HashAlgorithm, Id, ObjectData, ObjectKind, ShortId, Signature, TagData,
+    TreeBuilder, TreeEntry,
+};
+use rusty_git::reference::{self, TargetKind};
+use rusty_git::repository::{OpenError, Repository};
 
 use self::common::*;
 
@@ -72,6 +79,167 @@ fn reading_refs_produces_same_result_as_libgit2() {
     });
 }
 
+#[test]
+fn reading_reflog_produces_same_result_as_libgit2() {
+    run_test_in_new_repo(|path| {
+        git_commit_empty(path, "Second commit.");
+        git_branch(path, "feature");
+        git_checkout(path, "feature");
+        git_commit_empty(path, "Feature commit.");
+
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let repo = Repository::open(path).unwrap();
+
+        for name in &["HEAD", "refs/heads/master", "refs/heads/feature"] {
+            let lg2_reflog = lg2_repo.reflog(name).unwrap();
+            let reflog = repo.reference_database().reflog(name.as_bytes()).unwrap();
+            assert_eq!(reflog.len(), lg2_reflog.len());
+
+            // libgit2 yields the newest entry first.
+            for (entry, lg2_entry) in reflog.iter().rev().zip(lg2_reflog.iter()) {
+                assert_eq!(entry.old_id().to_string(), lg2_entry.id_old().to_string());
+                assert_eq!(entry.new_id().to_string(), lg2_entry.id_new().to_string());
+                assert_eq!(entry.committer().name(), lg2_entry.committer().name_bytes());
+                assert_eq!(
+                    entry.committer().time().unwrap().seconds(),
+                    lg2_entry.committer().when().seconds()
+                );
+                assert_eq!(
+                    entry.message(),
+                    lg2_entry.message_bytes().unwrap_or_default()
+                );
+            }
+            assert!(reflog.iter().next().unwrap().old_id().is_zero());
+        }
+
+        assert!(repo
+            .reference_database()
+            .reflog(b"refs/heads/missing")
+            .unwrap()
+            .is_empty());
+    });
+}
+
+#[test]
+fn for_each_ref_reports_target_kinds() {
+    run_test_in_new_repo(|path| {
+        git_branch(path, "other");
+        git_tag(path, "annotated", Some("my message"));
+        git_tag(path, "lightweight", None);
+
+        let repo = Repository::open(path).unwrap();
+        let mut refs = Vec::new();
+        repo.for_each_ref(|name, _, kind| refs.push((name.to_string(), kind)))
+            .unwrap();
+        refs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            refs,
+            vec![
+                ("refs/heads/master".to_owned(), TargetKind::Commit),
+                ("refs/heads/other".to_owned(), TargetKind::Commit),
+                ("refs/tags/annotated".to_owned(), TargetKind::Tag),
+                ("refs/tags/lightweight".to_owned(), TargetKind::Commit),
+            ]
+        );
+    });
+}
+
+#[test]
+fn for_each_ref_reports_dangling_refs_as_unknown() {
+    run_test_in_new_repo(|path| {
+        git_branch(path, "other");
+        // git refuses to create a reference to a missing object, so write it directly.
+        fs::write(
+            path.join(".git/refs/heads/dangling"),
+            "0123456789012345678901234567890123456789\n",
+        )
+        .unwrap();
+
+        let repo = Repository::open(path).unwrap();
+        let mut refs = Vec::new();
+        repo.for_each_ref(|name, _, kind| refs.push((name.to_string(), kind)))
+            .unwrap();
+        refs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            refs,
+            vec![
+                ("refs/heads/dangling".to_owned(), TargetKind::Unknown),
+                ("refs/heads/master".to_owned(), TargetKind::Commit),
+                ("refs/heads/other".to_owned(), TargetKind::Commit),
+            ]
+        );
+
+        let mut branches: Vec<_> = repo
+            .branches()
+            .unwrap()
+            .map(|branch| {
+                let branch = branch.unwrap();
+                (branch.short_name().to_string(), branch.kind())
+            })
+            .collect();
+        branches.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            branches,
+            vec![
+                ("dangling".to_owned(), TargetKind::Unknown),
+                ("master".to_owned(), TargetKind::Commit),
+                ("other".to_owned(), TargetKind::Commit),
+            ]
+        );
+    });
+}
+
+#[test]
+fn branches_and_tags_report_target_kinds() {
+    run_test_in_new_repo(|path| {
+        git_branch(path, "other");
+        git_tag(path, "packed", Some("packed message"));
+        git_pack_refs(path);
+        git_tag(path, "annotated", Some("my message"));
+        git_tag(path, "lightweight", None);
+
+        let repo = Repository::open(path).unwrap();
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+
+        let mut branches: Vec<_> = repo
+            .branches()
+            .unwrap()
+            .map(|branch| {
+                let branch = branch.unwrap();
+                (branch.short_name().to_string(), branch.kind())
+            })
+            .collect();
+        branches.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            branches,
+            vec![
+                ("master".to_owned(), TargetKind::Commit),
+                ("other".to_owned(), TargetKind::Commit),
+            ]
+        );
+
+        let mut tags: Vec<_> = repo
+            .tags()
+            .unwrap()
+            .map(|tag| {
+                let tag = tag.unwrap();
+                (tag.name().to_string(), tag.kind(), tag.peeled_id())
+            })
+            .collect();
+        tags.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            tags,
+            vec![
+                ("refs/tags/annotated".to_owned(), TargetKind::Tag, None),
+                ("refs/tags/lightweight".to_owned(), TargetKind::Commit, None),
+                ("refs/tags/packed".to_owned(), TargetKind::Tag, Some(head)),
+            ]
+        );
+    });
+}
+
 #[test]
 fn reading_file_produces_same_result_as_libgit2() {
     run_test(|path| {
@@ -123,15 +291,9 @@ fn reading_commit_produces_same_result_as_libgit2() {
         let object_id = rusty_git::object::Id::from_str(target_object_id.as_str())
             .expect("failed to read object ID using rusty_git");
 
-        let commit_object = repo
-            .object_database()
-            .parse_object(object_id)
-            .expect("failed to parse tree object with rusty git");
-
-        let commit = match commit_object.data() {
-            ObjectData::Commit(commit) => commit,
-            _ => panic!("expected object to be a commit"),
-        };
+        let commit = repo
+            .find_commit(object_id)
+            .expect("failed to parse commit object with rusty git");
 
         assert_eq!(git_author_name, commit.author().name());
         assert_eq!(git_author_email, commit.author().email());
@@ -140,6 +302,98 @@ fn reading_commit_produces_same_result_as_libgit2() {
     });
 }
 
+#[test]
+fn reading_signed_commit_data_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        let head = git_rev_parse(path, "HEAD");
+        let unsigned = git_cat_file(path, "commit", &head);
+
+        // Insert a (fake) signature at the end of the headers.
+        let headers_end = unsigned
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .unwrap()
+            + 1;
+        let mut signed = unsigned[..headers_end].to_vec();
+        signed.extend_from_slice(
+            b"gpgsig -----BEGIN PGP SIGNATURE-----\n \n signature\n -----END PGP SIGNATURE-----\n",
+        );
+        signed.extend_from_slice(&unsigned[headers_end..]);
+
+        let file = test_write_file(path, &signed, "commit");
+        let id = git_write_object(path, "commit", &file);
+
+        let mut expected = Vec::new();
+        let mut in_signature = false;
+        for line in git_cat_file(path, "commit", &id).split_inclusive(|&b| b == b'\n') {
+            in_signature = line.starts_with(b"gpgsig ") || (in_signature && line.starts_with(b" "));
+            if !in_signature {
+                expected.extend_from_slice(line);
+            }
+        }
+
+        let repo = Repository::open(path).expect("failed to open repository with rusty_git");
+        let commit = repo
+            .find_commit(Id::from_str(&id).unwrap())
+            .expect("failed to parse commit object with rusty git");
+
+        assert!(commit.gpg_signature().is_some());
+        assert_eq!(commit.signed_data(), expected);
+        assert_eq!(expected, unsigned);
+    });
+}
+
+#[test]
+fn reading_unusual_signatures_produces_same_result_as_libgit2() {
+    run_test_in_new_repo(|path| {
+        let repo = Repository::open(path).expect("failed to open repository with rusty_git");
+        let lg2_repo =
+            git2::Repository::open(path).expect("failed to open repository with libgit2");
+        let tree = repo
+            .find_commit(Id::from_str(&git_rev_parse(path, "HEAD")).unwrap())
+            .unwrap()
+            .tree();
+
+        for line in &[
+            "Foo <Bar> <foo@bar> 1596907199 +0100",
+            "Foo <> 1596907199 -0830",
+            "<foo@bar> 1596907199 +0000",
+            "Foo<foo@bar> 1596907199 +0100",
+            "  Foo   <  foo@bar  > 1596907199 +0100",
+            "Foo <<foo@bar>> 1596907199 +0100",
+        ] {
+            let signature = Signature::parse_line(line.as_bytes()).unwrap();
+            let id = repo
+                .object_database()
+                .write_commit(&CommitData::new(
+                    tree,
+                    signature.clone(),
+                    signature,
+                    "Unusual signature.\n",
+                ))
+                .unwrap();
+
+            let lg2_commit = lg2_repo
+                .find_commit(git2::Oid::from_str(&id.to_hex()).unwrap())
+                .expect("failed to read commit using lg2");
+            let commit = repo.find_commit(id).unwrap();
+
+            let lg2_author = lg2_commit.author();
+            let author = commit.author();
+            let time = author.time().unwrap();
+            assert_eq!(author.name(), lg2_author.name_bytes(), "{}", line);
+            assert_eq!(author.email(), lg2_author.email_bytes(), "{}", line);
+            assert_eq!(time.seconds(), lg2_author.when().seconds(), "{}", line);
+            assert_eq!(
+                time.offset(),
+                lg2_author.when().offset_minutes(),
+                "{}",
+                line
+            );
+        }
+    });
+}
+
 #[test]
 fn reading_tree_produces_same_result_as_libgit2() {
     run_test_in_new_repo(|path| {
@@ -171,13 +425,13 @@ fn reading_tree_produces_same_result_as_libgit2() {
             .object_database()
             .parse_object(target_tree_id)
             .expect("failed to parse tree object with rusty git");
-
         let tree = match tree_object.data() {
             ObjectData::Tree(tree) => tree,
             _ => panic!("expected object to be a tree"),
         };
 
         let tree_id = tree_object.id().to_string();
+
         let blob_id = tree.entries().collect::<Vec<TreeEntry>>()[0]
             .id()
             .to_string();
@@ -187,22 +441,723 @@ fn reading_tree_produces_same_result_as_libgit2() {
     });
 }
 
+#[test]
+fn finding_object_of_wrong_kind_fails() {
+    run_test_in_new_repo(|path| {
+        let commit_id = String::from_utf8(git_log(path, &["-1", "--format=%H"]).stdout)
+            .expect("failed to parse commit hash as utf8");
+
+        let repo = Repository::open(path).expect("failed to open repository with rusty_git");
+        let commit_id = rusty_git::object::Id::from_str(commit_id.trim())
+            .expect("failed to read object ID using rusty_git");
+
+        assert!(repo.find_commit(commit_id).is_ok());
+        assert!(repo.find_tree(commit_id).unwrap_err().is_wrong_kind());
+        assert!(repo.find_blob(commit_id).unwrap_err().is_wrong_kind());
+        assert!(repo.find_tag(commit_id).unwrap_err().is_wrong_kind());
+    });
+}
+
+#[test]
+fn finding_id_of_wrong_hash_algorithm_fails() {
+    run_test_in_new_repo(|path| {
+        let repo = Repository::open(path).unwrap();
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+        assert!(repo.find_commit(head).is_ok());
+
+        let sha256_id = HashAlgorithm::Sha256.hash(b"blob 0\0");
+        let err = repo.find_blob(sha256_id).unwrap_err();
+        assert!(err.is_wrong_hash_algorithm());
+        assert_eq!(
+            err.to_string(),
+            format!("object id `{}` is not a valid sha1 id", sha256_id)
+        );
+        assert!(repo
+            .object_database()
+            .contains(sha256_id)
+            .unwrap_err()
+            .is_wrong_hash_algorithm());
+        assert!(repo
+            .object_database()
+            .resolve(&ShortId::from(sha256_id))
+            .unwrap_err()
+            .is_wrong_hash_algorithm());
+    });
+}
+
+#[test]
+fn streaming_loose_object_produces_same_result_as_libgit2() {
+    run_test_in_new_repo(|path| {
+        let repo = Repository::open(path).expect("failed to open repository with rusty_git");
+
+        for id in git_get_objects(path).iter().filter(|id| !id.is_empty()) {
+            let object_id = rusty_git::object::Id::from_str(id)
+                .expect("failed to read object ID using rusty_git");
+
+            let mut data = Vec::new();
+            repo.stream_object(object_id, &mut data)
+                .expect("failed to stream object with rusty_git");
+
+            assert_eq!(test_libgit2_read_object(path, id), data);
+        }
+    });
+}
+
+#[test]
+fn reading_loose_blob_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        let repo = Repository::open(path).unwrap();
+        let blob = Id::from_str(&git_rev_parse(path, "HEAD:hello_world.txt")).unwrap();
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+
+        let mut data = Vec::new();
+        let mut reader = repo.object_database().open_blob_reader(blob).unwrap();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"Hello world!");
+
+        assert!(repo
+            .object_database()
+            .open_blob_reader(head)
+            .err()
+            .unwrap()
+            .is_wrong_kind());
+    });
+}
+
+#[test]
+fn verifying_corrupt_loose_object_fails() {
+    run_test_in_new_repo(|path| {
+        let repo = Repository::open(path).unwrap();
+        for id in git_get_objects(path).iter().filter(|id| !id.is_empty()) {
+            let id = Id::from_str(id).unwrap();
+            let object = repo.object_database().read_object_verified(id).unwrap();
+            assert_eq!(*object.id(), id);
+        }
+
+        let a = test_write_file(path, b"a", "a.txt");
+        let b = test_write_file(path, b"b", "b.txt");
+        git_add_file(path, &a);
+        git_add_file(path, &b);
+        let a = git_rev_parse(path, ":a.txt");
+        let b = git_rev_parse(path, ":b.txt");
+
+        // Replace the contents of `b` with the contents of `a`.
+        let object_path = |id: &str| path.join(".git/objects").join(&id[..2]).join(&id[2..]);
+        fs::remove_file(object_path(&b)).unwrap();
+        fs::copy(object_path(&a), object_path(&b)).unwrap();
+
+        let a = Id::from_str(&a).unwrap();
+        let b = Id::from_str(&b).unwrap();
+        assert!(repo.object_database().read_object_verified(a).is_ok());
+        assert!(repo.object_database().parse_object(b).is_ok());
+        let err = repo.object_database().read_object_verified(b).unwrap_err();
+        assert!(err.is_corrupt());
+        assert_eq!(
+            err.to_string(),
+            format!("object `{}` is corrupt, its contents hash to `{}`", b, a)
+        );
+    });
+}
+
+#[test]
+fn reading_truncated_loose_object_fails() {
+    run_test_in_new_repo(|path| {
+        let data: Vec<u8> = (0..4096u32)
+            .flat_map(|i| i.to_le_bytes().to_vec())
+            .collect();
+        let file = test_write_file(path, &data, "data.bin");
+        git_add_file(path, &file);
+        let id = git_rev_parse(path, ":data.bin");
+
+        // Cut the compressed stream off in the middle of the body.
+        let object_path = path.join(".git/objects").join(&id[..2]).join(&id[2..]);
+        let compressed = fs::read(&object_path).unwrap();
+        fs::remove_file(&object_path).unwrap();
+        fs::write(&object_path, &compressed[..compressed.len() / 2]).unwrap();
+
+        let repo = Repository::open(path).unwrap();
+        let id = Id::from_str(&id).unwrap();
+        let err = repo.object_database().parse_object(id).unwrap_err();
+        assert!(err.is_corrupt(), "{:?}", err);
+        assert_eq!(err.to_string(), format!("object `{}` is invalid", id));
+        let err = repo.object_database().read_object_verified(id).unwrap_err();
+        assert!(err.is_corrupt(), "{:?}", err);
+        let err = repo
+            .object_database()
+            .stream_object(id, &mut Vec::new())
+            .unwrap_err();
+        assert!(err.is_corrupt(), "{:?}", err);
+    });
+}
+
+#[test]
+fn hashing_object_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        for (kind, data) in &[
+            (ObjectKind::Blob, &b"Hello world!"[..]),
+            (ObjectKind::Blob, &b""[..]),
+            (ObjectKind::Commit, &b"not really a commit"[..]),
+        ] {
+            let file = test_write_file(path, data, "object");
+            assert_eq!(
+                Id::hash_object(*kind, data).to_string(),
+                git_hash_object(path, &kind.to_string(), &file)
+            );
+        }
+    });
+}
+
+#[test]
+fn is_ancestor_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        let initial = head_id(path);
+        git_branch(path, "feature");
+
+        test_write_file(path, b"Hello master!", "hello_world.txt");
+        git_add_file(path, Path::new("hello_world.txt"));
+        git_commit(path, "Master commit.");
+        let master = head_id(path);
+
+        git_checkout(path, "feature");
+        test_write_file(path, b"Hello feature!", "hello_world.txt");
+        git_add_file(path, Path::new("hello_world.txt"));
+        git_commit(path, "Feature commit.");
+        let feature = head_id(path);
+
+        let repo = Repository::open(path).expect("failed to open repository with rusty_git");
+
+        assert!(repo.is_ancestor(initial, initial).unwrap());
+        assert!(repo.is_ancestor(initial, master).unwrap());
+        assert!(repo.is_ancestor(initial, feature).unwrap());
+        assert!(!repo.is_ancestor(master, initial).unwrap());
+        assert!(!repo.is_ancestor(master, feature).unwrap());
+        assert!(!repo.is_ancestor(feature, master).unwrap());
+    });
+}
+
+#[test]
+fn is_ancestor_with_skewed_commit_times() {
+    run_test_in_new_repo(|path| {
+        let initial = head_id(path);
+
+        // A commit made with a clock running far behind.
+        test_write_file(path, b"Hello past!", "hello_world.txt");
+        git_add_file(path, Path::new("hello_world.txt"));
+        git_commit_at(path, "Skewed commit.", "@1000000000 +0000");
+
+        test_write_file(path, b"Hello present!", "hello_world.txt");
+        git_add_file(path, Path::new("hello_world.txt"));
+        git_commit(path, "Latest commit.");
+        let latest = head_id(path);
+
+        let repo = Repository::open(path).expect("failed to open repository with rusty_git");
+
+        assert!(repo.is_ancestor(initial, latest).unwrap());
+        assert!(!repo.is_ancestor(latest, initial).unwrap());
+    });
+}
+
+#[test]
+fn reading_config_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        let include = path.join("included.config");
+        git_config(path, &["remote.origin.url", "https://example.com/repo.git"]);
+        git_config(
+            path,
+            &[
+                "--add",
+                "remote.origin.fetch",
+                "+refs/heads/*:refs/remotes/origin/*",
+            ],
+        );
+        git_config(
+            path,
+            &["--add", "remote.origin.fetch", "+refs/tags/*:refs/tags/*"],
+        );
+        git_config(path, &["user.name", "  Spaced \"Name\"  "]);
+        git_config(path, &["include.path", include.to_str().unwrap()]);
+        git_config(
+            path,
+            &["--file", include.to_str().unwrap(), "pack.threads", "4"],
+        );
+        git_config(
+            path,
+            &["--file", include.to_str().unwrap(), "core.bare", "true"],
+        );
+
+        let repo = Repository::open(path).unwrap();
+        let config = repo.config();
+
+        for key in &[
+            "remote.origin.url",
+            "user.name",
+            "core.repositoryformatversion",
+        ] {
+            assert_eq!(
+                config.get_str(key).unwrap(),
+                git_config(path, &["--get", key]).trim_end_matches('\n')
+            );
+        }
+        assert_eq!(
+            config.get_all("remote.origin.fetch").collect::<Vec<_>>(),
+            git_config(path, &["--get-all", "remote.origin.fetch"])
+                .lines()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(config.get_int("pack.threads").unwrap(), Some(4));
+        assert_eq!(
+            config.get_int("core.repositoryformatversion").unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            config.get_bool("core.bare").unwrap(),
+            Some(git_config(path, &["--type=bool", "core.bare"]).trim() == "true")
+        );
+        assert_eq!(config.get_str("remote.upstream.url"), None);
+    });
+}
+
+#[test]
+fn reading_index_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        fs::create_dir_all(path.join("dir/nested")).unwrap();
+        for file in &["dir/a.txt", "dir/b.txt", "dir/nested/c.txt", "z.txt"] {
+            let file = test_write_file(path, file.as_bytes(), file);
+            git_add_file(path, &file);
+        }
+
+        let lg2_repo = git2::Repository::open(path).unwrap();
+
+        for &version in &[2, 4] {
+            git_update_index_version(path, version);
+
+            let repo = Repository::open(path).unwrap();
+            let index = repo.index().unwrap();
+            let entries: Vec<_> = index
+                .entries()
+                .map(|entry| {
+                    (
+                        format!("{:06o}", entry.file_mode().to_raw()),
+                        entry.id().to_string(),
+                        entry.stage().to_string(),
+                        entry.path().to_string(),
+                    )
+                })
+                .collect();
+            assert_eq!(entries, git_ls_files_stage(path));
+
+            let lg2_index = lg2_repo.index().unwrap();
+            for (entry, lg2_entry) in index.entries().zip(lg2_index.iter()) {
+                assert_eq!(
+                    entry.mtime().unix_seconds(),
+                    lg2_entry.mtime.seconds().into()
+                );
+                assert_eq!(entry.mtime().nanoseconds(), lg2_entry.mtime.nanoseconds());
+                assert_eq!(
+                    entry.ctime().unix_seconds(),
+                    lg2_entry.ctime.seconds().into()
+                );
+            }
+        }
+
+        // Corrupt a byte in the first entry.
+        let index_path = path.join(".git/index");
+        let mut data = fs::read(&index_path).unwrap();
+        data[20] ^= 1;
+        fs::write(&index_path, data).unwrap();
+
+        let repo = Repository::open(path).unwrap();
+        assert!(matches!(
+            repo.index(),
+            Err(ReadIndexError::Parse(ParseIndexError::ChecksumMismatch))
+        ));
+    });
+}
+
+#[test]
+fn walking_ancestry_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        git_branch(path, "feature");
+
+        test_write_file(path, b"Hello master!", "hello_world.txt");
+        git_add_file(path, Path::new("hello_world.txt"));
+        git_commit(path, "Master commit.");
+
+        git_checkout(path, "feature");
+        test_write_file(path, b"Hello feature!", "hello_world.txt");
+        git_add_file(path, Path::new("hello_world.txt"));
+        git_commit(path, "Feature commit.");
+
+        git_checkout(path, "master");
+        git_merge(path, "feature");
+        git_commit_empty(path, "After merge.");
+
+        let git_ids: Vec<Id> = String::from_utf8(git_log(path, &["--format=%H"]).stdout)
+            .unwrap()
+            .lines()
+            .map(|id| Id::from_str(id).unwrap())
+            .collect();
+        assert_eq!(git_ids.len(), 5);
+
+        let repo = Repository::open(path).unwrap();
+        let odb = repo.object_database();
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+
+        let ids: Vec<Id> = odb
+            .walk_ancestry(head)
+            .map(|result| result.unwrap().0)
+            .collect();
+        assert_eq!(ids, git_ids);
+
+        // The merge commit is returned along with both of its parents.
+        let (merge_id, merge) = odb.walk_ancestry(head).nth(1).unwrap().unwrap();
+        assert_eq!(merge_id, git_ids[1]);
+        assert_eq!(merge.parents().count(), 2);
+
+        let first: Vec<Id> = odb
+            .walk_ancestry(head)
+            .take(2)
+            .map(|result| result.unwrap().0)
+            .collect();
+        assert_eq!(first, &git_ids[..2]);
+
+        let blob = Id::from_str(&git_rev_parse(path, "HEAD:hello_world.txt")).unwrap();
+        let mut walk = odb.walk_ancestry(blob);
+        assert!(walk.next().unwrap().unwrap_err().is_wrong_kind());
+        assert!(walk.next().is_none());
+    });
+}
+
+#[test]
+fn writing_structured_objects_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        fs::create_dir(path.join("dir")).unwrap();
+        let file = test_write_file(path, b"Hello dir!", "dir/file.txt");
+        git_add_file(path, &file);
+        git_commit(path, "Second commit.\n\nWith a body.");
+        git_tag(path, "v1.0", Some("Version 1.0"));
+
+        let repo = Repository::open(path).unwrap();
+        let odb = repo.object_database();
+
+        // Rebuild the objects written by git from their parsed fields.
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+        let commit = repo.find_commit(head).unwrap();
+        let mut commit_data = CommitData::new(
+            commit.tree(),
+            Signature::parse_line(commit.author().as_bytes()).unwrap(),
+            Signature::parse_line(commit.committer().as_bytes()).unwrap(),
+            commit.message(),
+        );
+        for parent in commit.parents() {
+            commit_data = commit_data.with_parent(parent);
+        }
+        assert_eq!(odb.write_commit(&commit_data).unwrap(), head);
+
+        let tree = repo.find_tree(commit.tree()).unwrap();
+        let mut builder = TreeBuilder::new();
+        for entry in tree.entries() {
+            builder
+                .insert(entry.filename(), entry.file_mode(), entry.id())
+                .unwrap();
+        }
+        assert_eq!(odb.write_tree(&builder).unwrap(), commit.tree());
+
+        let tag_id = Id::from_str(&git_rev_parse(path, "v1.0")).unwrap();
+        let tag = repo.find_tag(tag_id).unwrap();
+        let tag_data = TagData::new(tag.object(), tag.kind(), tag.tag(), tag.message().unwrap())
+            .with_tagger(Signature::parse_line(tag.tagger().unwrap().as_bytes()).unwrap());
+        assert_eq!(odb.write_tag(&tag_data).unwrap(), tag_id);
+
+        // Write new objects and read them back.
+        let blob = odb.write_object(b"blob 3\0new").unwrap();
+        let mut builder = TreeBuilder::new();
+        builder
+            .insert(b"new.txt", FileMode::ExecutableBlob, blob)
+            .unwrap();
+        builder
+            .insert(b"dir", FileMode::Tree, commit.tree())
+            .unwrap();
+        let tree = builder.write(odb).unwrap();
+        let signature =
+            Signature::parse_line(b"A U Thor <author@example.com> 1234567890 +0100").unwrap();
+        let new_commit = odb
+            .write_commit(
+                &CommitData::new(tree, signature.clone(), signature, "New commit.\n")
+                    .with_parent(head)
+                    .with_encoding("ISO-8859-1"),
+            )
+            .unwrap();
+
+        let parsed = repo.find_commit(new_commit).unwrap();
+        assert_eq!(parsed.tree(), tree);
+        assert_eq!(parsed.parents().collect::<Vec<_>>(), vec![head]);
+        assert_eq!(parsed.author().name(), "A U Thor");
+        assert_eq!(parsed.committer().email(), "author@example.com");
+        assert_eq!(parsed.encoding().unwrap(), "ISO-8859-1");
+        assert_eq!(parsed.message(), "New commit.\n");
+
+        let parsed = repo.find_tree(tree).unwrap();
+        let entries: Vec<_> = parsed
+            .entries()
+            .map(|entry| (entry.filename().to_string(), entry.file_mode(), entry.id()))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("dir".to_owned(), FileMode::Tree, commit.tree()),
+                ("new.txt".to_owned(), FileMode::ExecutableBlob, blob),
+            ]
+        );
+        assert_eq!(
+            git_rev_parse(path, &format!("{}:new.txt", new_commit)),
+            blob.to_hex()
+        );
+    });
+}
+
+#[test]
+fn building_tree_produces_same_result_as_libgit2() {
+    run_test_in_new_repo(|path| {
+        let repo = Repository::open(path).unwrap();
+        let odb = repo.object_database();
+        let lg2_repo = git2::Repository::open(path).unwrap();
+        let mut lg2_builder = lg2_repo.treebuilder(None).unwrap();
+
+        let blob = odb.write_object(b"blob 4\0blob").unwrap();
+        let tree = repo
+            .find_commit(Id::from_str(&git_rev_parse(path, "HEAD")).unwrap())
+            .unwrap()
+            .tree();
+
+        // Trees are sorted as if their names ended with `/`, so `a` sorts after `a.txt`.
+        let entries = [
+            ("b", FileMode::Blob, blob),
+            ("a", FileMode::Tree, tree),
+            ("a.txt", FileMode::ExecutableBlob, blob),
+            ("a-b", FileMode::Symlink, blob),
+            ("module", FileMode::Gitlink, tree),
+        ];
+        let mut builder = TreeBuilder::new();
+        for &(name, mode, id) in &entries {
+            builder.insert(name.as_bytes(), mode, id).unwrap();
+            lg2_builder
+                .insert(
+                    name,
+                    git2::Oid::from_str(&id.to_hex()).unwrap(),
+                    i32::from(mode.to_raw()),
+                )
+                .unwrap();
+        }
+
+        let id = builder.write(odb).unwrap();
+        assert_eq!(id.to_hex(), lg2_builder.write().unwrap().to_string());
+
+        let names: Vec<_> = repo
+            .find_tree(id)
+            .unwrap()
+            .entries()
+            .map(|entry| entry.filename().to_string())
+            .collect();
+        assert_eq!(names, ["a-b", "a.txt", "a", "b", "module"]);
+    });
+}
+
+#[test]
+fn merging_trees_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        let tree =
+            |rev: &str| Id::from_str(&git_rev_parse(path, &format!("{}^{{tree}}", rev))).unwrap();
+
+        fs::create_dir(path.join("dir")).unwrap();
+        for &(name, content) in &[
+            ("a.txt", "a"),
+            ("b.txt", "b"),
+            ("dir/c.txt", "c"),
+            ("dir/d.txt", "d"),
+        ] {
+            test_write_file(path, content.as_bytes(), name);
+            git_add_file(path, Path::new(name));
+        }
+        git_commit(path, "Base commit.");
+        let base = git_rev_parse(path, "HEAD");
+        git_branch(path, "theirs");
+
+        test_write_file(path, b"ours", "a.txt");
+        git_add_file(path, Path::new("a.txt"));
+        git_rm_cached(path, Path::new("dir/d.txt"));
+        fs::remove_file(path.join("dir/d.txt")).unwrap();
+        test_write_file(path, b"e", "e.txt");
+        git_add_file(path, Path::new("e.txt"));
+        git_commit(path, "Our commit.");
+        let ours = git_rev_parse(path, "HEAD");
+
+        git_checkout(path, "theirs");
+        test_write_file(path, b"theirs", "b.txt");
+        git_add_file(path, Path::new("b.txt"));
+        test_write_file(path, b"theirs", "dir/c.txt");
+        git_add_file(path, Path::new("dir/c.txt"));
+        test_write_file(path, b"f", "dir/f.txt");
+        git_add_file(path, Path::new("dir/f.txt"));
+        git_commit(path, "Their commit.");
+        let theirs = git_rev_parse(path, "HEAD");
+
+        let repo = Repository::open(path).unwrap();
+        let result = repo
+            .merge_trees(tree(&base), tree(&ours), tree(&theirs))
+            .unwrap();
+        let (git_tree, git_clean) = git_merge_tree(path, &ours, &theirs);
+        assert!(git_clean);
+        assert!(result.is_clean());
+        assert_eq!(result.tree().to_hex(), git_tree);
+
+        // Change the same file differently on both sides.
+        test_write_file(path, b"also theirs", "a.txt");
+        git_add_file(path, Path::new("a.txt"));
+        git_commit(path, "Conflicting commit.");
+        let conflicting = git_rev_parse(path, "HEAD");
+
+        let result = repo
+            .merge_trees(tree(&base), tree(&ours), tree(&conflicting))
+            .unwrap();
+        let (_, git_clean) = git_merge_tree(path, &ours, &conflicting);
+        assert!(!git_clean);
+        assert_eq!(result.conflicts().len(), 1);
+        let conflict = &result.conflicts()[0];
+        assert_eq!(conflict.path(), "a.txt");
+        assert_eq!(
+            conflict.base(),
+            Some(Id::from_str(&git_rev_parse(path, &format!("{}:a.txt", base))).unwrap())
+        );
+        assert_eq!(
+            conflict.ours(),
+            Some(Id::from_str(&git_rev_parse(path, &format!("{}:a.txt", ours))).unwrap())
+        );
+        assert_eq!(
+            conflict.theirs(),
+            Some(Id::from_str(&git_rev_parse(path, &format!("{}:a.txt", conflicting))).unwrap())
+        );
+
+        // Non-conflicting paths are still merged.
+        let merged = repo.find_tree(result.tree()).unwrap();
+        assert_eq!(
+            merged.entry(b"a.txt").unwrap().id(),
+            conflict.ours().unwrap()
+        );
+        assert_eq!(
+            merged.entry(b"b.txt").unwrap().id(),
+            Id::from_str(&git_rev_parse(path, &format!("{}:b.txt", theirs))).unwrap()
+        );
+    });
+}
+
+#[test]
+fn empty_commits_have_the_same_tree_as_their_parent() {
+    run_test(|path| {
+        git_init(path).expect("failed to initialize git repository");
+        let repo = Repository::open(path).unwrap();
+        let head = || Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+
+        git_commit_empty(path, "Empty root commit.");
+        assert!(repo.is_empty_commit(head()).unwrap());
+
+        let test_file = test_write_file(path, b"Hello world!", "hello_world.txt");
+        git_add_file(path, &test_file);
+        git_commit(path, "Add file.");
+        assert!(!repo.is_empty_commit(head()).unwrap());
+
+        git_commit_empty(path, "Empty commit.");
+        assert!(repo.is_empty_commit(head()).unwrap());
+    });
+}
+
+#[test]
+fn reading_sha256_repository() {
+    run_test(|path| {
+        git_init_sha256(path);
+        let test_file = test_write_file(path, b"Hello world!", "hello_world.txt");
+        git_add_file(path, &test_file);
+        git_commit(path, "Initial commit.");
+
+        let repo = Repository::open(path).unwrap();
+        assert_eq!(
+            repo.object_database().hash_algorithm(),
+            HashAlgorithm::Sha256
+        );
+        assert!(repo.status().unwrap().is_empty());
+
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+        let tree = Id::from_str(&git_rev_parse(path, "HEAD^{tree}")).unwrap();
+        let blob = Id::from_str(&git_rev_parse(path, "HEAD:hello_world.txt")).unwrap();
+        assert_eq!(head.hash_algorithm(), HashAlgorithm::Sha256);
+
+        let read_objects = |repo: &Repository| {
+            assert_eq!(repo.find_commit(head).unwrap().tree(), tree);
+            let tree = repo.find_tree(tree).unwrap();
+            assert_eq!(tree.entry(b"hello_world.txt").unwrap().id(), blob);
+            assert_eq!(repo.find_blob(blob).unwrap().data(), "Hello world!");
+        };
+
+        read_objects(&repo);
+        let sha1_id = Id::from_hash(b"blob 0\0");
+        assert!(repo
+            .find_blob(sha1_id)
+            .unwrap_err()
+            .is_wrong_hash_algorithm());
+
+        git_repack(path);
+        read_objects(&Repository::open(path).unwrap());
+    });
+}
+
+#[test]
+fn reading_loose_object_headers_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        let repo = Repository::open(path).expect("failed to open repository with rusty_git");
+
+        for (id, kind, len) in git_get_object_headers(path) {
+            let object_id = rusty_git::object::Id::from_str(&id)
+                .expect("failed to read object ID using rusty_git");
+
+            let header = repo
+                .object_database()
+                .read_header(object_id)
+                .expect("failed to read object header with rusty_git");
+
+            assert_eq!(header.kind, kind.parse::<ObjectKind>().unwrap());
+            assert_eq!(header.len, len);
+        }
+    });
+}
+
+#[test]
+fn reading_object_kind_and_size_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        git_tag(path, "v1.0", Some("Version 1.0"));
+        let repo = Repository::open(path).expect("failed to open repository with rusty_git");
+
+        for (id, kind, len) in git_get_object_headers(path) {
+            let object = repo
+                .object_database()
+                .parse_object(Id::from_str(&id).unwrap())
+                .expect("failed to read object with rusty_git");
+
+            assert_eq!(object.kind(), kind.parse::<ObjectKind>().unwrap());
+            assert_eq!(object.size(), len);
+        }
+    });
+}
+
 fn test_rusty_git_read_blob(cwd: &Path, id: &str) -> Vec<u8> {
     let repo = Repository::open(cwd).expect("failed to open repository with rusty_git");
 
     let object_id =
         rusty_git::object::Id::from_str(id).expect("failed to read object ID using rusty_git");
 
-    let object = repo
-        .object_database()
-        .parse_object(object_id)
+    let blob = repo
+        .find_blob(object_id)
         .expect("failed to get object with rusty_git");
 
-    let blob = match object.data() {
-        ObjectData::Blob(blob) => blob,
-        _ => panic!("expected object to be a blob"),
-    };
-
     blob.data().to_vec()
 }
 
@@ -219,3 +1174,436 @@ fn test_libgit2_read_object(cwd: &Path, id: &str) -> Vec<u8> {
 
     object.data().to_vec()
 }
+
+#[test]
+fn staged_status_produces_same_result_as_git() {
+    run_test(|path| {
+        git_init(path).expect("failed to initialize git repository");
+
+        let repo = Repository::open(path).unwrap();
+        assert!(repo.status().unwrap().is_empty());
+
+        std::fs::create_dir(path.join("dir")).unwrap();
+        let unchanged = test_write_file(path, b"unchanged", "unchanged.txt");
+        let modified = test_write_file(path, b"before", "dir/modified.txt");
+        let deleted = test_write_file(path, b"deleted", "dir/deleted.txt");
+        git_add_file(path, &unchanged);
+        git_add_file(path, &modified);
+
+        let status = repo.status().unwrap();
+        assert_eq!(status.added(), &["dir/modified.txt", "unchanged.txt"]);
+        assert!(status.modified().is_empty());
+        assert!(status.deleted().is_empty());
+
+        git_add_file(path, &deleted);
+        git_commit(path, "Initial commit.");
+        assert!(repo.status().unwrap().is_empty());
+
+        test_write_file(path, b"after", "dir/modified.txt");
+        let added = test_write_file(path, b"added", "dir/added.txt");
+        git_add_file(path, &modified);
+        git_add_file(path, &added);
+        git_rm_cached(path, &deleted);
+
+        let status = repo.status().unwrap();
+        let mut actual = Vec::new();
+        actual.extend(
+            status
+                .added()
+                .iter()
+                .map(|p| ("A".to_owned(), p.to_string())),
+        );
+        actual.extend(
+            status
+                .modified()
+                .iter()
+                .map(|p| ("M".to_owned(), p.to_string())),
+        );
+        actual.extend(
+            status
+                .deleted()
+                .iter()
+                .map(|p| ("D".to_owned(), p.to_string())),
+        );
+        actual.sort_by(|l, r| l.1.cmp(&r.1));
+
+        assert_eq!(actual, git_diff_cached_name_status(path));
+    });
+}
+
+#[test]
+fn opening_linked_worktree() {
+    run_test_in_new_repo(|path| {
+        let worktree = path.join("worktree");
+        git_worktree_add(path, &worktree, "feature");
+
+        let test_file = test_write_file(&worktree, b"feature", "feature.txt");
+        git_add_file(&worktree, &test_file);
+        git_commit(&worktree, "Feature commit.");
+
+        let repo = Repository::open(&worktree).unwrap();
+        let head = repo.reference_database().head().unwrap();
+        assert_eq!(head.name(), Some("refs/heads/feature"));
+
+        let id = Id::from_str(&git_rev_parse(&worktree, "HEAD")).unwrap();
+        assert_eq!(head.peel(&repo).unwrap().id(), &id);
+        assert_eq!(repo.find_commit(id).unwrap().message(), "Feature commit.\n");
+        assert!(repo.status().unwrap().is_empty());
+
+        let main = Repository::open(path).unwrap();
+        assert_eq!(
+            main.reference_database().head().unwrap().name(),
+            Some("refs/heads/master")
+        );
+    });
+}
+
+#[test]
+fn finding_short_reference_names_produces_same_result_as_git() {
+    run_test_in_new_repo(|path| {
+        git_tag(path, "v1.0", None);
+        let test_file = test_write_file(path, b"Second", "second.txt");
+        git_add_file(path, &test_file);
+        git_commit(path, "Second commit.");
+        git_branch(path, "feature");
+
+        let repo = Repository::open(path).unwrap();
+        for name in &[
+            "HEAD",
+            "@",
+            "master",
+            "feature",
+            "heads/feature",
+            "refs/heads/feature",
+            "v1.0",
+            "tags/v1.0",
+        ] {
+            let id = Id::from_str(&git_rev_parse(path, name)).unwrap();
+            let reference = repo.find_reference(name.as_bytes()).unwrap();
+            assert_eq!(reference.peel(&repo).unwrap().id(), &id, "{}", name);
+        }
+
+        assert!(repo.find_reference(b"missing").is_err());
+    });
+}
+
+#[test]
+fn peeling_cyclic_symbolic_references() {
+    run_test_in_new_repo(|path| {
+        let heads = path.join(".git").join("refs").join("heads");
+        fs::write(heads.join("a"), "ref: refs/heads/b\n").unwrap();
+        fs::write(heads.join("b"), "ref: refs/heads/a\n").unwrap();
+        fs::write(heads.join("self"), "ref: refs/heads/self\n").unwrap();
+        fs::write(heads.join("chain"), "ref: refs/heads/master\n").unwrap();
+
+        let repo = Repository::open(path).unwrap();
+        let peel = |name: &[u8]| {
+            repo.reference_database()
+                .reference(name)
+                .unwrap()
+                .peel(&repo)
+        };
+
+        assert!(matches!(
+            peel(b"refs/heads/a"),
+            Err(reference::Error::SymbolicReferenceCycle(name)) if name == "refs/heads/b"
+        ));
+        assert!(matches!(
+            peel(b"refs/heads/self"),
+            Err(reference::Error::SymbolicReferenceCycle(name)) if name == "refs/heads/self"
+        ));
+
+        let id = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+        assert_eq!(peel(b"refs/heads/chain").unwrap().id(), &id);
+    });
+}
+
+#[test]
+fn opening_relative_gitdir_file() {
+    run_test_in_new_repo(|path| {
+        std::fs::rename(path.join(".git"), path.join("real.git")).unwrap();
+        std::fs::write(path.join(".git"), "gitdir: real.git\n").unwrap();
+
+        let repo = Repository::open(path).unwrap();
+        let id = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+        assert_eq!(
+            repo.reference_database()
+                .head()
+                .unwrap()
+                .peel(&repo)
+                .unwrap()
+                .id(),
+            &id
+        );
+
+        std::fs::write(path.join(".git"), "gitdir: missing.git\n").unwrap();
+        assert!(Repository::open(path).is_err());
+        std::fs::write(path.join(".git"), "not a gitdir file\n").unwrap();
+        assert!(Repository::open(path).is_err());
+    });
+}
+
+#[test]
+fn reading_objects_from_alternates() {
+    run_test_in_new_repo(|path| {
+        let test_file = test_write_file(path, b"packed", "packed.txt");
+        git_add_file(path, &test_file);
+        git_commit(path, "Packed commit.");
+        git_repack(path);
+        let test_file = test_write_file(path, b"loose", "loose.txt");
+        git_add_file(path, &test_file);
+        git_commit(path, "Loose commit.");
+
+        let clone = path.join("clone");
+        git_clone_shared(path, path, &clone);
+        let test_file = test_write_file(&clone, b"clone", "clone.txt");
+        git_add_file(&clone, &test_file);
+        git_commit(&clone, "Clone commit.");
+
+        let repo = Repository::open(&clone).unwrap();
+        let log = String::from_utf8(git_log(&clone, &["--format=%H %s"]).stdout).unwrap();
+        let mut subjects = Vec::new();
+        for (id, commit) in repo
+            .object_database()
+            .walk_ancestry(Id::from_str(&git_rev_parse(&clone, "HEAD")).unwrap())
+            .map(Result::unwrap)
+        {
+            subjects.push(format!("{} {}", id, commit.subject()));
+        }
+        assert_eq!(subjects, log.lines().collect::<Vec<_>>());
+
+        for (name, data) in &[
+            ("packed.txt", &b"packed"[..]),
+            ("loose.txt", b"loose"),
+            ("clone.txt", b"clone"),
+        ] {
+            let id = Id::from_str(&git_rev_parse(&clone, &format!("HEAD:{}", name))).unwrap();
+            assert!(repo.object_database().contains(id).unwrap());
+            assert_eq!(test_rusty_git_read_blob(&clone, &id.to_string()), *data);
+            let short_id = ShortId::from_str(&id.to_string()[..10]).unwrap();
+            assert_eq!(repo.object_database().resolve(&short_id).unwrap(), id);
+        }
+
+        let ids: Vec<_> = repo
+            .object_database()
+            .object_ids()
+            .map(|id| id.unwrap().to_string())
+            .collect();
+        let mut expected = git_get_objects(&clone);
+        expected.retain(|id| !id.is_empty());
+        expected.sort();
+        assert_eq!(ids, expected);
+    });
+}
+
+#[test]
+fn opening_bare_repository() {
+    run_test_in_new_repo(|path| {
+        let head = Id::from_str(&git_rev_parse(path, "HEAD")).unwrap();
+
+        let repo = Repository::open_bare(path.join(".git")).unwrap();
+        assert_eq!(repo.workdir(), None);
+        assert_eq!(repo.git_dir(), path.join(".git"));
+        assert_eq!(
+            repo.reference_database()
+                .head()
+                .unwrap()
+                .peel(&repo)
+                .unwrap()
+                .id(),
+            &head
+        );
+
+        let repo = Repository::open(path).unwrap();
+        assert_eq!(repo.workdir(), Some(path));
+        assert_eq!(repo.git_dir(), path.join(".git"));
+
+        assert!(matches!(
+            Repository::open_bare(path),
+            Err(OpenError::InvalidRepository { .. })
+        ));
+    });
+}
+
+#[test]
+fn opening_malformed_repository_fails() {
+    run_test(|path| {
+        std::fs::create_dir(path.join(".git")).unwrap();
+        match Repository::open(path) {
+            Err(OpenError::InvalidRepository { missing, .. }) => assert_eq!(missing, "objects"),
+            result => panic!("expected InvalidRepository, got {:?}", result.map(|_| ())),
+        }
+
+        git_init(path).unwrap();
+        Repository::open(path).unwrap();
+
+        std::fs::remove_file(path.join(".git").join("HEAD")).unwrap();
+        let err = Repository::open(path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "invalid repository at `{}`: `HEAD` is missing",
+                path.join(".git").display()
+            )
+        );
+    });
+}
+
+#[test]
+fn opening_repository_with_invalid_config_fails() {
+    run_test_in_new_repo(|path| {
+        let config_path = path.join(".git").join("config");
+
+        git_config(path, &["extensions.objectFormat", "sha512"]);
+        match Repository::open(path) {
+            Err(OpenError::InvalidConfig(err)) => assert_eq!(
+                err.to_string(),
+                "invalid object format value `sha512` for config key `extensions.objectFormat`"
+            ),
+            result => panic!("expected InvalidConfig, got {:?}", result.map(|_| ())),
+        }
+
+        fs::write(&config_path, "[core\n").unwrap();
+        match Repository::open(path) {
+            Err(OpenError::ReadConfig(_)) => (),
+            result => panic!("expected ReadConfig, got {:?}", result.map(|_| ())),
+        }
+
+        // A missing config is the same as an empty one.
+        fs::remove_file(&config_path).unwrap();
+        let repo = Repository::open(path).unwrap();
+        assert_eq!(repo.object_database().hash_algorithm(), HashAlgorithm::Sha1);
+    });
+}
+
+#[test]
+#[cfg(unix)]
+fn opening_inaccessible_repository_fails() {
+    use std::os::unix::fs::PermissionsExt;
+
+    run_test_in_new_repo(|path| {
+        let dotgit = path.join(".git");
+        fs::set_permissions(&dotgit, fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions are not enforced for privileged users.
+        let accessible = fs::read_dir(&dotgit).is_ok();
+        let result = Repository::open(path);
+        fs::set_permissions(&dotgit, fs::Permissions::from_mode(0o755)).unwrap();
+        if accessible {
+            eprintln!("skipping: permissions are not enforced for this user");
+            return;
+        }
+
+        match result {
+            Err(OpenError::PermissionDenied(denied)) => assert_eq!(denied, dotgit),
+            result => panic!("expected PermissionDenied, got {:?}", result.map(|_| ())),
+        }
+    });
+}
+
+#[test]
+fn discovering_repository_from_subdirectory() {
+    run_test_in_new_repo(|path| {
+        let subdir = path.join("a").join("b");
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let repo = Repository::discover(&subdir).unwrap();
+        assert_eq!(repo.workdir(), Some(path));
+        assert!(repo.reference_database().head().is_ok());
+
+        let ceiling = path.parent().unwrap().to_owned();
+        let repo = Repository::discover_with_ceiling_dirs(&subdir, &[ceiling]).unwrap();
+        assert_eq!(repo.workdir(), Some(path));
+
+        match Repository::discover_with_ceiling_dirs(&subdir, &[path.join("a")]) {
+            Err(OpenError::NotFound(start)) => assert_eq!(start, subdir),
+            result => panic!("expected NotFound, got {:?}", result.map(|_| ())),
+        }
+
+        // A `.git` folder which is not a valid git directory is skipped.
+        std::fs::create_dir(path.join("a").join(".git")).unwrap();
+        let repo = Repository::discover(&subdir).unwrap();
+        assert_eq!(repo.workdir(), Some(path));
+
+        match Repository::discover_with_ceiling_dirs(&subdir, &[path.to_owned()]) {
+            Err(OpenError::NotFound(start)) => assert_eq!(start, subdir),
+            result => panic!("expected NotFound, got {:?}", result.map(|_| ())),
+        }
+    });
+}
+
+#[test]
+fn discovering_repository_from_missing_path_fails() {
+    run_test_in_new_repo(|path| {
+        let missing = path.join("missing");
+        match Repository::discover(&missing) {
+            Err(OpenError::NotFound(start)) => assert_eq!(start, missing),
+            result => panic!("expected NotFound, got {:?}", result.map(|_| ())),
+        }
+    });
+}
+
+#[test]
+fn discovering_repository_from_path_with_parent_components() {
+    run_test_in_new_repo(|path| {
+        let subdir = path.join("a").join("b");
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        // `a/b/..` is `a`, so the search continues upwards from `a` without
+        // passing through the ceiling at `a/b`.
+        let start = subdir.join("..");
+        let repo = Repository::discover_with_ceiling_dirs(&start, &[subdir.clone()]).unwrap();
+        assert_eq!(repo.workdir(), Some(path));
+
+        // Ceilings are compared after resolving `..` components too.
+        let ceiling = subdir.join("..").join("..");
+        match Repository::discover_with_ceiling_dirs(&start, &[ceiling]) {
+            Err(OpenError::NotFound(not_found)) => assert_eq!(not_found, start),
+            result => panic!("expected NotFound, got {:?}", result.map(|_| ())),
+        }
+    });
+}
+
+#[test]
+fn tree_entry_at_path_produces_same_result_as_git() {
+    run_test(|path| {
+        git_init(path).expect("failed to initialize git repository");
+
+        std::fs::create_dir_all(path.join("dir").join("sub")).unwrap();
+        let file = test_write_file(path, b"Hello world!", "dir/sub/file.txt");
+        git_add_file(path, &file);
+        git_add_gitlink(path, "90012941912143fcf042590f8e152c41b13d5520", "module");
+        git_commit(path, "Initial commit.");
+
+        let repo = Repository::open(path).unwrap();
+        let root = Id::from_str(&git_rev_parse(path, "HEAD^{tree}")).unwrap();
+        let find = |p: &str| {
+            repo.object_database()
+                .tree_entry_at_path(root, Path::new(p))
+                .unwrap()
+        };
+
+        for p in &["dir", "dir/sub", "dir/sub/file.txt", "./dir/sub/file.txt"] {
+            let entry = find(p).unwrap();
+            assert_eq!(
+                entry.entry().id().to_hex(),
+                git_rev_parse(path, &format!("HEAD:{}", p.trim_start_matches("./")))
+            );
+        }
+        assert_eq!(find("dir/sub/file.txt").unwrap().entry().mode(), 0o100644);
+        assert_eq!(find("dir/sub").unwrap().entry().filename(), "sub");
+
+        let module = find("module/inner/file.txt").unwrap();
+        assert_eq!(module.entry().mode(), 0o160000);
+        assert_eq!(module.entry().filename(), "module");
+
+        assert!(find("missing").is_none());
+        assert!(find("dir/missing/file.txt").is_none());
+        assert!(find("").is_none());
+        assert!(find("../dir").is_none());
+
+        assert!(repo
+            .object_database()
+            .tree_entry_at_path(root, Path::new("dir/sub/file.txt/inner"))
+            .is_err());
+    });
+}